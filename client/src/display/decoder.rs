@@ -3,9 +3,11 @@
 //! サーバーから受信した画像データをデコードする機能を提供します。
 
 use super::ImageFormat;
-use image::{DynamicImage, ImageError};
+use image::{DynamicImage, GenericImageView, ImageError, RgbaImage};
+use remote_desktop_rs_common::qoi;
+use remote_desktop_rs_common::tile_cache::{TileCache, TILE_CACHE_CAPACITY};
 use thiserror::Error;
-use std::io::Cursor;
+use base64::Engine;
 
 /// デコードエラー
 #[derive(Error, Debug)]
@@ -18,6 +20,14 @@ pub enum DecodeError {
     #[error("画像のデコードに失敗しました: {0}")]
     DecodeFailure(#[from] ImageError),
     
+    /// 差分の合成元となる画像がない
+    #[error("差分を合成する画像がありません（キーフレームが必要です）")]
+    MissingKeyframe,
+    
+    /// 参照されたタイルがキャッシュにない
+    #[error("タイルがキャッシュにありません: {0:x}")]
+    MissingTile(u64),
+    
     /// その他のエラー
     #[error("デコード中に予期しないエラーが発生しました: {0}")]
     Other(String),
@@ -36,10 +46,58 @@ pub struct DecodedImage {
     pub timestamp: u64,
 }
 
+/// 差分フレームの幅・高さの上限（ピクセル）
+///
+/// キーフレームの大きさは受信したデータなので、画像を確保する前にこの値で制限します。
+pub const MAX_FRAME_DIMENSION: u32 = 16384;
+
+/// 差分フレームの矩形
+#[derive(Debug, Clone)]
+pub struct UpdateRect {
+    /// X座標
+    pub x: u32,
+    /// Y座標
+    pub y: u32,
+    /// 幅
+    pub width: u32,
+    /// 高さ
+    pub height: u32,
+    /// 矩形の内容
+    pub content: UpdateContent,
+}
+
+/// 差分フレームの矩形の内容
+#[derive(Debug, Clone)]
+pub enum UpdateContent {
+    /// エンコードされた画像
+    Encoded {
+        /// 画像フォーマット
+        format: ImageFormat,
+        /// 画像データ
+        data: Vec<u8>,
+        /// タイルキャッシュに保存するタイルのハッシュ（左から順）
+        tiles: Vec<u64>,
+    },
+    /// タイルキャッシュにあるタイル
+    TileRef(u64),
+    /// 合成中の画像内の領域のコピー（コピー元の位置）
+    CopyRect {
+        /// コピー元のX座標
+        src_x: u32,
+        /// コピー元のY座標
+        src_y: u32,
+    },
+}
+
 /// 画像デコーダ
 pub struct ImageDecoder {
     /// 最後にデコードした画像
     last_decoded: Option<DecodedImage>,
+    /// サーバーから受信したタイル（サーバー側のキャッシュと同じ順序で更新する）
+    tiles: TileCache<RgbaImage>,
+    /// H.264デコーダ（前のフレームを参照するため、キーフレームまで保持する）
+    #[cfg(feature = "openh264")]
+    video: Option<openh264::decoder::Decoder>,
 }
 
 impl ImageDecoder {
@@ -47,60 +105,100 @@ impl ImageDecoder {
     pub fn new() -> Self {
         Self {
             last_decoded: None,
+            tiles: TileCache::new(TILE_CACHE_CAPACITY),
+            #[cfg(feature = "openh264")]
+            video: None,
         }
     }
     
     /// 画像データをデコード
     pub fn decode(&mut self, data: &[u8], format: ImageFormat, width: u32, height: u32, timestamp: u64) -> Result<DecodedImage, DecodeError> {
-        let image = match format {
-            ImageFormat::JPEG => {
-                image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)
-                    .map_err(DecodeError::DecodeFailure)?
-            },
-            ImageFormat::PNG => {
-                image::load_from_memory_with_format(data, image::ImageFormat::Png)
-                    .map_err(DecodeError::DecodeFailure)?
-            },
-            ImageFormat::WebP => {
-                // WebPはcrate 'image'では直接サポートされていないため、webp crateを使用
-                #[cfg(feature = "webp")]
-                {
-                    let decoder = webp::Decoder::new(data);
-                    let webp_image = decoder.decode()
-                        .ok_or_else(|| DecodeError::InvalidFormat("WebP decoding failed".to_string()))?;
-                    
-                    let rgba = webp_image.to_rgba();
-                    let width = webp_image.width();
-                    let height = webp_image.height();
-                    
-                    DynamicImage::ImageRgba8(
-                        image::RgbaImage::from_raw(width, height, rgba.to_vec())
-                            .ok_or_else(|| DecodeError::Other("Failed to create image from WebP data".to_string()))?
-                    )
-                }
-                
-                #[cfg(not(feature = "webp"))]
-                {
-                    return Err(DecodeError::InvalidFormat("WebP format is not supported in this build".to_string()));
-                }
-            },
-            ImageFormat::AVIF => {
-                // AVIFもcrate 'image'では直接サポートされていないため、libavif-bindingsなどの外部crateが必要
-                #[cfg(feature = "avif")]
-                {
-                    // AVIF対応時に実装
-                    unimplemented!("AVIF decoding not yet implemented");
-                }
-                
-                #[cfg(not(feature = "avif"))]
-                {
-                    return Err(DecodeError::InvalidFormat("AVIF format is not supported in this build".to_string()));
+        let image = decode_image(data, format)?;
+        
+        let decoded = DecodedImage {
+            image,
+            original_width: width,
+            original_height: height,
+            timestamp,
+        };
+        
+        self.last_decoded = Some(decoded.clone());
+        Ok(decoded)
+    }
+    
+    /// 差分フレームを最後にデコードした画像に合成
+    ///
+    /// キーフレームでは新しい画像に描画し、タイルキャッシュを空にします。
+    /// 合成元の画像やタイルがない場合は`DecodeError::MissingKeyframe`または
+    /// `DecodeError::MissingTile`を返すので、サーバーにキーフレームを要求してください。
+    pub fn apply_update(&mut self, width: u32, height: u32, tile_size: u32, keyframe: bool, rects: Vec<UpdateRect>, timestamp: u64) -> Result<DecodedImage, DecodeError> {
+        let mut canvas = if keyframe {
+            if width > MAX_FRAME_DIMENSION || height > MAX_FRAME_DIMENSION {
+                return Err(DecodeError::InvalidFormat(format!("フレームが大きすぎます: {}x{}", width, height)));
+            }
+            self.tiles.clear();
+            #[cfg(feature = "openh264")]
+            {
+                self.video = None;
+            }
+            RgbaImage::new(width, height)
+        } else {
+            match self.last_decoded.take() {
+                Some(last) if last.image.width() == width && last.image.height() == height => last.image.into_rgba8(),
+                last => {
+                    self.last_decoded = last;
+                    return Err(DecodeError::MissingKeyframe);
                 }
-            },
+            }
         };
         
+        // 矩形はサーバーが送信した順に適用し、タイルキャッシュも同じ順に更新する
+        for rect in rects {
+            match rect.content {
+                UpdateContent::Encoded { format, data, tiles } => {
+                    let image = match format {
+                        ImageFormat::H264 => match self.decode_video(&data)? {
+                            Some(image) => image,
+                            // デコーダがまだ画像を出力しない場合は前の画像のまま
+                            None => continue,
+                        },
+                        format => decode_image(&data, format)?.to_rgba8(),
+                    };
+                    image::imageops::replace(&mut canvas, &image, rect.x as i64, rect.y as i64);
+                    
+                    if !tiles.is_empty() && tile_size == 0 {
+                        return Err(DecodeError::InvalidFormat("タイルサイズが0です".to_string()));
+                    }
+                    
+                    // タイルの数や大きさは受信したデータなので、画像の範囲内か確認してから切り出す
+                    for (i, hash) in tiles.into_iter().enumerate() {
+                        let x = (i as u32).checked_mul(tile_size)
+                            .filter(|&x| x < image.width())
+                            .ok_or_else(|| DecodeError::InvalidFormat(format!("タイル{}が画像の範囲外です (幅: {})", i, image.width())))?;
+                        let tile = image.view(x, 0, tile_size.min(image.width() - x), image.height()).to_image();
+                        self.tiles.insert(hash, tile);
+                    }
+                },
+                UpdateContent::TileRef(hash) => {
+                    let tile = self.tiles.get(hash).ok_or(DecodeError::MissingTile(hash))?;
+                    image::imageops::replace(&mut canvas, tile, rect.x as i64, rect.y as i64);
+                },
+                UpdateContent::CopyRect { src_x, src_y } => {
+                    let in_bounds = src_x.checked_add(rect.width).is_some_and(|right| right <= width)
+                        && src_y.checked_add(rect.height).is_some_and(|bottom| bottom <= height);
+                    if !in_bounds {
+                        return Err(DecodeError::InvalidFormat(format!("コピー元が画像の範囲外です: ({}, {})", src_x, src_y)));
+                    }
+                    
+                    // 重なっていてもよいように、コピー元を取り出してから書き込む
+                    let copied = canvas.view(src_x, src_y, rect.width, rect.height).to_image();
+                    image::imageops::replace(&mut canvas, &copied, rect.x as i64, rect.y as i64);
+                },
+            }
+        }
+        
         let decoded = DecodedImage {
-            image,
+            image: DynamicImage::ImageRgba8(canvas),
             original_width: width,
             original_height: height,
             timestamp,
@@ -110,9 +208,43 @@ impl ImageDecoder {
         Ok(decoded)
     }
     
+    /// H.264のフレームをデコード
+    fn decode_video(&mut self, data: &[u8]) -> Result<Option<RgbaImage>, DecodeError> {
+        #[cfg(feature = "openh264")]
+        {
+            let decoder = match &mut self.video {
+                Some(decoder) => decoder,
+                video => {
+                    let created = openh264::decoder::Decoder::new()
+                        .map_err(|e| DecodeError::Other(format!("H.264 decoder initialization failed: {}", e)))?;
+                    video.insert(created)
+                },
+            };
+            
+            let yuv = match decoder.decode(data).map_err(|e| DecodeError::InvalidFormat(format!("H.264 decoding failed: {}", e)))? {
+                Some(yuv) => yuv,
+                None => return Ok(None),
+            };
+            
+            let (width, height) = yuv.dimension_rgb();
+            let mut rgb = vec![0; width * height * 3];
+            yuv.write_rgb8(&mut rgb);
+            
+            let image = image::RgbImage::from_raw(width as u32, height as u32, rgb)
+                .ok_or_else(|| DecodeError::Other("Failed to create image from H.264 data".to_string()))?;
+            Ok(Some(DynamicImage::ImageRgb8(image).to_rgba8()))
+        }
+        
+        #[cfg(not(feature = "openh264"))]
+        {
+            let _ = data;
+            Err(DecodeError::InvalidFormat("H.264 format is not supported in this build".to_string()))
+        }
+    }
+    
     /// Base64エンコードされた画像データをデコード
     pub fn decode_base64(&mut self, base64_data: &str, format: ImageFormat, width: u32, height: u32, timestamp: u64) -> Result<DecodedImage, DecodeError> {
-        let data = base64::decode(base64_data)
+        let data = base64::engine::general_purpose::STANDARD.decode(base64_data)
             .map_err(|e| DecodeError::Other(format!("Base64 decoding failed: {}", e)))?;
         
         self.decode(&data, format, width, height, timestamp)
@@ -128,4 +260,199 @@ impl Default for ImageDecoder {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// 画像データを形式に応じてデコード
+fn decode_image(data: &[u8], format: ImageFormat) -> Result<DynamicImage, DecodeError> {
+    let image = match format {
+        ImageFormat::JPEG => {
+            image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)
+                .map_err(DecodeError::DecodeFailure)?
+        },
+        ImageFormat::PNG => {
+            image::load_from_memory_with_format(data, image::ImageFormat::Png)
+                .map_err(DecodeError::DecodeFailure)?
+        },
+        ImageFormat::WebP => {
+            // WebPはcrate 'image'では直接サポートされていないため、webp crateを使用
+            #[cfg(feature = "webp")]
+            {
+                let decoder = webp::Decoder::new(data);
+                let webp_image = decoder.decode()
+                    .ok_or_else(|| DecodeError::InvalidFormat("WebP decoding failed".to_string()))?;
+                
+                let rgba = webp_image.to_rgba();
+                let width = webp_image.width();
+                let height = webp_image.height();
+                
+                DynamicImage::ImageRgba8(
+                    image::RgbaImage::from_raw(width, height, rgba.to_vec())
+                        .ok_or_else(|| DecodeError::Other("Failed to create image from WebP data".to_string()))?
+                )
+            }
+            
+            #[cfg(not(feature = "webp"))]
+            {
+                return Err(DecodeError::InvalidFormat("WebP format is not supported in this build".to_string()));
+            }
+        },
+        ImageFormat::AVIF => {
+            // AVIFもcrate 'image'では直接サポートされていないため、libavif-bindingsなどの外部crateが必要
+            return Err(DecodeError::InvalidFormat("AVIF format is not supported in this build".to_string()));
+        },
+        ImageFormat::QOI => {
+            let (width, height, pixels) = qoi::decode(data)
+                .map_err(|e| DecodeError::InvalidFormat(format!("QOI decoding failed: {}", e)))?;
+            
+            DynamicImage::ImageRgba8(
+                RgbaImage::from_raw(width, height, pixels)
+                    .ok_or_else(|| DecodeError::Other("Failed to create image from QOI data".to_string()))?
+            )
+        },
+        ImageFormat::H264 => {
+            // 前のフレームを参照するため、ImageDecoder::apply_updateでのみデコードできる
+            return Err(DecodeError::InvalidFormat("H.264 frames can only be decoded as frame updates".to_string()));
+        },
+    };
+    
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, Rgba};
+    use std::io::Cursor;
+    
+    // 単色のPNG画像の矩形を作成
+    fn png_rect(x: u32, y: u32, width: u32, height: u32, color: Rgba<u8>, tiles: Vec<u64>) -> UpdateRect {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, color));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageOutputFormat::Png).unwrap();
+        
+        UpdateRect {
+            x,
+            y,
+            width,
+            height,
+            content: UpdateContent::Encoded { format: ImageFormat::PNG, data: data.into_inner(), tiles },
+        }
+    }
+    
+    fn tile_ref(x: u32, y: u32, width: u32, hash: u64) -> UpdateRect {
+        UpdateRect { x, y, width, height: width, content: UpdateContent::TileRef(hash) }
+    }
+    
+    #[test]
+    fn test_updates_are_composed_onto_last_image() {
+        let white = Rgba([255, 255, 255, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+        let mut decoder = ImageDecoder::new();
+        
+        // キーフレームを受信するまでは合成できない
+        assert!(matches!(decoder.apply_update(8, 8, 0, false, Vec::new(), 0), Err(DecodeError::MissingKeyframe)));
+        
+        decoder.apply_update(8, 8, 0, true, vec![png_rect(0, 0, 8, 8, white, Vec::new())], 1).unwrap();
+        let decoded = decoder.apply_update(8, 8, 0, false, vec![png_rect(4, 2, 2, 2, red, Vec::new())], 2).unwrap();
+        
+        assert_eq!(decoded.image.get_pixel(0, 0), white);
+        assert_eq!(decoded.image.get_pixel(4, 2), red);
+        assert_eq!(decoded.image.get_pixel(5, 3), red);
+        assert_eq!(decoded.image.get_pixel(6, 3), white);
+        assert_eq!(decoder.last_image().unwrap().timestamp, 2);
+        
+        // 画面サイズが変わった場合もキーフレームが必要
+        assert!(matches!(decoder.apply_update(16, 8, 0, false, Vec::new(), 3), Err(DecodeError::MissingKeyframe)));
+        assert!(decoder.last_image().is_some());
+    }
+    
+    #[test]
+    fn test_cached_tiles_are_reused() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let mut decoder = ImageDecoder::new();
+        
+        // 2つのタイルを1つの矩形で受信し、キャッシュに保存する
+        let keyframe = vec![png_rect(0, 0, 8, 4, red, vec![1, 1]), png_rect(0, 4, 8, 4, blue, vec![2, 2])];
+        decoder.apply_update(8, 8, 4, true, keyframe, 1).unwrap();
+        
+        // 参照したタイルが描画される
+        let decoded = decoder.apply_update(8, 8, 4, false, vec![tile_ref(0, 0, 4, 2), tile_ref(4, 4, 4, 1)], 2).unwrap();
+        assert_eq!(decoded.image.get_pixel(1, 1), blue);
+        assert_eq!(decoded.image.get_pixel(5, 1), red);
+        assert_eq!(decoded.image.get_pixel(5, 5), red);
+        assert_eq!(decoded.image.get_pixel(1, 5), blue);
+        
+        // キーフレームでキャッシュは空になる
+        decoder.apply_update(8, 8, 4, true, vec![png_rect(0, 0, 8, 8, red, Vec::new())], 3).unwrap();
+        assert!(matches!(decoder.apply_update(8, 8, 4, false, vec![tile_ref(0, 0, 4, 1)], 4), Err(DecodeError::MissingTile(1))));
+    }
+    
+    #[test]
+    fn test_invalid_tiles_are_rejected() {
+        let red = Rgba([255, 0, 0, 255]);
+        let mut decoder = ImageDecoder::new();
+        
+        // 幅8の矩形には4ピクセルのタイルが2つまでしか入らない
+        let too_many = vec![png_rect(0, 0, 8, 8, red, vec![1, 2, 3])];
+        assert!(matches!(decoder.apply_update(8, 8, 4, true, too_many, 1), Err(DecodeError::InvalidFormat(_))));
+        
+        // タイルサイズが0のままタイルを送られた場合
+        let zero_size = vec![png_rect(0, 0, 8, 8, red, vec![1])];
+        assert!(matches!(decoder.apply_update(8, 8, 0, true, zero_size, 2), Err(DecodeError::InvalidFormat(_))));
+        
+        // 画像より大きなタイルサイズは画像の幅に収める
+        decoder.apply_update(8, 8, 16, true, vec![png_rect(0, 0, 8, 8, red, vec![1])], 3).unwrap();
+        let decoded = decoder.apply_update(8, 8, 16, false, vec![tile_ref(0, 0, 8, 1)], 4).unwrap();
+        assert_eq!(decoded.image.get_pixel(7, 7), red);
+    }
+    
+    #[test]
+    fn test_copy_rect_moves_existing_pixels() {
+        let white = Rgba([255, 255, 255, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+        let mut decoder = ImageDecoder::new();
+        decoder.apply_update(8, 8, 0, true, vec![png_rect(0, 0, 8, 8, white, Vec::new()), png_rect(0, 4, 8, 1, red, Vec::new())], 1).unwrap();
+        
+        // 2ピクセル上にスクロールし、下端の新しい部分を描画
+        let scroll = UpdateRect { x: 0, y: 0, width: 8, height: 6, content: UpdateContent::CopyRect { src_x: 0, src_y: 2 } };
+        let decoded = decoder.apply_update(8, 8, 0, false, vec![scroll, png_rect(0, 6, 8, 2, white, Vec::new())], 2).unwrap();
+        assert_eq!(decoded.image.get_pixel(3, 2), red);
+        assert_eq!(decoded.image.get_pixel(3, 4), white);
+        
+        // 範囲外のコピーはエラー
+        let invalid = UpdateRect { x: 0, y: 0, width: 8, height: 8, content: UpdateContent::CopyRect { src_x: 1, src_y: 0 } };
+        assert!(decoder.apply_update(8, 8, 0, false, vec![invalid], 3).is_err());
+        
+        // 座標と大きさの和が桁あふれする場合もパニックせずにエラー
+        decoder.apply_update(8, 8, 0, true, vec![png_rect(0, 0, 8, 8, white, Vec::new())], 4).unwrap();
+        let overflow = UpdateRect { x: 0, y: 0, width: 8, height: 8, content: UpdateContent::CopyRect { src_x: u32::MAX - 4, src_y: 0 } };
+        assert!(matches!(decoder.apply_update(8, 8, 0, false, vec![overflow], 5), Err(DecodeError::InvalidFormat(_))));
+    }
+    
+    #[test]
+    fn test_oversized_keyframe_is_rejected() {
+        let mut decoder = ImageDecoder::new();
+        let result = decoder.apply_update(MAX_FRAME_DIMENSION + 1, u32::MAX, 0, true, Vec::new(), 1);
+        assert!(matches!(result, Err(DecodeError::InvalidFormat(_))));
+        assert!(decoder.apply_update(MAX_FRAME_DIMENSION, 1, 0, true, Vec::new(), 2).is_ok());
+    }
+    
+    #[test]
+    fn test_qoi_rects_are_decoded() {
+        let white = Rgba([255, 255, 255, 255]);
+        let gray = Rgba([40, 40, 40, 255]);
+        let mut decoder = ImageDecoder::new();
+        decoder.apply_update(8, 8, 0, true, vec![png_rect(0, 0, 8, 8, white, Vec::new())], 1).unwrap();
+        
+        let text = RgbaImage::from_fn(4, 2, |x, _| if x % 2 == 0 { gray } else { white });
+        let data = qoi::encode(text.as_raw(), 4, 2).unwrap();
+        let rect = UpdateRect { x: 2, y: 3, width: 4, height: 2, content: UpdateContent::Encoded { format: ImageFormat::QOI, data, tiles: Vec::new() } };
+        let decoded = decoder.apply_update(8, 8, 0, false, vec![rect], 2).unwrap();
+        
+        assert_eq!(decoded.image.get_pixel(2, 3), gray);
+        assert_eq!(decoded.image.get_pixel(3, 4), white);
+        assert_eq!(decoded.image.get_pixel(4, 4), gray);
+        assert_eq!(decoded.image.get_pixel(1, 3), white);
+    }
+}
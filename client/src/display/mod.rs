@@ -5,12 +5,10 @@
 
 mod decoder;
 mod renderer;
-mod command;
 
 
-pub use decoder::{ImageDecoder, DecodedImage, DecodeError};
+pub use decoder::{ImageDecoder, DecodedImage, DecodeError, UpdateRect, UpdateContent};
 pub use renderer::{DisplayRenderer, RenderError};
-pub use command::Command;
 
 /// 画像フォーマット
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -23,6 +21,10 @@ pub enum ImageFormat {
     WebP,
     /// AVIF形式
     AVIF,
+    /// H.264形式（動画）
+    H264,
+    /// QOI形式
+    QOI,
 }
 
 /// 画像データ
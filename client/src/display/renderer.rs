@@ -3,9 +3,8 @@
 //! デコードされた画像をGUIに表示するための機能を提供します。
 
 use super::decoder::DecodedImage;
-use egui::{Context, TextureHandle, TextureId, Color32, pos2, vec2, Rect, Pos2, Vec2};
+use egui::{Context, TextureHandle, TextureId, pos2, vec2, Rect, Pos2, Vec2};
 use thiserror::Error;
-use std::sync::{Arc, Mutex};
 
 /// レンダリングエラー
 #[derive(Error, Debug)]
@@ -51,20 +50,17 @@ impl DisplayRenderer {
     
     /// 画像を更新
     pub fn update_image(&mut self, data: Vec<u8>, width: u32, height: u32) -> Result<(), RenderError> {
-        // RGBA形式の画像データに変換
-        let size = [width as usize, height as usize];
-        
         // 画像データをeguiのテクスチャデータに変換
         let color_image = self.convert_to_egui_image(&data, width, height)?;
         
         // テクスチャを更新または作成
         if let Some(texture) = &mut self.texture {
-            texture.set(color_image);
+            texture.set(color_image, egui::TextureOptions::LINEAR);
         } else {
             self.texture = Some(self.ctx.load_texture(
                 "remote_display",
                 color_image,
-                egui::TextureFilter::Linear
+                egui::TextureOptions::LINEAR
             ));
         }
         
@@ -95,14 +91,9 @@ impl DisplayRenderer {
             ));
         }
         
-        let pixels: Vec<Color32> = data
-            .chunks_exact(4)
-            .map(|chunk| Color32::from_rgba_unmultiplied(chunk[0], chunk[1], chunk[2], chunk[3]))
-            .collect();
-        
         Ok(egui::ColorImage::from_rgba_unmultiplied(
             [width as usize, height as usize],
-            &pixels
+            data
         ))
     }
     
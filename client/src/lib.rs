@@ -0,0 +1,6 @@
+//! リモートデスクトップクライアントライブラリ
+//!
+//! このクレートはリモートデスクトップクライアントの機能を提供します。
+
+// 画面更新のデコードと表示
+pub mod display;
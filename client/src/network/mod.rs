@@ -11,10 +11,17 @@ mod protocol;
 pub use tcp_client::TcpClient;
 pub use websocket_client::WebSocketClient;
 pub use webrtc_client::WebRtcClient;
-pub use protocol::{Command, Response, ConnectionInfo, ConnectionState};
+pub use protocol::{Command, Response, Envelope, FrameRect, RectContent, ConnectionInfo, ConnectionState, ClientInfo, AuthMethod, WireFormat, Capabilities, Feature};
 
+use remote_desktop_rs_common::capabilities::PROTOCOL_VERSION;
+use remote_desktop_rs_common::compression::CompressionAlgorithm;
+use remote_desktop_rs_common::encryption::{compute_challenge_response, EncryptionAlgorithm};
+use remote_desktop_rs_common::protocol::ImageFormat;
+use remote_desktop_rs_common::signing::{default_key_path, ClientKeypair};
 use thiserror::Error;
+use std::collections::VecDeque;
 use std::io;
+use std::time::{Duration, Instant};
 
 /// ネットワークエラー
 #[derive(Error, Debug)]
@@ -39,6 +46,10 @@ pub enum NetworkError {
     #[error("認証エラー: {0}")]
     AuthenticationError(String),
     
+    /// ワンタイムパスワードが必要
+    #[error("ワンタイムパスワードが必要です: {0}")]
+    OtpRequired(String),
+    
     /// その他のエラー
     #[error("ネットワークエラー: {0}")]
     Other(String),
@@ -57,15 +68,294 @@ pub trait NetworkClient {
     /// サーバーに接続されているかどうかを確認
     fn is_connected(&self) -> bool;
     
-    /// コマンドを送信
-    fn send(&mut self, command: Command) -> Result<(), NetworkError>;
+    /// 封筒に入れたコマンドを送信
+    fn send_envelope(&mut self, envelope: Envelope<Command>) -> Result<(), NetworkError>;
+    
+    /// 封筒に入ったレスポンスを受信（`timeout`が`None`の場合はトランスポートの既定値まで待つ）
+    fn receive_envelope(&mut self, timeout: Option<Duration>) -> Result<Envelope<Response>, NetworkError>;
     
-    /// レスポンスを受信
-    fn receive(&mut self) -> Result<Response, NetworkError>;
+    /// 要求IDと応答待ちの間に届いたメッセージの管理
+    fn requests(&mut self) -> &mut RequestTracker;
     
     /// 接続状態を取得
     fn state(&self) -> ConnectionState;
     
     /// レイテンシを取得 (ミリ秒)
     fn latency(&self) -> Option<u64>;
-}
\ No newline at end of file
+    
+    /// サーバーとネゴシエーションした機能（`Hello`に対応しない旧サーバーでは`None`）
+    fn capabilities(&self) -> Option<&Capabilities>;
+    
+    /// 機能を使用できるかどうか（旧サーバーでは常に`true`）
+    fn supports(&self, feature: Feature) -> bool {
+        self.capabilities().map_or(true, |capabilities| capabilities.supports(feature))
+    }
+    
+    /// コマンドを送信（応答は待たない）
+    fn send(&mut self, command: Command) -> Result<(), NetworkError> {
+        let id = self.requests().next_id();
+        self.send_envelope(Envelope::new(id, command))
+    }
+    
+    /// レスポンスを受信（`request`の待機中に届いた他のメッセージから順に返す）
+    fn receive(&mut self) -> Result<Response, NetworkError> {
+        if let Some(envelope) = self.requests().take_deferred() {
+            return Ok(envelope.message);
+        }
+        Ok(self.receive_envelope(None)?.message)
+    }
+    
+    /// レスポンスをタイムアウト付きで受信
+    fn receive_timeout(&mut self, timeout: Duration) -> Result<Response, NetworkError> {
+        if let Some(envelope) = self.requests().take_deferred() {
+            return Ok(envelope.message);
+        }
+        Ok(self.receive_envelope(Some(timeout))?.message)
+    }
+    
+    /// コマンドを送信し、対応するレスポンスをタイムアウト付きで待つ
+    ///
+    /// 待機中に届いた他のメッセージは保持され、後の`receive`で受け取れます。
+    /// 相関IDを返さない旧サーバーでは、最初に届いたレスポンスを応答とみなします。
+    fn request(&mut self, command: Command, timeout: Duration) -> Result<Response, NetworkError> {
+        let id = self.requests().next_id();
+        self.send_envelope(Envelope::new(id, command))?;
+        
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(NetworkError::TimeoutError(format!("No response to request {}", id)));
+            }
+            
+            let envelope = self.receive_envelope(Some(remaining))?;
+            if envelope.reply_to == Some(id) || envelope.is_legacy() {
+                return Ok(envelope.message);
+            }
+            self.requests().defer(envelope);
+        }
+    }
+}
+
+/// 要求IDの採番と、応答待ちの間に届いたメッセージの保持
+#[derive(Debug, Default)]
+pub struct RequestTracker {
+    /// 最後に採番したID
+    last_id: u64,
+    /// 応答待ちの間に届いた他のメッセージ
+    deferred: VecDeque<Envelope<Response>>,
+}
+
+impl RequestTracker {
+    /// 次の要求IDを採番
+    pub fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+    
+    /// 後で受け取るメッセージとして保持
+    pub fn defer(&mut self, envelope: Envelope<Response>) {
+        self.deferred.push_back(envelope);
+    }
+    
+    /// 保持しているメッセージを古い順に取り出す
+    pub fn take_deferred(&mut self) -> Option<Envelope<Response>> {
+        self.deferred.pop_front()
+    }
+    
+    /// 保持しているメッセージを破棄（切断時）
+    pub fn clear(&mut self) {
+        self.deferred.clear();
+    }
+}
+
+/// このクライアントの情報を作成
+pub fn client_info() -> ClientInfo {
+    ClientInfo {
+        app_name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os_type: std::env::consts::OS.to_string(),
+        os_version: String::new(),
+        device_name: String::new(),
+        screen_width: 0,
+        screen_height: 0,
+        capabilities: Vec::new(),
+    }
+}
+
+/// このクライアントが対応する機能
+pub fn client_capabilities() -> Capabilities {
+    // QOIは色数の少ない矩形にだけ使うため、既定の形式にはしない
+    let mut codecs = vec![ImageFormat::JPEG, ImageFormat::PNG, ImageFormat::QOI];
+    if cfg!(feature = "webp-support") {
+        codecs.insert(0, ImageFormat::WebP);
+    }
+    // 動画は変更の多い間だけ使うため、優先順位は最後
+    if cfg!(feature = "video-support") {
+        codecs.push(ImageFormat::H264);
+    }
+    
+    let mut features = vec![Feature::RunApplication, Feature::Streaming, Feature::TileCache];
+    if cfg!(feature = "clipboard") {
+        features.push(Feature::Clipboard);
+    }
+    
+    Capabilities {
+        codecs,
+        compression: vec![
+            CompressionAlgorithm::LZ4,
+            CompressionAlgorithm::Zlib,
+            CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Deflate,
+            CompressionAlgorithm::None,
+        ],
+        encryption: vec![EncryptionAlgorithm::Aes256Gcm],
+        features,
+    }
+}
+
+/// 接続時に認証を行うかどうか
+pub(crate) fn wants_authentication(info: &ConnectionInfo) -> bool {
+    info.username.is_some() && (info.use_public_key || info.password.is_some())
+}
+
+/// このクライアントの鍵ペアを読み込む（存在しなければ生成して設定ディレクトリに保存）
+pub fn load_client_keypair() -> Result<ClientKeypair, NetworkError> {
+    ClientKeypair::load_or_generate(&default_key_path())
+        .map_err(|e| NetworkError::AuthenticationError(format!("鍵ペアを読み込めません: {}", e)))
+}
+
+/// ワイヤーフォーマットをネゴシエーション
+///
+/// 接続直後にJSONで対応形式を提示し、サーバーが選択した形式を返します。
+/// サーバーがエラーを返した場合や、未知のコマンドとして応答しないままタイムアウトした
+/// 場合はJSONのまま通信を続けます。その他の通信エラーはそのまま返します。
+pub(crate) fn negotiate_wire_format<C: NetworkClient + ?Sized>(
+    client: &mut C,
+    info: &ConnectionInfo,
+) -> Result<WireFormat, NetworkError> {
+    if info.wire_formats.iter().all(|format| *format == WireFormat::Json) {
+        return Ok(WireFormat::Json);
+    }
+    
+    let command = Command::NegotiateWireFormat { formats: info.wire_formats.clone() };
+    
+    match client.request(command, info.timeout()) {
+        Ok(Response::WireFormatSelected { format }) => Ok(format),
+        Ok(Response::Error { .. }) => Ok(WireFormat::Json),
+        Ok(_) => Err(NetworkError::ProtocolError("Unexpected wire format response".to_string())),
+        // 未知のコマンドを無視する旧サーバー
+        Err(NetworkError::TimeoutError(_)) => Ok(WireFormat::Json),
+        Err(e) => Err(e),
+    }
+}
+
+/// プロトコルバージョンと機能をネゴシエーション
+///
+/// サーバーが選択した共通の機能を返します。`Hello`に対応しない旧サーバーでは`None`を返し、
+/// サーバーがこのクライアントのバージョンを受け付けない場合はエラーになります。
+pub(crate) fn hello<C: NetworkClient + ?Sized>(
+    client: &mut C,
+    info: &ConnectionInfo,
+) -> Result<Option<Capabilities>, NetworkError> {
+    let command = Command::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: client_capabilities(),
+    };
+    
+    match client.request(command, info.timeout())? {
+        Response::Welcome { capabilities, .. } => Ok(Some(capabilities)),
+        Response::Error { code: 426, message } => Err(NetworkError::ProtocolError(message)),
+        Response::Error { .. } => Ok(None),
+        _ => Err(NetworkError::ProtocolError("Unexpected hello response".to_string())),
+    }
+}
+
+/// フレームのプッシュ配信を開始
+///
+/// 以降はサーバーから`Response::FrameUpdate`が届くので、受信するたびに
+/// `Command::StreamCredit`でクレジットを返します。サーバーが配信に対応していない
+/// 場合は`false`を返すので、従来どおり`Command::RequestScreenshot`で要求してください。
+pub fn start_stream<C: NetworkClient + ?Sized>(
+    client: &mut C,
+    fps: Option<u8>,
+    credits: u32,
+    timeout: Duration,
+) -> Result<bool, NetworkError> {
+    // 旧サーバーは機能一覧を返さないため、明示的に対応している場合のみ使用する
+    if !client.capabilities().map_or(false, |capabilities| capabilities.supports(Feature::Streaming)) {
+        return Ok(false);
+    }
+    
+    match client.request(Command::StartStream { fps, credits }, timeout)? {
+        Response::StreamStarted { .. } => Ok(true),
+        Response::Error { .. } => Ok(false),
+        _ => Err(NetworkError::ProtocolError("Unexpected stream response".to_string())),
+    }
+}
+
+/// チャレンジレスポンス方式で認証
+///
+/// パスワードは送信せず、サーバーが発行したノンスから計算した証明のみを返します。
+/// 公開鍵認証ではノンスにEd25519で署名して返します。
+pub(crate) fn authenticate<C: NetworkClient + ?Sized>(
+    client: &mut C,
+    info: &ConnectionInfo,
+) -> Result<(), NetworkError> {
+    let username = info.username.as_deref().unwrap_or_default();
+    let otp_code = info.otp_code.as_deref();
+    
+    let keypair = if info.use_public_key {
+        Some(load_client_keypair()?)
+    } else {
+        None
+    };
+    let method = match &keypair {
+        Some(keypair) => AuthMethod::PublicKey { public_key: keypair.public_key() },
+        None => AuthMethod::Password,
+    };
+    
+    let command = Command::Authenticate {
+        username: username.to_string(),
+        client_info: client_info(),
+        method,
+    };
+    
+    let response = match client.request(command, info.timeout())? {
+        Response::AuthChallenge { nonce, salt, iterations } => match &keypair {
+            Some(keypair) => keypair.sign_challenge(username, &nonce),
+            None => {
+                let password = info.password.as_deref().unwrap_or_default();
+                compute_challenge_response(password, &salt, iterations, &nonce)
+                    .map_err(|e| NetworkError::AuthenticationError(e.to_string()))?
+            },
+        },
+        Response::AuthResult { success: false, message } => {
+            return Err(NetworkError::AuthenticationError(message));
+        },
+        Response::Error { code, message } => {
+            // ログイン試行の制限（429/423）など
+            return Err(NetworkError::AuthenticationError(format!("{} ({})", message, code)));
+        },
+        _ => {
+            return Err(NetworkError::ProtocolError("Unexpected authentication response".to_string()));
+        }
+    };
+    
+    let mut result = client.request(Command::AuthChallengeResponse { response }, info.timeout())?;
+    
+    // 第二要素が必要な場合はワンタイムパスワードを送信
+    if let Response::OtpRequired { message } = result {
+        let code = match otp_code {
+            Some(code) if !code.trim().is_empty() => code,
+            _ => return Err(NetworkError::OtpRequired(message)),
+        };
+        result = client.request(Command::SubmitOtp { code: code.trim().to_string() }, info.timeout())?;
+    }
+    
+    match result {
+        Response::AuthResult { success: true, .. } => Ok(()),
+        Response::AuthResult { success: false, message } => Err(NetworkError::AuthenticationError(message)),
+        _ => Err(NetworkError::ProtocolError("Unexpected authentication response".to_string())),
+    }
+}
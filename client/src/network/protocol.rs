@@ -4,18 +4,50 @@
 
 use crate::input::MouseButton;
 use egui::Key;
+pub use remote_desktop_rs_common::protocol::{AuthMethod, ClientInfo, Envelope};
+pub use remote_desktop_rs_common::capabilities::{Capabilities, Feature};
+pub use remote_desktop_rs_common::wire::{WireFormat, SUPPORTED_FORMATS};
 use serde::{Serialize, Deserialize};
 use std::time::Duration;
 
 /// サーバーに送信するコマンド
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
-    /// 認証
+    /// 認証開始（サーバーは`Response::AuthChallenge`で応答する）
     Authenticate {
         /// ユーザー名
         username: String,
-        /// パスワード
-        password: String,
+        /// クライアント情報
+        client_info: ClientInfo,
+        /// 認証方式（省略時はパスワード）
+        #[serde(default)]
+        method: AuthMethod,
+    },
+    
+    /// 認証チャレンジへの応答
+    AuthChallengeResponse {
+        /// チャレンジのノンスに対するHMAC-SHA256（Base64）
+        response: String,
+    },
+    
+    /// ワンタイムパスワードの送信（`Response::OtpRequired`への応答）
+    SubmitOtp {
+        /// 認証アプリに表示されたコード
+        code: String,
+    },
+    
+    /// ワイヤーフォーマットのネゴシエーション（接続直後にJSONで送信）
+    NegotiateWireFormat {
+        /// 対応する形式（優先順）
+        formats: Vec<WireFormat>,
+    },
+    
+    /// プロトコルバージョンと対応機能の通知（サーバーは`Response::Welcome`で応答する）
+    Hello {
+        /// プロトコルバージョン
+        protocol_version: u32,
+        /// 対応する機能
+        capabilities: Capabilities,
     },
     
     /// スクリーンショット要求
@@ -96,6 +128,26 @@ pub enum Command {
         quality: u8,
     },
     
+    /// フレームのプッシュ配信を開始（サーバーは`Response::StreamStarted`で応答し、以降は`Response::FrameUpdate`を送り続ける）
+    StartStream {
+        /// フレームレート（省略時はサーバー側の設定値）
+        fps: Option<u8>,
+        /// 確認なしで送ってよいフレーム数
+        credits: u32,
+    },
+    
+    /// 配信フレームのクレジットを追加（受信したフレームの数だけ返す）
+    StreamCredit {
+        /// 追加するクレジット
+        credits: u32,
+    },
+    
+    /// フレームのプッシュ配信を停止
+    StopStream,
+    
+    /// 次のフレームをキーフレーム（画面全体）として送るよう要求
+    RequestKeyframe,
+    
     /// アプリケーション実行
     RunApplication {
         /// コマンド
@@ -115,6 +167,52 @@ pub enum Command {
 /// サーバーからのレスポンス
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
+    /// 認証チャレンジ
+    AuthChallenge {
+        /// ランダムなノンス（Base64）
+        nonce: String,
+        /// パスワードハッシュのソルト（Base64、公開鍵認証では空）
+        salt: String,
+        /// PBKDF2の反復回数（公開鍵認証では0）
+        iterations: u32,
+    },
+    
+    /// ワンタイムパスワードの要求（パスワード認証の成功後）
+    OtpRequired {
+        /// メッセージ
+        message: String,
+    },
+    
+    /// 選択されたワイヤーフォーマット（以降のメッセージはこの形式で送受信する）
+    WireFormatSelected {
+        /// 形式
+        format: WireFormat,
+    },
+    
+    /// `Command::Hello`への応答
+    Welcome {
+        /// 使用するプロトコルバージョン
+        protocol_version: u32,
+        /// サーバーのバージョン
+        server_version: String,
+        /// 双方が対応する機能（以降はこの範囲でのみ通信する）
+        capabilities: Capabilities,
+    },
+    
+    /// フレーム配信の開始
+    StreamStarted {
+        /// 配信するフレームレート
+        fps: u8,
+        /// 受け付けたクレジット（上限を超えた分は切り捨てられる）
+        credits: u32,
+    },
+    
+    /// フレーム配信の停止
+    StreamStopped {
+        /// 理由
+        reason: String,
+    },
+    
     /// 認証結果
     AuthResult {
         /// 成功したかどうか
@@ -126,6 +224,7 @@ pub enum Response {
     /// スクリーンショットデータ
     ScreenshotData {
         /// 画像データ
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
         /// 画像フォーマット
         format: ImageFormat,
@@ -137,12 +236,36 @@ pub enum Response {
         timestamp: u64,
     },
     
+    /// 差分フレーム（配信中に送信）
+    ///
+    /// キーフレームでは画面全体を1つの矩形で送ります。それ以外の矩形は
+    /// 直前のフレームに上書きして合成します。
+    FrameUpdate {
+        /// フレーム番号
+        sequence: u64,
+        /// キーフレームかどうか
+        keyframe: bool,
+        /// 画面全体の幅
+        width: u32,
+        /// 画面全体の高さ
+        height: u32,
+        /// タイルキャッシュのタイルの一辺（0ならキャッシュを使用しない）
+        tile_size: u32,
+        /// 変更された矩形（この順に適用し、タイルキャッシュも同じ順に更新する）
+        rects: Vec<FrameRect>,
+        /// タイムスタンプ（ミリ秒）
+        timestamp: u64,
+    },
+    
     /// コマンド実行結果
     CommandResult {
         /// 成功したかどうか
         success: bool,
         /// メッセージ
         message: String,
+        /// コマンド固有のデータ
+        #[serde(default)]
+        data: Option<serde_json::Value>,
     },
     
     /// Pong
@@ -162,6 +285,51 @@ pub enum Response {
     },
 }
 
+/// 差分フレームの矩形
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameRect {
+    /// X座標
+    pub x: u32,
+    /// Y座標
+    pub y: u32,
+    /// 幅
+    pub width: u32,
+    /// 高さ
+    pub height: u32,
+    /// 矩形の内容
+    pub content: RectContent,
+}
+
+/// 差分フレームの矩形の内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RectContent {
+    /// エンコードされた画像
+    Encoded {
+        /// 画像形式
+        format: ImageFormat,
+        /// 画像データ
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        /// タイルキャッシュに保存するタイルのハッシュ（左から順、空なら保存しない）
+        #[serde(default)]
+        tiles: Vec<u64>,
+    },
+    /// クライアントのタイルキャッシュにあるタイル
+    TileRef {
+        /// タイルのハッシュ
+        hash: u64,
+    },
+    /// 直前のフレーム内の領域のコピー（スクロール・ウィンドウの移動）
+    ///
+    /// コピー先は矩形の位置で、サイズは矩形と同じです。
+    CopyRect {
+        /// コピー元のX座標
+        src_x: u32,
+        /// コピー元のY座標
+        src_y: u32,
+    },
+}
+
 /// 画像フォーマット
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImageFormat {
@@ -173,6 +341,10 @@ pub enum ImageFormat {
     WebP,
     /// AVIF
     AVIF,
+    /// H.264（動画。前のフレームを参照するため`Response::FrameUpdate`でのみ使用）
+    H264,
+    /// QOI（色数の少ない画面向けの高速な可逆圧縮）
+    QOI,
 }
 
 /// 接続情報
@@ -186,10 +358,23 @@ pub struct ConnectionInfo {
     pub username: Option<String>,
     /// パスワード（オプション）
     pub password: Option<String>,
+    /// ワンタイムパスワード（サーバーが要求した場合のみ）
+    pub otp_code: Option<String>,
+    /// パスワードの代わりに公開鍵で認証するかどうか
+    pub use_public_key: bool,
     /// 接続タイムアウト（ミリ秒）
     pub timeout_ms: u64,
     /// TLS を使用するかどうか
     pub use_tls: bool,
+    /// 接続時に提示するワイヤーフォーマット（優先順、JSONのみなら交渉しない）
+    pub wire_formats: Vec<WireFormat>,
+}
+
+impl ConnectionInfo {
+    /// 要求の応答を待つ時間
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
 }
 
 impl Default for ConnectionInfo {
@@ -199,8 +384,11 @@ impl Default for ConnectionInfo {
             port: 9999,
             username: None,
             password: None,
+            otp_code: None,
+            use_public_key: false,
             timeout_ms: 5000,
             use_tls: false,
+            wire_formats: SUPPORTED_FORMATS.to_vec(),
         }
     }
 }
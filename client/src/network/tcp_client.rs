@@ -2,11 +2,11 @@
 //!
 //! TCP ソケットを使用してリモートサーバーと通信する機能を提供します。
 
-use super::{NetworkClient, NetworkError, Command, Response, ConnectionInfo, ConnectionState};
+use super::{authenticate, hello, negotiate_wire_format, wants_authentication, NetworkClient, NetworkError, Command, Response, Envelope, ConnectionInfo, ConnectionState, WireFormat, Capabilities, RequestTracker};
+use remote_desktop_rs_common::wire;
 use std::io::{Read, Write};
 use std::net::{TcpStream, SocketAddr};
 use std::time::{Duration, Instant};
-use serde_json;
 use std::sync::{Arc, Mutex};
 
 /// TCP クライアント
@@ -21,6 +21,12 @@ pub struct TcpClient {
     latency: Option<u64>,
     /// 最後のレイテンシ測定時刻
     last_latency_check: Option<Instant>,
+    /// ワイヤーフォーマット
+    wire_format: WireFormat,
+    /// ネゴシエーションした機能
+    capabilities: Option<Capabilities>,
+    /// 要求IDと応答待ちの間に届いたメッセージ
+    requests: RequestTracker,
 }
 
 impl TcpClient {
@@ -32,6 +38,9 @@ impl TcpClient {
             connection_info: None,
             latency: None,
             last_latency_check: None,
+            wire_format: WireFormat::Json,
+            capabilities: None,
+            requests: RequestTracker::default(),
         }
     }
     
@@ -67,9 +76,9 @@ impl TcpClient {
             .as_millis() as u64;
         
         let start = Instant::now();
-        self.send(Command::Ping { timestamp })?;
+        let timeout = self.connection_info.as_ref().map(ConnectionInfo::timeout).unwrap_or(Duration::from_secs(5));
         
-        if let Response::Pong { original_timestamp, .. } = self.receive()? {
+        if let Response::Pong { original_timestamp, .. } = self.request(Command::Ping { timestamp }, timeout)? {
             if original_timestamp == timestamp {
                 let latency = start.elapsed().as_millis() as u64;
                 self.latency = Some(latency);
@@ -99,12 +108,20 @@ impl TcpClient {
     }
     
     /// メッセージの受信
-    fn receive_message(&mut self) -> Result<Vec<u8>, NetworkError> {
+    fn receive_message(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, NetworkError> {
         if let Some(stream) = &mut self.stream {
+            stream.set_read_timeout(timeout)?;
+            
             // メッセージ長のプレフィックスを読み取る
             let mut len_bytes = [0u8; 4];
-            stream.read_exact(&mut len_bytes)?;
-            let len = u32::from_be_bytes(len_bytes) as usize;
+            stream.read_exact(&mut len_bytes).map_err(|e| match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                    NetworkError::TimeoutError("Receive timeout".to_string())
+                },
+                _ => NetworkError::IoError(e),
+            })?;
+            let len = wire::frame_len(len_bytes)
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
             
             // メッセージ本体を読み取る
             let mut buffer = vec![0u8; len];
@@ -143,29 +160,35 @@ impl NetworkClient for TcpClient {
         self.connection_info = Some(info.clone());
         self.state = ConnectionState::Connected;
         
+        // ワイヤーフォーマットをネゴシエーション（拒否・タイムアウト時はJSONのまま）
+        self.wire_format = WireFormat::Json;
+        match negotiate_wire_format(self, info) {
+            Ok(format) => self.wire_format = format,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        }
+        
+        // プロトコルバージョンと機能をネゴシエーション
+        self.capabilities = match hello(self, info) {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        };
+        
         // 認証が必要な場合
-        if info.username.is_some() && info.password.is_some() {
+        if wants_authentication(info) {
             self.state = ConnectionState::Authenticating;
             
-            self.send(Command::Authenticate {
-                username: info.username.clone().unwrap(),
-                password: info.password.clone().unwrap(),
-            })?;
-            
-            match self.receive()? {
-                Response::AuthResult { success, message } => {
-                    if success {
-                        self.state = ConnectionState::Connected;
-                    } else {
-                        self.state = ConnectionState::Error;
-                        return Err(NetworkError::AuthenticationError(message));
-                    }
-                },
-                _ => {
-                    self.state = ConnectionState::Error;
-                    return Err(NetworkError::ProtocolError("Unexpected authentication response".to_string()));
-                }
+            if let Err(e) = authenticate(self, info) {
+                self.state = ConnectionState::Error;
+                return Err(e);
             }
+            
+            self.state = ConnectionState::Connected;
         }
         
         // 初期レイテンシチェック
@@ -183,6 +206,9 @@ impl NetworkClient for TcpClient {
         self.stream = None;
         self.state = ConnectionState::Disconnected;
         self.latency = None;
+        self.wire_format = WireFormat::Json;
+        self.capabilities = None;
+        self.requests.clear();
         
         Ok(())
     }
@@ -191,18 +217,18 @@ impl NetworkClient for TcpClient {
         self.stream.is_some() && self.state == ConnectionState::Connected
     }
     
-    fn send(&mut self, command: Command) -> Result<(), NetworkError> {
-        let data = serde_json::to_vec(&command)
-            .map_err(|e| NetworkError::ProtocolError(format!("Serialization error: {}", e)))?;
+    fn send_envelope(&mut self, envelope: Envelope<Command>) -> Result<(), NetworkError> {
+        let data = self.wire_format.encode(&envelope)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
         
         self.send_message(&data)
     }
     
-    fn receive(&mut self) -> Result<Response, NetworkError> {
-        let data = self.receive_message()?;
+    fn receive_envelope(&mut self, timeout: Option<Duration>) -> Result<Envelope<Response>, NetworkError> {
+        let data = self.receive_message(timeout)?;
         
-        serde_json::from_slice(&data)
-            .map_err(|e| NetworkError::ProtocolError(format!("Deserialization error: {}", e)))
+        wire::decode_envelope(&data)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))
     }
     
     fn state(&self) -> ConnectionState {
@@ -212,6 +238,14 @@ impl NetworkClient for TcpClient {
     fn latency(&self) -> Option<u64> {
         self.latency
     }
+    
+    fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+    
+    fn requests(&mut self) -> &mut RequestTracker {
+        &mut self.requests
+    }
 }
 
 impl Default for TcpClient {
@@ -3,9 +3,9 @@
 //! WebRTC を使用してリモートサーバーと通信する機能を提供します。
 //! これにより、Web版クライアントへの対応や、よりリアルタイム性の高い通信が可能になります。
 
-use super::{NetworkClient, NetworkError, Command, Response, ConnectionInfo, ConnectionState};
+use super::{authenticate, hello, negotiate_wire_format, wants_authentication, NetworkClient, NetworkError, Command, Response, Envelope, ConnectionInfo, ConnectionState, WireFormat, Capabilities, RequestTracker};
+use remote_desktop_rs_common::wire;
 use std::time::{Duration, Instant};
-use serde_json;
 use webrtc::api::API;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::RTCPeerConnection;
@@ -34,6 +34,12 @@ pub struct WebRtcClient {
     latency: Option<u64>,
     /// 最後のレイテンシ測定時刻
     last_latency_check: Option<Instant>,
+    /// ワイヤーフォーマット
+    wire_format: WireFormat,
+    /// ネゴシエーションした機能
+    capabilities: Option<Capabilities>,
+    /// 要求IDと応答待ちの間に届いたメッセージ
+    requests: RequestTracker,
     /// 接続済みフラグ
     connected: Arc<AtomicBool>,
 }
@@ -56,6 +62,9 @@ impl WebRtcClient {
             connection_info: None,
             latency: None,
             last_latency_check: None,
+            wire_format: WireFormat::Json,
+            capabilities: None,
+            requests: RequestTracker::default(),
             connected: Arc::new(AtomicBool::new(false)),
         })
     }
@@ -92,9 +101,9 @@ impl WebRtcClient {
             .as_millis() as u64;
         
         let start = Instant::now();
-        self.send(Command::Ping { timestamp })?;
+        let timeout = self.connection_info.as_ref().map(ConnectionInfo::timeout).unwrap_or(Duration::from_secs(5));
         
-        if let Response::Pong { original_timestamp, .. } = self.receive()? {
+        if let Response::Pong { original_timestamp, .. } = self.request(Command::Ping { timestamp }, timeout)? {
             if original_timestamp == timestamp {
                 let latency = start.elapsed().as_millis() as u64;
                 self.latency = Some(latency);
@@ -227,7 +236,36 @@ impl NetworkClient for WebRtcClient {
         
         self.state = ConnectionState::Connected;
         
-        // 認証が必要な場合は処理を追加
+        // ワイヤーフォーマットをネゴシエーション（拒否・タイムアウト時はJSONのまま）
+        self.wire_format = WireFormat::Json;
+        match negotiate_wire_format(self, info) {
+            Ok(format) => self.wire_format = format,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        }
+        
+        // プロトコルバージョンと機能をネゴシエーション
+        self.capabilities = match hello(self, info) {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        };
+        
+        // 認証が必要な場合
+        if wants_authentication(info) {
+            self.state = ConnectionState::Authenticating;
+            
+            if let Err(e) = authenticate(self, info) {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+            
+            self.state = ConnectionState::Connected;
+        }
         
         // 初期レイテンシチェック
         let _ = self.check_latency();
@@ -260,6 +298,9 @@ impl NetworkClient for WebRtcClient {
         self.message_rx = None;
         self.state = ConnectionState::Disconnected;
         self.latency = None;
+        self.wire_format = WireFormat::Json;
+        self.capabilities = None;
+        self.requests.clear();
         self.connected.store(false, Ordering::SeqCst);
         
         Ok(())
@@ -269,14 +310,20 @@ impl NetworkClient for WebRtcClient {
         self.connected.load(Ordering::SeqCst) && self.state == ConnectionState::Connected
     }
     
-    fn send(&mut self, command: Command) -> Result<(), NetworkError> {
+    fn send_envelope(&mut self, envelope: Envelope<Command>) -> Result<(), NetworkError> {
         if let Some(dc) = &self.data_channel {
-            let data = serde_json::to_string(&command)
-                .map_err(|e| NetworkError::ProtocolError(format!("Serialization error: {}", e)))?;
+            let data = self.wire_format.encode(&envelope)
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            let binary = self.wire_format.is_binary();
             
+            // バイナリ形式はバイナリメッセージ、JSONはテキストメッセージで送信
             self.runtime.block_on(async {
-                dc.send_text(&data).await
-                    .map_err(|e| NetworkError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))
+                let result = if binary {
+                    dc.send(&bytes::Bytes::from(data)).await
+                } else {
+                    dc.send_text(String::from_utf8_lossy(&data).into_owned()).await
+                };
+                result.map_err(|e| NetworkError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))
             })?;
             
             Ok(())
@@ -285,21 +332,21 @@ impl NetworkClient for WebRtcClient {
         }
     }
     
-    fn receive(&mut self) -> Result<Response, NetworkError> {
+    fn receive_envelope(&mut self, timeout: Option<Duration>) -> Result<Envelope<Response>, NetworkError> {
         if let Some(rx) = &mut self.message_rx {
             // タイムアウト付きでメッセージを受信
-            let timeout = Duration::from_secs(10);
+            let timeout = timeout.unwrap_or(Duration::from_secs(10));
             let data = self.runtime.block_on(async {
                 tokio::time::timeout(timeout, rx.recv()).await
                     .map_err(|_| NetworkError::TimeoutError("Receive timeout".to_string()))?
                     .ok_or_else(|| NetworkError::ConnectionError("Channel closed".to_string()))
             })?;
             
-            // JSONデータをデシリアライズ
-            let response: Response = serde_json::from_slice(&data)
-                .map_err(|e| NetworkError::ProtocolError(format!("Deserialization error: {}", e)))?;
+            // 形式は先頭バイトから判別する
+            let envelope: Envelope<Response> = wire::decode_envelope(&data)
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
             
-            Ok(response)
+            Ok(envelope)
         } else {
             Err(NetworkError::ConnectionError("Not connected".to_string()))
         }
@@ -312,4 +359,12 @@ impl NetworkClient for WebRtcClient {
     fn latency(&self) -> Option<u64> {
         self.latency
     }
+    
+    fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+    
+    fn requests(&mut self) -> &mut RequestTracker {
+        &mut self.requests
+    }
 }
\ No newline at end of file
@@ -2,9 +2,9 @@
 //!
 //! WebSocket を使用してリモートサーバーと通信する機能を提供します。
 
-use super::{NetworkClient, NetworkError, Command, Response, ConnectionInfo, ConnectionState};
+use super::{authenticate, hello, negotiate_wire_format, wants_authentication, NetworkClient, NetworkError, Command, Response, Envelope, ConnectionInfo, ConnectionState, WireFormat, Capabilities, RequestTracker};
+use remote_desktop_rs_common::wire;
 use std::time::{Duration, Instant};
-use serde_json;
 use tungstenite::{connect, Message, WebSocket};
 use tungstenite::stream::MaybeTlsStream;
 use url::Url;
@@ -22,6 +22,12 @@ pub struct WebSocketClient {
     latency: Option<u64>,
     /// 最後のレイテンシ測定時刻
     last_latency_check: Option<Instant>,
+    /// ワイヤーフォーマット
+    wire_format: WireFormat,
+    /// ネゴシエーションした機能
+    capabilities: Option<Capabilities>,
+    /// 要求IDと応答待ちの間に届いたメッセージ
+    requests: RequestTracker,
 }
 
 impl WebSocketClient {
@@ -33,6 +39,9 @@ impl WebSocketClient {
             connection_info: None,
             latency: None,
             last_latency_check: None,
+            wire_format: WireFormat::Json,
+            capabilities: None,
+            requests: RequestTracker::default(),
         }
     }
     
@@ -68,9 +77,9 @@ impl WebSocketClient {
             .as_millis() as u64;
         
         let start = Instant::now();
-        self.send(Command::Ping { timestamp })?;
+        let timeout = self.connection_info.as_ref().map(ConnectionInfo::timeout).unwrap_or(Duration::from_secs(5));
         
-        if let Response::Pong { original_timestamp, .. } = self.receive()? {
+        if let Response::Pong { original_timestamp, .. } = self.request(Command::Ping { timestamp }, timeout)? {
             if original_timestamp == timestamp {
                 let latency = start.elapsed().as_millis() as u64;
                 self.latency = Some(latency);
@@ -101,29 +110,35 @@ impl NetworkClient for WebSocketClient {
         self.connection_info = Some(info.clone());
         self.state = ConnectionState::Connected;
         
+        // ワイヤーフォーマットをネゴシエーション（拒否・タイムアウト時はJSONのまま）
+        self.wire_format = WireFormat::Json;
+        match negotiate_wire_format(self, info) {
+            Ok(format) => self.wire_format = format,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        }
+        
+        // プロトコルバージョンと機能をネゴシエーション
+        self.capabilities = match hello(self, info) {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                self.state = ConnectionState::Error;
+                return Err(e);
+            }
+        };
+        
         // 認証が必要な場合
-        if info.username.is_some() && info.password.is_some() {
+        if wants_authentication(info) {
             self.state = ConnectionState::Authenticating;
             
-            self.send(Command::Authenticate {
-                username: info.username.clone().unwrap(),
-                password: info.password.clone().unwrap(),
-            })?;
-            
-            match self.receive()? {
-                Response::AuthResult { success, message } => {
-                    if success {
-                        self.state = ConnectionState::Connected;
-                    } else {
-                        self.state = ConnectionState::Error;
-                        return Err(NetworkError::AuthenticationError(message));
-                    }
-                },
-                _ => {
-                    self.state = ConnectionState::Error;
-                    return Err(NetworkError::ProtocolError("Unexpected authentication response".to_string()));
-                }
+            if let Err(e) = authenticate(self, info) {
+                self.state = ConnectionState::Error;
+                return Err(e);
             }
+            
+            self.state = ConnectionState::Connected;
         }
         
         // 初期レイテンシチェック
@@ -146,6 +161,9 @@ impl NetworkClient for WebSocketClient {
         self.socket = None;
         self.state = ConnectionState::Disconnected;
         self.latency = None;
+        self.wire_format = WireFormat::Json;
+        self.capabilities = None;
+        self.requests.clear();
         
         Ok(())
     }
@@ -154,12 +172,19 @@ impl NetworkClient for WebSocketClient {
         self.socket.is_some() && self.state == ConnectionState::Connected
     }
     
-    fn send(&mut self, command: Command) -> Result<(), NetworkError> {
+    fn send_envelope(&mut self, envelope: Envelope<Command>) -> Result<(), NetworkError> {
         if let Some(socket) = &mut self.socket {
-            let data = serde_json::to_string(&command)
-                .map_err(|e| NetworkError::ProtocolError(format!("Serialization error: {}", e)))?;
+            let data = self.wire_format.encode(&envelope)
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            
+            // バイナリ形式はBinaryフレーム、JSONはTextフレームで送信
+            let message = if self.wire_format.is_binary() {
+                Message::Binary(data)
+            } else {
+                Message::Text(String::from_utf8_lossy(&data).into_owned())
+            };
             
-            socket.write_message(Message::Text(data))
+            socket.write_message(message)
                 .map_err(|e| NetworkError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))?;
             
             Ok(())
@@ -168,19 +193,30 @@ impl NetworkClient for WebSocketClient {
         }
     }
     
-    fn receive(&mut self) -> Result<Response, NetworkError> {
+    fn receive_envelope(&mut self, timeout: Option<Duration>) -> Result<Envelope<Response>, NetworkError> {
         if let Some(socket) = &mut self.socket {
-            let message = socket.read_message()
-                .map_err(|e| NetworkError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))?;
+            // 下位のTCPストリームに読み取りタイムアウトを設定
+            match socket.get_mut() {
+                MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout)?,
+                MaybeTlsStream::NativeTls(stream) => stream.get_ref().set_read_timeout(timeout)?,
+                _ => {},
+            }
+            
+            let message = socket.read_message().map_err(|e| match e {
+                tungstenite::Error::Io(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                    NetworkError::TimeoutError("Receive timeout".to_string())
+                },
+                e => NetworkError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
+            })?;
             
             match message {
                 Message::Text(text) => {
-                    serde_json::from_str(&text)
+                    wire::decode_envelope(text.as_bytes())
                         .map_err(|e| NetworkError::ProtocolError(format!("Deserialization error: {}", e)))
                 },
                 Message::Binary(data) => {
-                    serde_json::from_slice(&data)
-                        .map_err(|e| NetworkError::ProtocolError(format!("Deserialization error: {}", e)))
+                    wire::decode_envelope(&data)
+                        .map_err(|e| NetworkError::ProtocolError(e.to_string()))
                 },
                 Message::Close(_) => {
                     self.state = ConnectionState::Disconnected;
@@ -201,6 +237,14 @@ impl NetworkClient for WebSocketClient {
     fn latency(&self) -> Option<u64> {
         self.latency
     }
+    
+    fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+    
+    fn requests(&mut self) -> &mut RequestTracker {
+        &mut self.requests
+    }
 }
 
 impl Default for WebSocketClient {
@@ -2,6 +2,8 @@
 //!
 //! このモジュールはリモートデスクトップクライアントのユーザーインターフェースを担当します。
 
+use remote_desktop_rs_common::protocol::StreamQuality;
+
 mod window;
 mod controls;
 mod settings;
@@ -51,6 +53,8 @@ pub struct PerformanceInfo {
     pub frame_time: f32,
     /// 転送データサイズ (バイト/秒)
     pub data_rate: u64,
+    /// サーバーが帯域に合わせて調整した配信品質
+    pub stream_quality: Option<StreamQuality>,
 }
 
 impl Default for PerformanceInfo {
@@ -61,6 +65,7 @@ impl Default for PerformanceInfo {
             quality: 50,
             frame_time: 0.0,
             data_rate: 0,
+            stream_quality: None,
         }
     }
 }
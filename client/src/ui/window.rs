@@ -3,9 +3,10 @@
 //! アプリケーションのメインウィンドウを実装します。
 
 use super::{ControlPanel, SettingsPanel, AppSettings, AppState, DisplayMode, PerformanceInfo, Styles};
-use crate::display::{DisplayRenderer, ImageData, ImageFormat};
+use crate::display::{DecodedImage, DisplayRenderer, ImageData, ImageDecoder, ImageFormat, UpdateContent, UpdateRect};
 use crate::input::{InputEventHandler, InputEvent, MouseButton};
-use crate::network::{NetworkClient, ConnectionInfo, TcpClient, WebSocketClient, WebRtcClient, Command, Response};
+use crate::network::{load_client_keypair, start_stream, NetworkClient, NetworkError, ConnectionInfo, TcpClient, WebSocketClient, WebRtcClient, Command, Response, FrameRect, RectContent};
+use remote_desktop_rs_common::protocol::StreamQuality;
 
 use eframe::{egui, epi};
 use egui::{vec2, Rect, Ui, Key, Pos2, Context, ColorImage};
@@ -13,6 +14,9 @@ use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 
+/// フレーム配信で受信側が先行して受け付けるフレーム数
+const STREAM_CREDITS: u32 = 4;
+
 /// メインウィンドウ
 pub struct MainWindow {
     /// UI状態
@@ -49,6 +53,10 @@ pub struct MainWindow {
     show_connection_dialog: bool,
     /// 接続情報
     connection_info: ConnectionInfo,
+    /// サーバーがワンタイムパスワードを要求しているかどうか
+    otp_required: bool,
+    /// このクライアントの公開鍵（サーバーへの登録用に表示）
+    client_public_key: Option<String>,
     /// エラーメッセージ
     error_message: Option<String>,
     /// ネットワークスレッド
@@ -61,6 +69,8 @@ pub struct MainWindow {
 struct ThreadCommunication {
     /// 最新の画像データ
     image_data: RwLock<Option<ImageData>>,
+    /// 差分フレームを合成した最新の画像（配信中のみ）
+    frame: RwLock<Option<DecodedImage>>,
     /// 実行中かどうか
     running: std::sync::atomic::AtomicBool,
     /// レイテンシー
@@ -71,6 +81,65 @@ struct ThreadCommunication {
     response_queue: Mutex<Vec<Response>>,
 }
 
+impl ThreadCommunication {
+    /// 受信したフレームを表示用に保存
+    fn store_frame(&self, data: Vec<u8>, format: crate::network::protocol::ImageFormat, width: u32, height: u32, timestamp: u64) {
+        let image_data = ImageData {
+            data,
+            format: display_format(format),
+            width,
+            height,
+            timestamp,
+        };
+        
+        *self.image_data.write().unwrap() = Some(image_data);
+    }
+    
+    /// 差分フレームを合成して表示用に保存
+    ///
+    /// 合成できない場合（合成元の画像やタイルがない場合）はキーフレームが必要なので`false`を返します。
+    fn store_update(&self, decoder: &mut ImageDecoder, width: u32, height: u32, tile_size: u32, keyframe: bool, rects: Vec<FrameRect>, timestamp: u64) -> bool {
+        let rects = rects
+            .into_iter()
+            .map(|rect| UpdateRect {
+                x: rect.x,
+                y: rect.y,
+                width: rect.width,
+                height: rect.height,
+                content: match rect.content {
+                    RectContent::Encoded { format, data, tiles } => UpdateContent::Encoded {
+                        format: display_format(format),
+                        data,
+                        tiles,
+                    },
+                    RectContent::TileRef { hash } => UpdateContent::TileRef(hash),
+                    RectContent::CopyRect { src_x, src_y } => UpdateContent::CopyRect { src_x, src_y },
+                },
+            })
+            .collect();
+        
+        match decoder.apply_update(width, height, tile_size, keyframe, rects, timestamp) {
+            Ok(decoded) => {
+                *self.frame.write().unwrap() = Some(decoded);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+/// プロトコルの画像フォーマットを表示用に変換
+fn display_format(format: crate::network::protocol::ImageFormat) -> ImageFormat {
+    match format {
+        crate::network::protocol::ImageFormat::JPEG => ImageFormat::JPEG,
+        crate::network::protocol::ImageFormat::PNG => ImageFormat::PNG,
+        crate::network::protocol::ImageFormat::WebP => ImageFormat::WebP,
+        crate::network::protocol::ImageFormat::AVIF => ImageFormat::AVIF,
+        crate::network::protocol::ImageFormat::H264 => ImageFormat::H264,
+        crate::network::protocol::ImageFormat::QOI => ImageFormat::QOI,
+    }
+}
+
 impl MainWindow {
     /// 新しいメインウィンドウを作成
     pub fn new(cc: &eframe::CreationContext) -> Self {
@@ -103,6 +172,8 @@ impl MainWindow {
             show_controls: true,
             show_connection_dialog: false,
             connection_info: ConnectionInfo::default(),
+            otp_required: false,
+            client_public_key: None,
             error_message: None,
             network_thread: None,
             thread_comm: None,
@@ -143,14 +214,41 @@ impl MainWindow {
                             ui.text_edit_singleline(username);
                         });
                         
-                        ui.horizontal(|ui| {
-                            ui.label("パスワード:");
-                            let password = self.connection_info.password.get_or_insert_with(String::new);
-                            let mut password_display = "*".repeat(password.len());
-                            if ui.text_edit_singleline(&mut password_display).changed() {
-                                *password = password_display;
+                        ui.checkbox(&mut self.connection_info.use_public_key, "公開鍵で認証");
+                        
+                        if self.connection_info.use_public_key {
+                            // 鍵ペアは設定ディレクトリに保存され、初回に生成される
+                            if self.client_public_key.is_none() {
+                                match load_client_keypair() {
+                                    Ok(keypair) => self.client_public_key = Some(keypair.public_key()),
+                                    Err(e) => self.error_message = Some(e.to_string()),
+                                }
                             }
-                        });
+                            
+                            if let Some(public_key) = &self.client_public_key {
+                                ui.label("公開鍵（サーバーのauthorized_keysに登録してください）:");
+                                let mut text = public_key.clone();
+                                ui.add(egui::TextEdit::singleline(&mut text).desired_width(380.0));
+                            }
+                        } else {
+                            ui.horizontal(|ui| {
+                                ui.label("パスワード:");
+                                let password = self.connection_info.password.get_or_insert_with(String::new);
+                                let mut password_display = "*".repeat(password.len());
+                                if ui.text_edit_singleline(&mut password_display).changed() {
+                                    *password = password_display;
+                                }
+                            });
+                        }
+                        
+                        // サーバーが要求した場合のみワンタイムパスワードを入力
+                        if self.otp_required {
+                            ui.horizontal(|ui| {
+                                ui.label("ワンタイムパスワード:");
+                                let otp_code = self.connection_info.otp_code.get_or_insert_with(String::new);
+                                ui.add(egui::TextEdit::singleline(otp_code).desired_width(100.0));
+                            });
+                        }
                     }
                 });
                 
@@ -216,6 +314,7 @@ impl MainWindow {
         // スレッド間通信用構造体を作成
         let thread_comm = Arc::new(ThreadCommunication {
             image_data: RwLock::new(None),
+            frame: RwLock::new(None),
             running: std::sync::atomic::AtomicBool::new(true),
             latency: std::sync::atomic::AtomicU64::new(0),
             command_queue: Mutex::new(Vec::new()),
@@ -245,6 +344,12 @@ impl MainWindow {
                         message: format!("{}に接続しました", conn_info.host) 
                     });
                 },
+                Err(NetworkError::OtpRequired(message)) => {
+                    // ワンタイムパスワードを入力してから再接続する
+                    let mut responses = thread_comm.response_queue.lock().unwrap();
+                    responses.push(Response::OtpRequired { message });
+                    return;
+                },
                 Err(e) => {
                     // 接続失敗のレスポンスをキューに追加
                     let mut responses = thread_comm.response_queue.lock().unwrap();
@@ -258,6 +363,10 @@ impl MainWindow {
 
             // 画質設定を送信
             let _ = client.send(Command::SetQuality { quality: 50 });
+            
+            // サーバーが対応していればフレームをプッシュ配信させる（非対応なら従来どおり要求ごとに取得）
+            let mut streaming = start_stream(&mut client, None, STREAM_CREDITS, conn_info.timeout()).unwrap_or(false);
+            let mut decoder = ImageDecoder::new();
 
             // 通信ループ
             while thread_comm.running.load(std::sync::atomic::Ordering::Relaxed) {
@@ -283,6 +392,40 @@ impl MainWindow {
                     }
                 }
 
+                // プッシュ配信されたフレームを受信し、受け取った分のクレジットを返す
+                if streaming {
+                    match client.receive_timeout(Duration::from_millis(50)) {
+                        Ok(Response::FrameUpdate { keyframe, width, height, tile_size, rects, timestamp, .. }) => {
+                            // 合成元の画像やタイルがなければキーフレームを要求
+                            if !thread_comm.store_update(&mut decoder, width, height, tile_size, keyframe, rects, timestamp) {
+                                let _ = client.send(Command::RequestKeyframe);
+                            }
+                            let _ = client.send(Command::StreamCredit { credits: 1 });
+                        },
+                        Ok(stopped @ Response::StreamStopped { .. }) => {
+                            // サーバーが配信を止めた場合は要求ごとの取得に戻る
+                            streaming = false;
+                            let mut responses = thread_comm.response_queue.lock().unwrap();
+                            responses.push(stopped);
+                        },
+                        Ok(other) => {
+                            let mut responses = thread_comm.response_queue.lock().unwrap();
+                            responses.push(other);
+                        },
+                        Err(NetworkError::TimeoutError(_)) => {},
+                        Err(e) => {
+                            // 接続が切れた場合は受信を繰り返さずに終了
+                            let mut responses = thread_comm.response_queue.lock().unwrap();
+                            responses.push(Response::ConnectionStatus { 
+                                connected: false, 
+                                message: format!("フレーム受信に失敗しました: {}", e) 
+                            });
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                
                 // スクリーンショットを定期的に要求
                 if thread_comm.image_data.read().unwrap().is_none() || 
                    commands.iter().any(|cmd| matches!(cmd, Command::RequestScreenshot { .. })) {
@@ -303,22 +446,7 @@ impl MainWindow {
                                     thread_comm.latency.store(latency, std::sync::atomic::Ordering::Relaxed);
                                     
                                     // 画像データを保存
-                                    let format = match format {
-                                        crate::network::protocol::ImageFormat::JPEG => ImageFormat::JPEG,
-                                        crate::network::protocol::ImageFormat::PNG => ImageFormat::PNG,
-                                        crate::network::protocol::ImageFormat::WebP => ImageFormat::WebP,
-                                        crate::network::protocol::ImageFormat::AVIF => ImageFormat::AVIF,
-                                    };
-                                    
-                                    let image_data = ImageData {
-                                        data,
-                                        format,
-                                        width,
-                                        height,
-                                        timestamp,
-                                    };
-                                    
-                                    *thread_comm.image_data.write().unwrap() = Some(image_data);
+                                    thread_comm.store_frame(data, format, width, height, timestamp);
                                 },
                                 Ok(other) => {
                                     let mut responses = thread_comm.response_queue.lock().unwrap();
@@ -531,6 +659,11 @@ impl MainWindow {
 
         // スレッド通信を処理
         if let Some(thread_comm) = &self.thread_comm {
+            // 配信で合成されたフレームを確認
+            if let Some(frame) = thread_comm.frame.write().unwrap().take() {
+                let _ = self.renderer.update_from_decoded(&frame);
+            }
+            
             // 画像データの更新を確認
             if let Some(image_data) = thread_comm.image_data.read().unwrap().clone() {
                 // レンダラーに画像を転送
@@ -552,13 +685,32 @@ impl MainWindow {
                 match response {
                     Response::ConnectionStatus { connected, message } => {
                         self.state.connected = connected;
-                        if !connected {
+                        if connected {
+                            // ワンタイムパスワードは使い捨て
+                            self.otp_required = false;
+                            self.connection_info.otp_code = None;
+                        } else {
                             self.error_message = Some(message);
                         }
                     },
                     Response::Error { code: _, message } => {
                         self.error_message = Some(message);
                     },
+                    Response::CommandResult { data: Some(data), .. } => {
+                        // サーバーが調整した配信品質をステータスバーに表示
+                        if let Some(stream_quality) = StreamQuality::from_data(&data) {
+                            self.state.performance.quality = stream_quality.quality;
+                            self.state.performance.data_rate = stream_quality.bitrate / 8;
+                            self.state.performance.stream_quality = Some(stream_quality);
+                        }
+                    },
+                    Response::OtpRequired { message } => {
+                        self.state.connected = false;
+                        self.auto_update = false;
+                        self.otp_required = true;
+                        self.show_connection_dialog = true;
+                        self.error_message = Some(message);
+                    },
                     _ => {}
                 }
             }
@@ -710,6 +862,15 @@ impl eframe::App for MainWindow {
                     // パフォーマンス情報
                     ui.label(format!("{:.1} FPS", self.state.performance.fps));
                     ui.label(format!("遅延: {}ms", self.state.performance.latency));
+                    if let Some(stream_quality) = &self.state.performance.stream_quality {
+                        ui.label(format!(
+                            "配信: 画質{} / {:.0}% / {}fps / {}kbps",
+                            stream_quality.quality,
+                            stream_quality.scale * 100.0,
+                            stream_quality.fps,
+                            stream_quality.bitrate / 1000
+                        ));
+                    }
                 });
             });
         });
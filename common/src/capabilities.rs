@@ -0,0 +1,204 @@
+//! プロトコルバージョンと機能ネゴシエーション
+//!
+//! 接続直後の`Hello`/`Welcome`でやり取りする機能一覧を定義します。
+//! サーバーは双方が対応する機能の共通部分を選択し、以降はその範囲でのみ通信します。
+
+use crate::compression::CompressionAlgorithm;
+use crate::encryption::EncryptionAlgorithm;
+use crate::protocol::{Command, ImageFormat};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// 現在のプロトコルバージョン
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// サーバーが受け付ける最小のプロトコルバージョン
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 任意機能のフラグ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Feature {
+    /// クリップボード共有
+    Clipboard,
+    /// ファイル転送
+    FileTransfer,
+    /// システム情報の取得
+    SystemInfo,
+    /// リモートでのアプリケーション実行
+    RunApplication,
+    /// フレームのプッシュ配信
+    Streaming,
+    /// 送信済みタイルのキャッシュ
+    TileCache,
+}
+
+impl Feature {
+    /// コマンドの実行に必要な機能
+    ///
+    /// 基本機能（画面・入力・認証など）のコマンドは`None`を返します。
+    pub fn required_for(command: &Command) -> Option<Self> {
+        match command {
+            Command::RequestClipboardContent | Command::SetClipboardContent { .. } => Some(Feature::Clipboard),
+            Command::StartFileTransfer { .. } | Command::FileData { .. } => Some(Feature::FileTransfer),
+            Command::RequestSystemInfo => Some(Feature::SystemInfo),
+            Command::RunApplication { .. } => Some(Feature::RunApplication),
+            Command::StartStream { .. }
+            | Command::StreamCredit { .. }
+            | Command::StopStream
+            | Command::RequestKeyframe => Some(Feature::Streaming),
+            _ => None,
+        }
+    }
+}
+
+/// 対応する機能の一覧（各リストは優先順）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// 画像コーデック
+    #[serde(default, deserialize_with = "known_only")]
+    pub codecs: Vec<ImageFormat>,
+    /// 圧縮アルゴリズム
+    #[serde(default, deserialize_with = "known_only")]
+    pub compression: Vec<CompressionAlgorithm>,
+    /// 暗号化アルゴリズム
+    #[serde(default, deserialize_with = "known_only")]
+    pub encryption: Vec<EncryptionAlgorithm>,
+    /// 任意機能
+    #[serde(default, deserialize_with = "known_only")]
+    pub features: Vec<Feature>,
+}
+
+impl Capabilities {
+    /// 双方が対応する機能の共通部分（順序は`self`の優先順）
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        fn common<T: Copy + PartialEq>(ours: &[T], theirs: &[T]) -> Vec<T> {
+            ours.iter().copied().filter(|item| theirs.contains(item)).collect()
+        }
+
+        Capabilities {
+            codecs: common(&self.codecs, &other.codecs),
+            compression: common(&self.compression, &other.compression),
+            encryption: common(&self.encryption, &other.encryption),
+            features: common(&self.features, &other.features),
+        }
+    }
+
+    /// 機能に対応しているかどうか
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// コーデックに対応しているかどうか
+    pub fn supports_codec(&self, codec: ImageFormat) -> bool {
+        self.codecs.contains(&codec)
+    }
+
+    /// 最優先のコーデック
+    pub fn preferred_codec(&self) -> Option<ImageFormat> {
+        self.codecs.first().copied()
+    }
+
+    /// 最優先の圧縮アルゴリズム（共通のものがなければ無圧縮）
+    pub fn preferred_compression(&self) -> CompressionAlgorithm {
+        self.compression.first().copied().unwrap_or(CompressionAlgorithm::None)
+    }
+}
+
+/// 相手のプロトコルバージョンから使用するバージョンを決定
+///
+/// 相手が古すぎる場合は`None`を返します。
+pub fn negotiate_version(peer_version: u32) -> Option<u32> {
+    if peer_version < MIN_PROTOCOL_VERSION {
+        None
+    } else {
+        Some(peer_version.min(PROTOCOL_VERSION))
+    }
+}
+
+/// 新しいバージョンの相手が送った未知の値を読み飛ばしてリストを逆直列化
+fn known_only<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MaybeKnown<T> {
+        Known(T),
+        Unknown(IgnoredAny),
+    }
+
+    let items: Vec<MaybeKnown<T>> = Vec::deserialize(deserializer)?;
+    Ok(items
+        .into_iter()
+        .filter_map(|item| match item {
+            MaybeKnown::Known(value) => Some(value),
+            MaybeKnown::Unknown(_) => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::WireFormat;
+
+    fn client() -> Capabilities {
+        Capabilities {
+            codecs: vec![ImageFormat::WebP, ImageFormat::JPEG, ImageFormat::PNG],
+            compression: vec![CompressionAlgorithm::Zstd, CompressionAlgorithm::LZ4],
+            encryption: vec![EncryptionAlgorithm::ChaCha20Poly1305, EncryptionAlgorithm::Aes256Gcm],
+            features: vec![Feature::Clipboard, Feature::FileTransfer],
+        }
+    }
+
+    #[test]
+    fn test_intersection_keeps_client_order() {
+        let server = Capabilities {
+            codecs: vec![ImageFormat::PNG, ImageFormat::JPEG],
+            compression: vec![CompressionAlgorithm::LZ4, CompressionAlgorithm::Gzip],
+            encryption: vec![EncryptionAlgorithm::Aes256Gcm],
+            features: vec![Feature::FileTransfer, Feature::SystemInfo],
+        };
+
+        let agreed = client().intersect(&server);
+        assert_eq!(agreed.codecs, vec![ImageFormat::JPEG, ImageFormat::PNG]);
+        assert_eq!(agreed.preferred_codec(), Some(ImageFormat::JPEG));
+        assert_eq!(agreed.preferred_compression(), CompressionAlgorithm::LZ4);
+        assert_eq!(agreed.encryption, vec![EncryptionAlgorithm::Aes256Gcm]);
+        assert!(agreed.supports(Feature::FileTransfer));
+        assert!(!agreed.supports(Feature::Clipboard));
+        assert!(!agreed.supports(Feature::SystemInfo));
+
+        assert_eq!(Capabilities::default().intersect(&server).preferred_compression(), CompressionAlgorithm::None);
+    }
+
+    #[test]
+    fn test_unknown_values_are_ignored() {
+        let json = r#"{
+            "codecs": ["H265", "PNG"],
+            "compression": ["Brotli", "Zstd"],
+            "features": ["Clipboard", "Teleport"],
+            "future_field": true
+        }"#;
+
+        let caps: Capabilities = serde_json::from_str(json).unwrap();
+        assert_eq!(caps.codecs, vec![ImageFormat::PNG]);
+        assert_eq!(caps.compression, vec![CompressionAlgorithm::Zstd]);
+        assert!(caps.encryption.is_empty());
+        assert_eq!(caps.features, vec![Feature::Clipboard]);
+
+        // バイナリ形式でも同様に往復できる
+        let data = WireFormat::MessagePack.encode(&client()).unwrap();
+        assert_eq!(WireFormat::decode::<Capabilities>(&data).unwrap(), client());
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        assert_eq!(negotiate_version(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(PROTOCOL_VERSION + 5), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION - 1), None);
+        assert_eq!(Feature::required_for(&Command::RequestSystemInfo), Some(Feature::SystemInfo));
+        assert_eq!(Feature::required_for(&Command::Ping { timestamp: 0 }), None);
+    }
+}
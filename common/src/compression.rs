@@ -6,10 +6,11 @@ use std::io::{self, Read, Write};
 use flate2::read::{GzDecoder, ZlibDecoder, DeflateDecoder};
 use flate2::write::{GzEncoder, ZlibEncoder, DeflateEncoder};
 use flate2::Compression;
+use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
 /// 圧縮方式
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CompressionAlgorithm {
     /// 無圧縮
     None,
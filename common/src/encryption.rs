@@ -9,10 +9,11 @@ use aes_gcm::{
 use base64::Engine;
 use rand::{rngs::OsRng, RngCore};
 use sha2::{Sha256, Digest};
+use serde::{Serialize, Deserialize};
 use thiserror::Error;
 use std::fmt;
 use pbkdf2::pbkdf2;  // 関数をインポート
-use hmac::Hmac;      // 型をインポート
+use hmac::{Hmac, Mac}; // 型をインポート
 
 /// 暗号化エラー
 #[derive(Error, Debug)]
@@ -39,7 +40,7 @@ pub enum EncryptionError {
 }
 
 /// 暗号化アルゴリズム
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EncryptionAlgorithm {
     /// AES-256-GCM
     Aes256Gcm,
@@ -493,6 +494,178 @@ pub fn generate_salt(len: usize) -> Vec<u8> {
     salt
 }
 
+/// パスワードハッシュの識別子
+const PASSWORD_HASH_SCHEME: &str = "pbkdf2-sha256";
+
+/// パスワードハッシュのデフォルト反復回数
+pub const PASSWORD_HASH_ITERATIONS: u32 = 100_000;
+
+/// パスワードハッシュのソルト長（バイト）
+const PASSWORD_SALT_LEN: usize = 16;
+
+/// クライアントキーを導出するHMACのラベル
+const CLIENT_KEY_LABEL: &[u8] = b"Client Key";
+
+/// パスワードをソルト付きでハッシュ化
+///
+/// 結果は `pbkdf2-sha256$<反復回数>$<ソルト(Base64)>$<StoredKey(Base64)>` 形式の文字列です。
+/// StoredKeyは `SHA256(HMAC(PBKDF2(パスワード), "Client Key"))` で、
+/// これだけではチャレンジに応答できません（SCRAMと同じ構成）。
+pub fn hash_password(password: &str) -> Result<String, EncryptionError> {
+    hash_password_with(password, &generate_salt(PASSWORD_SALT_LEN), PASSWORD_HASH_ITERATIONS)
+}
+
+/// ソルトと反復回数を指定してパスワードをハッシュ化
+pub fn hash_password_with(password: &str, salt: &[u8], iterations: u32) -> Result<String, EncryptionError> {
+    if iterations == 0 {
+        return Err(EncryptionError::KeyDerivationError("反復回数は1以上である必要があります".to_string()));
+    }
+
+    let client_key = derive_client_key(password, salt, iterations)?;
+    let engine = base64::engine::general_purpose::STANDARD;
+
+    Ok(format!(
+        "{}${}${}${}",
+        PASSWORD_HASH_SCHEME,
+        iterations,
+        engine.encode(salt),
+        engine.encode(stored_key(&client_key))
+    ))
+}
+
+/// パスワードからクライアントキー `HMAC(PBKDF2(パスワード), "Client Key")` を導出
+fn derive_client_key(password: &str, salt: &[u8], iterations: u32) -> Result<Vec<u8>, EncryptionError> {
+    let salted = derive_key_pbkdf2(password, salt, iterations, EncryptionAlgorithm::Aes256Gcm)?;
+    hmac_sha256(salted.as_bytes(), CLIENT_KEY_LABEL)
+}
+
+/// クライアントキーからサーバーに保存するStoredKeyを計算
+fn stored_key(client_key: &[u8]) -> Vec<u8> {
+    Sha256::digest(client_key).to_vec()
+}
+
+/// バイト列同士のXOR
+fn xor_bytes(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+}
+
+/// パスワードハッシュを（反復回数、ソルト、StoredKey）に分解
+fn parse_password_hash(password_hash: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    let parts: Vec<&str> = password_hash.split('$').collect();
+    if parts.len() != 4 || parts[0] != PASSWORD_HASH_SCHEME {
+        return None;
+    }
+
+    let iterations = match parts[1].parse::<u32>() {
+        Ok(iterations) if iterations > 0 => iterations,
+        _ => return None,
+    };
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    match (engine.decode(parts[2]), engine.decode(parts[3])) {
+        (Ok(salt), Ok(key)) => Some((iterations, salt, key)),
+        _ => None,
+    }
+}
+
+/// パスワードがハッシュと一致するかを検証
+///
+/// 形式が不正なハッシュは常に不一致として扱います。
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    let (iterations, salt, expected) = match parse_password_hash(password_hash) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+
+    match derive_client_key(password, &salt, iterations) {
+        Ok(client_key) => constant_time_eq(&stored_key(&client_key), &expected),
+        Err(_) => false,
+    }
+}
+
+/// 認証チャレンジのノンス長（バイト）
+pub const AUTH_NONCE_LEN: usize = 32;
+
+/// 認証チャレンジ用のノンスを生成（Base64）
+pub fn generate_auth_nonce() -> String {
+    base64::engine::general_purpose::STANDARD.encode(generate_salt(AUTH_NONCE_LEN))
+}
+
+/// パスワードハッシュからチャレンジに含める反復回数とソルト（Base64）を取得
+pub fn password_hash_params(password_hash: &str) -> Option<(u32, String)> {
+    parse_password_hash(password_hash)
+        .map(|(iterations, salt, _)| (iterations, base64::engine::general_purpose::STANDARD.encode(salt)))
+}
+
+/// HMAC-SHA256を計算
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| EncryptionError::InvalidKey(format!("HMACの初期化に失敗: {}", e)))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// 認証チャレンジへの応答を計算（クライアント側）
+///
+/// パスワードからクライアントキーを導出し、`ClientKey XOR HMAC(StoredKey, ノンス)` を
+/// Base64で返します。
+pub fn compute_challenge_response(
+    password: &str,
+    salt: &str,
+    iterations: u32,
+    nonce: &str,
+) -> Result<String, EncryptionError> {
+    if iterations == 0 {
+        return Err(EncryptionError::KeyDerivationError("反復回数は1以上である必要があります".to_string()));
+    }
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let salt = engine.decode(salt)
+        .map_err(|e| EncryptionError::InvalidData(format!("ソルトのデコードに失敗: {}", e)))?;
+    let nonce = engine.decode(nonce)
+        .map_err(|e| EncryptionError::InvalidData(format!("ノンスのデコードに失敗: {}", e)))?;
+
+    let client_key = derive_client_key(password, &salt, iterations)?;
+    let signature = hmac_sha256(&stored_key(&client_key), &nonce)?;
+    Ok(engine.encode(xor_bytes(&client_key, &signature)))
+}
+
+/// 認証チャレンジへの応答を検証（サーバー側）
+///
+/// 応答から `HMAC(StoredKey, ノンス)` を取り除いてクライアントキーを復元し、
+/// そのハッシュが保存済みのStoredKeyと一致するかを定数時間で比較します。
+pub fn verify_challenge_response(password_hash: &str, nonce: &str, response: &str) -> bool {
+    let (_, _, stored) = match parse_password_hash(password_hash) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let (nonce, response) = match (engine.decode(nonce), engine.decode(response)) {
+        (Ok(nonce), Ok(response)) => (nonce, response),
+        _ => return false,
+    };
+
+    let signature = match hmac_sha256(&stored, &nonce) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    if response.len() != signature.len() {
+        return false;
+    }
+
+    constant_time_eq(&stored_key(&xor_bytes(&response, &signature)), &stored)
+}
+
+/// タイミング攻撃を避けるためにバイト列を定数時間で比較
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Base64エンコードされた暗号化データを生成
 pub fn encrypt_to_base64(
     plaintext: &[u8],
@@ -633,4 +806,63 @@ mod tests {
         let key3 = derive_key_pbkdf2(password, &salt, 5000, EncryptionAlgorithm::Aes256Gcm).unwrap();
         assert_ne!(key.as_bytes(), key3.as_bytes());
     }
+
+    #[test]
+    fn test_password_hash_verification() {
+        let hash = hash_password_with("correct horse", &generate_salt(16), 1000).unwrap();
+        assert!(hash.starts_with("pbkdf2-sha256$1000$"));
+
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+
+        // 同じパスワードでもソルトが異なればハッシュは異なるはず
+        let hash2 = hash_password_with("correct horse", &generate_salt(16), 1000).unwrap();
+        assert_ne!(hash, hash2);
+        assert!(verify_password("correct horse", &hash2));
+    }
+
+    #[test]
+    fn test_password_hash_rejects_malformed() {
+        // 旧形式（MD5）や壊れたハッシュは常に不一致
+        assert!(!verify_password("password", "5f4dcc3b5aa765d61d8327deb882cf99"));
+        assert!(!verify_password("password", "pbkdf2-sha256$0$AAAA$AAAA"));
+        assert!(!verify_password("password", "pbkdf2-sha256$1000$!!$AAAA"));
+        assert!(!verify_password("password", ""));
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        let hash = hash_password_with("secret", b"0123456789abcdef", 1000).unwrap();
+        let (iterations, salt) = password_hash_params(&hash).unwrap();
+        assert_eq!(iterations, 1000);
+
+        let nonce = generate_auth_nonce();
+        let response = compute_challenge_response("secret", &salt, iterations, &nonce).unwrap();
+        assert!(verify_challenge_response(&hash, &nonce, &response));
+
+        // パスワード違い
+        let wrong = compute_challenge_response("wrong", &salt, iterations, &nonce).unwrap();
+        assert!(!verify_challenge_response(&hash, &nonce, &wrong));
+
+        // 別のノンスに対する応答は再利用できない
+        let other_nonce = generate_auth_nonce();
+        assert_ne!(nonce, other_nonce);
+        assert!(!verify_challenge_response(&hash, &other_nonce, &response));
+    }
+
+    #[test]
+    fn test_stored_key_alone_cannot_authenticate() {
+        let hash = hash_password_with("secret", b"0123456789abcdef", 1000).unwrap();
+        let engine = base64::engine::general_purpose::STANDARD;
+        let stored = engine.decode(hash.rsplit('$').next().unwrap()).unwrap();
+        let nonce = generate_auth_nonce();
+        let signature = hmac_sha256(&stored, &engine.decode(&nonce).unwrap()).unwrap();
+
+        // 保存値を鍵にしたHMACや、保存値そのものをクライアントキーとみなした応答は通らない
+        assert!(!verify_challenge_response(&hash, &nonce, &engine.encode(&signature)));
+        assert!(!verify_challenge_response(&hash, &nonce, &engine.encode(xor_bytes(&stored, &signature))));
+
+        // 保存値はパスワードとしても使えない
+        assert!(!verify_password(&engine.encode(&stored), &hash));
+    }
 }
\ No newline at end of file
@@ -3,17 +3,24 @@
 //! このクレートは、リモートデスクトップアプリケーションで使用される
 //! 共通の機能を提供します。クライアントとサーバーの両方で使用されます。
 
+pub mod capabilities;
 pub mod compression;
 pub mod config;
 pub mod encryption;
 pub mod error;
 pub mod protocol;
+pub mod qoi;
+pub mod signing;
+pub mod tile_cache;
+pub mod totp;
 pub mod utils;
+pub mod wire;
 
 // 主要コンポーネントを再エクスポート
 pub use error::{CommonError, ErrorCode, ErrorDetails, Result};
 pub use config::Config;
 pub use protocol::{Command, Response, ConnectionInfo, ImageFormat};
+pub use wire::WireFormat;
 
 /// ライブラリのバージョン
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -4,6 +4,8 @@
 //! クライアントとサーバー間で送受信されるコマンドとレスポンスを含みます。
 
 use serde::{Serialize, Deserialize};
+use crate::capabilities::Capabilities;
+use crate::wire::WireFormat;
 use std::time::Duration;
 
 /// マウスボタン
@@ -49,6 +51,10 @@ pub enum ImageFormat {
     WebP,
     /// AVIF
     AVIF,
+    /// H.264（動画。前のフレームを参照するため`Response::FrameUpdate`でのみ使用）
+    H264,
+    /// QOI（色数の少ない画面向けの高速な可逆圧縮）
+    QOI,
 }
 
 /// 画質設定
@@ -109,14 +115,41 @@ pub enum ConnectionState {
 /// クライアントからサーバーへのコマンド
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
-    /// 認証
+    /// 認証開始（サーバーは`Response::AuthChallenge`で応答する）
     Authenticate {
         /// ユーザー名
         username: String,
-        /// パスワード（ハッシュ済み）
-        password_hash: String,
         /// クライアント情報
         client_info: ClientInfo,
+        /// 認証方式（省略時はパスワード）
+        #[serde(default)]
+        method: AuthMethod,
+    },
+    
+    /// 認証チャレンジへの応答
+    AuthChallengeResponse {
+        /// `ClientKey XOR HMAC(StoredKey, ノンス)`（Base64）
+        response: String,
+    },
+    
+    /// ワンタイムパスワードの送信（`Response::OtpRequired`への応答）
+    SubmitOtp {
+        /// 認証アプリに表示されたコード
+        code: String,
+    },
+    
+    /// ワイヤーフォーマットのネゴシエーション（接続直後にJSONで送信）
+    NegotiateWireFormat {
+        /// 対応する形式（優先順）
+        formats: Vec<WireFormat>,
+    },
+    
+    /// プロトコルバージョンと対応機能の通知（サーバーは`Response::Welcome`で応答する）
+    Hello {
+        /// プロトコルバージョン
+        protocol_version: u32,
+        /// 対応する機能
+        capabilities: Capabilities,
     },
     
     /// スクリーンショット要求
@@ -215,6 +248,26 @@ pub enum Command {
         fps: u8,
     },
     
+    /// フレームのプッシュ配信を開始（サーバーは`Response::StreamStarted`で応答し、以降は`Response::FrameUpdate`を送り続ける）
+    StartStream {
+        /// フレームレート（省略時は`SetFps`で設定した値）
+        fps: Option<u8>,
+        /// 確認なしで送ってよいフレーム数
+        credits: u32,
+    },
+    
+    /// 配信フレームのクレジットを追加（受信したフレームの数だけ返す）
+    StreamCredit {
+        /// 追加するクレジット
+        credits: u32,
+    },
+    
+    /// フレームのプッシュ配信を停止
+    StopStream,
+    
+    /// 次のフレームをキーフレーム（画面全体）として送るよう要求
+    RequestKeyframe,
+    
     /// アプリケーション実行
     RunApplication {
         /// 実行コマンド
@@ -248,6 +301,7 @@ pub enum Command {
         /// 転送ID
         transfer_id: u32,
         /// データ
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
         /// オフセット
         offset: u64,
@@ -266,6 +320,52 @@ pub enum Command {
 /// サーバーからクライアントへのレスポンス
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
+    /// 認証チャレンジ
+    AuthChallenge {
+        /// ランダムなノンス（Base64）
+        nonce: String,
+        /// パスワードハッシュのソルト（Base64、公開鍵認証では空）
+        salt: String,
+        /// PBKDF2の反復回数（公開鍵認証では0）
+        iterations: u32,
+    },
+    
+    /// ワンタイムパスワードの要求（パスワード認証の成功後）
+    OtpRequired {
+        /// メッセージ
+        message: String,
+    },
+    
+    /// 選択されたワイヤーフォーマット（以降のメッセージはこの形式で送受信する）
+    WireFormatSelected {
+        /// 形式
+        format: WireFormat,
+    },
+    
+    /// `Command::Hello`への応答
+    Welcome {
+        /// 使用するプロトコルバージョン
+        protocol_version: u32,
+        /// サーバーのバージョン
+        server_version: String,
+        /// 双方が対応する機能（以降はこの範囲でのみ通信する）
+        capabilities: Capabilities,
+    },
+    
+    /// フレーム配信の開始
+    StreamStarted {
+        /// 配信するフレームレート
+        fps: u8,
+        /// 受け付けたクレジット（上限を超えた分は切り捨てられる）
+        credits: u32,
+    },
+    
+    /// フレーム配信の停止
+    StreamStopped {
+        /// 理由
+        reason: String,
+    },
+    
     /// 認証結果
     AuthResult {
         /// 成功したかどうか
@@ -277,6 +377,7 @@ pub enum Response {
     /// スクリーンショットデータ
     ScreenshotData {
         /// 画像データ
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
         /// 画像形式
         format: ImageFormat,
@@ -288,6 +389,27 @@ pub enum Response {
         timestamp: u64,
     },
     
+    /// 差分フレーム（配信中に送信）
+    ///
+    /// キーフレームでは画面全体を1つの矩形で送ります。それ以外の矩形は
+    /// 直前のフレームに上書きして合成します。
+    FrameUpdate {
+        /// フレーム番号
+        sequence: u64,
+        /// キーフレームかどうか
+        keyframe: bool,
+        /// 画面全体の幅
+        width: u32,
+        /// 画面全体の高さ
+        height: u32,
+        /// タイルキャッシュのタイルの一辺（0ならキャッシュを使用しない）
+        tile_size: u32,
+        /// 変更された矩形（この順に適用し、タイルキャッシュも同じ順に更新する）
+        rects: Vec<FrameRect>,
+        /// タイムスタンプ（ミリ秒）
+        timestamp: u64,
+    },
+    
     /// コマンド実行結果
     CommandResult {
         /// 成功したかどうか
@@ -361,6 +483,154 @@ pub enum Response {
     },
 }
 
+/// 差分フレームの矩形
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameRect {
+    /// X座標
+    pub x: u32,
+    /// Y座標
+    pub y: u32,
+    /// 幅
+    pub width: u32,
+    /// 高さ
+    pub height: u32,
+    /// 矩形の内容
+    pub content: RectContent,
+}
+
+/// 差分フレームの矩形の内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RectContent {
+    /// エンコードされた画像
+    Encoded {
+        /// 画像形式
+        format: ImageFormat,
+        /// 画像データ
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        /// タイルキャッシュに保存するタイルのハッシュ（左から順、空なら保存しない）
+        #[serde(default)]
+        tiles: Vec<u64>,
+    },
+    /// クライアントのタイルキャッシュにあるタイル
+    TileRef {
+        /// タイルのハッシュ
+        hash: u64,
+    },
+    /// 直前のフレーム内の領域のコピー（スクロール・ウィンドウの移動）
+    ///
+    /// コピー先は矩形の位置で、サイズは矩形と同じです。
+    CopyRect {
+        /// コピー元のX座標
+        src_x: u32,
+        /// コピー元のY座標
+        src_y: u32,
+    },
+}
+
+/// サーバーが帯域に合わせて調整した配信品質
+///
+/// 調整のたびに`Response::CommandResult`の`data`として通知されます。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StreamQuality {
+    /// 画質（1-100）
+    pub quality: u8,
+    /// 解像度の倍率（1.0で元の解像度）
+    pub scale: f32,
+    /// フレームレート
+    pub fps: u8,
+    /// 直近の送信ビットレート（bps）
+    pub bitrate: u64,
+    /// 推定RTT（ミリ秒、未計測の場合は`None`）
+    pub rtt_ms: Option<u64>,
+}
+
+impl StreamQuality {
+    /// `data`内のキー
+    const DATA_KEY: &'static str = "stream_quality";
+
+    /// `Response::CommandResult`の`data`に変換
+    pub fn to_data(&self) -> serde_json::Value {
+        serde_json::json!({ Self::DATA_KEY: self })
+    }
+
+    /// `Response::CommandResult`の`data`から取り出す（含まれていなければ`None`）
+    pub fn from_data(data: &serde_json::Value) -> Option<Self> {
+        data.get(Self::DATA_KEY)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+}
+
+/// 相関IDを付けたメッセージ
+///
+/// すべての`Command`/`Response`はこの封筒に入れて送受信します。
+/// IDが`LEGACY_ID`の封筒は、封筒に対応していない旧クライアントとの互換のため
+/// 中身のメッセージだけが直列化されます。
+#[derive(Debug, Clone, Deserialize)]
+pub struct Envelope<T> {
+    /// メッセージID（送信側で一意）
+    pub id: u64,
+    /// 応答先のメッセージID（要求への応答でない場合は`None`）
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+    /// メッセージ本体
+    pub message: T,
+}
+
+impl<T> Envelope<T> {
+    /// 封筒を持たない旧形式のメッセージのID
+    pub const LEGACY_ID: u64 = 0;
+
+    /// 新しいメッセージの封筒を作成
+    pub fn new(id: u64, message: T) -> Self {
+        Self { id, reply_to: None, message }
+    }
+
+    /// 要求への応答として封筒を作成
+    pub fn reply(id: u64, reply_to: Option<u64>, message: T) -> Self {
+        Self { id, reply_to, message }
+    }
+
+    /// 旧形式（封筒なし）のメッセージとして扱う
+    pub fn legacy(message: T) -> Self {
+        Self::new(Self::LEGACY_ID, message)
+    }
+
+    /// 旧形式のメッセージかどうか
+    pub fn is_legacy(&self) -> bool {
+        self.id == Self::LEGACY_ID
+    }
+}
+
+impl<T: Serialize> Serialize for Envelope<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        if self.is_legacy() {
+            return self.message.serialize(serializer);
+        }
+
+        let mut state = serializer.serialize_struct("Envelope", 3)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("reply_to", &self.reply_to)?;
+        state.serialize_field("message", &self.message)?;
+        state.end()
+    }
+}
+
+/// 認証方式
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AuthMethod {
+    /// パスワード（チャレンジのノンスに対する証明で応答）
+    #[default]
+    Password,
+    /// Ed25519公開鍵（チャレンジのノンスへの署名で応答）
+    PublicKey {
+        /// 公開鍵（`ed25519 <Base64>`形式）
+        public_key: String,
+    },
+}
+
 /// クライアント情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientInfo {
@@ -0,0 +1,280 @@
+//! QOI（Quite OK Image）形式
+//!
+//! 色数の少ない画面（ターミナルやIDEなど）向けの高速な可逆圧縮です。
+//! PNGのようなエントロピー符号化を行わないため、フレームごとにエンコードしても
+//! 十分に速く、JPEGのように文字がにじむこともありません。
+//! サーバーのエンコーダとクライアントのデコーダの両方で使用します。
+//!
+//! 形式は<https://qoiformat.org/qoi-specification.pdf>に従い、常にRGBA（4チャンネル）で出力します。
+
+use thiserror::Error;
+
+/// ファイルの先頭のマジック
+const MAGIC: &[u8; 4] = b"qoif";
+
+/// ヘッダーのサイズ
+const HEADER_SIZE: usize = 14;
+
+/// 終端のマーカー
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// 画素数の上限（仕様の上限）
+pub const MAX_PIXELS: u64 = 400_000_000;
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xC0;
+const OP_RGB: u8 = 0xFE;
+const OP_RGBA: u8 = 0xFF;
+const OP_MASK: u8 = 0xC0;
+
+/// 連続する同じ画素の最大数
+const MAX_RUN: u8 = 62;
+
+/// QOIエラー
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum QoiError {
+    /// 画素データのサイズが画像のサイズと一致しない
+    #[error("画素データのサイズが不正です: {actual}バイト（{expected}バイト必要）")]
+    SizeMismatch {
+        /// 必要なバイト数
+        expected: usize,
+        /// 実際のバイト数
+        actual: usize,
+    },
+
+    /// 画像のサイズが不正
+    #[error("画像のサイズが不正です: {0}x{1}")]
+    InvalidDimensions(u32, u32),
+
+    /// ヘッダーが不正
+    #[error("QOIのヘッダーが不正です")]
+    InvalidHeader,
+
+    /// データが途中で終わっている
+    #[error("QOIのデータが途中で終わっています")]
+    Truncated,
+}
+
+/// RGBAの画素をエンコード
+pub fn encode(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, QoiError> {
+    let count = pixel_count(width, height)?;
+    if pixels.len() != count * 4 {
+        return Err(QoiError::SizeMismatch { expected: count * 4, actual: pixels.len() });
+    }
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + count + END_MARKER.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&height.to_be_bytes());
+    out.push(4); // チャンネル数
+    out.push(0); // sRGB
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+
+    for (i, chunk) in pixels.chunks_exact(4).enumerate() {
+        let px = [chunk[0], chunk[1], chunk[2], chunk[3]];
+
+        if px == prev {
+            run += 1;
+            if run == MAX_RUN || i == count - 1 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let slot = hash(px);
+        if index[slot] == px {
+            out.push(OP_INDEX | slot as u8);
+        } else {
+            index[slot] = px;
+
+            if px[3] == prev[3] {
+                let dr = px[0].wrapping_sub(prev[0]) as i8;
+                let dg = px[1].wrapping_sub(prev[1]) as i8;
+                let db = px[2].wrapping_sub(prev[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                } else if (-8..=7).contains(&dr_dg) && (-32..=31).contains(&dg) && (-8..=7).contains(&db_dg) {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+
+        prev = px;
+    }
+
+    out.extend_from_slice(&END_MARKER);
+    Ok(out)
+}
+
+/// デコードしてサイズとRGBAの画素を返す
+pub fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), QoiError> {
+    if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+        return Err(QoiError::InvalidHeader);
+    }
+
+    let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    if !matches!(data[12], 3 | 4) || data[13] > 1 {
+        return Err(QoiError::InvalidHeader);
+    }
+    let count = pixel_count(width, height)?;
+
+    // 1バイトで表せる画素は最大でもMAX_RUN個なので、本体の長さに見合わない画素数は確保する前に拒否する
+    let body_len = data.len().saturating_sub(HEADER_SIZE + END_MARKER.len());
+    if count > body_len.saturating_mul(MAX_RUN as usize) {
+        return Err(QoiError::Truncated);
+    }
+
+    let mut pixels = Vec::with_capacity(count * 4);
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let mut pos = HEADER_SIZE;
+    let body_end = data.len().saturating_sub(END_MARKER.len());
+
+    while pixels.len() < count * 4 {
+        let op = *data.get(pos).filter(|_| pos < body_end).ok_or(QoiError::Truncated)?;
+        pos += 1;
+
+        let mut repeat = 1;
+        match op {
+            OP_RGB => {
+                let bytes = data.get(pos..pos + 3).ok_or(QoiError::Truncated)?;
+                px[..3].copy_from_slice(bytes);
+                pos += 3;
+            },
+            OP_RGBA => {
+                let bytes = data.get(pos..pos + 4).ok_or(QoiError::Truncated)?;
+                px.copy_from_slice(bytes);
+                pos += 4;
+            },
+            _ => match op & OP_MASK {
+                OP_INDEX => px = index[op as usize],
+                OP_DIFF => {
+                    px[0] = px[0].wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                    px[1] = px[1].wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                    px[2] = px[2].wrapping_add(op & 0x03).wrapping_sub(2);
+                },
+                OP_LUMA => {
+                    let next = *data.get(pos).ok_or(QoiError::Truncated)?;
+                    pos += 1;
+                    let dg = (op & 0x3F).wrapping_sub(32);
+                    px[0] = px[0].wrapping_add(dg).wrapping_add(next >> 4).wrapping_sub(8);
+                    px[1] = px[1].wrapping_add(dg);
+                    px[2] = px[2].wrapping_add(dg).wrapping_add(next & 0x0F).wrapping_sub(8);
+                },
+                _ => repeat = (op & 0x3F) as usize + 1,
+            },
+        }
+
+        index[hash(px)] = px;
+        for _ in 0..repeat.min(count - pixels.len() / 4) {
+            pixels.extend_from_slice(&px);
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// 画素数を計算（空の画像や上限を超える画像はエラー）
+fn pixel_count(width: u32, height: u32) -> Result<usize, QoiError> {
+    let count = width as u64 * height as u64;
+    if count == 0 || count > MAX_PIXELS {
+        return Err(QoiError::InvalidDimensions(width, height));
+    }
+    Ok(count as usize)
+}
+
+/// 画素の索引の位置
+fn hash(px: [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 文字のような細い線と単色の背景、グラデーションを含む画像
+    fn sample_pixels(width: u32, height: u32) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let px = if y % 7 == 3 && x % 5 != 0 {
+                    [220, 220, 220, 255]
+                } else if x > width / 2 {
+                    [(x * 3) as u8, (y * 5) as u8, (x + y) as u8, 255]
+                } else {
+                    [30, 30, 30, if x == 1 { 128 } else { 255 }]
+                };
+                pixels.extend_from_slice(&px);
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let pixels = sample_pixels(67, 41);
+        let encoded = encode(&pixels, 67, 41).unwrap();
+        assert_eq!(&encoded[..4], MAGIC);
+        assert_eq!(&encoded[encoded.len() - 8..], &END_MARKER);
+        assert!(encoded.len() < pixels.len());
+
+        assert_eq!(decode(&encoded).unwrap(), (67, 41, pixels));
+    }
+
+    #[test]
+    fn test_solid_image_is_run_length_encoded() {
+        let pixels = [10u8, 20, 30, 255].repeat(200);
+        let encoded = encode(&pixels, 20, 10).unwrap();
+
+        // RGB1つと最大長のランが4つ
+        assert_eq!(encoded.len(), HEADER_SIZE + 4 + 4 + END_MARKER.len());
+        assert_eq!(decode(&encoded).unwrap().2, pixels);
+    }
+
+    #[test]
+    fn test_invalid_input_is_rejected() {
+        assert_eq!(encode(&[0; 8], 3, 1), Err(QoiError::SizeMismatch { expected: 12, actual: 8 }));
+        assert_eq!(encode(&[], 0, 1), Err(QoiError::InvalidDimensions(0, 1)));
+        assert_eq!(decode(b"qoix"), Err(QoiError::InvalidHeader));
+
+        let encoded = encode(&sample_pixels(16, 16), 16, 16).unwrap();
+        assert_eq!(decode(&encoded[..encoded.len() / 2]), Err(QoiError::Truncated));
+    }
+
+    #[test]
+    fn test_header_larger_than_body_is_rejected() {
+        // 本体が1画素分しかないのに10000x10000の画像を名乗るデータ
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&10_000u32.to_be_bytes());
+        data.extend_from_slice(&10_000u32.to_be_bytes());
+        data.extend_from_slice(&[4, 0, OP_RUN]);
+        data.extend_from_slice(&END_MARKER);
+        assert_eq!(decode(&data), Err(QoiError::Truncated));
+
+        // 最長の連続だけで表せる画像は受け付ける
+        let pixels = [0u8, 0, 0, 255].repeat(MAX_RUN as usize * 2);
+        let encoded = encode(&pixels, MAX_RUN as u32 * 2, 1).unwrap();
+        assert_eq!(encoded.len(), HEADER_SIZE + 2 + END_MARKER.len());
+        assert_eq!(decode(&encoded).unwrap().2, pixels);
+    }
+}
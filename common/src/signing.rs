@@ -0,0 +1,243 @@
+//! 公開鍵認証
+//!
+//! Ed25519の鍵ペアによるクライアント認証を提供します。
+//! クライアントはサーバーのノンスに署名し、サーバーは登録済みの公開鍵で検証します。
+
+use crate::utils::path::{ensure_dir_exists, get_config_dir};
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// 公開鍵の種別を表す接頭辞
+pub const KEY_TYPE: &str = "ed25519";
+
+/// 鍵ペアのデフォルトのファイル名
+pub const DEFAULT_KEY_FILE: &str = "client_ed25519.key";
+
+/// 署名対象に含めるドメイン分離用の文字列
+const SIGNATURE_CONTEXT: &[u8] = b"remote-desktop-rs-auth-v1";
+
+/// 公開鍵認証エラー
+#[derive(Error, Debug)]
+pub enum SigningError {
+    /// 鍵の形式が不正
+    #[error("鍵の形式が不正です: {0}")]
+    InvalidKey(String),
+
+    /// 署名の形式が不正
+    #[error("署名の形式が不正です")]
+    InvalidSignature,
+
+    /// 鍵ファイルの入出力エラー
+    #[error("鍵ファイルの入出力エラー: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// クライアントの鍵ペア
+pub struct ClientKeypair {
+    /// 署名鍵
+    signing_key: SigningKey,
+}
+
+impl ClientKeypair {
+    /// 新しい鍵ペアを生成
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Base64エンコードされた秘密鍵から復元
+    pub fn from_base64(secret: &str) -> Result<Self, SigningError> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(secret.trim())
+            .map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| SigningError::InvalidKey("秘密鍵は32バイトである必要があります".to_string()))?;
+
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// 秘密鍵をBase64で取得
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.signing_key.to_bytes())
+    }
+
+    /// 公開鍵を`ed25519 <Base64>`形式で取得（サーバーの登録用）
+    pub fn public_key(&self) -> String {
+        format_public_key(&self.signing_key.verifying_key())
+    }
+
+    /// 認証チャレンジのノンスに署名し、Base64で返す
+    pub fn sign_challenge(&self, username: &str, nonce: &str) -> String {
+        let signature = self.signing_key.sign(&signed_message(username, nonce));
+        base64::engine::general_purpose::STANDARD.encode(signature.to_bytes())
+    }
+
+    /// ファイルから読み込む
+    pub fn load(path: &Path) -> Result<Self, SigningError> {
+        Self::from_base64(&fs::read_to_string(path)?)
+    }
+
+    /// ファイルに保存（Unixでは所有者のみ読み書き可能にする）
+    ///
+    /// 秘密鍵が他のユーザーから読める瞬間がないよう、権限を付けて作成した
+    /// 一時ファイルに書き込んでから置き換えます。
+    pub fn save(&self, path: &Path) -> Result<(), SigningError> {
+        if let Some(parent) = path.parent() {
+            ensure_dir_exists(parent)?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        let _ = fs::remove_file(&temp_path);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&temp_path)?;
+        file.write_all(self.to_base64().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// ファイルから読み込み、存在しなければ生成して保存
+    pub fn load_or_generate(path: &Path) -> Result<Self, SigningError> {
+        if path.exists() {
+            return Self::load(path);
+        }
+
+        let keypair = Self::generate();
+        keypair.save(path)?;
+        Ok(keypair)
+    }
+}
+
+/// 鍵ペアのデフォルトの保存先
+pub fn default_key_path() -> PathBuf {
+    get_config_dir().join(DEFAULT_KEY_FILE)
+}
+
+/// 公開鍵を`ed25519 <Base64>`形式に変換
+pub fn format_public_key(key: &VerifyingKey) -> String {
+    format!("{} {}", KEY_TYPE, base64::engine::general_purpose::STANDARD.encode(key.as_bytes()))
+}
+
+/// `ed25519 <Base64> [コメント]`形式、またはBase64のみの公開鍵を解釈
+pub fn parse_public_key(text: &str) -> Result<VerifyingKey, SigningError> {
+    let mut fields = text.split_whitespace();
+    let encoded = match fields.next() {
+        Some(KEY_TYPE) => fields.next(),
+        other => other,
+    }
+    .ok_or_else(|| SigningError::InvalidKey("公開鍵が空です".to_string()))?;
+
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| SigningError::InvalidKey("公開鍵は32バイトである必要があります".to_string()))?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|e| SigningError::InvalidKey(e.to_string()))
+}
+
+/// 認証チャレンジへの署名を検証
+pub fn verify_challenge_signature(public_key: &VerifyingKey, username: &str, nonce: &str, signature: &str) -> bool {
+    let bytes = match base64::engine::general_purpose::STANDARD.decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_slice(&bytes) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    public_key
+        .verify(&signed_message(username, nonce), &signature)
+        .is_ok()
+}
+
+/// 署名対象のメッセージ（コンテキスト、ユーザー名、ノンスを連結）
+fn signed_message(username: &str, nonce: &str) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNATURE_CONTEXT.len() + username.len() + nonce.len() + 2);
+    message.extend_from_slice(SIGNATURE_CONTEXT);
+    message.push(0);
+    message.extend_from_slice(username.as_bytes());
+    message.push(0);
+    message.extend_from_slice(nonce.as_bytes());
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify_challenge() {
+        let keypair = ClientKeypair::generate();
+        let public_key = parse_public_key(&keypair.public_key()).unwrap();
+
+        let signature = keypair.sign_challenge("robot", "bm9uY2U=");
+        assert!(verify_challenge_signature(&public_key, "robot", "bm9uY2U=", &signature));
+
+        // 別のノンス・別のユーザー・別の鍵では検証に失敗する
+        assert!(!verify_challenge_signature(&public_key, "robot", "b3RoZXI=", &signature));
+        assert!(!verify_challenge_signature(&public_key, "admin", "bm9uY2U=", &signature));
+        let other = parse_public_key(&ClientKeypair::generate().public_key()).unwrap();
+        assert!(!verify_challenge_signature(&other, "robot", "bm9uY2U=", &signature));
+        assert!(!verify_challenge_signature(&public_key, "robot", "bm9uY2U=", "not-base64"));
+    }
+
+    #[test]
+    fn test_public_key_formats() {
+        let keypair = ClientKeypair::generate();
+        let text = keypair.public_key();
+        let encoded = text.strip_prefix("ed25519 ").unwrap();
+
+        let expected = parse_public_key(&text).unwrap();
+        assert_eq!(parse_public_key(encoded).unwrap(), expected);
+        assert_eq!(parse_public_key(&format!("{} automation@ci", text)).unwrap(), expected);
+        assert!(parse_public_key("").is_err());
+        assert!(parse_public_key("ed25519 AAAA").is_err());
+    }
+
+    #[test]
+    fn test_keypair_file_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("remote-desktop-rs-test-{}", std::process::id()))
+            .join(DEFAULT_KEY_FILE);
+
+        let created = ClientKeypair::load_or_generate(&path).unwrap();
+        let loaded = ClientKeypair::load_or_generate(&path).unwrap();
+        assert_eq!(created.public_key(), loaded.public_key());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // 既存の鍵は上書きできる
+        let replaced = ClientKeypair::generate();
+        replaced.save(&path).unwrap();
+        assert_eq!(ClientKeypair::load(&path).unwrap().public_key(), replaced.public_key());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
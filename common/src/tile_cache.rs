@@ -0,0 +1,133 @@
+//! タイルキャッシュ
+//!
+//! 一度送信した画面のタイルをハッシュで参照するためのLRUキャッシュです。
+//! サーバーはクライアントが保持しているタイルを追跡するために同じキャッシュを使い、
+//! 双方が同じ容量・同じ順序で操作するため、内容は常に一致します。
+
+use std::collections::{BTreeMap, HashMap};
+
+/// クライアントが保持するタイル数
+pub const TILE_CACHE_CAPACITY: usize = 4096;
+
+/// タイルハッシュのマスク（JavaScriptの数値で正確に扱える53ビットに切り詰める）
+pub const TILE_HASH_MASK: u64 = (1 << 53) - 1;
+
+/// タイルのLRUキャッシュ
+#[derive(Debug, Clone)]
+pub struct TileCache<T> {
+    /// 最大のタイル数
+    capacity: usize,
+    /// 最後に使用した順序の番号
+    tick: u64,
+    /// ハッシュごとの最終使用番号と値
+    entries: HashMap<u64, (u64, T)>,
+    /// 最終使用番号からハッシュへの索引（古い順）
+    order: BTreeMap<u64, u64>,
+}
+
+impl<T> TileCache<T> {
+    /// 新しいキャッシュを作成
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// タイルを取得（使用済みとして記録）
+    pub fn get(&mut self, hash: u64) -> Option<&T> {
+        let tick = self.next_tick();
+        let (used, value) = self.entries.get_mut(&hash)?;
+        self.order.remove(used);
+        self.order.insert(tick, hash);
+        *used = tick;
+        Some(value)
+    }
+
+    /// タイルを追加（満杯なら最も長く使われていないタイルを破棄）
+    pub fn insert(&mut self, hash: u64, value: T) {
+        let tick = self.next_tick();
+        if let Some((used, _)) = self.entries.remove(&hash) {
+            self.order.remove(&used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(hash, (tick, value));
+        self.order.insert(tick, hash);
+    }
+
+    /// すべてのタイルを破棄
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    /// 保持しているタイル数
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// 空かどうか
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_tile_is_evicted() {
+        let mut cache = TileCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+
+        // 1を使用したので、次に追加すると2が破棄される
+        assert_eq!(cache.get(1), Some(&"a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(&"a"));
+        assert_eq!(cache.get(3), Some(&"c"));
+
+        // 既存のタイルの追加は置き換え
+        cache.insert(3, "d");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(3), Some(&"d"));
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_mirrored_caches_stay_in_sync() {
+        // サーバー（値なし）とクライアント（値あり）が同じ操作列で同じ内容になる
+        let mut server = TileCache::new(3);
+        let mut client = TileCache::new(3);
+        let hashes = [5u64, 7, 5, 9, 11, 7, 5, 13, 9];
+
+        for hash in hashes {
+            if server.get(hash).is_some() {
+                assert_eq!(client.get(hash), Some(&hash));
+            } else {
+                server.insert(hash, ());
+                client.insert(hash, hash);
+            }
+        }
+
+        for hash in hashes {
+            assert_eq!(server.get(hash).is_some(), client.get(hash).is_some());
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! ワンタイムパスワード
+//!
+//! RFC 6238に基づく時間ベースのワンタイムパスワード（TOTP）を生成・検証します。
+//! シークレットは認証アプリと同じBase32形式で保存します。
+
+use crate::encryption::{constant_time_eq, generate_salt};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// デフォルトの桁数
+pub const DEFAULT_DIGITS: u32 = 6;
+
+/// デフォルトの時間ステップ（秒）
+pub const DEFAULT_PERIOD: u64 = 30;
+
+/// 時計のずれとして許容する前後のステップ数
+pub const DEFAULT_SKEW: u64 = 1;
+
+/// 生成するシークレットの長さ（バイト）
+pub const SECRET_LEN: usize = 20;
+
+/// Base32のアルファベット（RFC 4648）
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// TOTPエラー
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TotpError {
+    /// シークレットがBase32として解釈できない
+    #[error("シークレットが不正なBase32文字列です")]
+    InvalidSecret,
+
+    /// シークレットが空
+    #[error("シークレットが空です")]
+    EmptySecret,
+
+    /// 桁数が範囲外
+    #[error("桁数は6～8で指定してください: {0}")]
+    InvalidDigits(u32),
+
+    /// 時間ステップが0
+    #[error("時間ステップが不正です")]
+    InvalidPeriod,
+}
+
+/// HMACのハッシュアルゴリズム
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TotpAlgorithm {
+    /// HMAC-SHA1（認証アプリの標準）
+    #[default]
+    Sha1,
+    /// HMAC-SHA256
+    Sha256,
+    /// HMAC-SHA512
+    Sha512,
+}
+
+/// TOTPジェネレータ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Totp {
+    /// 共有シークレット
+    secret: Vec<u8>,
+    /// 桁数
+    digits: u32,
+    /// 時間ステップ（秒）
+    period: u64,
+    /// ハッシュアルゴリズム
+    algorithm: TotpAlgorithm,
+}
+
+impl Totp {
+    /// パラメータを指定して作成
+    pub fn new(secret: Vec<u8>, digits: u32, period: u64, algorithm: TotpAlgorithm) -> Result<Self, TotpError> {
+        if secret.is_empty() {
+            return Err(TotpError::EmptySecret);
+        }
+        if !(6..=8).contains(&digits) {
+            return Err(TotpError::InvalidDigits(digits));
+        }
+        if period == 0 {
+            return Err(TotpError::InvalidPeriod);
+        }
+
+        Ok(Self { secret, digits, period, algorithm })
+    }
+
+    /// Base32のシークレットから認証アプリ互換の設定（SHA1、6桁、30秒）で作成
+    pub fn from_base32(secret: &str) -> Result<Self, TotpError> {
+        Self::new(base32_decode(secret)?, DEFAULT_DIGITS, DEFAULT_PERIOD, TotpAlgorithm::Sha1)
+    }
+
+    /// UNIX時刻に対応する時間ステップ
+    pub fn time_step(&self, unix_time: u64) -> u64 {
+        unix_time / self.period
+    }
+
+    /// 指定したUNIX時刻のコードを生成
+    pub fn generate_at(&self, unix_time: u64) -> String {
+        self.generate_for_step(self.time_step(unix_time))
+    }
+
+    /// 現在時刻のコードを生成
+    pub fn generate(&self) -> String {
+        self.generate_at(unix_time())
+    }
+
+    /// コードを検証し、一致した時間ステップを返す
+    ///
+    /// 前後`skew`ステップまでの時計のずれを許容します。
+    /// 呼び出し側は返されたステップを記録して再利用を拒否してください。
+    pub fn verify_at(&self, code: &str, unix_time: u64, skew: u64) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != self.digits as usize {
+            return None;
+        }
+
+        let current = self.time_step(unix_time);
+        let first = current.saturating_sub(skew);
+        let last = current.saturating_add(skew);
+
+        // 一致しても途中で抜けず、すべての候補を比較する
+        let mut matched = None;
+        for step in first..=last {
+            if constant_time_eq(self.generate_for_step(step).as_bytes(), code.as_bytes()) && matched.is_none() {
+                matched = Some(step);
+            }
+        }
+        matched
+    }
+
+    /// 現在時刻でコードを検証
+    pub fn verify(&self, code: &str) -> Option<u64> {
+        self.verify_at(code, unix_time(), DEFAULT_SKEW)
+    }
+
+    /// 時間ステップのコードを生成（RFC 4226の動的切り詰め）
+    fn generate_for_step(&self, step: u64) -> String {
+        let digest = hmac_digest(self.algorithm, &self.secret, &step.to_be_bytes());
+
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+
+        let code = binary % 10u32.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+}
+
+/// HMACを計算
+fn hmac_digest(algorithm: TotpAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMACは任意長のキーを受け付けるため初期化は失敗しない
+    match algorithm {
+        TotpAlgorithm::Sha1 => {
+            let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMACの初期化に失敗");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        },
+        TotpAlgorithm::Sha256 => {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMACの初期化に失敗");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        },
+        TotpAlgorithm::Sha512 => {
+            let mut mac = <Hmac<Sha512> as Mac>::new_from_slice(key).expect("HMACの初期化に失敗");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        },
+    }
+}
+
+/// 現在のUNIX時刻（秒）
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// 新しいシークレットをBase32で生成
+pub fn generate_secret() -> String {
+    base32_encode(&generate_salt(SECRET_LEN))
+}
+
+/// Base32エンコード（パディングなし）
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+/// Base32デコード
+///
+/// 大文字小文字、空白、区切りのハイフン、末尾のパディングは無視します。
+pub fn base32_decode(input: &str) -> Result<Vec<u8>, TotpError> {
+    let mut output = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.trim_end_matches('=').chars() {
+        if c.is_whitespace() || c == '-' {
+            continue;
+        }
+
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())
+            .ok_or(TotpError::InvalidSecret)?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 6238 付録Bのテストベクタ（時刻, SHA1, SHA256, SHA512）
+    const RFC6238_VECTORS: &[(u64, &str, &str, &str)] = &[
+        (59, "94287082", "46119246", "90693936"),
+        (1111111109, "07081804", "68084774", "25091201"),
+        (1111111111, "14050471", "67062674", "99943326"),
+        (1234567890, "89005924", "91819424", "93441116"),
+        (2000000000, "69279037", "90698825", "38618901"),
+        (20000000000, "65353130", "77737706", "47863826"),
+    ];
+
+    fn rfc_totp(algorithm: TotpAlgorithm) -> Totp {
+        let secret: &[u8] = match algorithm {
+            TotpAlgorithm::Sha1 => b"12345678901234567890",
+            TotpAlgorithm::Sha256 => b"12345678901234567890123456789012",
+            TotpAlgorithm::Sha512 => b"1234567890123456789012345678901234567890123456789012345678901234",
+        };
+        Totp::new(secret.to_vec(), 8, 30, algorithm).unwrap()
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let sha1 = rfc_totp(TotpAlgorithm::Sha1);
+        let sha256 = rfc_totp(TotpAlgorithm::Sha256);
+        let sha512 = rfc_totp(TotpAlgorithm::Sha512);
+
+        for &(time, expected_sha1, expected_sha256, expected_sha512) in RFC6238_VECTORS {
+            assert_eq!(sha1.generate_at(time), expected_sha1, "SHA1 t={}", time);
+            assert_eq!(sha256.generate_at(time), expected_sha256, "SHA256 t={}", time);
+            assert_eq!(sha512.generate_at(time), expected_sha512, "SHA512 t={}", time);
+        }
+    }
+
+    #[test]
+    fn test_verify_with_skew() {
+        let totp = rfc_totp(TotpAlgorithm::Sha1);
+
+        // 1111111109はステップ37037036、1111111111はステップ37037037
+        assert_eq!(totp.verify_at("07081804", 1111111109, 0), Some(37037036));
+        assert_eq!(totp.verify_at("0708 1804", 1111111111, 1), Some(37037036));
+        assert_eq!(totp.verify_at("07081804", 1111111111, 0), None);
+        assert_eq!(totp.verify_at("0708180", 1111111109, 1), None);
+        assert_eq!(totp.verify_at("00000000", 1111111109, 1), None);
+    }
+
+    #[test]
+    fn test_base32_round_trip() {
+        // RFC 4648のテストベクタ
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw 6ytb-oi").unwrap(), b"foobar");
+        assert_eq!(base32_decode("MZXW1"), Err(TotpError::InvalidSecret));
+
+        let secret = generate_secret();
+        assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_LEN);
+
+        // 認証アプリ形式のシークレットはRFCのSHA1シークレットと同じ値になる
+        let totp = Totp::from_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(totp.generate_at(59), "287082");
+    }
+}
@@ -0,0 +1,236 @@
+//! ワイヤーフォーマット
+//!
+//! `Command`/`Response`をネットワークに流す際の直列化形式を定義します。
+//! バイナリ形式（MessagePack）のメッセージは先頭にマジックバイトを付けるため、
+//! 受信側はJSONのメッセージと混在していても形式を判別できます。
+
+use crate::protocol::Envelope;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// バイナリ形式のメッセージの先頭に付けるマジックバイト
+///
+/// JSONのメッセージは`{`または`"`で始まるため衝突しません。
+pub const BINARY_MAGIC: u8 = 0xB1;
+
+/// 長さプレフィックス付きフレームの最大長（バイト）
+///
+/// 長さは認証前の相手からも届くため、この値を超えるフレームは確保する前に拒否します。
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// このビルドが対応する形式（優先順）
+pub const SUPPORTED_FORMATS: &[WireFormat] = &[WireFormat::MessagePack, WireFormat::Json];
+
+/// ワイヤーフォーマットエラー
+#[derive(Error, Debug)]
+pub enum WireError {
+    /// 直列化に失敗
+    #[error("シリアライズエラー: {0}")]
+    Encode(String),
+
+    /// 逆直列化に失敗
+    #[error("デシリアライズエラー: {0}")]
+    Decode(String),
+
+    /// 空のメッセージ
+    #[error("空のメッセージを受信しました")]
+    Empty,
+
+    /// 長さプレフィックスが上限を超えている
+    #[error("フレームが大きすぎます: {0}バイト（上限 {MAX_FRAME_SIZE}バイト）")]
+    FrameTooLarge(usize),
+}
+
+/// 直列化形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum WireFormat {
+    /// JSON（既定、Webクライアントや旧バージョンとの互換用）
+    #[default]
+    Json,
+    /// MessagePack（バイト列をそのまま送れるため画像データに適する）
+    MessagePack,
+}
+
+impl WireFormat {
+    /// 形式の名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::MessagePack => "msgpack",
+        }
+    }
+
+    /// バイナリ形式かどうか（WebSocketのフレーム種別の選択に使用）
+    pub fn is_binary(&self) -> bool {
+        matches!(self, WireFormat::MessagePack)
+    }
+
+    /// メッセージを直列化
+    pub fn encode<T: Serialize>(&self, message: &T) -> Result<Vec<u8>, WireError> {
+        match self {
+            WireFormat::Json => serde_json::to_vec(message).map_err(|e| WireError::Encode(e.to_string())),
+            WireFormat::MessagePack => {
+                let mut data = vec![BINARY_MAGIC];
+                rmp_serde::encode::write_named(&mut data, message).map_err(|e| WireError::Encode(e.to_string()))?;
+                Ok(data)
+            },
+        }
+    }
+
+    /// 受信データの形式を判別
+    pub fn detect(data: &[u8]) -> WireFormat {
+        match data.first() {
+            Some(&BINARY_MAGIC) => WireFormat::MessagePack,
+            _ => WireFormat::Json,
+        }
+    }
+
+    /// メッセージを逆直列化（形式は先頭バイトから判別）
+    pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, WireError> {
+        if data.is_empty() {
+            return Err(WireError::Empty);
+        }
+
+        match Self::detect(data) {
+            WireFormat::Json => serde_json::from_slice(data).map_err(|e| WireError::Decode(e.to_string())),
+            WireFormat::MessagePack => rmp_serde::from_slice(&data[1..]).map_err(|e| WireError::Decode(e.to_string())),
+        }
+    }
+}
+
+/// メッセージを封筒として逆直列化
+///
+/// 封筒のない旧形式のメッセージは`Envelope::legacy`として返します。
+pub fn decode_envelope<T: DeserializeOwned>(data: &[u8]) -> Result<Envelope<T>, WireError> {
+    match WireFormat::decode::<Envelope<T>>(data) {
+        Ok(envelope) => Ok(envelope),
+        Err(_) => WireFormat::decode::<T>(data).map(Envelope::legacy),
+    }
+}
+
+/// 4バイトのビッグエンディアンの長さプレフィックスを検証
+pub fn frame_len(prefix: [u8; 4]) -> Result<usize, WireError> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(WireError::FrameTooLarge(len));
+    }
+    Ok(len)
+}
+
+/// 相手が提示した形式（優先順）から使用する形式を選択
+///
+/// 共通の形式がない場合はJSONにフォールバックします。
+pub fn negotiate(offered: &[WireFormat]) -> WireFormat {
+    offered
+        .iter()
+        .copied()
+        .find(|format| SUPPORTED_FORMATS.contains(format))
+        .unwrap_or(WireFormat::Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Command, ImageFormat, Response};
+
+    fn screenshot() -> Response {
+        Response::ScreenshotData {
+            data: (0..=255u8).cycle().take(64 * 1024).collect(),
+            format: ImageFormat::JPEG,
+            width: 640,
+            height: 480,
+            timestamp: 1,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        let commands = vec![
+            Command::Ping { timestamp: 42 },
+            Command::Disconnect,
+            Command::SubmitOtp { code: "123456".to_string() },
+            Command::NegotiateWireFormat { formats: SUPPORTED_FORMATS.to_vec() },
+        ];
+
+        for format in [WireFormat::Json, WireFormat::MessagePack] {
+            for command in &commands {
+                let data = format.encode(command).unwrap();
+                assert_eq!(WireFormat::detect(&data), format);
+                let decoded: Command = WireFormat::decode(&data).unwrap();
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", command));
+            }
+
+            // 自己記述的な値を含むレスポンス
+            let response = Response::CommandResult {
+                success: true,
+                message: "ok".to_string(),
+                data: Some(serde_json::json!({ "transfer_id": 3, "name": "a.txt" })),
+            };
+            let decoded: Response = WireFormat::decode(&format.encode(&response).unwrap()).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", response));
+        }
+    }
+
+    #[test]
+    fn test_binary_is_compact_for_images() {
+        let json = WireFormat::Json.encode(&screenshot()).unwrap();
+        let binary = WireFormat::MessagePack.encode(&screenshot()).unwrap();
+
+        // 画像データはバイト列のまま格納される
+        assert!(binary.len() < 64 * 1024 + 128, "{}", binary.len());
+        assert!(json.len() > binary.len() * 3);
+
+        match WireFormat::decode::<Response>(&binary).unwrap() {
+            Response::ScreenshotData { data, width, .. } => {
+                assert_eq!(data.len(), 64 * 1024);
+                assert_eq!(width, 640);
+            },
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        for format in [WireFormat::Json, WireFormat::MessagePack] {
+            let request = Envelope::new(7, Command::SetQuality { quality: 80 });
+            let decoded: Envelope<Command> = decode_envelope(&format.encode(&request).unwrap()).unwrap();
+            assert_eq!(decoded.id, 7);
+            assert_eq!(decoded.reply_to, None);
+            assert!(matches!(decoded.message, Command::SetQuality { quality: 80 }));
+
+            let reply = Envelope::reply(3, Some(7), &Response::Pong { original_timestamp: 1, server_time: 2 });
+            let decoded: Envelope<Response> = decode_envelope(&format.encode(&reply).unwrap()).unwrap();
+            assert_eq!((decoded.id, decoded.reply_to), (3, Some(7)));
+        }
+    }
+
+    #[test]
+    fn test_frame_len_limit() {
+        assert_eq!(frame_len(16u32.to_be_bytes()).unwrap(), 16);
+        assert_eq!(frame_len((MAX_FRAME_SIZE as u32).to_be_bytes()).unwrap(), MAX_FRAME_SIZE);
+        assert!(matches!(frame_len(u32::MAX.to_be_bytes()), Err(WireError::FrameTooLarge(_))));
+    }
+
+    #[test]
+    fn test_legacy_messages_have_no_envelope() {
+        // 旧クライアントの封筒なしのメッセージ
+        let decoded: Envelope<Command> = decode_envelope(br#"{"Ping":{"timestamp":5}}"#).unwrap();
+        assert!(decoded.is_legacy());
+        assert!(matches!(decoded.message, Command::Ping { timestamp: 5 }));
+
+        // 旧クライアントへの応答は封筒なしで送る
+        let data = WireFormat::Json.encode(&Envelope::legacy(Command::Disconnect)).unwrap();
+        assert_eq!(data, br#""Disconnect""#);
+
+        assert!(decode_envelope::<Command>(br#"{"id":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_json() {
+        assert_eq!(negotiate(&[WireFormat::MessagePack, WireFormat::Json]), WireFormat::MessagePack);
+        assert_eq!(negotiate(&[WireFormat::Json]), WireFormat::Json);
+        assert_eq!(negotiate(&[]), WireFormat::Json);
+        assert!(WireFormat::decode::<Command>(&[]).is_err());
+    }
+}
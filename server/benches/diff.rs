@@ -0,0 +1,94 @@
+//! 差分計算のベンチマーク
+//!
+//! 1080pと4Kの画面で、ウィンドウ1つ分の変更がある場合と変更がない場合について、
+//! `DiffCalculator::calculate`と以前の`get_pixel`による逐次計算を比較します。
+//!
+//! ```sh
+//! cargo bench -p remote-desktop-rs-server --bench diff
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use remote_desktop_rs_server::capture::diff::DiffConfig;
+use remote_desktop_rs_server::capture::{CapturedImage, DiffCalculator};
+
+const RESOLUTIONS: [(&str, u32, u32); 2] = [("1080p", 1920, 1080), ("4k", 3840, 2160)];
+
+// グラデーションの背景に、指定した位置のウィンドウを描いた画面を作成
+fn create_screen(width: u32, height: u32, window: Option<(u32, u32)>) -> CapturedImage {
+    let img = ImageBuffer::from_fn(width, height, |x, y| match window {
+        Some((wx, wy)) if x >= wx && x < wx + 640 && y >= wy && y < wy + 480 => Rgba([240, 240, 240, 255]),
+        _ => Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255]),
+    });
+    CapturedImage::new(DynamicImage::ImageRgba8(img), 0)
+}
+
+// 以前の実装と同じ、1スレッドで全ピクセルを`get_pixel`で比較する差分計算
+fn sequential_diff(prev: &DynamicImage, current: &DynamicImage, config: &DiffConfig) -> usize {
+    let (width, height) = current.dimensions();
+    let mut changed_blocks = 0;
+
+    for by in (0..height).step_by(config.block_size as usize) {
+        for bx in (0..width).step_by(config.block_size as usize) {
+            let block_width = config.block_size.min(width - bx);
+            let block_height = config.block_size.min(height - by);
+            let mut changed = 0;
+
+            for y in by..by + block_height {
+                for x in bx..bx + block_width {
+                    let p1 = current.get_pixel(x, y);
+                    let p2 = prev.get_pixel(x, y);
+                    let diff = (p1[0].abs_diff(p2[0]) as u32 + p1[1].abs_diff(p2[1]) as u32 + p1[2].abs_diff(p2[2]) as u32) / 3;
+                    if diff > config.threshold as u32 {
+                        changed += 1;
+                    }
+                }
+            }
+
+            if changed as f32 / (block_width * block_height) as f32 >= config.change_ratio_threshold {
+                changed_blocks += 1;
+            }
+        }
+    }
+
+    changed_blocks
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    group.sample_size(20);
+
+    for (name, width, height) in RESOLUTIONS {
+        let previous = create_screen(width, height, None);
+        let scenarios = [
+            ("window", create_screen(width, height, Some((width / 4, height / 4)))),
+            ("unchanged", create_screen(width, height, None)),
+        ];
+
+        for (scenario, current) in &scenarios {
+            let id = format!("{}/{}", name, scenario);
+
+            group.bench_with_input(BenchmarkId::new("sequential", &id), current, |b, current| {
+                let config = DiffConfig::default();
+                b.iter(|| sequential_diff(black_box(&previous.image), black_box(&current.image), &config))
+            });
+
+            group.bench_with_input(BenchmarkId::new("parallel", &id), current, |b, current| {
+                b.iter_batched(
+                    || {
+                        let mut calculator = DiffCalculator::default();
+                        calculator.set_previous(&previous);
+                        calculator
+                    },
+                    |mut calculator| calculator.calculate(black_box(current)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff);
+criterion_main!(benches);
@@ -2,9 +2,12 @@
 //!
 //! リモートデスクトップサーバーのメインアプリケーションクラスを実装します。
 
-use crate::capture::ScreenCapture;
+use crate::capture::{create_source, MonitorInfo, ScreenCapture};
 use crate::input::InputHandler;
+use crate::input::sink::{create_sink, InputArea, DEFAULT_VIRTUAL_SCREEN};
 use crate::network::{NetworkServer, ServerConfig, ServerFactory, NetworkError};
+use crate::network::login_tracker::{LockoutPolicy, LoginAttemptTracker};
+use crate::network::congestion::QualityBounds;
 use crate::ui::{ServerSettings, ServerState, StatusInfo, TrayHandler};
 
 use std::sync::{Arc, Mutex, mpsc};
@@ -49,6 +52,8 @@ pub struct App {
     server_state: Arc<Mutex<ServerState>>,
     /// ステータス情報
     status_info: Arc<Mutex<StatusInfo>>,
+    /// ログイン試行トラッカー（サーバーを再起動しても保持する）
+    login_tracker: Arc<LoginAttemptTracker>,
     /// システムトレイハンドラ
     tray_handler: Option<TrayHandler>,
     /// 設定ファイルパス
@@ -168,12 +173,19 @@ impl App {
             .map_err(|e| format!("設定の読み込みに失敗しました: {}", e))?;
         
         // キャプチャーを初期化
-        let screen_capture = ScreenCapture::new()
+        let capture_source = create_source(&settings.capture.source)
             .map_err(|e| format!("スクリーンキャプチャの初期化に失敗しました: {}", e))?;
+        info!("キャプチャ元: {}", capture_source.name());
+        let screen_capture = ScreenCapture::with_source(capture_source);
         
         // 入力ハンドラを初期化
-        let input_handler = InputHandler::new()
+        // 絶対座標の範囲は全モニターを含む領域で、クライアントの座標は配信するモニターからの位置
+        let input_area = Self::input_area(&screen_capture, settings.capture.monitor_index);
+        let input_sink = create_sink(&settings.input.backend, input_area.screen_size)
             .map_err(|e| format!("入力ハンドラの初期化に失敗しました: {}", e))?;
+        info!("入力の出力先: {}", input_sink.name());
+        let mut input_handler = InputHandler::with_sink(input_sink);
+        input_handler.set_area(input_area);
         
         // 終了用チャネルを作成
         let (exit_sender, exit_receiver) = mpsc::channel();
@@ -191,6 +203,13 @@ impl App {
         // ステータス情報を初期化
         let status_info = StatusInfo::default();
         
+        // ログイン試行トラッカーを初期化
+        let login_tracker = LoginAttemptTracker::new(LockoutPolicy {
+            max_failures: settings.security.max_login_failures,
+            lockout_duration: Duration::from_secs(settings.security.lockout_duration_secs),
+            ..Default::default()
+        });
+        
         Ok(Self {
             state: AppState::Initializing,
             settings,
@@ -199,6 +218,7 @@ impl App {
             server: None,
             server_state: Arc::new(Mutex::new(server_state)),
             status_info: Arc::new(Mutex::new(status_info)),
+            login_tracker: Arc::new(login_tracker),
             tray_handler: None,
             settings_path,
             start_time: Instant::now(),
@@ -233,6 +253,7 @@ impl App {
             let mut screen_capture = self.screen_capture.lock().unwrap();
             let _ = screen_capture.select_monitor(monitor_index);
         }
+        self.update_input_area();
         
         self.state = AppState::Ready;
         info!("リモートデスクトップサーバーの初期化が完了しました");
@@ -249,6 +270,20 @@ impl App {
         info!("リモートデスクトップサーバーを起動中...");
         self.state = AppState::Starting;
         
+        // 画質の調整範囲は下限と上限が逆転しないように補正する
+        let configured_bounds = QualityBounds {
+            min_quality: self.settings.capture.min_quality,
+            max_quality: 100,
+            min_scale: self.settings.capture.min_scale,
+            max_scale: 1.0,
+            min_fps: self.settings.capture.min_fps,
+            max_fps: self.settings.capture.max_fps,
+        };
+        let quality_bounds = configured_bounds.normalized();
+        if quality_bounds != configured_bounds {
+            warn!("画質の調整範囲の設定が不正なため補正しました: {:?}", quality_bounds);
+        }
+        
         // サーバー設定を構築
         let server_config = ServerConfig {
             bind_address: self.settings.network.bind_address.clone(),
@@ -260,6 +295,12 @@ impl App {
             max_connections: self.settings.network.max_connections,
             client_timeout: self.settings.network.client_timeout,
             keep_alive_interval: self.settings.network.keep_alive_interval,
+            users: self.settings.security.users.clone(),
+            allowed_ips: self.settings.security.allowed_ips.clone(),
+            denied_ips: self.settings.security.denied_ips.clone(),
+            login_tracker: self.login_tracker.clone(),
+            max_protocol_violations: self.settings.security.max_protocol_violations,
+            quality_bounds,
         };
         
         // サーバーを選択して作成
@@ -402,6 +443,7 @@ impl App {
                     status.uptime = self.start_time.elapsed().as_secs();
                     status.sent_bytes = self.stats.get_sent_bytes();
                     status.received_bytes = self.stats.get_received_bytes();
+                    status.rejected_connections = server.rejected_connections();
                     
                     // CPU・メモリ使用状況の取得はプラットフォーム依存
                     #[cfg(feature = "system-info")]
@@ -495,8 +537,22 @@ impl App {
         }
         
         // 設定を更新
+        let monitor_changed = settings.capture.monitor_index != self.settings.capture.monitor_index;
         self.settings = settings;
         
+        // 配信するモニターが変わったら入力をずらす位置も変える
+        if monitor_changed {
+            if let Some(monitor_index) = self.settings.capture.monitor_index {
+                let mut screen_capture = self.screen_capture.lock().unwrap();
+                let monitor = screen_capture.get_monitors().ok()
+                    .and_then(|monitors| monitors.into_iter().nth(monitor_index));
+                if let Some(monitor) = monitor {
+                    screen_capture.set_monitor(monitor);
+                }
+            }
+            self.update_input_area();
+        }
+        
         // 設定を保存
         self.save_settings()?;
         
@@ -507,6 +563,30 @@ impl App {
         Ok(())
     }
     
+    /// 配信するモニターに合わせて入力を送る画面の範囲を計算
+    fn input_area(screen_capture: &ScreenCapture, monitor_index: Option<usize>) -> InputArea {
+        screen_capture.get_monitors().ok()
+            .filter(|monitors| !monitors.is_empty())
+            .map(|monitors| {
+                let streamed = monitors.get(monitor_index.unwrap_or(0))
+                    .unwrap_or(&monitors[0])
+                    .clone();
+                let (min_x, min_y, max_x, max_y) = MonitorInfo::from_monitors(monitors).total_area;
+                InputArea {
+                    screen_size: ((max_x - min_x) as u32, (max_y - min_y) as u32),
+                    origin: (streamed.position.0 - min_x, streamed.position.1 - min_y),
+                    size: streamed.size,
+                }
+            })
+            .unwrap_or_else(|| InputArea::full_screen(DEFAULT_VIRTUAL_SCREEN))
+    }
+    
+    /// 入力ハンドラの画面の範囲を現在の配信モニターに合わせる
+    fn update_input_area(&self) {
+        let area = Self::input_area(&self.screen_capture.lock().unwrap(), self.settings.capture.monitor_index);
+        self.input_handler.lock().unwrap().set_area(area);
+    }
+    
     /// 終了する
     pub fn shutdown(&mut self) -> Result<(), String> {
         if self.state == AppState::Running {
@@ -0,0 +1,176 @@
+//! 領域の内容の分類
+//!
+//! 差分フレームの矩形ごとに、色数・エッジの密度・隣接する画素の輝度差の分散から内容を推定します。
+//! 文字やUIは可逆圧縮しないとにじみが目立ち、写真や動画は非可逆圧縮のほうが
+//! はるかに小さくなるため、`UpdateEncoder`は分類に応じて矩形ごとに形式を選びます。
+
+use image::RgbaImage;
+use std::collections::HashSet;
+
+/// 文字とみなす最大の色数
+pub const TEXT_MAX_COLORS: usize = 256;
+
+/// 分析する最大の画素数（大きな矩形は行を間引く）
+const MAX_SAMPLES: usize = 64 * 1024;
+
+/// 隣接する画素の輝度差がこれ以上ならエッジとみなす
+const EDGE_THRESHOLD: i32 = 64;
+
+/// UIとみなす、隣接する画素が同じ色である割合の下限
+const GRAPHICS_MIN_FLAT_RATIO: f32 = 0.6;
+
+/// UIとみなすエッジの密度の下限
+const GRAPHICS_MIN_EDGE_DENSITY: f32 = 0.01;
+
+/// なめらかなグラデーション（UIの背景など）とみなす、隣接する画素の輝度差の分散の上限
+///
+/// 写真は細かな濃淡（ノイズ）があるため、隣接する画素の間でも輝度がばらつきます。
+const SMOOTH_MAX_VARIANCE: f32 = 16.0;
+
+/// 領域の内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentClass {
+    /// 文字（色数が少ない）
+    Text,
+    /// UI（単色の部分が多く、輪郭がはっきりしている）
+    Graphics,
+    /// 写真や動画（色が連続的に変化する）
+    Photo,
+}
+
+impl ContentClass {
+    /// 可逆圧縮で送るべきかどうか
+    pub fn is_lossless(&self) -> bool {
+        !matches!(self, ContentClass::Photo)
+    }
+}
+
+/// 領域の統計
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentStats {
+    /// 色数（`TEXT_MAX_COLORS`を超えた時点で数えるのをやめる）
+    pub colors: usize,
+    /// 隣接する画素の組のうち、輝度差の大きい組の割合
+    pub edge_density: f32,
+    /// 隣接する画素の組のうち、同じ色の組の割合
+    pub flat_ratio: f32,
+    /// 隣接する画素の輝度差の分散（平均は0とみなす）
+    pub variance: f32,
+}
+
+impl ContentStats {
+    /// 画像の統計を計算
+    pub fn measure(pixels: &RgbaImage) -> Self {
+        let (width, height) = pixels.dimensions();
+        let row_step = (width as usize * height as usize / MAX_SAMPLES).max(1);
+
+        let mut colors = HashSet::with_capacity(TEXT_MAX_COLORS + 1);
+        let mut pairs = 0u64;
+        let mut edges = 0u64;
+        let mut flat = 0u64;
+        let mut sum_sq = 0f64;
+
+        for y in (0..height).step_by(row_step) {
+            let mut prev: Option<([u8; 4], i32)> = None;
+            for x in 0..width {
+                let px = pixels.get_pixel(x, y).0;
+                let y_value = luma(px);
+
+                if colors.len() <= TEXT_MAX_COLORS {
+                    colors.insert(px);
+                }
+
+                if let Some((prev_px, prev_y)) = prev {
+                    let delta = y_value - prev_y;
+                    pairs += 1;
+                    sum_sq += (delta * delta) as f64;
+                    if prev_px == px {
+                        flat += 1;
+                    } else if delta.abs() >= EDGE_THRESHOLD {
+                        edges += 1;
+                    }
+                }
+                prev = Some((px, y_value));
+            }
+        }
+
+        let ratio = |n: f64| if pairs > 0 { (n / pairs as f64) as f32 } else { 0.0 };
+
+        Self {
+            colors: colors.len(),
+            edge_density: ratio(edges as f64),
+            flat_ratio: ratio(flat as f64),
+            variance: ratio(sum_sq),
+        }
+    }
+
+    /// 統計から内容を分類
+    pub fn class(&self) -> ContentClass {
+        if self.colors <= TEXT_MAX_COLORS {
+            ContentClass::Text
+        } else if (self.flat_ratio >= GRAPHICS_MIN_FLAT_RATIO && self.edge_density >= GRAPHICS_MIN_EDGE_DENSITY)
+            || self.variance <= SMOOTH_MAX_VARIANCE
+        {
+            ContentClass::Graphics
+        } else {
+            ContentClass::Photo
+        }
+    }
+}
+
+/// 画像の内容を分類
+pub fn classify(pixels: &RgbaImage) -> ContentClass {
+    ContentStats::measure(pixels).class()
+}
+
+/// 輝度（ITU-R BT.601の整数近似）
+fn luma(px: [u8; 4]) -> i32 {
+    (px[0] as i32 * 299 + px[1] as i32 * 587 + px[2] as i32 * 114) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_text_has_few_colors() {
+        let text = RgbaImage::from_fn(64, 32, |x, y| {
+            if (x / 2 + y) % 5 == 0 { Rgba([20, 20, 20, 255]) } else { Rgba([250, 250, 250, 255]) }
+        });
+        assert_eq!(classify(&text), ContentClass::Text);
+    }
+
+    #[test]
+    fn test_ui_with_many_colors_is_graphics() {
+        // 多くの色のアイコンが並んだ単色のツールバー
+        let ui = RgbaImage::from_fn(256, 64, |x, y| {
+            if (8..24).contains(&(x % 32)) && (8..24).contains(&y) {
+                Rgba([(x * 7 % 128) as u8, (y * 13 % 128) as u8, ((x + y) % 128) as u8, 255])
+            } else {
+                Rgba([240, 240, 240, 255])
+            }
+        });
+        let stats = ContentStats::measure(&ui);
+        assert!(stats.colors > TEXT_MAX_COLORS);
+        assert_eq!(stats.class(), ContentClass::Graphics);
+
+        // なめらかなグラデーション
+        let gradient = RgbaImage::from_fn(600, 8, |x, _| Rgba([(x / 3) as u8, (x / 4) as u8, (x % 256) as u8, 255]));
+        assert_eq!(classify(&gradient), ContentClass::Graphics);
+    }
+
+    #[test]
+    fn test_noisy_image_is_photo() {
+        let mut seed = 12345u32;
+        let photo = RgbaImage::from_fn(128, 128, |x, y| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (seed >> 16) as u8 % 24;
+            Rgba([(x + y) as u8 / 2 + noise, (x * 2) as u8 / 3 + noise, y as u8 + noise, 255])
+        });
+        let stats = ContentStats::measure(&photo);
+        assert!(stats.flat_ratio < 0.1);
+        assert_eq!(stats.class(), ContentClass::Photo);
+        assert!(!stats.class().is_lossless());
+    }
+}
@@ -2,10 +2,25 @@
 //!
 //! 連続する画面キャプチャ間の差分を効率的に計算し、
 //! 変更のあった領域のみを送信するための機能を提供します。
+//!
+//! スクロールやウィンドウの移動で領域全体がずれた場合は、ずれを`CopyRect`として検出し、
+//! 前回の画像をずらした上で残りの差分（新しく現れた部分）だけを変更領域とします。
+//!
+//! 差分はRGBAのバッファを直接比較し、ブロックの行ごとに並列で計算します。
+//! 前回の画像は毎フレーム複製せず、変更領域だけを書き換えてクライアントが持つ画像と一致させます。
 
 use super::CapturedImage;
-use image::{DynamicImage, GenericImageView, Rgba};
-use std::collections::HashSet;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+/// ずれの検出で候補にしない、前回の画像で何度も現れる行（列）の数
+///
+/// 単色の背景などの行はどのずれにも一致してしまうため、投票に使用しません。
+const MAX_LINE_REPEATS: usize = 4;
 
 /// 矩形領域
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -39,6 +54,14 @@ impl Rectangle {
         self.y + self.height > other.y
     }
     
+    /// 矩形が別の矩形と重なっているか、辺で接しているか確認
+    pub fn touches(&self, other: &Rectangle) -> bool {
+        self.x <= other.x + other.width &&
+        self.x + self.width >= other.x &&
+        self.y <= other.y + other.height &&
+        self.y + self.height >= other.y
+    }
+    
     /// 2つの矩形を統合した新しい矩形を作成
     pub fn merge(&self, other: &Rectangle) -> Self {
         let min_x = self.x.min(other.x);
@@ -55,12 +78,23 @@ impl Rectangle {
     }
 }
 
+/// 前回の画像内での領域のコピー（スクロール・ウィンドウの移動）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyRect {
+    /// コピー元（前回の画像上の位置）
+    pub src: Rectangle,
+    /// コピー先（サイズは`src`と同じ）
+    pub dst: Rectangle,
+}
+
 /// 差分結果
 #[derive(Debug, Clone)]
 pub struct DiffResult {
+    /// 前回の画像に順に適用するコピー（`changed_regions`より先に適用する）
+    pub moved_regions: Vec<CopyRect>,
     /// 変更された領域
     pub changed_regions: Vec<Rectangle>,
-    /// 変更されたピクセル数
+    /// 変更ありと判定したブロックのピクセル数
     pub changed_pixels: u32,
     /// 合計ピクセル数
     pub total_pixels: u32,
@@ -81,6 +115,8 @@ pub struct DiffConfig {
     pub merge_adjacent: bool,
     /// 最小差分サイズ（これより小さい差分領域は無視）
     pub min_diff_size: u32,
+    /// スクロールやウィンドウの移動を検出する
+    pub detect_motion: bool,
 }
 
 impl Default for DiffConfig {
@@ -91,6 +127,7 @@ impl Default for DiffConfig {
             change_ratio_threshold: 0.05, // ブロック内の5%以上のピクセルが変化した場合に差分とみなす
             merge_adjacent: true,
             min_diff_size: 8, // 8x8より小さい差分領域は無視
+            detect_motion: true,
         }
     }
 }
@@ -99,8 +136,8 @@ impl Default for DiffConfig {
 pub struct DiffCalculator {
     /// 設定
     config: DiffConfig,
-    /// 前回の画像
-    previous: Option<DynamicImage>,
+    /// 前回の画像（送信した変更領域だけを更新するため、クライアントが持つ画像と一致する）
+    previous: Option<RgbaImage>,
 }
 
 impl DiffCalculator {
@@ -129,100 +166,114 @@ impl DiffCalculator {
     
     /// 前回の画像を設定
     pub fn set_previous(&mut self, image: &CapturedImage) {
-        self.previous = Some(image.image.clone());
+        self.previous = Some(image.image.to_rgba8());
     }
     
     /// 差分を計算
     pub fn calculate(&mut self, current: &CapturedImage) -> DiffResult {
-        let current_img = &current.image;
-        
-        // 前回の画像がなければ、画面全体を差分と判定
-        if self.previous.is_none() {
-            let width = current_img.width();
-            let height = current_img.height();
-            let changed_region = Rectangle::new(0, 0, width, height);
-            let total_pixels = width * height;
-            
-            // 現在の画像を保存
-            self.previous = Some(current_img.clone());
-            
-            return DiffResult {
-                changed_regions: vec![changed_region],
-                changed_pixels: total_pixels,
-                total_pixels,
-                change_ratio: 1.0,
-            };
-        }
+        let current_img = to_rgba(&current.image);
+        let (width, height) = current_img.dimensions();
+        let total_pixels = width * height;
+        
+        // 前回の画像がないか、画像サイズが変わっていれば、画面全体を差分と判定
+        let previous = match self.previous.take() {
+            Some(previous) if previous.dimensions() == (width, height) => previous,
+            _ => {
+                // 現在の画像を保存
+                self.previous = Some(current_img.into_owned());
+                
+                return DiffResult {
+                    moved_regions: Vec::new(),
+                    changed_regions: vec![Rectangle::new(0, 0, width, height)],
+                    changed_pixels: total_pixels,
+                    total_pixels,
+                    change_ratio: 1.0,
+                };
+            }
+        };
         
-        let prev_img = self.previous.as_ref().unwrap();
+        let (mut changed_regions, mut changed_pixels) = self.diff_blocks(&previous, &current_img);
+        let mut moved_regions = Vec::new();
+        let mut reference = previous;
         
-        // 画像サイズが変わっていれば、画面全体を差分と判定
-        if prev_img.width() != current_img.width() || prev_img.height() != current_img.height() {
-            let width = current_img.width();
-            let height = current_img.height();
-            let changed_region = Rectangle::new(0, 0, width, height);
-            let total_pixels = width * height;
+        // 大きな変更領域はずれを検出し、ずらした前回の画像との差分に置き換える
+        if self.config.detect_motion && !changed_regions.is_empty() {
+            let min_size = self.config.block_size * 2;
             
-            // 現在の画像を保存
-            self.previous = Some(current_img.clone());
+            moved_regions = changed_regions
+                .iter()
+                .filter(|region| region.width >= min_size && region.height >= min_size)
+                .filter_map(|region| detect_shift(&reference, &current_img, *region))
+                .collect();
             
-            return DiffResult {
-                changed_regions: vec![changed_region],
-                changed_pixels: total_pixels,
-                total_pixels,
-                change_ratio: 1.0,
-            };
-        }
-        
-        let width = current_img.width();
-        let height = current_img.height();
-        let block_size = self.config.block_size;
-        let threshold = self.config.threshold;
-        let change_threshold = self.config.change_ratio_threshold;
-        let total_pixels = width * height;
-        
-        // ブロック単位で差分を計算
-        let mut changed_blocks = HashSet::new();
-        let mut changed_pixels_count = 0;
-        
-        for by in (0..height).step_by(block_size as usize) {
-            for bx in (0..width).step_by(block_size as usize) {
-                let block_width = block_size.min(width - bx);
-                let block_height = block_size.min(height - by);
-                let block_pixels = block_width * block_height;
-                let mut block_changed_pixels = 0;
-                
-                // ブロック内の各ピクセルを比較
-                for y in by..by + block_height {
-                    for x in bx..bx + block_width {
-                        let current_pixel = current_img.get_pixel(x, y);
-                        let prev_pixel = prev_img.get_pixel(x, y);
-                        
-                        // ピクセル差を計算
-                        let diff = pixel_diff(current_pixel, prev_pixel);
-                        
-                        if diff > threshold {
-                            block_changed_pixels += 1;
-                        }
-                    }
+            if !moved_regions.is_empty() {
+                let mut shifted = reference.clone();
+                for copy in &moved_regions {
+                    apply_copy(&mut shifted, copy);
                 }
                 
-                // ブロック内の変更率を計算
-                let block_change_ratio = block_changed_pixels as f32 / block_pixels as f32;
+                let (residual_regions, residual_pixels) = self.diff_blocks(&shifted, &current_img);
+                let area = |regions: &[Rectangle]| regions.iter().map(Rectangle::area).sum::<u32>();
                 
-                // 閾値を超えたブロックを「変更あり」として記録
-                if block_change_ratio >= change_threshold {
-                    changed_blocks.insert(Rectangle::new(bx, by, block_width, block_height));
-                    changed_pixels_count += block_changed_pixels;
+                // コピーで送信する領域が減らなければ使用しない
+                if area(&residual_regions) < area(&changed_regions) {
+                    reference = shifted;
+                    changed_regions = residual_regions;
+                    changed_pixels = residual_pixels;
+                } else {
+                    moved_regions.clear();
                 }
             }
         }
         
+        // 変更率を計算
+        let change_ratio = changed_pixels as f32 / total_pixels as f32;
+        
+        // 送信する領域だけを現在の画像で更新し、次回比較用に保存
+        for region in &changed_regions {
+            copy_region(&mut reference, &current_img, region);
+        }
+        self.previous = Some(reference);
+        
+        DiffResult {
+            moved_regions,
+            changed_regions,
+            changed_pixels,
+            total_pixels,
+            change_ratio,
+        }
+    }
+    
+    /// ブロック単位で差分を計算し、変更された領域と変更ありと判定したブロックのピクセル数を返す
+    ///
+    /// ブロックの行ごとに並列で計算します。
+    fn diff_blocks(&self, prev_img: &RgbaImage, current_img: &RgbaImage) -> (Vec<Rectangle>, u32) {
+        let (width, height) = current_img.dimensions();
+        let block_size = self.config.block_size.max(1);
+        let block_rows = height.div_ceil(block_size);
+        let block_cols = width.div_ceil(block_size);
+        
+        let changed_blocks: Vec<Rectangle> = (0..block_rows)
+            .into_par_iter()
+            .flat_map_iter(|row| {
+                let by = row * block_size;
+                let block_height = block_size.min(height - by);
+                
+                (0..block_cols)
+                    .map(move |col| col * block_size)
+                    .map(move |bx| Rectangle::new(bx, by, block_size.min(width - bx), block_height))
+                    .filter(|block| self.block_changed(prev_img, current_img, block))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        
+        let changed_pixels_count = changed_blocks.iter().map(Rectangle::area).sum();
+        
         // 隣接するブロックをマージ
         let mut changed_regions = if self.config.merge_adjacent {
-            merge_adjacent_regions(changed_blocks.into_iter().collect())
+            merge_adjacent_regions(changed_blocks)
         } else {
-            changed_blocks.into_iter().collect()
+            changed_blocks
         };
         
         // 最小サイズ以下の領域を除外
@@ -230,18 +281,43 @@ impl DiffCalculator {
             changed_regions.retain(|r| r.width >= self.config.min_diff_size && r.height >= self.config.min_diff_size);
         }
         
-        // 変更率を計算
-        let change_ratio = changed_pixels_count as f32 / total_pixels as f32;
-        
-        // 現在の画像を次回比較用に保存
-        self.previous = Some(current_img.clone());
+        (changed_regions, changed_pixels_count)
+    }
+    
+    /// ブロック内で変化したピクセルの比率が閾値に達しているか確認
+    ///
+    /// 閾値に達した時点で残りのピクセルの比較を打ち切ります。
+    fn block_changed(&self, prev_img: &RgbaImage, current_img: &RgbaImage, block: &Rectangle) -> bool {
+        let required = (block.area() as f32 * self.config.change_ratio_threshold).ceil() as usize;
+        let threshold = self.config.threshold;
+        let stride = current_img.width() as usize * 4;
+        let prev_raw = prev_img.as_raw();
+        let current_raw = current_img.as_raw();
+        let mut changed = 0;
         
-        DiffResult {
-            changed_regions,
-            changed_pixels: changed_pixels_count,
-            total_pixels,
-            change_ratio,
+        for y in block.y..block.y + block.height {
+            if changed >= required {
+                break;
+            }
+            
+            let start = y as usize * stride + block.x as usize * 4;
+            let end = start + block.width as usize * 4;
+            let prev_row = &prev_raw[start..end];
+            let current_row = &current_raw[start..end];
+            
+            // 変更のない行はまとめて比較して飛ばす
+            if prev_row == current_row {
+                continue;
+            }
+            
+            changed += prev_row
+                .chunks_exact(4)
+                .zip(current_row.chunks_exact(4))
+                .filter(|(p1, p2)| pixel_diff(p1, p2) > threshold)
+                .count();
         }
+        
+        changed >= required
     }
 }
 
@@ -251,16 +327,133 @@ impl Default for DiffCalculator {
     }
 }
 
-/// ピクセル間の色差を計算
-fn pixel_diff(p1: Rgba<u8>, p2: Rgba<u8>) -> u8 {
-    let r_diff = (p1[0] as i16 - p2[0] as i16).abs() as u32;
-    let g_diff = (p1[1] as i16 - p2[1] as i16).abs() as u32;
-    let b_diff = (p1[2] as i16 - p2[2] as i16).abs() as u32;
+/// ピクセル（RGBAの4バイト）間の色差を計算
+fn pixel_diff(p1: &[u8], p2: &[u8]) -> u8 {
+    let r_diff = p1[0].abs_diff(p2[0]) as u32;
+    let g_diff = p1[1].abs_diff(p2[1]) as u32;
+    let b_diff = p1[2].abs_diff(p2[2]) as u32;
     
     // 色差の平均値
     ((r_diff + g_diff + b_diff) / 3) as u8
 }
 
+/// RGBA画像として参照（必要な場合のみ変換）
+fn to_rgba(image: &DynamicImage) -> Cow<'_, RgbaImage> {
+    match image.as_rgba8() {
+        Some(rgba) => Cow::Borrowed(rgba),
+        None => Cow::Owned(image.to_rgba8()),
+    }
+}
+
+/// 領域内の縦、次に横のずれを検出してコピーを返す
+fn detect_shift(prev: &RgbaImage, current: &RgbaImage, region: Rectangle) -> Option<CopyRect> {
+    let vertical = find_shift(&line_hashes(prev, region, true), &line_hashes(current, region, true))
+        .map(|dy| shifted_copy(region, 0, dy));
+    
+    vertical.or_else(|| {
+        find_shift(&line_hashes(prev, region, false), &line_hashes(current, region, false))
+            .map(|dx| shifted_copy(region, dx, 0))
+    })
+}
+
+/// 領域内の各行（`rows`が`false`なら各列）のハッシュ
+fn line_hashes(image: &RgbaImage, region: Rectangle, rows: bool) -> Vec<u64> {
+    let stride = image.width() as usize * 4;
+    let raw = image.as_raw();
+    
+    if rows {
+        (region.y..region.y + region.height)
+            .map(|y| {
+                let start = y as usize * stride + region.x as usize * 4;
+                let mut hasher = DefaultHasher::new();
+                hasher.write(&raw[start..start + region.width as usize * 4]);
+                hasher.finish()
+            })
+            .collect()
+    } else {
+        (region.x..region.x + region.width)
+            .map(|x| {
+                let mut hasher = DefaultHasher::new();
+                for y in region.y..region.y + region.height {
+                    let start = y as usize * stride + x as usize * 4;
+                    hasher.write(&raw[start..start + 4]);
+                }
+                hasher.finish()
+            })
+            .collect()
+    }
+}
+
+/// 前回と今回の行ハッシュから、内容がずれた量を求める
+///
+/// 一致する行の位置の差で投票し、最も多いずれについて重なる行の半数以上が
+/// 一致した場合にそのずれを返します。
+fn find_shift(prev: &[u64], current: &[u64]) -> Option<i32> {
+    let mut positions: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, hash) in prev.iter().enumerate() {
+        positions.entry(*hash).or_default().push(i);
+    }
+    
+    let mut votes: HashMap<i32, usize> = HashMap::new();
+    for (i, hash) in current.iter().enumerate() {
+        match positions.get(hash) {
+            Some(found) if found.len() <= MAX_LINE_REPEATS => {
+                for &p in found {
+                    let shift = i as i32 - p as i32;
+                    if shift != 0 {
+                        *votes.entry(shift).or_default() += 1;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+    
+    let (shift, _) = votes
+        .into_iter()
+        .max_by_key(|&(shift, count)| (count, std::cmp::Reverse(shift.abs())))?;
+    
+    let overlap = current.len().checked_sub(shift.unsigned_abs() as usize)?;
+    let start = shift.max(0) as usize;
+    let matched = (start..start + overlap)
+        .filter(|&i| current[i] == prev[(i as i32 - shift) as usize])
+        .count();
+    
+    (overlap > 0 && matched * 2 >= overlap).then_some(shift)
+}
+
+/// 領域の内容が(dx, dy)ずれたときのコピー
+fn shifted_copy(region: Rectangle, dx: i32, dy: i32) -> CopyRect {
+    let width = region.width - dx.unsigned_abs();
+    let height = region.height - dy.unsigned_abs();
+    let src_x = region.x + (-dx).max(0) as u32;
+    let src_y = region.y + (-dy).max(0) as u32;
+    let dst_x = region.x + dx.max(0) as u32;
+    let dst_y = region.y + dy.max(0) as u32;
+    
+    CopyRect {
+        src: Rectangle::new(src_x, src_y, width, height),
+        dst: Rectangle::new(dst_x, dst_y, width, height),
+    }
+}
+
+/// 画像の領域を別の画像の同じ位置からコピー
+fn copy_region(target: &mut RgbaImage, source: &RgbaImage, region: &Rectangle) {
+    let stride = source.width() as usize * 4;
+    let target_raw: &mut [u8] = target;
+    for y in region.y..region.y + region.height {
+        let start = y as usize * stride + region.x as usize * 4;
+        let end = start + region.width as usize * 4;
+        target_raw[start..end].copy_from_slice(&source.as_raw()[start..end]);
+    }
+}
+
+/// 画像内でコピーを適用（コピー元を取り出してから書き込むため、重なっていてもよい）
+fn apply_copy(image: &mut RgbaImage, copy: &CopyRect) {
+    let src = image.view(copy.src.x, copy.src.y, copy.src.width, copy.src.height).to_image();
+    image::imageops::replace(image, &src, copy.dst.x as i64, copy.dst.y as i64);
+}
+
 /// 隣接する領域をマージする
 fn merge_adjacent_regions(regions: Vec<Rectangle>) -> Vec<Rectangle> {
     if regions.is_empty() {
@@ -287,7 +480,8 @@ fn merge_adjacent_regions(regions: Vec<Rectangle>) -> Vec<Rectangle> {
                     continue;
                 }
                 
-                if current_region.overlaps(&regions[j]) {
+                // ブロックは互いに重ならないため、接しているものもマージする
+                if current_region.touches(&regions[j]) {
                     current_region = current_region.merge(&regions[j]);
                     merged[j] = true;
                     merged_any = true;
@@ -304,7 +498,7 @@ fn merge_adjacent_regions(regions: Vec<Rectangle>) -> Vec<Rectangle> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use image::{RgbaImage, ImageBuffer};
+    use image::{RgbaImage, ImageBuffer, Rgba};
     
     // テスト用の画像を作成
     fn create_test_image(width: u32, height: u32, color: Rgba<u8>) -> DynamicImage {
@@ -333,6 +527,12 @@ mod tests {
         assert!(r2.overlaps(&r1));
         assert!(!r1.overlaps(&r3));
         assert!(!r3.overlaps(&r1));
+        
+        // 接しているだけの矩形は重ならない
+        let r4 = Rectangle::new(10, 0, 10, 10);
+        assert!(!r1.overlaps(&r4));
+        assert!(r1.touches(&r4));
+        assert!(!r1.touches(&r3));
     }
     
     #[test]
@@ -389,15 +589,102 @@ mod tests {
         assert_eq!(result.change_ratio, 1.0);
     }
     
+    #[test]
+    fn test_small_changes_accumulate_until_sent() {
+        let config = DiffConfig { block_size: 10, min_diff_size: 0, ..DiffConfig::default() };
+        let mut calculator = DiffCalculator::new(config);
+        let mut img = RgbaImage::from_pixel(20, 10, Rgba([0, 0, 0, 255]));
+        let _ = calculator.calculate(&CapturedImage::new(DynamicImage::ImageRgba8(img.clone()), 0));
+        
+        // 閾値未満の変更は送らず、前回の画像にも反映しない
+        for x in 0..4 {
+            img.put_pixel(x, 0, Rgba([255, 255, 255, 255]));
+        }
+        let result = calculator.calculate(&CapturedImage::new(DynamicImage::ImageRgba8(img.clone()), 0));
+        assert!(result.changed_regions.is_empty());
+        
+        // 変更が積み重なって閾値に達したブロックだけを送る
+        img.put_pixel(4, 0, Rgba([255, 255, 255, 255]));
+        let result = calculator.calculate(&CapturedImage::new(DynamicImage::ImageRgba8(img.clone()), 0));
+        assert_eq!(result.changed_regions, vec![Rectangle::new(0, 0, 10, 10)]);
+        assert_eq!(result.changed_pixels, 100);
+        
+        // 送った領域は前回の画像に反映される
+        let result = calculator.calculate(&CapturedImage::new(DynamicImage::ImageRgba8(img), 0));
+        assert!(result.changed_regions.is_empty());
+    }
+    
     #[test]
     fn test_pixel_diff() {
         let p1 = Rgba([100, 100, 100, 255]);
         let p2 = Rgba([120, 120, 120, 255]);
         let p3 = Rgba([100, 100, 100, 100]); // アルファ値が異なる
         
-        assert_eq!(pixel_diff(p1, p1), 0);
-        assert_eq!(pixel_diff(p1, p2), 20);
+        assert_eq!(pixel_diff(&p1.0, &p1.0), 0);
+        assert_eq!(pixel_diff(&p1.0, &p2.0), 20);
         // アルファ値の差は無視
-        assert_eq!(pixel_diff(p1, p3), 0);
+        assert_eq!(pixel_diff(&p1.0, &p3.0), 0);
+    }
+    
+    // 行（vertical=falseなら列）ごとに異なる模様の画像を、offsetだけずらして作成
+    // ずれて新しく現れた部分は灰色で塗る
+    fn create_shifted_image(width: u32, height: u32, offset: i32, vertical: bool) -> CapturedImage {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            let line = if vertical { y as i32 } else { x as i32 } - offset;
+            let extent = if vertical { height } else { width } as i32;
+            if line < 0 || line >= extent {
+                Rgba([128, 128, 128, 255])
+            } else {
+                let line = line as u32;
+                Rgba([(line * 3) as u8, (line * 7 + 40) as u8, (line * 11 + 90) as u8, 255])
+            }
+        });
+        CapturedImage::new(DynamicImage::ImageRgba8(img), 0)
+    }
+    
+    #[test]
+    fn test_vertical_scroll_is_detected_as_copy() {
+        let mut calculator = DiffCalculator::default();
+        let _ = calculator.calculate(&create_shifted_image(128, 128, 0, true));
+        
+        // 内容が10ピクセル上にスクロールし、下端に新しい内容が現れる
+        let result = calculator.calculate(&create_shifted_image(128, 128, -10, true));
+        
+        assert_eq!(result.moved_regions, vec![CopyRect {
+            src: Rectangle::new(0, 10, 128, 118),
+            dst: Rectangle::new(0, 0, 128, 118),
+        }]);
+        assert_eq!(result.changed_regions, vec![Rectangle::new(0, 96, 128, 32)]);
+    }
+    
+    #[test]
+    fn test_horizontal_move_is_detected_as_copy() {
+        let mut calculator = DiffCalculator::default();
+        let previous = create_shifted_image(128, 64, 0, false);
+        let current = create_shifted_image(128, 64, 20, false);
+        let _ = calculator.calculate(&previous);
+        let result = calculator.calculate(&current);
+        
+        assert_eq!(result.moved_regions, vec![CopyRect {
+            src: Rectangle::new(0, 0, 108, 64),
+            dst: Rectangle::new(20, 0, 108, 64),
+        }]);
+        assert_eq!(result.changed_regions, vec![Rectangle::new(0, 0, 32, 64)]);
+        
+        // 前回の画像にコピーを適用すると、変更領域の外は今回の画像と一致する
+        let mut reference = previous.image.to_rgba8();
+        apply_copy(&mut reference, &result.moved_regions[0]);
+        let current = current.image.to_rgba8();
+        assert!((0..64).all(|y| (32..128).all(|x| reference.get_pixel(x, y) == current.get_pixel(x, y))));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_motion_detection_can_be_disabled() {
+        let mut calculator = DiffCalculator::new(DiffConfig { detect_motion: false, ..DiffConfig::default() });
+        let _ = calculator.calculate(&create_shifted_image(128, 128, 0, true));
+        let result = calculator.calculate(&create_shifted_image(128, 128, -10, true));
+        
+        assert!(result.moved_regions.is_empty());
+        assert_eq!(result.changed_regions, vec![Rectangle::new(0, 0, 128, 128)]);
+    }
+}
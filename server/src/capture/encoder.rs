@@ -1,11 +1,14 @@
 //! 画像エンコーダモジュール
 //!
 //! キャプチャした画像を効率的にエンコードするための機能を提供します。
+//!
+//! 動画や動きの多い画面には、前のフレームを参照してエンコードする`VideoEncoder`（H.264）を使用します。
 
 use super::{CapturedImage, EncodedImage, ImageFormat};
 use crate::error::ServerError;
+use remote_desktop_rs_common::qoi;
 
-use image::{DynamicImage, ImageOutputFormat};
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
 use thiserror::Error;
 use std::io::{Cursor, BufWriter};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
@@ -81,8 +84,13 @@ impl ImageEncoder {
     
     /// キャプチャした画像をエンコード
     pub fn encode(&self, image: &CapturedImage) -> Result<EncodedImage, EncoderError> {
+        self.encode_as(image, self.config.format)
+    }
+    
+    /// 設定とは別の形式でエンコード（画質などその他の設定はそのまま使用）
+    pub fn encode_as(&self, image: &CapturedImage, format: ImageFormat) -> Result<EncodedImage, EncoderError> {
         // 必要に応じてリサイズ
-        let processed_image = self.preprocess_image(image)?;
+        let processed_image = self.preprocess_image(image, format)?;
         
         // 元画像のサイズを保存（圧縮率計算用）
         let original_size = processed_image.width() * processed_image.height() * 4; // RGBA 4バイト/ピクセル
@@ -91,7 +99,7 @@ impl ImageEncoder {
         let mut buffer = Cursor::new(Vec::new());
         let start_time = Instant::now();
         
-        match self.config.format {
+        match format {
             ImageFormat::JPEG => {
                 let quality = self.config.quality.clamp(1, 100);
                 processed_image.write_to(&mut buffer, ImageOutputFormat::Jpeg(quality))?;
@@ -139,6 +147,16 @@ impl ImageEncoder {
                     return Err(EncoderError::FormatError("AVIFサポートが有効になっていません".to_string()));
                 }
             },
+            ImageFormat::QOI => {
+                let rgba = processed_image.to_rgba8();
+                let data = qoi::encode(rgba.as_raw(), rgba.width(), rgba.height())
+                    .map_err(|e| EncoderError::EncodeError(format!("QOIエンコードエラー: {}", e)))?;
+                buffer.get_mut().extend_from_slice(&data);
+            },
+            ImageFormat::H264 => {
+                // 前のフレームの状態が必要なため、静止画としてはエンコードできない
+                return Err(EncoderError::FormatError("H.264はVideoEncoderでエンコードしてください".to_string()));
+            },
         }
         
         let encode_time = start_time.elapsed();
@@ -163,7 +181,7 @@ impl ImageEncoder {
         
         Ok(EncodedImage {
             data,
-            format: self.config.format,
+            format,
             width: processed_image.width(),
             height: processed_image.height(),
             timestamp,
@@ -173,7 +191,7 @@ impl ImageEncoder {
     }
     
     /// 画像の前処理（リサイズなど）
-    fn preprocess_image(&self, image: &CapturedImage) -> Result<DynamicImage, EncoderError> {
+    fn preprocess_image(&self, image: &CapturedImage, format: ImageFormat) -> Result<DynamicImage, EncoderError> {
         let mut processed = image.image.clone();
         
         // リサイズが必要か確認
@@ -221,7 +239,7 @@ impl ImageEncoder {
         }
         
         // アルファチャンネルの処理
-        if !self.config.preserve_alpha && matches!(self.config.format, ImageFormat::JPEG) {
+        if !self.config.preserve_alpha && matches!(format, ImageFormat::JPEG) {
             // JPEGはアルファをサポートしないので、背景で合成
             processed = DynamicImage::ImageRgb8(processed.to_rgb8());
         }
@@ -250,4 +268,219 @@ impl Default for ImageEncoder {
     fn default() -> Self {
         Self::new(EncoderConfig::default())
     }
-}
\ No newline at end of file
+}
+
+/// 動画エンコードの既定のビットレート（bps）
+pub const DEFAULT_VIDEO_BITRATE: u32 = 5_000_000;
+
+/// H.264動画エンコーダ
+///
+/// 前のフレームとの差分をエンコードするため、セッションごとに作成します。
+/// H.264の4:2:0形式は偶数のサイズが必要なため、奇数の幅・高さでは右端・下端の画素を複製して
+/// 偶数に広げてエンコードします（クライアントは画面の外にはみ出した分を描画しません）。
+pub struct VideoEncoder {
+    /// openh264のエンコーダ（最初のフレームまたは画面サイズの変更時に作成）
+    #[cfg(feature = "openh264")]
+    encoder: Option<openh264::encoder::Encoder>,
+    /// エンコード中の画面サイズ
+    size: (u32, u32),
+    /// 目標ビットレート（bps）
+    bitrate: u32,
+    /// 次のフレームをキーフレーム（IDR）にするかどうか
+    keyframe_requested: bool,
+}
+
+impl VideoEncoder {
+    /// 新しい動画エンコーダを作成
+    pub fn new(bitrate: u32) -> Self {
+        Self {
+            #[cfg(feature = "openh264")]
+            encoder: None,
+            size: (0, 0),
+            bitrate,
+            keyframe_requested: true,
+        }
+    }
+    
+    /// このビルドで動画エンコードが使用できるかどうか
+    pub fn is_available() -> bool {
+        cfg!(feature = "openh264")
+    }
+    
+    /// 目標ビットレートを取得
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+    
+    /// 目標ビットレートを設定（次のキーフレームから反映）
+    pub fn set_bitrate(&mut self, bitrate: u32) {
+        if bitrate != self.bitrate {
+            self.bitrate = bitrate;
+            self.reset();
+        }
+    }
+    
+    /// 次のフレームをキーフレームにする
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+    
+    /// エンコーダの状態を破棄（次のフレームはキーフレームになる）
+    pub fn reset(&mut self) {
+        #[cfg(feature = "openh264")]
+        {
+            self.encoder = None;
+        }
+        self.size = (0, 0);
+        self.keyframe_requested = true;
+    }
+    
+    /// 画面をエンコード
+    pub fn encode(&mut self, image: &CapturedImage) -> Result<EncodedImage, EncoderError> {
+        let (source_width, source_height) = image.size();
+        if source_width == 0 || source_height == 0 {
+            return Err(EncoderError::EncodeError(format!("動画にできない画面サイズです: {}x{}", source_width, source_height)));
+        }
+        let width = source_width + (source_width & 1);
+        let height = source_height + (source_height & 1);
+        
+        if self.size != (width, height) {
+            self.reset();
+            self.size = (width, height);
+        }
+        let keyframe = std::mem::take(&mut self.keyframe_requested);
+        
+        #[cfg(feature = "openh264")]
+        {
+            use openh264::encoder::{Encoder, EncoderConfig};
+            use openh264::formats::YUVBuffer;
+            
+            let start_time = Instant::now();
+            
+            // 作成直後のエンコーダは最初のフレームをIDRにするため、キーフレームでは作り直す
+            if keyframe {
+                self.encoder = None;
+            }
+            let encoder = match &mut self.encoder {
+                Some(encoder) => encoder,
+                encoder => {
+                    let config = EncoderConfig::new(width, height).set_bitrate_bps(self.bitrate);
+                    let created = Encoder::with_config(config)
+                        .map_err(|e| EncoderError::EncodeError(format!("H.264エンコーダの初期化エラー: {}", e)))?;
+                    encoder.insert(created)
+                },
+            };
+            
+            let rgb = padded_rgb(&image.image, width, height);
+            let yuv = YUVBuffer::with_rgb(width as usize, height as usize, rgb.as_raw());
+            let data = encoder.encode(&yuv)
+                .map_err(|e| EncoderError::EncodeError(format!("H.264エンコードエラー: {}", e)))?
+                .to_vec();
+            
+            log::debug!("動画エンコード時間: {:?}", start_time.elapsed());
+            
+            let original_size = (width * height * 4) as usize;
+            let compression_ratio = data.len() as f32 / original_size as f32 * 100.0;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            
+            Ok(EncodedImage {
+                data,
+                format: ImageFormat::H264,
+                width,
+                height,
+                timestamp,
+                original_size,
+                compression_ratio,
+            })
+        }
+        
+        #[cfg(not(feature = "openh264"))]
+        {
+            let _ = keyframe;
+            Err(EncoderError::FormatError("動画サポートが有効になっていません".to_string()))
+        }
+    }
+}
+
+impl Default for VideoEncoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIDEO_BITRATE)
+    }
+}
+
+/// 画像をRGBに変換し、右端・下端の画素を複製して指定したサイズに広げる
+#[cfg_attr(not(feature = "openh264"), allow(dead_code))]
+fn padded_rgb(image: &DynamicImage, width: u32, height: u32) -> RgbImage {
+    let rgb = image.to_rgb8();
+    if rgb.dimensions() == (width, height) {
+        return rgb;
+    }
+    
+    let (max_x, max_y) = (rgb.width() - 1, rgb.height() - 1);
+    RgbImage::from_fn(width, height, |x, y| *rgb.get_pixel(x.min(max_x), y.min(max_y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    
+    // 横にずらしたグラデーションの画面を作成
+    fn gradient_frame(width: u32, height: u32, offset: u32) -> CapturedImage {
+        let image = RgbaImage::from_fn(width, height, |x, y| Rgba([((x + offset) * 4) as u8, (y * 4) as u8, 128, 255]));
+        CapturedImage::new(DynamicImage::ImageRgba8(image), 0)
+    }
+    
+    // Annex B形式のビットストリームにIDRのNALユニット（種類5）が含まれるかどうか
+    #[cfg(feature = "openh264")]
+    fn contains_idr(data: &[u8]) -> bool {
+        data.windows(4).any(|w| w[0] == 0 && w[1] == 0 && w[2] == 1 && w[3] & 0x1f == 5)
+    }
+    
+    #[test]
+    #[cfg(feature = "openh264")]
+    fn test_video_frames_are_decodable() {
+        let mut encoder = VideoEncoder::default();
+        let mut decoder = openh264::decoder::Decoder::new().unwrap();
+        
+        for i in 0..4 {
+            // 3フレーム目の前にキーフレームを要求する
+            if i == 2 {
+                encoder.request_keyframe();
+            }
+            
+            // 奇数の幅は偶数に広げてエンコードされる
+            let encoded = encoder.encode(&gradient_frame(65, 48, i * 3)).unwrap();
+            assert_eq!(encoded.format, ImageFormat::H264);
+            assert_eq!((encoded.width, encoded.height), (66, 48));
+            assert_eq!(contains_idr(&encoded.data), i == 0 || i == 2, "フレーム{}", i);
+            
+            let yuv = decoder.decode(&encoded.data).unwrap().expect("デコーダがフレームを出力しません");
+            assert_eq!(yuv.dimension_rgb(), (66, 48));
+        }
+    }
+    
+    #[test]
+    fn test_odd_frames_are_padded_with_edge_pixels() {
+        let frame = gradient_frame(5, 3, 0);
+        let rgb = frame.image.to_rgb8();
+        let padded = padded_rgb(&frame.image, 6, 4);
+        
+        assert_eq!(padded.dimensions(), (6, 4));
+        assert_eq!(padded.get_pixel(2, 1), rgb.get_pixel(2, 1));
+        assert_eq!(padded.get_pixel(5, 1), rgb.get_pixel(4, 1));
+        assert_eq!(padded.get_pixel(2, 3), rgb.get_pixel(2, 2));
+        assert_eq!(padded.get_pixel(5, 3), rgb.get_pixel(4, 2));
+    }
+    
+    #[test]
+    #[cfg(not(feature = "openh264"))]
+    fn test_video_encoding_requires_feature() {
+        let mut encoder = VideoEncoder::default();
+        assert!(!VideoEncoder::is_available());
+        assert!(matches!(encoder.encode(&gradient_frame(64, 48, 0)), Err(EncoderError::FormatError(_))));
+    }
+}
@@ -6,12 +6,26 @@ pub mod screenshot;
 pub mod encoder;
 pub mod monitor;
 pub mod diff;
+pub mod update;
+pub mod classify;
+pub mod source;
+pub mod synthetic;
+pub mod replay;
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+pub mod x11;
 
 // 主要なコンポーネントを再エクスポート
-pub use screenshot::{ScreenCapture, CaptureError};
-pub use encoder::{ImageEncoder, EncoderConfig, EncoderError};
+pub use screenshot::{ScreenCapture, SystemCaptureSource, CaptureError};
+pub use encoder::{ImageEncoder, EncoderConfig, EncoderError, VideoEncoder};
 pub use monitor::{Monitor, MonitorInfo};
-pub use diff::{DiffCalculator, DiffResult, Rectangle};
+pub use diff::{DiffCalculator, DiffResult, CopyRect, Rectangle};
+pub use update::{UpdateEncoder, FrameUpdate, EncodedRegion, RegionContent};
+pub use classify::ContentClass;
+pub use source::{create_source, CaptureSource};
+pub use synthetic::TestPatternSource;
+pub use replay::ReplaySource;
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+pub use x11::X11Capture;
 
 use image::{DynamicImage, ImageBuffer, Rgba};
 use std::time::Instant;
@@ -124,6 +138,10 @@ pub enum ImageFormat {
     WebP,
     /// AVIF
     AVIF,
+    /// H.264（動画。`VideoEncoder`でのみエンコードできる）
+    H264,
+    /// QOI（色数の少ない画面向けの高速な可逆圧縮）
+    QOI,
 }
 
 impl ImageFormat {
@@ -134,6 +152,8 @@ impl ImageFormat {
             "png" => Some(ImageFormat::PNG),
             "webp" => Some(ImageFormat::WebP),
             "avif" => Some(ImageFormat::AVIF),
+            "h264" => Some(ImageFormat::H264),
+            "qoi" => Some(ImageFormat::QOI),
             _ => None,
         }
     }
@@ -145,6 +165,8 @@ impl ImageFormat {
             ImageFormat::PNG => "image/png",
             ImageFormat::WebP => "image/webp",
             ImageFormat::AVIF => "image/avif",
+            ImageFormat::H264 => "video/h264",
+            ImageFormat::QOI => "image/qoi",
         }
     }
     
@@ -155,6 +177,35 @@ impl ImageFormat {
             ImageFormat::PNG => "png",
             ImageFormat::WebP => "webp",
             ImageFormat::AVIF => "avif",
+            ImageFormat::H264 => "h264",
+            ImageFormat::QOI => "qoi",
+        }
+    }
+}
+
+impl From<ImageFormat> for remote_desktop_rs_common::protocol::ImageFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::JPEG => Self::JPEG,
+            ImageFormat::PNG => Self::PNG,
+            ImageFormat::WebP => Self::WebP,
+            ImageFormat::AVIF => Self::AVIF,
+            ImageFormat::H264 => Self::H264,
+            ImageFormat::QOI => Self::QOI,
+        }
+    }
+}
+
+impl From<remote_desktop_rs_common::protocol::ImageFormat> for ImageFormat {
+    fn from(format: remote_desktop_rs_common::protocol::ImageFormat) -> Self {
+        use remote_desktop_rs_common::protocol::ImageFormat as WireFormat;
+        match format {
+            WireFormat::JPEG => Self::JPEG,
+            WireFormat::PNG => Self::PNG,
+            WireFormat::WebP => Self::WebP,
+            WireFormat::AVIF => Self::AVIF,
+            WireFormat::H264 => Self::H264,
+            WireFormat::QOI => Self::QOI,
         }
     }
 }
\ No newline at end of file
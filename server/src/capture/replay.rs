@@ -0,0 +1,174 @@
+//! PNGの連番の再生
+//!
+//! ディレクトリ内のPNGファイルをファイル名の順に1枚ずつ返すキャプチャ元です。
+//! 実際の画面を録画した連番（`frame-0001.png`のようにゼロ埋めした名前）を再生して、
+//! 差分計算やエンコードの不具合を再現するために使用します。
+
+use super::{CaptureError, CaptureSource, CapturedImage, Monitor};
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+/// PNGの連番のキャプチャ元
+pub struct ReplaySource {
+    /// PNGファイル（ファイル名の順）
+    frames: Vec<PathBuf>,
+    /// 次に返すフレーム
+    position: usize,
+    /// 最後まで再生したら先頭に戻るかどうか（戻らない場合は最後のフレームを返し続ける）
+    looping: bool,
+    /// 画面サイズ（最初のフレームのサイズ）
+    size: (u32, u32),
+}
+
+impl ReplaySource {
+    /// ディレクトリ内のPNGファイルを読み込む
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| CaptureError::DeviceError(format!("{}を開けません: {}", dir.display(), e)))?;
+
+        let mut frames: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            })
+            .collect();
+        frames.sort();
+
+        let first = frames.first()
+            .ok_or_else(|| CaptureError::DeviceError(format!("{}にPNGファイルがありません", dir.display())))?;
+        let size = image::image_dimensions(first)?;
+
+        Ok(Self {
+            frames,
+            position: 0,
+            looping: true,
+            size,
+        })
+    }
+
+    /// 最後まで再生したら先頭に戻るかどうかを設定
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// フレーム数を取得
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// フレームがないかどうか（`open`はフレームがないとエラーになるため常に`false`）
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// 次に返すフレームの番号を取得
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// 次に返すフレームの番号を設定
+    pub fn seek(&mut self, position: usize) {
+        self.position = position.min(self.frames.len() - 1);
+    }
+}
+
+impl CaptureSource for ReplaySource {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        Ok(vec![Monitor::new(0, (0, 0), self.size, true, "Replay".to_string())])
+    }
+
+    fn capture_monitor(&mut self, monitor_index: usize) -> Result<CapturedImage, CaptureError> {
+        if monitor_index != 0 {
+            return Err(CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index)));
+        }
+
+        if self.position >= self.frames.len() {
+            self.position = if self.looping { 0 } else { self.frames.len() - 1 };
+        }
+
+        let path = &self.frames[self.position];
+        let image = image::open(path)?.to_rgba8();
+        if image.dimensions() != self.size {
+            return Err(CaptureError::ImageConversionError(format!(
+                "{}のサイズ{:?}が最初のフレームのサイズ{:?}と異なります",
+                path.display(),
+                image.dimensions(),
+                self.size,
+            )));
+        }
+        self.position += 1;
+
+        Ok(CapturedImage::new(DynamicImage::ImageRgba8(image), monitor_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    /// テスト用のディレクトリを作成（`name`ごとに空のディレクトリ）
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("replay-source-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 単色のフレームを保存
+    fn write_frame(dir: &Path, name: &str, value: u8, size: (u32, u32)) {
+        RgbaImage::from_pixel(size.0, size.1, Rgba([value, value, value, 255]))
+            .save(dir.join(name))
+            .unwrap();
+    }
+
+    /// キャプチャした画像の左上の画素の値
+    fn first_value(source: &mut ReplaySource) -> u8 {
+        source.capture_monitor(0).unwrap().image.to_rgba8().get_pixel(0, 0)[0]
+    }
+
+    #[test]
+    fn test_frames_are_replayed_in_name_order() {
+        let dir = test_dir("order");
+        write_frame(&dir, "frame-0002.png", 20, (8, 6));
+        write_frame(&dir, "frame-0001.png", 10, (8, 6));
+        write_frame(&dir, "frame-0003.png", 30, (8, 6));
+        std::fs::write(dir.join("notes.txt"), "PNG以外は無視する").unwrap();
+
+        let mut source = ReplaySource::open(&dir).unwrap();
+        assert_eq!(source.len(), 3);
+        assert_eq!(source.monitors().unwrap()[0].size, (8, 6));
+
+        let values: Vec<u8> = (0..4).map(|_| first_value(&mut source)).collect();
+        assert_eq!(values, vec![10, 20, 30, 10]);
+
+        // ループしない場合は最後のフレームを返し続ける
+        source.set_looping(false);
+        source.seek(1);
+        let values: Vec<u8> = (0..3).map(|_| first_value(&mut source)).collect();
+        assert_eq!(values, vec![20, 30, 30]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_sequences_are_rejected() {
+        let dir = test_dir("invalid");
+        assert!(ReplaySource::open(&dir).is_err());
+
+        write_frame(&dir, "a.png", 0, (8, 6));
+        write_frame(&dir, "b.png", 0, (4, 4));
+        let mut source = ReplaySource::open(&dir).unwrap();
+        assert!(source.capture_monitor(0).is_ok());
+        assert!(matches!(source.capture_monitor(0), Err(CaptureError::ImageConversionError(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
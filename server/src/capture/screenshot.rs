@@ -2,7 +2,7 @@
 //!
 //! システムからスクリーンショットをキャプチャするための機能を提供します。
 
-use super::{CapturedImage, Monitor};
+use super::{CaptureSource, CapturedImage, Monitor};
 use crate::error::ServerError;
 
 use image::{DynamicImage, RgbaImage, ImageBuffer, Rgba};
@@ -49,19 +49,32 @@ pub struct ScreenCapture {
     last_capture_time: std::time::Instant,
     /// 使用するモニター
     monitor: Option<Monitor>,
+    /// キャプチャ元
+    source: Box<dyn CaptureSource>,
 }
 
 impl ScreenCapture {
-    /// 新しいスクリーンキャプチャを作成
+    /// OSの画面をキャプチャするスクリーンキャプチャを作成
     pub fn new() -> Self {
+        Self::with_source(Box::new(SystemCaptureSource::new()))
+    }
+    
+    /// キャプチャ元を指定してスクリーンキャプチャを作成
+    pub fn with_source(source: Box<dyn CaptureSource>) -> Self {
         Self {
             last_capture: None,
             min_interval: Duration::from_millis(50), // デフォルトは50ms（20fps相当）
             last_capture_time: std::time::Instant::now(),
             monitor: None,
+            source,
         }
     }
     
+    /// キャプチャ元の名前を取得
+    pub fn source_name(&self) -> &str {
+        self.source.name()
+    }
+    
     /// 最小キャプチャ間隔を設定
     pub fn set_min_interval(&mut self, interval: Duration) {
         self.min_interval = interval;
@@ -105,8 +118,7 @@ impl ScreenCapture {
             }
         }
         
-        // キャプチャ処理は各プラットフォーム向けに実装
-        let image = self.platform_capture_monitor(monitor_index)?;
+        let image = self.source.capture_monitor(monitor_index)?;
         
         // 結果を保存
         self.last_capture = Some(image.clone());
@@ -131,7 +143,32 @@ impl ScreenCapture {
     
     /// 使用可能なモニターの一覧を取得
     pub fn get_monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
-        self.platform_get_monitors()
+        self.source.monitors()
+    }
+}
+
+impl Default for ScreenCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// OSの画面のキャプチャ元
+///
+/// プラットフォームごとのAPI（Windows GDI、X11、CoreGraphics）でキャプチャします。
+pub struct SystemCaptureSource {
+    /// X11サーバーへの接続（最初に使用するときに接続）
+    #[cfg(all(target_os = "linux", feature = "x11-support"))]
+    x11: parking_lot::Mutex<Option<super::x11::X11Capture>>,
+}
+
+impl SystemCaptureSource {
+    /// 新しいキャプチャ元を作成
+    pub fn new() -> Self {
+        Self {
+            #[cfg(all(target_os = "linux", feature = "x11-support"))]
+            x11: parking_lot::Mutex::new(None),
+        }
     }
     
     /// プラットフォーム固有のモニター一覧取得
@@ -208,29 +245,9 @@ impl ScreenCapture {
     
     #[cfg(target_os = "linux")]
     fn platform_get_monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
-        // x11rbを使用したLinux実装
         #[cfg(feature = "x11-support")]
         {
-            use x11rb::connection::Connection;
-            use x11rb::protocol::xproto::{ConnectionExt, Screen, get_geometry};
-            
-            let (conn, screen_num) = x11rb::connect(None)
-                .map_err(|e| CaptureError::MonitorError(format!("X11接続エラー: {}", e)))?;
-            
-            let screen = &conn.setup().roots[screen_num];
-            
-            // 単一モニターとして処理（複数モニターの詳細情報を取得するにはRandR拡張が必要）
-            let monitor = Monitor {
-                index: 0,
-                position: (0, 0),
-                size: (screen.width_in_pixels as u32, screen.height_in_pixels as u32),
-                work_area: (screen.width_in_pixels as u32, screen.height_in_pixels as u32),
-                is_primary: true,
-                name: "Primary Display".to_string(),
-                handle: screen.root as usize,
-            };
-            
-            Ok(vec![monitor])
+            self.with_x11(|x11| x11.monitors())
         }
         
         #[cfg(not(feature = "x11-support"))]
@@ -239,6 +256,26 @@ impl ScreenCapture {
         }
     }
     
+    /// X11の接続でキャプチャ処理を実行
+    ///
+    /// エラーになった場合は接続を破棄し、次回に接続し直します（X11サーバーの再起動などに対応するため）。
+    #[cfg(all(target_os = "linux", feature = "x11-support"))]
+    fn with_x11<T>(
+        &self,
+        f: impl FnOnce(&mut super::x11::X11Capture) -> Result<T, CaptureError>,
+    ) -> Result<T, CaptureError> {
+        let mut x11 = self.x11.lock();
+        if x11.is_none() {
+            *x11 = Some(super::x11::X11Capture::connect(None)?);
+        }
+        
+        let result = f(x11.as_mut().expect("X11 connection is established above"));
+        if result.is_err() {
+            *x11 = None;
+        }
+        result
+    }
+    
     #[cfg(target_os = "macos")]
     fn platform_get_monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
         // macOS実装（CoreGraphicsを使用）
@@ -337,7 +374,7 @@ impl ScreenCapture {
         use std::ptr::null_mut;
         
         // モニター情報を取得
-        let monitors = self.get_monitors()?;
+        let monitors = self.platform_get_monitors()?;
         let monitor = monitors.get(monitor_index).ok_or_else(|| {
             CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index))
         })?;
@@ -447,56 +484,19 @@ impl ScreenCapture {
     fn platform_capture_monitor(&self, monitor_index: usize) -> Result<CapturedImage, CaptureError> {
         #[cfg(feature = "x11-support")]
         {
-            use x11rb::connection::Connection;
-            use x11rb::protocol::xproto::{ConnectionExt, get_geometry, get_image, ImageFormat as X11ImageFormat};
-            
-            // モニター情報を取得
-            let monitors = self.get_monitors()?;
-            let monitor = monitors.get(monitor_index).ok_or_else(|| {
-                CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index))
+            let image = self.with_x11(|x11| {
+                // モニター情報はフレームごとに問い合わせず、キャッシュした一覧から取得
+                let (x, y, width, height) = x11.cached_monitors()?
+                    .get(monitor_index)
+                    .map(|monitor| (monitor.position.0, monitor.position.1, monitor.size.0, monitor.size.1))
+                    .ok_or_else(|| {
+                        CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index))
+                    })?;
+                
+                x11.capture(x, y, width, height)
             })?;
             
-            let (width, height) = monitor.size;
-            let (x, y) = monitor.position;
-            
-            let (conn, _) = x11rb::connect(None)
-                .map_err(|e| CaptureError::DeviceError(format!("X11接続エラー: {}", e)))?;
-            
-            let root = conn.setup().roots[0].root;
-            
-            // 画像を取得
-            let image_reply = conn.get_image(
-                X11ImageFormat::Z_PIXMAP.into(),
-                root,
-                x as i16, y as i16,
-                width, height,
-                !0, // プレーンマスク（すべて）
-            )
-            .map_err(|e| CaptureError::ProcessError(format!("X11 get_image失敗: {}", e)))?
-            .reply()
-            .map_err(|e| CaptureError::ProcessError(format!("X11 reply失敗: {}", e)))?;
-            
-            let data = image_reply.data;
-            
-            // BGRAからRGBAに変換
-            let mut rgba_data = Vec::with_capacity(data.len());
-            for i in (0..data.len()).step_by(4) {
-                if i + 3 < data.len() {
-                    rgba_data.push(data[i + 2]); // R
-                    rgba_data.push(data[i + 1]); // G
-                    rgba_data.push(data[i]);     // B
-                    rgba_data.push(data[i + 3]); // A
-                }
-            }
-            
-            // ImageBufferを作成
-            let image_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, rgba_data)
-                .ok_or_else(|| CaptureError::ImageConversionError("画像バッファの作成に失敗しました".to_string()))?;
-            
-            // DynamicImageに変換
-            let dynamic_image = DynamicImage::ImageRgba8(image_buffer);
-            
-            Ok(CapturedImage::new(dynamic_image, monitor_index))
+            Ok(CapturedImage::new(DynamicImage::ImageRgba8(image), monitor_index))
         }
         
         #[cfg(not(feature = "x11-support"))]
@@ -514,7 +514,7 @@ impl ScreenCapture {
             use core_foundation::base::TCFType;
             
             // モニター情報を取得
-            let monitors = self.get_monitors()?;
+            let monitors = self.platform_get_monitors()?;
             let monitor = monitors.get(monitor_index).ok_or_else(|| {
                 CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index))
             })?;
@@ -590,8 +590,22 @@ impl ScreenCapture {
     }
 }
 
-impl Default for ScreenCapture {
+impl Default for SystemCaptureSource {
     fn default() -> Self {
         Self::new()
     }
+}
+
+impl CaptureSource for SystemCaptureSource {
+    fn name(&self) -> &str {
+        "system"
+    }
+    
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        self.platform_get_monitors()
+    }
+    
+    fn capture_monitor(&mut self, monitor_index: usize) -> Result<CapturedImage, CaptureError> {
+        self.platform_capture_monitor(monitor_index)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+//! キャプチャ元
+//!
+//! `ScreenCapture`は画面の取得を`CaptureSource`に任せます。OSの画面のほかに、
+//! 決まった動きをする合成画像（`TestPatternSource`）や、保存したPNGの連番の再生
+//! （`ReplaySource`）に差し替えられるため、ディスプレイのない環境でも
+//! 差分計算からエンコードまでの処理を試せます。
+
+use super::{CaptureError, CapturedImage, Monitor, ReplaySource, SystemCaptureSource, TestPatternSource};
+
+/// サイズを指定しない場合のテストパターンのサイズ
+pub const DEFAULT_TEST_PATTERN_SIZE: (u32, u32) = (1280, 720);
+
+/// キャプチャ元
+pub trait CaptureSource: Send {
+    /// キャプチャ元の名前
+    fn name(&self) -> &str;
+
+    /// 使用可能なモニターの一覧を取得
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError>;
+
+    /// モニターをキャプチャ
+    fn capture_monitor(&mut self, monitor_index: usize) -> Result<CapturedImage, CaptureError>;
+}
+
+/// 名前からキャプチャ元を作成
+///
+/// `"system"`はOSの画面、`"test-pattern"`は合成のテストパターン（`"test-pattern:640x480"`のように
+/// サイズを指定可能）、`"replay:<ディレクトリ>"`はディレクトリ内のPNGの連番を再生します。
+pub fn create_source(source: &str) -> Result<Box<dyn CaptureSource>, CaptureError> {
+    match source.split_once(':') {
+        None if source == "system" => Ok(Box::new(SystemCaptureSource::new())),
+        None if source == "test-pattern" => {
+            let (width, height) = DEFAULT_TEST_PATTERN_SIZE;
+            Ok(Box::new(TestPatternSource::new(width, height)))
+        },
+        Some(("test-pattern", size)) => {
+            let (width, height) = size.split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                .ok_or_else(|| CaptureError::DeviceError(format!("テストパターンのサイズが不正です: {}", size)))?;
+            Ok(Box::new(TestPatternSource::new(width, height)))
+        },
+        Some(("replay", dir)) => Ok(Box::new(ReplaySource::open(dir)?)),
+        _ => Err(CaptureError::DeviceError(format!("不明なキャプチャ元: {}", source))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_source_by_name() {
+        let source = create_source("test-pattern").unwrap();
+        assert_eq!(source.name(), "test-pattern");
+        assert_eq!(source.monitors().unwrap()[0].size, DEFAULT_TEST_PATTERN_SIZE);
+
+        let source = create_source("test-pattern:320x240").unwrap();
+        assert_eq!(source.monitors().unwrap()[0].size, (320, 240));
+
+        assert!(create_source("test-pattern:320").is_err());
+        assert!(create_source("replay:/nonexistent/frames").is_err());
+        assert!(create_source("unknown").is_err());
+    }
+}
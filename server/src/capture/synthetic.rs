@@ -0,0 +1,269 @@
+//! 合成のテストパターン
+//!
+//! ディスプレイのない環境向けのキャプチャ元です。画面を3つの帯に分け、
+//! 上から順に動く図形、スクロールする文字、時計を描きます。
+//! 画像はフレーム番号だけで決まるため、同じフレーム番号からは常に同じ画像が得られます。
+
+use super::{CaptureError, CaptureSource, CapturedImage, Monitor};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// 既定のフレームレート（時計の進み方に使用）
+pub const DEFAULT_FPS: u32 = 30;
+
+/// 動く四角形が1フレームに進む距離（ピクセル）
+const BOX_SPEED: u64 = 4;
+
+/// 文字が1フレームにスクロールする距離（ピクセル）
+const SCROLL_SPEED: u64 = 2;
+
+/// 文字の1文字の幅（5ピクセルの字形と1ピクセルの間隔）
+const GLYPH_WIDTH: u32 = 6;
+
+/// 文字の1行の高さ（上下の余白を含む）
+const LINE_HEIGHT: u32 = 12;
+
+/// 上部の帯の色（カラーバー）
+const BARS: [[u8; 3]; 8] = [
+    [192, 192, 192], [192, 192, 0], [0, 192, 192], [0, 192, 0],
+    [192, 0, 192], [192, 0, 0], [0, 0, 192], [16, 16, 16],
+];
+
+/// 時計の字形（3x5、各行の下位3ビット）
+const CLOCK_FONT: [(char, [u8; 5]); 12] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+];
+
+/// 合成のテストパターンのキャプチャ元
+pub struct TestPatternSource {
+    /// 幅
+    width: u32,
+    /// 高さ
+    height: u32,
+    /// フレームレート
+    fps: u32,
+    /// 次にキャプチャするフレーム番号
+    frame: u64,
+}
+
+impl TestPatternSource {
+    /// 新しいテストパターンを作成
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width.max(1),
+            height: height.max(4),
+            fps: DEFAULT_FPS,
+            frame: 0,
+        }
+    }
+
+    /// フレームレートを設定
+    pub fn set_fps(&mut self, fps: u32) {
+        self.fps = fps.max(1);
+    }
+
+    /// 次にキャプチャするフレーム番号を取得
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// 次にキャプチャするフレーム番号を設定
+    pub fn seek(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// 指定したフレームの画像を生成
+    pub fn render(&self, frame: u64) -> RgbaImage {
+        let (width, height) = (self.width, self.height);
+        let text_top = height / 4;
+        let clock_top = height * 3 / 4;
+
+        // 動く四角形（左右の端で跳ね返る）
+        let box_size = (text_top / 2).max(1);
+        let travel = (width.saturating_sub(box_size) as u64).max(1);
+        let position = frame * BOX_SPEED % (travel * 2);
+        let box_x = if position < travel { position } else { travel * 2 - position } as u32;
+        let box_y = (text_top - box_size) / 2;
+
+        let mut image = RgbaImage::from_fn(width, height, |x, y| {
+            if y < text_top {
+                if (box_x..box_x + box_size).contains(&x) && (box_y..box_y + box_size).contains(&y) {
+                    Rgba([255, 0, 128, 255])
+                } else {
+                    let [r, g, b] = BARS[(x * BARS.len() as u32 / width) as usize];
+                    Rgba([r, g, b, 255])
+                }
+            } else if y < clock_top {
+                text_pixel(x, (y - text_top) as u64 + frame * SCROLL_SPEED)
+            } else {
+                Rgba([32, 32, 32, 255])
+            }
+        });
+
+        self.draw_clock(&mut image, frame, clock_top);
+        image
+    }
+
+    /// 下部の帯に時計（フレーム番号から求めた経過時間）を描く
+    fn draw_clock(&self, image: &mut RgbaImage, frame: u64, top: u32) {
+        let millis = frame * 1000 / self.fps as u64;
+        let text = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            millis / 3_600_000 % 24,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000,
+        );
+
+        let scale = ((self.height - top) / 8).max(1);
+        let color = Rgba([0, 255, 96, 255]);
+
+        for (i, c) in text.chars().enumerate() {
+            let Some((_, rows)) = CLOCK_FONT.iter().find(|(ch, _)| *ch == c) else { continue };
+            let left = scale * 2 + i as u32 * scale * 4;
+
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let x = left + col * scale + dx;
+                            let y = top + scale + row as u32 * scale + dy;
+                            if x < self.width && y < self.height {
+                                image.put_pixel(x, y, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CaptureSource for TestPatternSource {
+    fn name(&self) -> &str {
+        "test-pattern"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        Ok(vec![Monitor::new(0, (0, 0), (self.width, self.height), true, "Test Pattern".to_string())])
+    }
+
+    fn capture_monitor(&mut self, monitor_index: usize) -> Result<CapturedImage, CaptureError> {
+        if monitor_index != 0 {
+            return Err(CaptureError::MonitorError(format!("モニターインデックス{}は範囲外です", monitor_index)));
+        }
+
+        let image = self.render(self.frame);
+        self.frame += 1;
+
+        Ok(CapturedImage::new(DynamicImage::ImageRgba8(image), monitor_index))
+    }
+}
+
+/// スクロールする文字の画素（`line_y`は文章の先頭からの位置）
+///
+/// 行ごとに長さの異なる単語の並びを、乱数で作った字形で描きます。
+fn text_pixel(x: u32, line_y: u64) -> Rgba<u8> {
+    const BACKGROUND: Rgba<u8> = Rgba([250, 250, 250, 255]);
+    const FOREGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+
+    let line = line_y / LINE_HEIGHT as u64;
+    let row = (line_y % LINE_HEIGHT as u64) as u32;
+    let column = x / GLYPH_WIDTH;
+    let glyph_x = x % GLYPH_WIDTH;
+
+    // 字形は上下に2ピクセルずつ余白を空けた5x7
+    if !(2..9).contains(&row) || glyph_x == 5 {
+        return BACKGROUND;
+    }
+
+    let line_length = 16 + mix(line) % 48;
+    let glyph = mix((line << 16) | column as u64);
+    if column as u64 >= line_length || glyph.is_multiple_of(7) {
+        // 行末と単語の区切り
+        return BACKGROUND;
+    }
+
+    let bit = (row - 2) * 5 + glyph_x;
+    if (glyph >> bit) & 1 == 1 { FOREGROUND } else { BACKGROUND }
+}
+
+/// 整数をかき混ぜる（SplitMix64）
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{ContentClass, ScreenCapture};
+    use crate::capture::classify::classify;
+    use image::GenericImageView;
+    use std::time::Duration;
+
+    #[test]
+    fn test_frames_are_deterministic() {
+        let mut a = TestPatternSource::new(320, 240);
+        let mut b = TestPatternSource::new(320, 240);
+
+        let first = a.capture_monitor(0).unwrap();
+        assert_eq!(first.image, b.capture_monitor(0).unwrap().image);
+        assert_eq!(first.size(), (320, 240));
+
+        let second = a.capture_monitor(0).unwrap();
+        assert_ne!(first.image, second.image);
+
+        b.seek(0);
+        assert_eq!(b.capture_monitor(0).unwrap().image, first.image);
+        assert_eq!(b.frame(), 1);
+        assert!(a.capture_monitor(1).is_err());
+    }
+
+    #[test]
+    fn test_every_band_changes_between_frames() {
+        let source = TestPatternSource::new(320, 240);
+        let (a, b) = (source.render(0), source.render(1));
+
+        let band_changed = |top: u32, bottom: u32| {
+            (top..bottom).any(|y| (0..320).any(|x| a.get_pixel(x, y) != b.get_pixel(x, y)))
+        };
+        assert!(band_changed(0, 60), "動く四角形");
+        assert!(band_changed(60, 180), "スクロールする文字");
+        assert!(band_changed(180, 240), "時計");
+
+        // 文字は色数が少ないため、可逆圧縮の対象になる
+        let text = a.view(0, 60, 320, 120).to_image();
+        assert_eq!(classify(&text), ContentClass::Text);
+    }
+
+    #[test]
+    fn test_screen_capture_delegates_to_source() {
+        let mut capture = ScreenCapture::with_source(Box::new(TestPatternSource::new(64, 48)));
+        capture.set_min_interval(Duration::ZERO);
+
+        assert_eq!(capture.source_name(), "test-pattern");
+        let monitors = capture.get_monitors().unwrap();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].size, (64, 48));
+
+        let first = capture.capture().unwrap();
+        let second = capture.capture().unwrap();
+        assert_ne!(first.image, second.image);
+    }
+}
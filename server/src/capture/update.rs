@@ -0,0 +1,610 @@
+//! 差分フレーム作成モジュール
+//!
+//! `DiffCalculator`が検出した変更領域だけを切り出してエンコードし、
+//! 画面全体を送る代わりに変更された矩形の一覧を作成します。
+//! 一定のフレーム数ごと、または要求に応じて画面全体のキーフレームを作成します。
+//!
+//! タイルキャッシュを有効にすると、変更領域を`DiffConfig::block_size`の格子でタイルに分け、
+//! クライアントが既に持っているタイルは画素の代わりにハッシュだけを送ります。
+//!
+//! スクロールなどで検出された領域の移動は、画素を送らずに`RegionContent::CopyRect`として
+//! 他の矩形より先に出力します。
+//!
+//! 矩形ごとに内容を分類し（`classify`）、文字やUIは可逆圧縮（QOIに対応していればQOI、
+//! それ以外はPNG）、写真などは設定された非可逆の形式で、同じフレームの中でも使い分けます。
+//!
+//! 動画を有効にすると、変更率の高いフレームが続いた間は画面全体を`VideoEncoder`（H.264）で
+//! エンコードし、変更が落ち着いたら静止画の差分に戻ります。
+
+use super::{CapturedImage, DiffCalculator, EncodedImage, EncoderError, ImageEncoder, ImageFormat, Rectangle, VideoEncoder};
+use super::classify::classify;
+use super::diff::DiffConfig;
+use remote_desktop_rs_common::tile_cache::{TileCache, TILE_HASH_MASK};
+
+use image::RgbaImage;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+/// 既定のキーフレーム間隔（フレーム数）
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 300;
+
+/// 動画に切り替える変更率
+pub const VIDEO_CHANGE_RATIO: f32 = 0.3;
+
+/// 変更率の高いフレームがこの数だけ続いたら動画に切り替える
+pub const VIDEO_ENTER_FRAMES: u32 = 10;
+
+/// 変更率の低いフレームがこの数だけ続いたら静止画に戻す
+pub const VIDEO_EXIT_FRAMES: u32 = 30;
+
+/// 差分フレームの矩形
+#[derive(Debug, Clone)]
+pub struct EncodedRegion {
+    /// 画面上の位置とサイズ
+    pub rect: Rectangle,
+    /// 矩形の内容
+    pub content: RegionContent,
+}
+
+/// 差分フレームの矩形の内容
+#[derive(Debug, Clone)]
+pub enum RegionContent {
+    /// エンコードされた画像と、クライアントがキャッシュに保存するタイルのハッシュ
+    Encoded {
+        /// エンコードされた画像
+        image: EncodedImage,
+        /// 矩形に含まれるタイルのハッシュ（左から順）
+        tiles: Vec<u64>,
+    },
+    /// クライアントがキャッシュに持っているタイル
+    TileRef(u64),
+    /// 直前のフレーム内の領域のコピー（コピー元の位置、サイズは`EncodedRegion::rect`と同じ）
+    CopyRect {
+        /// コピー元のX座標
+        src_x: u32,
+        /// コピー元のY座標
+        src_y: u32,
+    },
+}
+
+/// 差分フレーム
+#[derive(Debug, Clone)]
+pub struct FrameUpdate {
+    /// フレーム番号
+    pub sequence: u64,
+    /// キーフレームかどうか
+    pub keyframe: bool,
+    /// 画面全体の幅
+    pub width: u32,
+    /// 画面全体の高さ
+    pub height: u32,
+    /// タイルの一辺（タイルキャッシュを使用しない場合は0）
+    pub tile_size: u32,
+    /// 変更された矩形
+    pub regions: Vec<EncodedRegion>,
+    /// 変更率（0.0～1.0）
+    pub change_ratio: f32,
+}
+
+impl FrameUpdate {
+    /// 変更がないかどうか
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+}
+
+/// 差分フレームのエンコーダ
+///
+/// 前回のフレームを保持するため、クライアント（セッション）ごとに作成します。
+pub struct UpdateEncoder {
+    /// 差分計算機
+    diff: DiffCalculator,
+    /// 矩形のエンコーダ
+    encoder: ImageEncoder,
+    /// キーフレーム間隔（フレーム数）
+    keyframe_interval: u32,
+    /// 最後のキーフレームからのフレーム数
+    frames_since_keyframe: u32,
+    /// 次のフレームをキーフレームにするかどうか
+    keyframe_requested: bool,
+    /// 最後に作成したフレーム番号
+    sequence: u64,
+    /// クライアントが持っているタイル
+    tile_cache: Option<TileCache<()>>,
+    /// 動画エンコーダ（クライアントがH.264に対応している場合のみ）
+    video: Option<VideoEncoder>,
+    /// 動画と静止画の切り替え
+    video_mode: VideoModeSwitch,
+    /// 文字やUIの矩形をQOIでエンコードするかどうか（クライアントが対応している場合のみ、それ以外はPNG）
+    qoi: bool,
+}
+
+impl UpdateEncoder {
+    /// 新しい差分フレームのエンコーダを作成
+    pub fn new(diff_config: DiffConfig, mut encoder: ImageEncoder, keyframe_interval: u32) -> Self {
+        // 矩形を縮小すると画面上の位置がずれるため、サイズ制限は使用しない
+        encoder.set_max_size(None, None);
+
+        Self {
+            diff: DiffCalculator::new(diff_config),
+            encoder,
+            keyframe_interval: keyframe_interval.max(1),
+            frames_since_keyframe: 0,
+            keyframe_requested: true,
+            sequence: 0,
+            tile_cache: None,
+            video: None,
+            video_mode: VideoModeSwitch::default(),
+            qoi: false,
+        }
+    }
+
+    /// 文字やUIの矩形のQOIでのエンコードを設定
+    pub fn set_qoi(&mut self, enabled: bool) {
+        self.qoi = enabled;
+    }
+
+    /// 動画への自動切り替えを設定
+    ///
+    /// このビルドで動画エンコードが使用できない場合は常に無効です。
+    pub fn set_video(&mut self, enabled: bool) {
+        if self.is_video_active() {
+            self.keyframe_requested = true;
+        }
+        self.video = (enabled && VideoEncoder::is_available()).then(VideoEncoder::default);
+        self.video_mode = VideoModeSwitch::default();
+    }
+
+    /// 動画でエンコードしているかどうか
+    pub fn is_video_active(&self) -> bool {
+        self.video.is_some() && self.video_mode.active
+    }
+
+    /// 動画エンコーダを取得（ビットレートの変更用）
+    pub fn video_mut(&mut self) -> Option<&mut VideoEncoder> {
+        self.video.as_mut()
+    }
+
+    /// タイルキャッシュを設定（`None`で無効）
+    ///
+    /// クライアントのキャッシュは次のキーフレームで空になるため、キーフレームを要求します。
+    pub fn set_tile_cache(&mut self, capacity: Option<usize>) {
+        self.tile_cache = capacity.map(TileCache::new);
+        self.keyframe_requested = true;
+    }
+
+    /// 矩形のエンコーダを取得（画質や形式の変更用）
+    pub fn encoder_mut(&mut self) -> &mut ImageEncoder {
+        &mut self.encoder
+    }
+
+    /// 次のフレームをキーフレームにする
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// キャプチャした画像から次のフレームを作成
+    ///
+    /// 前回から変更がない場合は矩形のないフレームを返します（フレーム番号は進みません）。
+    pub fn next_frame(&mut self, image: &CapturedImage) -> Result<FrameUpdate, EncoderError> {
+        let (width, height) = image.size();
+        let full_screen = Rectangle::new(0, 0, width, height);
+        let diff = self.diff.calculate(image);
+
+        // 変更率の高い状態が続いたら動画、低い状態が続いたら静止画に切り替える（切り替え時はキーフレーム）
+        if self.video.is_some() && self.video_mode.update(diff.change_ratio) {
+            self.keyframe_requested = true;
+        }
+
+        // 初回やサイズ変更時は差分計算機も画面全体を返す
+        let keyframe = self.keyframe_requested
+            || self.frames_since_keyframe + 1 >= self.keyframe_interval
+            || diff.changed_regions.contains(&full_screen);
+
+        let (moves, rects) = if keyframe {
+            (Vec::new(), vec![full_screen])
+        } else {
+            (diff.moved_regions, diff.changed_regions)
+        };
+
+        // キーフレームでは画面全体を送るため、差分の比較元と双方のタイルキャッシュを同期し直す
+        if keyframe {
+            self.diff.set_previous(image);
+            if let Some(cache) = &mut self.tile_cache {
+                cache.clear();
+            }
+        }
+
+        if self.is_video_active() {
+            let regions = self.encode_video(image, keyframe, !moves.is_empty() || !rects.is_empty())?;
+            return Ok(self.finish_frame(keyframe, width, height, regions, diff.change_ratio));
+        }
+
+        // コピーは直前のフレームを参照するため、他の矩形より先に適用させる
+        let mut regions: Vec<EncodedRegion> = moves
+            .into_iter()
+            .map(|copy| EncodedRegion {
+                rect: copy.dst,
+                content: RegionContent::CopyRect { src_x: copy.src.x, src_y: copy.src.y },
+            })
+            .collect();
+        if self.tile_cache.is_some() {
+            let pixels = match image.image.as_rgba8() {
+                Some(pixels) => Cow::Borrowed(pixels),
+                None => Cow::Owned(image.image.to_rgba8()),
+            };
+            for rect in rects {
+                self.encode_tiles(image, &pixels, rect, &mut regions)?;
+            }
+        } else {
+            for rect in rects {
+                if let Some(region) = self.encode_region(image, rect, Vec::new())? {
+                    regions.push(region);
+                }
+            }
+        }
+
+        Ok(self.finish_frame(keyframe, width, height, regions, diff.change_ratio))
+    }
+
+    /// フレーム番号とキーフレームの間隔を更新してフレームを作成
+    fn finish_frame(&mut self, keyframe: bool, width: u32, height: u32, regions: Vec<EncodedRegion>, change_ratio: f32) -> FrameUpdate {
+        if !regions.is_empty() {
+            self.sequence += 1;
+            if keyframe {
+                self.keyframe_requested = false;
+                self.frames_since_keyframe = 0;
+            } else {
+                self.frames_since_keyframe += 1;
+            }
+        }
+
+        FrameUpdate {
+            sequence: self.sequence,
+            keyframe,
+            width,
+            height,
+            tile_size: if self.tile_cache.is_some() { self.diff.config().block_size } else { 0 },
+            regions,
+            change_ratio: if keyframe { 1.0 } else { change_ratio },
+        }
+    }
+
+    /// 画面全体を動画としてエンコード（変更がなければ何も出力しない）
+    fn encode_video(&mut self, image: &CapturedImage, keyframe: bool, changed: bool) -> Result<Vec<EncodedRegion>, EncoderError> {
+        if !keyframe && !changed {
+            return Ok(Vec::new());
+        }
+
+        let video = self.video.as_mut().expect("動画エンコーダが無効です");
+        if keyframe {
+            video.request_keyframe();
+        }
+
+        // 動画は偶数のサイズに広げてエンコードされるが、矩形は画面の大きさのまま
+        let (width, height) = image.size();
+        let encoded = video.encode(image)?;
+        Ok(vec![EncodedRegion {
+            rect: Rectangle::new(0, 0, width, height),
+            content: RegionContent::Encoded {
+                image: encoded,
+                tiles: Vec::new(),
+            },
+        }])
+    }
+
+    /// 矩形を切り出してエンコード
+    fn encode_region(&self, image: &CapturedImage, rect: Rectangle, tiles: Vec<u64>) -> Result<Option<EncodedRegion>, EncoderError> {
+        let cropped = match image.crop(rect.x, rect.y, rect.width, rect.height) {
+            Some(cropped) => cropped,
+            None => return Ok(None),
+        };
+
+        // 文字やUIは可逆圧縮、写真などは設定された形式
+        let pixels = match cropped.image.as_rgba8() {
+            Some(pixels) => Cow::Borrowed(pixels),
+            None => Cow::Owned(cropped.image.to_rgba8()),
+        };
+        let encoded = if classify(&pixels).is_lossless() {
+            self.encoder.encode_as(&cropped, if self.qoi { ImageFormat::QOI } else { ImageFormat::PNG })?
+        } else {
+            self.encoder.encode(&cropped)?
+        };
+
+        let (width, height) = cropped.size();
+        Ok(Some(EncodedRegion {
+            rect: Rectangle::new(rect.x, rect.y, width, height),
+            content: RegionContent::Encoded {
+                image: encoded,
+                tiles,
+            },
+        }))
+    }
+
+    /// 矩形をタイルに分け、キャッシュにあるタイルは参照、ないタイルは行ごとにまとめてエンコード
+    ///
+    /// クライアントは矩形を受信した順にキャッシュを更新するため、
+    /// ここでもキャッシュの操作と同じ順序で矩形を出力します。
+    fn encode_tiles(&mut self, image: &CapturedImage, pixels: &RgbaImage, rect: Rectangle, regions: &mut Vec<EncodedRegion>) -> Result<(), EncoderError> {
+        let tile_size = self.diff.config().block_size;
+        let right = rect.x + rect.width;
+        let bottom = rect.y + rect.height;
+
+        for y in (rect.y..bottom).step_by(tile_size as usize) {
+            let height = tile_size.min(bottom - y);
+            // まだ送信していない、連続したタイル（開始X座標とハッシュ）
+            let mut run: Option<(u32, Vec<u64>)> = None;
+
+            for x in (rect.x..right).step_by(tile_size as usize) {
+                let width = tile_size.min(right - x);
+                let hash = tile_hash(pixels, Rectangle::new(x, y, width, height));
+                let cache = self.tile_cache.as_mut().expect("タイルキャッシュが無効です");
+
+                if cache.get(hash).is_some() {
+                    if let Some((start, tiles)) = run.take() {
+                        let region = Rectangle::new(start, y, x - start, height);
+                        regions.extend(self.encode_region(image, region, tiles)?);
+                    }
+                    regions.push(EncodedRegion {
+                        rect: Rectangle::new(x, y, width, height),
+                        content: RegionContent::TileRef(hash),
+                    });
+                } else {
+                    cache.insert(hash, ());
+                    run.get_or_insert_with(|| (x, Vec::new())).1.push(hash);
+                }
+            }
+
+            if let Some((start, tiles)) = run.take() {
+                let region = Rectangle::new(start, y, right - start, height);
+                regions.extend(self.encode_region(image, region, tiles)?);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// タイルの画素からハッシュを計算
+fn tile_hash(pixels: &RgbaImage, tile: Rectangle) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u32(tile.width);
+    hasher.write_u32(tile.height);
+
+    let stride = pixels.width() as usize * 4;
+    let raw = pixels.as_raw();
+    for y in tile.y..tile.y + tile.height {
+        let start = y as usize * stride + tile.x as usize * 4;
+        hasher.write(&raw[start..start + tile.width as usize * 4]);
+    }
+
+    hasher.finish() & TILE_HASH_MASK
+}
+
+/// 変更率の推移による動画と静止画の切り替え
+#[derive(Debug, Clone, Default)]
+struct VideoModeSwitch {
+    /// 動画でエンコードしているかどうか
+    active: bool,
+    /// 現在のモードと逆の傾向のフレームが続いた数
+    streak: u32,
+}
+
+impl VideoModeSwitch {
+    /// フレームの変更率を記録し、モードが切り替わった場合は`true`を返す
+    fn update(&mut self, change_ratio: f32) -> bool {
+        let high = change_ratio >= VIDEO_CHANGE_RATIO;
+        if high == self.active {
+            self.streak = 0;
+            return false;
+        }
+
+        self.streak += 1;
+        let limit = if self.active { VIDEO_EXIT_FRAMES } else { VIDEO_ENTER_FRAMES };
+        if self.streak < limit {
+            return false;
+        }
+
+        self.active = !self.active;
+        self.streak = 0;
+        true
+    }
+}
+
+impl Default for UpdateEncoder {
+    fn default() -> Self {
+        Self::new(DiffConfig::default(), ImageEncoder::default(), DEFAULT_KEYFRAME_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer, Rgba};
+
+    // 指定した矩形だけ色を変えたテスト用の画像を作成
+    fn create_test_image(width: u32, height: u32, patch: Option<Rectangle>) -> CapturedImage {
+        let img = ImageBuffer::from_fn(width, height, |x, y| match patch {
+            Some(r) if x >= r.x && x < r.x + r.width && y >= r.y && y < r.y + r.height => Rgba([0, 0, 255, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        CapturedImage::new(DynamicImage::ImageRgba8(img), 0)
+    }
+
+    #[test]
+    fn test_only_changed_regions_are_encoded() {
+        let mut encoder = UpdateEncoder::default();
+
+        // 初回はキーフレーム
+        let first = encoder.next_frame(&create_test_image(128, 96, None)).unwrap();
+        assert!(first.keyframe);
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.regions.len(), 1);
+        assert_eq!(first.regions[0].rect, Rectangle::new(0, 0, 128, 96));
+
+        // 変更がなければ矩形なし
+        let unchanged = encoder.next_frame(&create_test_image(128, 96, None)).unwrap();
+        assert!(unchanged.is_empty());
+        assert_eq!(unchanged.sequence, 1);
+
+        // 変更されたブロックだけを送る
+        let patch = Rectangle::new(40, 40, 10, 10);
+        let update = encoder.next_frame(&create_test_image(128, 96, Some(patch))).unwrap();
+        assert!(!update.keyframe);
+        assert_eq!(update.sequence, 2);
+        assert_eq!(update.regions.len(), 1);
+        assert_eq!(update.regions[0].rect, Rectangle::new(32, 32, 32, 32));
+        assert!(matches!(&update.regions[0].content, RegionContent::Encoded { image, tiles } if !image.data.is_empty() && tiles.is_empty()));
+    }
+
+    #[test]
+    fn test_keyframes_are_sent_periodically_and_on_request() {
+        let mut encoder = UpdateEncoder::new(DiffConfig::default(), ImageEncoder::default(), 3);
+        let blank = create_test_image(64, 64, None);
+        let patched = create_test_image(64, 64, Some(Rectangle::new(0, 0, 16, 16)));
+
+        assert!(encoder.next_frame(&blank).unwrap().keyframe);
+        assert!(!encoder.next_frame(&patched).unwrap().keyframe);
+        assert!(!encoder.next_frame(&blank).unwrap().keyframe);
+        assert!(encoder.next_frame(&patched).unwrap().keyframe);
+
+        // 要求があれば変更がなくてもキーフレームを送る
+        encoder.request_keyframe();
+        let keyframe = encoder.next_frame(&patched).unwrap();
+        assert!(keyframe.keyframe);
+        assert_eq!(keyframe.regions[0].rect, Rectangle::new(0, 0, 64, 64));
+    }
+
+    #[test]
+    fn test_cached_tiles_are_sent_as_references() {
+        let mut encoder = UpdateEncoder::default();
+        encoder.set_tile_cache(Some(64));
+
+        // 白い画面の最初のタイルだけを送り、残りは同じタイルへの参照になる
+        let keyframe = encoder.next_frame(&create_test_image(96, 32, None)).unwrap();
+        assert!(keyframe.keyframe);
+        assert_eq!(keyframe.tile_size, 32);
+        assert_eq!(keyframe.regions.len(), 3);
+        let blank = match &keyframe.regions[0].content {
+            RegionContent::Encoded { tiles, .. } => tiles[0],
+            other => panic!("unexpected content: {:?}", other),
+        };
+        assert!(keyframe.regions[1..].iter().all(|region| matches!(region.content, RegionContent::TileRef(hash) if hash == blank)));
+
+        // 新しい内容は画素で送り、元に戻した内容は参照で送る
+        let patch = Rectangle::new(32, 0, 32, 32);
+        let update = encoder.next_frame(&create_test_image(96, 32, Some(patch))).unwrap();
+        assert_eq!(update.regions.len(), 1);
+        assert_eq!(update.regions[0].rect, patch);
+        assert!(matches!(&update.regions[0].content, RegionContent::Encoded { tiles, .. } if tiles.len() == 1 && tiles[0] != blank));
+
+        let restored = encoder.next_frame(&create_test_image(96, 32, None)).unwrap();
+        assert!(matches!(restored.regions[0].content, RegionContent::TileRef(hash) if hash == blank));
+    }
+
+    #[test]
+    fn test_scrolled_content_is_sent_as_copy() {
+        let mut encoder = UpdateEncoder::default();
+        let striped = |offset: u32| {
+            let img = ImageBuffer::from_fn(96, 128, |_, y| {
+                let line = y + offset;
+                Rgba([(line * 3) as u8, (line * 7) as u8, (line * 11) as u8, 255])
+            });
+            CapturedImage::new(DynamicImage::ImageRgba8(img), 0)
+        };
+
+        assert!(encoder.next_frame(&striped(0)).unwrap().keyframe);
+
+        // 8ピクセル下にスクロールすると、コピーと下端の新しい部分だけを送る
+        let update = encoder.next_frame(&striped(8)).unwrap();
+        assert!(!update.keyframe);
+        assert_eq!(update.regions.len(), 2);
+        assert_eq!(update.regions[0].rect, Rectangle::new(0, 0, 96, 120));
+        assert!(matches!(update.regions[0].content, RegionContent::CopyRect { src_x: 0, src_y: 8 }));
+        assert_eq!(update.regions[1].rect, Rectangle::new(0, 96, 96, 32));
+        assert!(matches!(update.regions[1].content, RegionContent::Encoded { .. }));
+    }
+
+    #[test]
+    fn test_video_mode_follows_sustained_motion() {
+        let mut mode = VideoModeSwitch::default();
+
+        // 一時的な大きな変更では切り替えない
+        assert!(!mode.update(1.0));
+        assert!(!mode.update(0.0));
+        assert!((1..VIDEO_ENTER_FRAMES).all(|_| !mode.update(0.8)));
+        assert!(!mode.active);
+
+        assert!(mode.update(0.8));
+        assert!(mode.active);
+
+        // 変更が落ち着いたら静止画に戻る
+        assert!((1..VIDEO_EXIT_FRAMES).all(|_| !mode.update(0.01)));
+        assert!(mode.update(0.01));
+        assert!(!mode.active);
+    }
+
+    #[test]
+    fn test_text_is_lossless_and_photo_is_lossy_in_one_frame() {
+        let mut encoder = UpdateEncoder::default();
+        encoder.set_qoi(true);
+        encoder.next_frame(&create_test_image(128, 64, None)).unwrap();
+
+        // 左に文字、右に写真のような濃淡を描画
+        let mut seed = 1u32;
+        let img = ImageBuffer::from_fn(128, 64, |x, y| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (seed >> 16) as u8 % 32;
+            if x >= 96 {
+                Rgba([x as u8 + noise, (y * 2) as u8 + noise, 100 + noise, 255])
+            } else if x < 32 && (x + y) % 3 == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let update = encoder.next_frame(&CapturedImage::new(DynamicImage::ImageRgba8(img), 0)).unwrap();
+
+        let formats: Vec<_> = update.regions
+            .iter()
+            .map(|region| match &region.content {
+                RegionContent::Encoded { image, .. } => (region.rect.x, image.format),
+                _ => panic!("エンコードされた矩形がありません"),
+            })
+            .collect();
+        assert_eq!(formats, vec![(0, ImageFormat::QOI), (96, ImageFormat::JPEG)]);
+
+        // QOIに対応していなければ文字はPNG
+        encoder.set_qoi(false);
+        encoder.request_keyframe();
+        let keyframe = encoder.next_frame(&create_test_image(128, 64, Some(Rectangle::new(0, 0, 8, 8)))).unwrap();
+        assert!(matches!(&keyframe.regions[0].content, RegionContent::Encoded { image, .. } if image.format == ImageFormat::PNG));
+    }
+
+    #[test]
+    fn test_synthetic_source_end_to_end() {
+        use crate::capture::{CaptureSource, TestPatternSource};
+
+        let mut source = TestPatternSource::new(320, 240);
+        let mut encoder = UpdateEncoder::default();
+        encoder.set_qoi(true);
+
+        let keyframe = encoder.next_frame(&source.capture_monitor(0).unwrap()).unwrap();
+        assert!(keyframe.keyframe);
+        assert_eq!(keyframe.regions[0].rect, Rectangle::new(0, 0, 320, 240));
+
+        // 動く四角形、スクロールする文字、時計の部分だけを送る
+        for _ in 0..5 {
+            let update = encoder.next_frame(&source.capture_monitor(0).unwrap()).unwrap();
+            assert!(!update.keyframe);
+            assert!(!update.is_empty());
+            assert!(update.change_ratio < 1.0);
+            assert!(update.regions.iter().all(|r| r.rect.x + r.rect.width <= 320 && r.rect.y + r.rect.height <= 240));
+        }
+
+        // 同じフレームをもう一度キャプチャしても変更はない
+        source.seek(source.frame() - 1);
+        assert!(encoder.next_frame(&source.capture_monitor(0).unwrap()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,398 @@
+//! X11のスクリーンキャプチャ
+//!
+//! モニターの一覧はXRandR（1.5の`GetMonitors`）から取得し、画面はMIT-SHMの
+//! `GetImage`で共有メモリに直接書き込ませて取得します。X11サーバーが別のホストや
+//! 別のコンテナにあって共有メモリを使えない場合は、通常の`GetImage`で取得します。
+
+use super::{CaptureError, Monitor};
+use image::RgbaImage;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat, ImageOrder, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+/// 取得する画像のビット数（24ビットと32ビットの深さはどちらも1画素32ビット）
+const BITS_PER_PIXEL: u8 = 32;
+
+/// X11のスクリーンキャプチャ
+pub struct X11Capture {
+    /// X11サーバーへの接続
+    conn: RustConnection,
+    /// ルートウィンドウ
+    root: Window,
+    /// 画素のバイト順が下位バイトから（BGRX）かどうか
+    lsb_first: bool,
+    /// MIT-SHMを使用するかどうか
+    use_shm: bool,
+    /// 共有メモリのセグメント（最初のキャプチャで確保）
+    segment: Option<ShmSegment>,
+    /// モニターの一覧（画面構成の変更を通知されたら取得し直す）
+    monitors: Option<Vec<Monitor>>,
+    /// ルートウィンドウのサイズ（モニターの一覧と同時に取得し直す）
+    root_size: Option<(u32, u32)>,
+}
+
+impl X11Capture {
+    /// X11サーバーに接続（`None`の場合は環境変数DISPLAYのサーバー）
+    pub fn connect(display: Option<&str>) -> Result<Self, CaptureError> {
+        let (conn, screen_num) = x11rb::connect(display)
+            .map_err(|e| CaptureError::DeviceError(format!("X11接続エラー: {}", e)))?;
+
+        let setup = conn.setup();
+        let screen = &setup.roots[screen_num];
+        let bits_per_pixel = setup.pixmap_formats.iter()
+            .find(|f| f.depth == screen.root_depth)
+            .map(|f| f.bits_per_pixel);
+        if bits_per_pixel != Some(BITS_PER_PIXEL) {
+            return Err(CaptureError::DeviceError(format!(
+                "サポートされていない画素形式です（深さ{}）", screen.root_depth
+            )));
+        }
+
+        let root = screen.root;
+        let lsb_first = setup.image_byte_order == ImageOrder::LSB_FIRST;
+        let use_shm = conn.extension_information(shm::X11_EXTENSION_NAME)
+            .map_err(x11_error)?
+            .is_some();
+
+        // 画面構成が変わったらモニターの一覧を取得し直すため、変更を通知させる
+        if conn.extension_information(randr::X11_EXTENSION_NAME).map_err(x11_error)?.is_some() {
+            conn.randr_select_input(root, randr::NotifyMask::SCREEN_CHANGE).map_err(x11_error)?;
+        }
+
+        Ok(Self {
+            conn,
+            root,
+            lsb_first,
+            use_shm,
+            segment: None,
+            monitors: None,
+            root_size: None,
+        })
+    }
+
+    /// MIT-SHMを使用しているかどうか
+    pub fn is_shm_enabled(&self) -> bool {
+        self.use_shm
+    }
+
+    /// MIT-SHMの使用をやめ、通常の`GetImage`で取得する
+    pub fn disable_shm(&mut self) {
+        self.use_shm = false;
+        if let Some(segment) = self.segment.take() {
+            segment.release(&self.conn);
+        }
+    }
+
+    /// モニターの一覧をX11サーバーから取得し直す（プライマリモニターが先頭）
+    ///
+    /// XRandR 1.5が使えない場合は、画面全体を1つのモニターとして返します。
+    /// 取得した一覧は`cached_monitors`でも使用します。
+    pub fn monitors(&mut self) -> Result<Vec<Monitor>, CaptureError> {
+        let monitors = self.query_monitors()?;
+        self.monitors = Some(monitors.clone());
+        Ok(monitors)
+    }
+
+    /// キャッシュしたモニターの一覧を取得
+    ///
+    /// フレームごとにXRandRに問い合わせないよう、`RRScreenChangeNotify`を受け取るまでは
+    /// 前回取得した一覧を返します。
+    pub fn cached_monitors(&mut self) -> Result<&[Monitor], CaptureError> {
+        self.poll_screen_change()?;
+
+        let monitors = match self.monitors.take() {
+            Some(monitors) => monitors,
+            None => self.query_monitors()?,
+        };
+        Ok(self.monitors.insert(monitors))
+    }
+
+    /// `RRScreenChangeNotify`を受け取っていたら、キャッシュしたモニターの一覧と画面サイズを破棄
+    fn poll_screen_change(&mut self) -> Result<(), CaptureError> {
+        while let Some(event) = self.conn.poll_for_event().map_err(x11_error)? {
+            if let Event::RandrScreenChangeNotify(_) = event {
+                self.monitors = None;
+                self.root_size = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// モニターの一覧をX11サーバーに問い合わせる
+    fn query_monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        let mut monitors = match self.randr_monitors()? {
+            Some(monitors) if !monitors.is_empty() => monitors,
+            _ => {
+                let (width, height) = self.screen_size()?;
+                vec![Monitor::new(0, (0, 0), (width, height), true, "Primary Display".to_string())]
+            },
+        };
+
+        // プライマリーモニターが先頭になるようにソート
+        monitors.sort_by_key(|m| !m.is_primary);
+
+        // インデックスを更新
+        for (i, monitor) in monitors.iter_mut().enumerate() {
+            monitor.index = i;
+            monitor.handle = self.root as usize;
+        }
+
+        Ok(monitors)
+    }
+
+    /// XRandRからモニターの一覧を取得
+    fn randr_monitors(&self) -> Result<Option<Vec<Monitor>>, CaptureError> {
+        if self.conn.extension_information(randr::X11_EXTENSION_NAME).map_err(x11_error)?.is_none() {
+            return Ok(None);
+        }
+
+        let version = self.conn.randr_query_version(1, 5)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if (version.major_version, version.minor_version) < (1, 5) {
+            return Ok(None);
+        }
+
+        let reply = self.conn.randr_get_monitors(self.root, true)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let mut monitors = Vec::with_capacity(reply.monitors.len());
+        for (i, info) in reply.monitors.iter().enumerate() {
+            let name = self.conn.get_atom_name(info.name)
+                .map_err(x11_error)?
+                .reply()
+                .map(|r| String::from_utf8_lossy(&r.name).into_owned())
+                .unwrap_or_else(|_| format!("Monitor {}", i));
+
+            monitors.push(Monitor::new(
+                i,
+                (info.x as i32, info.y as i32),
+                (info.width as u32, info.height as u32),
+                info.primary,
+                name,
+            ));
+        }
+
+        // プライマリが設定されていない場合は最初のモニターをプライマリとする
+        if !monitors.iter().any(|m| m.is_primary) {
+            if let Some(first) = monitors.first_mut() {
+                first.is_primary = true;
+            }
+        }
+
+        Ok(Some(monitors))
+    }
+
+    /// 画面全体のサイズを取得
+    pub fn screen_size(&self) -> Result<(u32, u32), CaptureError> {
+        let geometry = self.conn.get_geometry(self.root)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        Ok((geometry.width as u32, geometry.height as u32))
+    }
+
+    /// キャッシュした画面全体のサイズを取得
+    ///
+    /// `cached_monitors`と同じく、`RRScreenChangeNotify`を受け取るまでは前回の値を返します。
+    fn cached_screen_size(&mut self) -> Result<(u32, u32), CaptureError> {
+        self.poll_screen_change()?;
+
+        match self.root_size {
+            Some(size) => Ok(size),
+            None => Ok(*self.root_size.insert(self.screen_size()?)),
+        }
+    }
+
+    /// 画面の領域をキャプチャ
+    pub fn capture(&mut self, x: i32, y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+        // 画面外を指定するとBadMatchになるため、画面内に収める
+        let (screen_width, screen_height) = self.cached_screen_size()?;
+        let x = x.clamp(0, screen_width as i32 - 1);
+        let y = y.clamp(0, screen_height as i32 - 1);
+        let width = width.min(screen_width - x as u32);
+        let height = height.min(screen_height - y as u32);
+        if width == 0 || height == 0 {
+            return Err(CaptureError::MonitorError(format!("キャプチャ領域が空です: {}x{}", width, height)));
+        }
+
+        let data = if self.use_shm {
+            match self.shm_get_image(x, y, width, height) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("MIT-SHMでのキャプチャに失敗したため、GetImageに切り替えます: {}", e);
+                    self.disable_shm();
+                    self.get_image(x, y, width, height)?
+                },
+            }
+        } else {
+            self.get_image(x, y, width, height)?
+        };
+
+        RgbaImage::from_raw(width, height, data)
+            .ok_or_else(|| CaptureError::ImageConversionError("画像バッファの作成に失敗しました".to_string()))
+    }
+
+    /// 通常の`GetImage`で取得してRGBAに変換
+    fn get_image(&self, x: i32, y: i32, width: u32, height: u32) -> Result<Vec<u8>, CaptureError> {
+        let reply = self.conn.get_image(
+            ImageFormat::Z_PIXMAP,
+            self.root,
+            x as i16, y as i16,
+            width as u16, height as u16,
+            !0, // プレーンマスク（すべて）
+        )
+        .map_err(|e| CaptureError::ProcessError(format!("X11 get_image失敗: {}", e)))?
+        .reply()
+        .map_err(|e| CaptureError::ProcessError(format!("X11 reply失敗: {}", e)))?;
+
+        Ok(to_rgba(&reply.data, self.lsb_first))
+    }
+
+    /// MIT-SHMの`GetImage`で取得してRGBAに変換（共有メモリから直接変換する）
+    fn shm_get_image(&mut self, x: i32, y: i32, width: u32, height: u32) -> Result<Vec<u8>, CaptureError> {
+        let size = width as usize * height as usize * (BITS_PER_PIXEL / 8) as usize;
+
+        if self.segment.as_ref().is_none_or(|s| s.size < size) {
+            if let Some(segment) = self.segment.take() {
+                segment.release(&self.conn);
+            }
+            self.segment = Some(ShmSegment::new(&self.conn, size)?);
+        }
+        let segment = self.segment.as_ref().expect("segment is allocated above");
+
+        let reply = self.conn.shm_get_image(
+            self.root,
+            x as i16, y as i16,
+            width as u16, height as u16,
+            !0,
+            ImageFormat::Z_PIXMAP.into(),
+            segment.seg,
+            0,
+        )
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+
+        if (reply.size as usize) < size {
+            return Err(CaptureError::ProcessError(format!("共有メモリの画像サイズが不正です: {}", reply.size)));
+        }
+
+        Ok(to_rgba(&segment.as_slice()[..size], self.lsb_first))
+    }
+}
+
+impl Drop for X11Capture {
+    fn drop(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            segment.release(&self.conn);
+        }
+    }
+}
+
+/// MIT-SHMの共有メモリセグメント
+struct ShmSegment {
+    /// X11側のセグメントID
+    seg: shm::Seg,
+    /// 共有メモリの先頭
+    addr: *mut u8,
+    /// 共有メモリのサイズ
+    size: usize,
+}
+
+// 共有メモリは`ShmSegment`が排他的に所有しており、スレッド間で移動しても問題ない
+unsafe impl Send for ShmSegment {}
+
+impl ShmSegment {
+    /// 共有メモリを確保してX11サーバーにアタッチ
+    fn new(conn: &RustConnection, size: usize) -> Result<Self, CaptureError> {
+        let seg = conn.generate_id().map_err(x11_error)?;
+
+        // SAFETY: 確保したセグメントはアタッチ後にIPC_RMIDで削除予約し、
+        // `release`でデタッチするまで`addr`から`size`バイトが有効
+        unsafe {
+            let shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shmid < 0 {
+                return Err(CaptureError::DeviceError("共有メモリの確保に失敗しました".to_string()));
+            }
+
+            let addr = libc::shmat(shmid, std::ptr::null(), 0);
+            if addr as isize == -1 {
+                libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut());
+                return Err(CaptureError::DeviceError("共有メモリのアタッチに失敗しました".to_string()));
+            }
+
+            let attached = conn.shm_attach(seg, shmid as u32, false)
+                .map_err(x11_error)
+                .and_then(|cookie| cookie.check().map_err(x11_error));
+
+            // X11サーバーがアタッチした後（または失敗した後）は削除予約してよい
+            libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut());
+
+            if let Err(e) = attached {
+                libc::shmdt(addr);
+                return Err(e);
+            }
+
+            Ok(Self { seg, addr: addr as *mut u8, size })
+        }
+    }
+
+    /// 共有メモリの内容
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: `new`で確保した`size`バイトの領域
+        unsafe { std::slice::from_raw_parts(self.addr, self.size) }
+    }
+
+    /// X11サーバーからデタッチして解放
+    fn release(self, conn: &RustConnection) {
+        let _ = conn.shm_detach(self.seg).map(|cookie| cookie.ignore_error());
+        let _ = conn.flush();
+
+        // SAFETY: `new`でアタッチした領域
+        unsafe {
+            libc::shmdt(self.addr as *const libc::c_void);
+        }
+    }
+}
+
+/// X11のエラーをキャプチャエラーに変換
+fn x11_error<E: std::fmt::Display>(e: E) -> CaptureError {
+    CaptureError::DeviceError(e.to_string())
+}
+
+/// X11の32ビット画素（BGRXまたはXRGB）をRGBAに変換
+///
+/// 深さ24のルートウィンドウではXの部分が不定のため、アルファは常に255にします。
+fn to_rgba(data: &[u8], lsb_first: bool) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(data.len());
+    for px in data.chunks_exact(4) {
+        if lsb_first {
+            rgba.extend_from_slice(&[px[2], px[1], px[0], 255]);
+        } else {
+            rgba.extend_from_slice(&[px[1], px[2], px[3], 255]);
+        }
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba() {
+        let bgrx = [0x10, 0x20, 0x30, 0x00, 0xFF, 0x00, 0x00, 0x7F];
+        assert_eq!(to_rgba(&bgrx, true), vec![0x30, 0x20, 0x10, 255, 0x00, 0x00, 0xFF, 255]);
+
+        let xrgb = [0x00, 0x30, 0x20, 0x10];
+        assert_eq!(to_rgba(&xrgb, false), vec![0x30, 0x20, 0x10, 255]);
+    }
+}
@@ -2,14 +2,15 @@
 //!
 //! リモートデスクトップサーバーにおけるキーボード入力処理を実装します。
 
-use super::{system::{SystemInput, SystemKey, SystemKeyModifier, SystemError}, InputError, KeyboardConfig};
+use super::{sink::InputSink, system::{SystemInput, SystemKey, SystemKeyModifier, SystemError}, InputError, KeyboardConfig};
+use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
 
 /// キーボード入力処理
 pub struct KeyboardHandler {
-    /// システム入力
-    system: SystemInput,
+    /// 入力の出力先
+    sink: Arc<dyn InputSink>,
     /// キーボード設定
     config: KeyboardConfig,
     /// キーマッピング
@@ -22,11 +23,16 @@ impl KeyboardHandler {
         let system = SystemInput::new()
             .map_err(|e| InputError::SystemError(e.to_string()))?;
         
-        Ok(Self {
-            system,
+        Ok(Self::with_sink(Arc::new(system)))
+    }
+    
+    /// 入力の出力先を指定してキーボードハンドラを作成
+    pub fn with_sink(sink: Arc<dyn InputSink>) -> Self {
+        Self {
+            sink,
             config: KeyboardConfig::default(),
             key_map: KeyMapping::new(),
-        })
+        }
     }
     
     /// 設定を更新
@@ -58,12 +64,12 @@ impl KeyboardHandler {
         // 修飾キーを押下
         for modifier in modifiers {
             let modifier_key = self.modifier_to_key(*modifier);
-            self.system.key_down(modifier_key)
+            self.sink.key_down(modifier_key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
         }
         
         // キーを押下
-        self.system.key_down(key)
+        self.sink.key_down(key)
             .map_err(|e| InputError::SystemError(e.to_string()))?;
         
         Ok(())
@@ -72,13 +78,13 @@ impl KeyboardHandler {
     /// キーを解放
     pub fn key_up(&self, key: SystemKey, modifiers: &[SystemKeyModifier]) -> Result<(), InputError> {
         // キーを解放
-        self.system.key_up(key)
+        self.sink.key_up(key)
             .map_err(|e| InputError::SystemError(e.to_string()))?;
         
         // 修飾キーを解放
         for modifier in modifiers {
             let modifier_key = self.modifier_to_key(*modifier);
-            self.system.key_up(modifier_key)
+            self.sink.key_up(modifier_key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
         }
         
@@ -87,7 +93,7 @@ impl KeyboardHandler {
     
     /// テキストを入力
     pub fn input_text(&self, text: &str) -> Result<(), InputError> {
-        self.system.input_text(text)
+        self.sink.input_text(text)
             .map_err(|e| InputError::SystemError(e.to_string()))
     }
     
@@ -101,16 +107,16 @@ impl KeyboardHandler {
         // 修飾キーを押下
         for modifier in modifiers {
             let modifier_key = self.modifier_to_key(*modifier);
-            self.system.key_down(modifier_key)
+            self.sink.key_down(modifier_key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
         }
         
         // 各キーを押下して解放
         for key in keys {
-            self.system.key_down(*key)
+            self.sink.key_down(*key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
             thread::sleep(Duration::from_millis(10));
-            self.system.key_up(*key)
+            self.sink.key_up(*key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
             thread::sleep(Duration::from_millis(10));
         }
@@ -118,7 +124,7 @@ impl KeyboardHandler {
         // 修飾キーを解放
         for modifier in modifiers {
             let modifier_key = self.modifier_to_key(*modifier);
-            self.system.key_up(modifier_key)
+            self.sink.key_up(modifier_key)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
         }
         
@@ -0,0 +1,255 @@
+//! キーコードの変換表
+//!
+//! クライアントから届くキーコード（`SystemKey`）はWindowsの仮想キーコードです。
+//! Windows以外のバックエンドは、ここの変換表でそれぞれのキーコードに変換します。
+
+/// 仮想キーコードをX11のキーシムに変換
+pub fn vk_to_keysym(vk: u32) -> Option<u32> {
+    let keysym = match vk {
+        0x08 => 0xFF08, // BackSpace
+        0x09 => 0xFF09, // Tab
+        0x0D => 0xFF0D, // Return
+        0x10 | 0xA0 => 0xFFE1, // Shift_L
+        0xA1 => 0xFFE2, // Shift_R
+        0x11 | 0xA2 => 0xFFE3, // Control_L
+        0xA3 => 0xFFE4, // Control_R
+        0x12 | 0xA4 => 0xFFE9, // Alt_L
+        0xA5 => 0xFFEA, // Alt_R
+        0x13 => 0xFF13, // Pause
+        0x14 => 0xFFE5, // Caps_Lock
+        0x1B => 0xFF1B, // Escape
+        0x20 => 0x0020, // space
+        0x21 => 0xFF55, // Prior
+        0x22 => 0xFF56, // Next
+        0x23 => 0xFF57, // End
+        0x24 => 0xFF50, // Home
+        0x25 => 0xFF51, // Left
+        0x26 => 0xFF52, // Up
+        0x27 => 0xFF53, // Right
+        0x28 => 0xFF54, // Down
+        0x2C => 0xFF61, // Print
+        0x2D => 0xFF63, // Insert
+        0x2E => 0xFFFF, // Delete
+        0x30..=0x39 => vk, // 0-9
+        0x41..=0x5A => vk + 0x20, // a-z（小文字のキーシム）
+        0x5B => 0xFFEB, // Super_L
+        0x5C => 0xFFEC, // Super_R
+        0x5D => 0xFF67, // Menu
+        0x60..=0x69 => 0xFFB0 + (vk - 0x60), // KP_0-KP_9
+        0x6A => 0xFFAA, // KP_Multiply
+        0x6B => 0xFFAB, // KP_Add
+        0x6C => 0xFFAC, // KP_Separator
+        0x6D => 0xFFAD, // KP_Subtract
+        0x6E => 0xFFAE, // KP_Decimal
+        0x6F => 0xFFAF, // KP_Divide
+        0x70..=0x87 => 0xFFBE + (vk - 0x70), // F1-F24
+        0x90 => 0xFF7F, // Num_Lock
+        0x91 => 0xFF14, // Scroll_Lock
+        0xBA => 0x003B, // semicolon
+        0xBB => 0x003D, // equal
+        0xBC => 0x002C, // comma
+        0xBD => 0x002D, // minus
+        0xBE => 0x002E, // period
+        0xBF => 0x002F, // slash
+        0xC0 => 0x0060, // grave
+        0xDB => 0x005B, // bracketleft
+        0xDC => 0x005C, // backslash
+        0xDD => 0x005D, // bracketright
+        0xDE => 0x0027, // apostrophe
+        _ => return None,
+    };
+    Some(keysym)
+}
+
+/// 文字をX11のキーシムに変換
+///
+/// Latin-1の文字はコードポイントがそのままキーシムになり、
+/// それ以外のUnicode文字は`0x01000000`を足した値になります。
+pub fn char_to_keysym(c: char) -> Option<u32> {
+    let code = c as u32;
+    match c {
+        '\n' | '\r' => Some(0xFF0D), // Return
+        '\t' => Some(0xFF09), // Tab
+        '\u{8}' => Some(0xFF08), // BackSpace
+        '\u{1B}' => Some(0xFF1B), // Escape
+        '\u{20}'..='\u{7E}' | '\u{A0}'..='\u{FF}' => Some(code),
+        _ if c.is_control() => None,
+        _ => Some(0x0100_0000 | code),
+    }
+}
+
+/// 仮想キーコードをLinuxのevdevのキーコード（`KEY_*`）に変換
+pub fn vk_to_evdev(vk: u32) -> Option<u16> {
+    /// A-Zのキーコード（`KEY_A`から`KEY_Z`）
+    const LETTERS: [u16; 26] = [
+        30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50,
+        49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45, 21, 44,
+    ];
+
+    let code = match vk {
+        0x08 => 14, // KEY_BACKSPACE
+        0x09 => 15, // KEY_TAB
+        0x0D => 28, // KEY_ENTER
+        0x10 | 0xA0 => 42, // KEY_LEFTSHIFT
+        0xA1 => 54, // KEY_RIGHTSHIFT
+        0x11 | 0xA2 => 29, // KEY_LEFTCTRL
+        0xA3 => 97, // KEY_RIGHTCTRL
+        0x12 | 0xA4 => 56, // KEY_LEFTALT
+        0xA5 => 100, // KEY_RIGHTALT
+        0x13 => 119, // KEY_PAUSE
+        0x14 => 58, // KEY_CAPSLOCK
+        0x1B => 1, // KEY_ESC
+        0x20 => 57, // KEY_SPACE
+        0x21 => 104, // KEY_PAGEUP
+        0x22 => 109, // KEY_PAGEDOWN
+        0x23 => 107, // KEY_END
+        0x24 => 102, // KEY_HOME
+        0x25 => 105, // KEY_LEFT
+        0x26 => 103, // KEY_UP
+        0x27 => 106, // KEY_RIGHT
+        0x28 => 108, // KEY_DOWN
+        0x2C => 99, // KEY_SYSRQ
+        0x2D => 110, // KEY_INSERT
+        0x2E => 111, // KEY_DELETE
+        0x30 => 11, // KEY_0
+        0x31..=0x39 => 2 + (vk - 0x31) as u16, // KEY_1-KEY_9
+        0x41..=0x5A => LETTERS[(vk - 0x41) as usize],
+        0x5B => 125, // KEY_LEFTMETA
+        0x5C => 126, // KEY_RIGHTMETA
+        0x5D => 127, // KEY_COMPOSE
+        0x60 => 82, // KEY_KP0
+        0x61 => 79, // KEY_KP1
+        0x62 => 80, // KEY_KP2
+        0x63 => 81, // KEY_KP3
+        0x64 => 75, // KEY_KP4
+        0x65 => 76, // KEY_KP5
+        0x66 => 77, // KEY_KP6
+        0x67 => 71, // KEY_KP7
+        0x68 => 72, // KEY_KP8
+        0x69 => 73, // KEY_KP9
+        0x6A => 55, // KEY_KPASTERISK
+        0x6B => 78, // KEY_KPPLUS
+        0x6C => 121, // KEY_KPCOMMA
+        0x6D => 74, // KEY_KPMINUS
+        0x6E => 83, // KEY_KPDOT
+        0x6F => 98, // KEY_KPSLASH
+        0x70..=0x79 => 59 + (vk - 0x70) as u16, // KEY_F1-KEY_F10
+        0x7A => 87, // KEY_F11
+        0x7B => 88, // KEY_F12
+        0x7C..=0x87 => 183 + (vk - 0x7C) as u16, // KEY_F13-KEY_F24
+        0x90 => 69, // KEY_NUMLOCK
+        0x91 => 70, // KEY_SCROLLLOCK
+        0xBA => 39, // KEY_SEMICOLON
+        0xBB => 13, // KEY_EQUAL
+        0xBC => 51, // KEY_COMMA
+        0xBD => 12, // KEY_MINUS
+        0xBE => 52, // KEY_DOT
+        0xBF => 53, // KEY_SLASH
+        0xC0 => 41, // KEY_GRAVE
+        0xDB => 26, // KEY_LEFTBRACE
+        0xDC => 43, // KEY_BACKSLASH
+        0xDD => 27, // KEY_RIGHTBRACE
+        0xDE => 40, // KEY_APOSTROPHE
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// 文字をevdevのキーコードとShiftの要否に変換（USキー配列）
+///
+/// キー配列にない文字（ASCII以外など）は`None`になります。
+pub fn char_to_evdev(c: char) -> Option<(u16, bool)> {
+    let (vk, shift) = match c {
+        '\n' | '\r' => (0x0D, false),
+        '\t' => (0x09, false),
+        '\u{8}' => (0x08, false),
+        '\u{1B}' => (0x1B, false),
+        ' ' => (0x20, false),
+        'a'..='z' => (c.to_ascii_uppercase() as u32, false),
+        'A'..='Z' => (c as u32, true),
+        '0'..='9' => (c as u32, false),
+        ')' => (0x30, true),
+        '!' => (0x31, true),
+        '@' => (0x32, true),
+        '#' => (0x33, true),
+        '$' => (0x34, true),
+        '%' => (0x35, true),
+        '^' => (0x36, true),
+        '&' => (0x37, true),
+        '*' => (0x38, true),
+        '(' => (0x39, true),
+        ';' => (0xBA, false),
+        ':' => (0xBA, true),
+        '=' => (0xBB, false),
+        '+' => (0xBB, true),
+        ',' => (0xBC, false),
+        '<' => (0xBC, true),
+        '-' => (0xBD, false),
+        '_' => (0xBD, true),
+        '.' => (0xBE, false),
+        '>' => (0xBE, true),
+        '/' => (0xBF, false),
+        '?' => (0xBF, true),
+        '`' => (0xC0, false),
+        '~' => (0xC0, true),
+        '[' => (0xDB, false),
+        '{' => (0xDB, true),
+        '\\' => (0xDC, false),
+        '|' => (0xDC, true),
+        ']' => (0xDD, false),
+        '}' => (0xDD, true),
+        '\'' => (0xDE, false),
+        '"' => (0xDE, true),
+        _ => return None,
+    };
+    vk_to_evdev(vk).map(|code| (code, shift))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vk_to_keysym() {
+        assert_eq!(vk_to_keysym(0x41), Some(0x61)); // A -> a
+        assert_eq!(vk_to_keysym(0x35), Some(0x35)); // 5
+        assert_eq!(vk_to_keysym(0x0D), Some(0xFF0D)); // Return
+        assert_eq!(vk_to_keysym(0x10), vk_to_keysym(0xA0)); // Shift
+        assert_eq!(vk_to_keysym(0x73), Some(0xFFC1)); // F4
+        assert_eq!(vk_to_keysym(0x87), Some(0xFFD5)); // F24
+        assert_eq!(vk_to_keysym(0xFF), None);
+    }
+
+    #[test]
+    fn test_char_to_keysym() {
+        assert_eq!(char_to_keysym('a'), Some(0x61));
+        assert_eq!(char_to_keysym('é'), Some(0xE9));
+        assert_eq!(char_to_keysym('あ'), Some(0x0100_3042));
+        assert_eq!(char_to_keysym('\n'), Some(0xFF0D));
+        assert_eq!(char_to_keysym('\u{7}'), None);
+    }
+
+    #[test]
+    fn test_vk_to_evdev() {
+        assert_eq!(vk_to_evdev(0x41), Some(30)); // KEY_A
+        assert_eq!(vk_to_evdev(0x5A), Some(44)); // KEY_Z
+        assert_eq!(vk_to_evdev(0x30), Some(11)); // KEY_0
+        assert_eq!(vk_to_evdev(0x39), Some(10)); // KEY_9
+        assert_eq!(vk_to_evdev(0x10), Some(42)); // KEY_LEFTSHIFT
+        assert_eq!(vk_to_evdev(0x73), Some(62)); // KEY_F4
+        assert_eq!(vk_to_evdev(0x7B), Some(88)); // KEY_F12
+        assert_eq!(vk_to_evdev(0x87), Some(194)); // KEY_F24
+        assert_eq!(vk_to_evdev(0x2E), Some(111)); // KEY_DELETE
+        assert_eq!(vk_to_evdev(0xFF), None);
+    }
+
+    #[test]
+    fn test_char_to_evdev() {
+        assert_eq!(char_to_evdev('h'), Some((35, false)));
+        assert_eq!(char_to_evdev('H'), Some((35, true)));
+        assert_eq!(char_to_evdev('!'), Some((2, true)));
+        assert_eq!(char_to_evdev('\n'), Some((28, false)));
+        assert_eq!(char_to_evdev('"'), Some((40, true)));
+        assert_eq!(char_to_evdev('あ'), None);
+    }
+}
@@ -7,9 +7,13 @@ pub mod system;
 pub mod keyboard;
 pub mod mouse;
 pub mod mapping;
+pub mod sink;
+#[cfg(all(target_os = "linux", feature = "uinput-support"))]
+pub mod uinput;
 
 use crate::error::ServerError;
 use remote_desktop_rs_common::protocol::{Command, KeyModifier, MouseButton};
+use std::sync::Arc;
 use thiserror::Error;
 
 /// 入力エラー
@@ -49,15 +53,25 @@ pub struct InputHandler {
 impl InputHandler {
     /// 新しい入力ハンドラを作成
     pub fn new() -> Result<Self, InputError> {
-        let keyboard = keyboard::KeyboardHandler::new()?;
-        let mouse = mouse::MouseHandler::new()?;
+        let system = system::SystemInput::new()
+            .map_err(|e| InputError::SystemError(e.to_string()))?;
+        
+        Ok(Self::with_sink(Arc::new(system)))
+    }
+    
+    /// 入力の出力先を指定して入力ハンドラを作成
+    ///
+    /// キーボードとマウスは同じ出力先に書き込みます。
+    pub fn with_sink(sink: Arc<dyn sink::InputSink>) -> Self {
+        let keyboard = keyboard::KeyboardHandler::with_sink(sink.clone());
+        let mouse = mouse::MouseHandler::with_sink(sink);
         let config = InputConfig::default();
         
-        Ok(Self {
+        Self {
             keyboard,
             mouse,
             config,
-        })
+        }
     }
     
     /// 設定を更新
@@ -72,6 +86,11 @@ impl InputHandler {
         &self.config
     }
     
+    /// 入力を送る画面の範囲を設定（配信するモニターが変わったら設定し直す）
+    pub fn set_area(&mut self, area: sink::InputArea) {
+        self.mouse.set_area(area);
+    }
+    
     /// コマンドを処理
     pub fn handle_command(&mut self, command: &Command) -> Result<(), InputError> {
         if !self.config.enabled {
@@ -114,7 +133,7 @@ impl InputHandler {
             Command::KeyDown { key_code, modifiers } => {
                 if self.config.keyboard.enabled {
                     let mapped_key = self.keyboard.map_key_code(*key_code);
-                    let mapped_modifiers = modifiers.iter()
+                    let mapped_modifiers: Vec<_> = modifiers.iter()
                         .map(|m| self.map_key_modifier(*m))
                         .collect();
                     self.keyboard.key_down(mapped_key, &mapped_modifiers)?;
@@ -123,7 +142,7 @@ impl InputHandler {
             Command::KeyUp { key_code, modifiers } => {
                 if self.config.keyboard.enabled {
                     let mapped_key = self.keyboard.map_key_code(*key_code);
-                    let mapped_modifiers = modifiers.iter()
+                    let mapped_modifiers: Vec<_> = modifiers.iter()
                         .map(|m| self.map_key_modifier(*m))
                         .collect();
                     self.keyboard.key_up(mapped_key, &mapped_modifiers)?;
@@ -136,10 +155,10 @@ impl InputHandler {
             },
             Command::KeyCombo { key_codes, modifiers } => {
                 if self.config.keyboard.enabled {
-                    let mapped_keys = key_codes.iter()
+                    let mapped_keys: Vec<_> = key_codes.iter()
                         .map(|k| self.keyboard.map_key_code(*k))
                         .collect();
-                    let mapped_modifiers = modifiers.iter()
+                    let mapped_modifiers: Vec<_> = modifiers.iter()
                         .map(|m| self.map_key_modifier(*m))
                         .collect();
                     self.keyboard.key_combo(&mapped_keys, &mapped_modifiers)?;
@@ -238,4 +257,139 @@ impl Default for MouseConfig {
             scroll_speed: 1.0,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::sink::{InputArea, InputEvent, RecordingSink};
+    use super::system::{SystemKey, SystemMouseButton};
+
+    /// 記録する出力先を使う入力ハンドラを作成
+    fn recording_handler() -> (InputHandler, RecordingSink) {
+        let sink = RecordingSink::new(800, 600);
+        (InputHandler::with_sink(Arc::new(sink.clone())), sink)
+    }
+
+    #[test]
+    fn test_key_combo_modifier_ordering() {
+        let (mut handler, sink) = recording_handler();
+
+        handler.handle_command(&Command::KeyCombo {
+            key_codes: vec![0x43, 0x56],
+            modifiers: vec![KeyModifier::Control, KeyModifier::Shift],
+        }).unwrap();
+
+        // 修飾キーをすべて押してから各キーを押して離し、最後に修飾キーを離す
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::KeyDown(SystemKey(0x11)),
+            InputEvent::KeyDown(SystemKey(0x10)),
+            InputEvent::KeyDown(SystemKey(0x43)),
+            InputEvent::KeyUp(SystemKey(0x43)),
+            InputEvent::KeyDown(SystemKey(0x56)),
+            InputEvent::KeyUp(SystemKey(0x56)),
+            InputEvent::KeyUp(SystemKey(0x11)),
+            InputEvent::KeyUp(SystemKey(0x10)),
+        ]);
+
+        // 単独のキー押下では修飾キーが先、解放では修飾キーが後
+        handler.handle_command(&Command::KeyDown { key_code: 0x41, modifiers: vec![KeyModifier::Alt] }).unwrap();
+        handler.handle_command(&Command::KeyUp { key_code: 0x41, modifiers: vec![KeyModifier::Alt] }).unwrap();
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::KeyDown(SystemKey(0x12)),
+            InputEvent::KeyDown(SystemKey(0x41)),
+            InputEvent::KeyUp(SystemKey(0x41)),
+            InputEvent::KeyUp(SystemKey(0x12)),
+        ]);
+    }
+
+    #[test]
+    fn test_dangerous_shortcuts_are_blocked() {
+        let (mut handler, sink) = recording_handler();
+
+        let alt_f4 = Command::KeyCombo { key_codes: vec![0x73], modifiers: vec![KeyModifier::Alt] };
+        assert!(matches!(handler.handle_command(&alt_f4), Err(InputError::InvalidInput(_))));
+        let win_r = Command::KeyDown { key_code: 0x52, modifiers: vec![KeyModifier::Meta] };
+        assert!(matches!(handler.handle_command(&win_r), Err(InputError::InvalidInput(_))));
+        let ctrl_alt_del = Command::KeyCombo {
+            key_codes: vec![0x2E],
+            modifiers: vec![KeyModifier::Control, KeyModifier::Alt],
+        };
+        assert!(handler.handle_command(&ctrl_alt_del).is_err());
+
+        // ブロックした場合は修飾キーも含めて何も送らない
+        assert!(sink.events().is_empty());
+
+        let mut config = *handler.config();
+        config.keyboard.block_shortcuts = false;
+        handler.set_config(config);
+        handler.handle_command(&alt_f4).unwrap();
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::KeyDown(SystemKey(0x12)),
+            InputEvent::KeyDown(SystemKey(0x73)),
+            InputEvent::KeyUp(SystemKey(0x73)),
+            InputEvent::KeyUp(SystemKey(0x12)),
+        ]);
+    }
+
+    #[test]
+    fn test_scroll_is_scaled_by_speed() {
+        let (mut handler, sink) = recording_handler();
+
+        let mut config = *handler.config();
+        config.mouse.scroll_speed = 2.5;
+        handler.set_config(config);
+        handler.handle_command(&Command::MouseScroll { delta_x: 1, delta_y: -3 }).unwrap();
+
+        // 小数部は0方向に切り捨てる
+        assert_eq!(sink.take_events(), vec![InputEvent::MouseScroll { delta_x: 2, delta_y: -7 }]);
+
+        config.mouse.enabled = false;
+        handler.set_config(config);
+        handler.handle_command(&Command::MouseScroll { delta_x: 0, delta_y: 1 }).unwrap();
+        assert!(sink.events().is_empty());
+    }
+
+    #[test]
+    fn test_mouse_commands_move_virtual_cursor() {
+        let (mut handler, sink) = recording_handler();
+
+        let mut config = *handler.config();
+        config.mouse.use_relative = true;
+        handler.set_config(config);
+
+        // 相対モードでも最初の移動は絶対座標
+        handler.handle_command(&Command::MouseMove { x: 100, y: 100 }).unwrap();
+        handler.handle_command(&Command::MouseMove { x: 110, y: 90 }).unwrap();
+        handler.handle_command(&Command::MouseClick { button: MouseButton::Right, double: false }).unwrap();
+        handler.handle_command(&Command::TextInput { text: "abc".to_string() }).unwrap();
+
+        assert_eq!(sink.cursor(), (110, 90));
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::MouseMove { x: 100, y: 100 },
+            InputEvent::MouseMoveRelative { dx: 10, dy: -10 },
+            InputEvent::MouseDown(SystemMouseButton::Right),
+            InputEvent::MouseUp(SystemMouseButton::Right),
+            InputEvent::Text("abc".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_mouse_move_is_offset_to_streamed_monitor() {
+        // 1600x600の画面の右半分のモニターを配信している場合
+        let sink = RecordingSink::new(1600, 600);
+        let mut handler = InputHandler::with_sink(Arc::new(sink.clone()));
+        handler.set_area(InputArea { screen_size: (1600, 600), origin: (800, 0), size: (800, 600) });
+
+        handler.handle_command(&Command::MouseMove { x: 10, y: 20 }).unwrap();
+        handler.handle_command(&Command::MouseMove { x: 900, y: 20 }).unwrap();
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::MouseMove { x: 810, y: 20 },
+            InputEvent::MouseMove { x: 1599, y: 20 },
+        ]);
+
+        // 配信するモニターが変わったら新しい位置でずらす
+        handler.set_area(InputArea { screen_size: (1600, 600), origin: (0, 0), size: (800, 600) });
+        handler.handle_command(&Command::MouseMove { x: 900, y: 20 }).unwrap();
+        assert_eq!(sink.take_events(), vec![InputEvent::MouseMove { x: 799, y: 20 }]);
+    }
+}
@@ -2,18 +2,21 @@
 //!
 //! リモートデスクトップサーバーにおけるマウス入力処理を実装します。
 
-use super::{system::{SystemInput, SystemMouseButton, SystemError}, InputError, MouseConfig};
+use super::{sink::{InputArea, InputSink}, system::{SystemInput, SystemMouseButton, SystemError}, InputError, MouseConfig};
+use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
 
 /// マウス入力処理
 pub struct MouseHandler {
-    /// システム入力
-    system: SystemInput,
+    /// 入力の出力先
+    sink: Arc<dyn InputSink>,
     /// マウス設定
     config: MouseConfig,
     /// 前回の座標
     last_position: Option<(i32, i32)>,
+    /// 入力を送る画面の範囲（`None`の場合は座標をそのまま送る）
+    area: Option<InputArea>,
 }
 
 impl MouseHandler {
@@ -22,11 +25,26 @@ impl MouseHandler {
         let system = SystemInput::new()
             .map_err(|e| InputError::SystemError(e.to_string()))?;
         
-        Ok(Self {
-            system,
+        Ok(Self::with_sink(Arc::new(system)))
+    }
+    
+    /// 入力の出力先を指定してマウスハンドラを作成
+    pub fn with_sink(sink: Arc<dyn InputSink>) -> Self {
+        Self {
+            sink,
             config: MouseConfig::default(),
             last_position: None,
-        })
+            area: None,
+        }
+    }
+    
+    /// 入力を送る画面の範囲を設定
+    ///
+    /// 以降の絶対座標は配信しているモニターからの位置として扱い、出力先によらず
+    /// モニターの位置だけずらして送ります。
+    pub fn set_area(&mut self, area: InputArea) {
+        self.area = Some(area);
+        self.last_position = None;
     }
     
     /// 設定を更新
@@ -47,11 +65,15 @@ impl MouseHandler {
             let dy = y - last_y;
             
             if dx != 0 || dy != 0 {
-                self.system.mouse_move_relative(dx, dy)
+                self.sink.mouse_move_relative(dx, dy)
                     .map_err(|e| InputError::SystemError(e.to_string()))?;
             }
         } else {
-            self.system.mouse_move(x, y)
+            let (x, y) = match &self.area {
+                Some(area) => area.to_screen(x, y),
+                None => (x, y),
+            };
+            self.sink.mouse_move(x, y)
                 .map_err(|e| InputError::SystemError(e.to_string()))?;
         }
         
@@ -62,11 +84,11 @@ impl MouseHandler {
     
     /// マウスを相対的に移動
     pub fn move_relative(&mut self, dx: i32, dy: i32) -> Result<(), InputError> {
-        self.system.mouse_move_relative(dx, dy)
+        self.sink.mouse_move_relative(dx, dy)
             .map_err(|e| InputError::SystemError(e.to_string()))?;
         
         // 現在位置を更新
-        if let Ok(position) = self.system.get_mouse_position() {
+        if let Ok(position) = self.get_position() {
             self.last_position = Some(position);
         }
         
@@ -75,13 +97,13 @@ impl MouseHandler {
     
     /// マウスボタン押下
     pub fn button_down(&self, button: SystemMouseButton) -> Result<(), InputError> {
-        self.system.mouse_down(button)
+        self.sink.mouse_down(button)
             .map_err(|e| InputError::SystemError(e.to_string()))
     }
     
     /// マウスボタン解放
     pub fn button_up(&self, button: SystemMouseButton) -> Result<(), InputError> {
-        self.system.mouse_up(button)
+        self.sink.mouse_up(button)
             .map_err(|e| InputError::SystemError(e.to_string()))
     }
     
@@ -109,19 +131,28 @@ impl MouseHandler {
         let dx = (delta_x as f32 * scale) as i32;
         let dy = (delta_y as f32 * scale) as i32;
         
-        self.system.mouse_scroll(dx, dy)
+        self.sink.mouse_scroll(dx, dy)
             .map_err(|e| InputError::SystemError(e.to_string()))
     }
     
     /// 現在位置を取得
     pub fn get_position(&self) -> Result<(i32, i32), InputError> {
-        self.system.get_mouse_position()
-            .map_err(|e| InputError::SystemError(e.to_string()))
+        let (x, y) = self.sink.get_mouse_position()
+            .map_err(|e| InputError::SystemError(e.to_string()))?;
+        
+        Ok(match &self.area {
+            Some(area) => area.from_screen(x, y),
+            None => (x, y),
+        })
     }
     
     /// スクリーンサイズを取得
     pub fn get_screen_size(&self) -> Result<(u32, u32), InputError> {
-        self.system.get_screen_size()
+        if let Some(area) = &self.area {
+            return Ok(area.size);
+        }
+        
+        self.sink.get_screen_size()
             .map_err(|e| InputError::SystemError(e.to_string()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,418 @@
+//! 入力の出力先
+//!
+//! キーボードハンドラとマウスハンドラは、変換した入力イベントを`InputSink`に書き込みます。
+//! OSに入力を送る`SystemInput`のほかに、イベントを記録するだけの`RecordingSink`に
+//! 差し替えられるため、コマンドから実際に生成されたイベントの並びをテストで確認できます。
+
+use super::system::{SystemError, SystemInput, SystemKey, SystemMouseButton};
+use super::InputError;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// 画面サイズを指定しない（取得できない）場合の仮想画面のサイズ
+pub const DEFAULT_VIRTUAL_SCREEN: (u32, u32) = (1920, 1080);
+
+/// 記録するイベントの既定の上限（超えた分は古いものから捨てる）
+pub const MAX_RECORDED_EVENTS: usize = 10_000;
+
+/// 入力を送る画面の範囲
+///
+/// クライアントが送る座標は配信しているモニターの左上からの位置です。
+/// 出力先の絶対座標は全モニターを含む画面の座標のため、マウスハンドラがモニターの位置
+/// （`origin`）だけずらしてから出力先に送ります。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputArea {
+    /// 全モニターを含む画面のサイズ
+    pub screen_size: (u32, u32),
+    /// 配信しているモニターの画面内の位置
+    pub origin: (i32, i32),
+    /// 配信しているモニターのサイズ
+    pub size: (u32, u32),
+}
+
+impl InputArea {
+    /// 画面全体を1つのモニターとして使用
+    pub fn full_screen(screen_size: (u32, u32)) -> Self {
+        Self {
+            screen_size,
+            origin: (0, 0),
+            size: screen_size,
+        }
+    }
+
+    /// 配信しているモニター内の座標を画面の座標に変換（モニター内と画面内に収める）
+    pub fn to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        let (screen_width, screen_height) = self.screen_size;
+        let (width, height) = self.size;
+        let x = self.origin.0.saturating_add(x.clamp(0, width.saturating_sub(1) as i32));
+        let y = self.origin.1.saturating_add(y.clamp(0, height.saturating_sub(1) as i32));
+        (
+            x.clamp(0, screen_width.saturating_sub(1) as i32),
+            y.clamp(0, screen_height.saturating_sub(1) as i32),
+        )
+    }
+
+    /// 画面の座標を配信しているモニター内の座標に変換
+    pub fn from_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        (x.saturating_sub(self.origin.0), y.saturating_sub(self.origin.1))
+    }
+}
+
+/// 入力の出力先
+pub trait InputSink: Send + Sync {
+    /// 出力先の名前
+    fn name(&self) -> &str;
+
+    /// マウスを指定座標に移動
+    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError>;
+
+    /// マウスを相対的に移動
+    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError>;
+
+    /// マウスボタンを押下
+    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError>;
+
+    /// マウスボタンを解放
+    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError>;
+
+    /// マウスホイールをスクロール
+    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError>;
+
+    /// キーを押下
+    fn key_down(&self, key: SystemKey) -> Result<(), SystemError>;
+
+    /// キーを解放
+    fn key_up(&self, key: SystemKey) -> Result<(), SystemError>;
+
+    /// テキストを入力
+    fn input_text(&self, text: &str) -> Result<(), SystemError>;
+
+    /// 現在のマウス位置を取得
+    fn get_mouse_position(&self) -> Result<(i32, i32), SystemError>;
+
+    /// スクリーン解像度を取得
+    fn get_screen_size(&self) -> Result<(u32, u32), SystemError>;
+}
+
+impl InputSink for SystemInput {
+    fn name(&self) -> &str {
+        "system"
+    }
+
+    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError> {
+        SystemInput::mouse_move(self, x, y)
+    }
+
+    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError> {
+        SystemInput::mouse_move_relative(self, dx, dy)
+    }
+
+    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        SystemInput::mouse_down(self, button)
+    }
+
+    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        SystemInput::mouse_up(self, button)
+    }
+
+    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError> {
+        SystemInput::mouse_scroll(self, delta_x, delta_y)
+    }
+
+    fn key_down(&self, key: SystemKey) -> Result<(), SystemError> {
+        SystemInput::key_down(self, key)
+    }
+
+    fn key_up(&self, key: SystemKey) -> Result<(), SystemError> {
+        SystemInput::key_up(self, key)
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), SystemError> {
+        SystemInput::input_text(self, text)
+    }
+
+    fn get_mouse_position(&self) -> Result<(i32, i32), SystemError> {
+        SystemInput::get_mouse_position(self)
+    }
+
+    fn get_screen_size(&self) -> Result<(u32, u32), SystemError> {
+        SystemInput::get_screen_size(self)
+    }
+}
+
+/// 記録した入力イベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// 絶対座標への移動（画面内に収めた後の座標）
+    MouseMove { x: i32, y: i32 },
+    /// 相対移動（要求された移動量）
+    MouseMoveRelative { dx: i32, dy: i32 },
+    /// マウスボタン押下
+    MouseDown(SystemMouseButton),
+    /// マウスボタン解放
+    MouseUp(SystemMouseButton),
+    /// スクロール
+    MouseScroll { delta_x: i32, delta_y: i32 },
+    /// キー押下
+    KeyDown(SystemKey),
+    /// キー解放
+    KeyUp(SystemKey),
+    /// テキスト入力
+    Text(String),
+}
+
+/// 記録の状態
+#[derive(Debug)]
+struct Recording {
+    /// 記録したイベント（古いものから順）
+    events: VecDeque<InputEvent>,
+    /// 記録するイベントの上限
+    limit: usize,
+    /// 上限を超えて捨てたイベントの数
+    dropped: u64,
+    /// 仮想画面上のカーソル位置
+    cursor: (i32, i32),
+    /// 仮想画面のサイズ
+    screen_size: (u32, u32),
+}
+
+impl Recording {
+    /// イベントを追加（上限を超えたら最も古いイベントを捨てる）
+    fn push(&mut self, event: InputEvent) {
+        while self.events.len() >= self.limit {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// カーソルを仮想画面内に収めて移動
+    fn move_cursor(&mut self, x: i32, y: i32) {
+        let (width, height) = self.screen_size;
+        self.cursor = (
+            x.clamp(0, width.saturating_sub(1) as i32),
+            y.clamp(0, height.saturating_sub(1) as i32),
+        );
+    }
+}
+
+/// 入力イベントを記録する出力先
+///
+/// OSには何も送らず、受け取ったイベントをそのままの順で記録します。
+/// マウスの移動は仮想画面上のカーソルに反映します。複製したものは同じ記録を共有するため、
+/// ハンドラに渡した後も複製からイベントを確認できます。
+/// 長時間動かしてもメモリを使い続けないよう、記録は`MAX_RECORDED_EVENTS`件までで、
+/// 超えた分は古いイベントから捨てます。
+#[derive(Debug, Clone)]
+pub struct RecordingSink {
+    /// 記録の状態
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordingSink {
+    /// 指定したサイズの仮想画面で記録を開始（カーソルは画面の中央）
+    pub fn new(width: u32, height: u32) -> Self {
+        let screen_size = (width.max(1), height.max(1));
+        Self {
+            recording: Arc::new(Mutex::new(Recording {
+                events: VecDeque::new(),
+                limit: MAX_RECORDED_EVENTS,
+                dropped: 0,
+                cursor: ((screen_size.0 / 2) as i32, (screen_size.1 / 2) as i32),
+                screen_size,
+            })),
+        }
+    }
+
+    /// 記録するイベントの上限を設定（1件以上、超えている分は古いものから捨てる）
+    pub fn set_limit(&self, limit: usize) {
+        let mut recording = self.recording.lock();
+        recording.limit = limit.max(1);
+        while recording.events.len() > recording.limit {
+            recording.events.pop_front();
+            recording.dropped += 1;
+        }
+    }
+
+    /// 上限を超えて捨てたイベントの数を取得
+    pub fn dropped(&self) -> u64 {
+        self.recording.lock().dropped
+    }
+
+    /// 記録したイベントを取得
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.recording.lock().events.iter().cloned().collect()
+    }
+
+    /// 記録したイベントを取り出して記録を空にする
+    pub fn take_events(&self) -> Vec<InputEvent> {
+        self.recording.lock().events.drain(..).collect()
+    }
+
+    /// 仮想画面上のカーソル位置を取得
+    pub fn cursor(&self) -> (i32, i32) {
+        self.recording.lock().cursor
+    }
+
+    /// イベントを記録
+    fn record(&self, event: InputEvent) -> Result<(), SystemError> {
+        self.recording.lock().push(event);
+        Ok(())
+    }
+}
+
+impl Default for RecordingSink {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIRTUAL_SCREEN.0, DEFAULT_VIRTUAL_SCREEN.1)
+    }
+}
+
+impl InputSink for RecordingSink {
+    fn name(&self) -> &str {
+        "recording"
+    }
+
+    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError> {
+        let mut recording = self.recording.lock();
+        recording.move_cursor(x, y);
+        let (x, y) = recording.cursor;
+        recording.push(InputEvent::MouseMove { x, y });
+        Ok(())
+    }
+
+    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError> {
+        let mut recording = self.recording.lock();
+        let (x, y) = recording.cursor;
+        recording.move_cursor(x.saturating_add(dx), y.saturating_add(dy));
+        recording.push(InputEvent::MouseMoveRelative { dx, dy });
+        Ok(())
+    }
+
+    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        self.record(InputEvent::MouseDown(button))
+    }
+
+    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        self.record(InputEvent::MouseUp(button))
+    }
+
+    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError> {
+        self.record(InputEvent::MouseScroll { delta_x, delta_y })
+    }
+
+    fn key_down(&self, key: SystemKey) -> Result<(), SystemError> {
+        self.record(InputEvent::KeyDown(key))
+    }
+
+    fn key_up(&self, key: SystemKey) -> Result<(), SystemError> {
+        self.record(InputEvent::KeyUp(key))
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), SystemError> {
+        self.record(InputEvent::Text(text.to_string()))
+    }
+
+    fn get_mouse_position(&self) -> Result<(i32, i32), SystemError> {
+        Ok(self.cursor())
+    }
+
+    fn get_screen_size(&self) -> Result<(u32, u32), SystemError> {
+        Ok(self.recording.lock().screen_size)
+    }
+}
+
+/// 名前から入力の出力先を作成
+///
+/// `"system"`はOSに入力を送り、`"uinput"`はLinuxの`/dev/uinput`の仮想デバイスに送り、
+/// `"recording"`は仮想画面にイベントを記録します。`screen_size`は絶対座標の範囲
+/// （uinput）と仮想画面のサイズ（recording）に使用します。
+pub fn create_sink(backend: &str, screen_size: (u32, u32)) -> Result<Arc<dyn InputSink>, InputError> {
+    match backend {
+        "system" => {
+            let system = SystemInput::new()
+                .map_err(|e| InputError::SystemError(e.to_string()))?;
+            Ok(Arc::new(system))
+        },
+        #[cfg(all(target_os = "linux", feature = "uinput-support"))]
+        "uinput" => {
+            let uinput = super::uinput::UinputInput::new(screen_size)
+                .map_err(|e| InputError::SystemError(e.to_string()))?;
+            Ok(Arc::new(uinput))
+        },
+        #[cfg(not(all(target_os = "linux", feature = "uinput-support")))]
+        "uinput" => Err(InputError::SystemError("uinput support is not enabled".to_string())),
+        "recording" => Ok(Arc::new(RecordingSink::new(screen_size.0, screen_size.1))),
+        _ => Err(InputError::InvalidInput(format!("不明な入力バックエンド: {}", backend))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_cursor_is_clamped_to_screen() {
+        let sink = RecordingSink::new(100, 50);
+        assert_eq!(sink.cursor(), (50, 25));
+
+        sink.mouse_move(10, 20).unwrap();
+        sink.mouse_move_relative(-30, 5).unwrap();
+        assert_eq!(sink.cursor(), (0, 25));
+
+        sink.mouse_move(500, -3).unwrap();
+        assert_eq!(sink.get_mouse_position().unwrap(), (99, 0));
+        assert_eq!(sink.get_screen_size().unwrap(), (100, 50));
+
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::MouseMove { x: 10, y: 20 },
+            InputEvent::MouseMoveRelative { dx: -30, dy: 5 },
+            InputEvent::MouseMove { x: 99, y: 0 },
+        ]);
+        assert!(sink.events().is_empty());
+    }
+
+    #[test]
+    fn test_create_sink_by_name() {
+        let sink = create_sink("recording", (640, 480)).unwrap();
+        assert_eq!(sink.name(), "recording");
+        assert_eq!(sink.get_screen_size().unwrap(), (640, 480));
+        assert!(matches!(create_sink("unknown", DEFAULT_VIRTUAL_SCREEN), Err(InputError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_area_is_offset_and_clamped() {
+        // 1600x600の画面の右半分のモニターを配信している場合
+        let area = InputArea { screen_size: (1600, 600), origin: (800, 0), size: (800, 600) };
+        assert_eq!(area.to_screen(10, 20), (810, 20));
+        assert_eq!(area.to_screen(-5, 900), (800, 599));
+        assert_eq!(area.from_screen(810, 20), (10, 20));
+
+        // 画面からはみ出すモニターは画面内に収める
+        let area = InputArea { screen_size: (1600, 600), origin: (1500, -10), size: (800, 600) };
+        assert_eq!(area.to_screen(799, 599), (1599, 589));
+        assert_eq!(InputArea::full_screen((640, 480)).to_screen(700, 10), (639, 10));
+    }
+
+    #[test]
+    fn test_recording_is_bounded() {
+        let sink = RecordingSink::new(100, 50);
+        sink.set_limit(3);
+
+        for dy in 1..=5 {
+            sink.mouse_scroll(0, dy).unwrap();
+        }
+
+        // 古いイベントから捨てる
+        assert_eq!(sink.events(), vec![
+            InputEvent::MouseScroll { delta_x: 0, delta_y: 3 },
+            InputEvent::MouseScroll { delta_x: 0, delta_y: 4 },
+            InputEvent::MouseScroll { delta_x: 0, delta_y: 5 },
+        ]);
+        assert_eq!(sink.dropped(), 2);
+
+        sink.set_limit(1);
+        assert_eq!(sink.take_events(), vec![InputEvent::MouseScroll { delta_x: 0, delta_y: 5 }]);
+        assert_eq!(sink.dropped(), 4);
+    }
+}
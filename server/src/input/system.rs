@@ -6,6 +6,8 @@
 use super::InputError;
 use thiserror::Error;
 use std::fmt;
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+use std::{thread, time::Duration};
 
 /// システム入力エラー
 #[derive(Error, Debug)]
@@ -486,77 +488,381 @@ impl WindowsSystemInput {
     }
 }
 
-// Linux実装
-#[cfg(target_os = "linux")]
+// Linux実装（X11のXTEST拡張で入力イベントを合成）
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
 struct LinuxSystemInput {
-    // X11固有のフィールド
-    display: *mut std::ffi::c_void, // 実際にはXDisplay*だが、依存関係を減らすために型消去
+    /// X11サーバーへの接続
+    conn: x11rb::rust_connection::RustConnection,
+    /// ルートウィンドウ
+    root: u32,
+    /// キーボードマッピング（テキスト入力で空きキーコードを書き換えるため可変）
+    keymap: parking_lot::Mutex<X11Keymap>,
 }
 
-#[cfg(target_os = "linux")]
+/// X11のキーボードマッピング
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+struct X11Keymap {
+    /// 最小のキーコード
+    min_keycode: u8,
+    /// キーコードごとのキーシムの数
+    keysyms_per_keycode: u8,
+    /// キーシム（`min_keycode`から順に`keysyms_per_keycode`個ずつ）
+    keysyms: Vec<u32>,
+    /// キーシムの割り当てられていないキーコード（キーマップにない文字の入力に使う）
+    spare_keycode: Option<u8>,
+    /// 空きキーコードに一時的に割り当てているキーシム
+    spare_keysym: Option<u32>,
+}
+
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+const XK_SHIFT_L: u32 = 0xFFE1;
+
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+impl X11Keymap {
+    /// サーバーからキーボードマッピングを読み込む
+    fn load(conn: &x11rb::rust_connection::RustConnection) -> Result<Self, SystemError> {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xproto::ConnectionExt;
+
+        let setup = conn.setup();
+        let min_keycode = setup.min_keycode;
+        let count = setup.max_keycode - min_keycode + 1;
+        let reply = conn.get_keyboard_mapping(min_keycode, count)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let mut keymap = Self {
+            min_keycode,
+            keysyms_per_keycode: reply.keysyms_per_keycode,
+            keysyms: reply.keysyms,
+            spare_keycode: None,
+            spare_keysym: None,
+        };
+
+        // 最後尾から探すほうが、キーボードに存在するキーと重なりにくい
+        keymap.spare_keycode = keymap.entries()
+            .filter(|(_, syms)| syms.iter().all(|&s| s == 0))
+            .map(|(keycode, _)| keycode)
+            .last();
+
+        Ok(keymap)
+    }
+
+    /// キーコードとキーシムの組を列挙
+    fn entries(&self) -> impl Iterator<Item = (u8, &[u32])> {
+        let min_keycode = self.min_keycode;
+        self.keysyms
+            .chunks(self.keysyms_per_keycode.max(1) as usize)
+            .enumerate()
+            .map(move |(i, syms)| (min_keycode + i as u8, syms))
+    }
+
+    /// キーシムを入力するキーコードと、Shiftが必要かどうかを探す
+    fn find(&self, keysym: u32) -> Option<(u8, bool)> {
+        self.entries().find_map(|(keycode, syms)| {
+            syms.iter()
+                .take(2)
+                .position(|&s| s == keysym)
+                .map(|level| (keycode, level == 1))
+        })
+    }
+
+    /// 空きキーコードのキーシムを書き換える
+    fn set_spare(&mut self, conn: &x11rb::rust_connection::RustConnection, keysym: u32) -> Result<u8, SystemError> {
+        use x11rb::protocol::xproto::ConnectionExt;
+
+        let keycode = self.spare_keycode
+            .ok_or_else(|| SystemError::UnsupportedOperation("No spare keycode for text input".to_string()))?;
+        if self.spare_keysym == Some(keysym) {
+            return Ok(keycode);
+        }
+
+        let per = self.keysyms_per_keycode.max(1);
+        let syms = vec![keysym; per as usize];
+        conn.change_keyboard_mapping(1, keycode, per, &syms)
+            .map_err(x11_error)?
+            .check()
+            .map_err(x11_error)?;
+
+        let offset = (keycode - self.min_keycode) as usize * per as usize;
+        self.keysyms[offset..offset + per as usize].copy_from_slice(&syms);
+        self.spare_keysym = if keysym == 0 { None } else { Some(keysym) };
+
+        Ok(keycode)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+impl LinuxSystemInput {
+    /// XTESTでイベントを送信
+    fn fake_input(&self, type_: u8, detail: u8, root: u32, x: i16, y: i16) -> Result<(), SystemError> {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xtest::ConnectionExt;
+
+        self.conn.xtest_fake_input(type_, detail, x11rb::CURRENT_TIME, root, x, y, 0)
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)
+    }
+
+    /// キーコードを押下または解放
+    fn fake_key(&self, keycode: u8, press: bool) -> Result<(), SystemError> {
+        use x11rb::protocol::xproto::{KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+
+        let type_ = if press { KEY_PRESS_EVENT } else { KEY_RELEASE_EVENT };
+        self.fake_input(type_, keycode, x11rb::NONE, 0, 0)
+    }
+
+    /// ボタンを押下または解放
+    fn fake_button(&self, button: u8, press: bool) -> Result<(), SystemError> {
+        use x11rb::protocol::xproto::{BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT};
+
+        let type_ = if press { BUTTON_PRESS_EVENT } else { BUTTON_RELEASE_EVENT };
+        self.fake_input(type_, button, x11rb::NONE, 0, 0)
+    }
+
+    /// 仮想キーコードに対応するキーコードを取得
+    fn keycode(&self, key: SystemKey) -> Result<u8, SystemError> {
+        super::mapping::vk_to_keysym(key.0)
+            .and_then(|keysym| self.keymap.lock().find(keysym))
+            .map(|(keycode, _)| keycode)
+            .ok_or_else(|| SystemError::UnsupportedOperation(format!("No keycode for {}", key)))
+    }
+
+    /// キーシムを1文字入力
+    fn type_keysym(&self, keysym: u32) -> Result<(), SystemError> {
+        let mut keymap = self.keymap.lock();
+
+        let (keycode, shift) = match keymap.find(keysym) {
+            Some(found) => found,
+            None => {
+                // 直前に別の文字を割り当てていた場合、アプリケーションがそのキーを
+                // 処理し終える前に書き換えないよう少し待つ
+                if keymap.spare_keysym.is_some() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                (keymap.set_spare(&self.conn, keysym)?, false)
+            },
+        };
+        let shift_keycode = if shift {
+            keymap.find(XK_SHIFT_L).map(|(keycode, _)| keycode)
+        } else {
+            None
+        };
+        drop(keymap);
+
+        if let Some(shift_keycode) = shift_keycode {
+            self.fake_key(shift_keycode, true)?;
+        }
+        self.fake_key(keycode, true)?;
+        self.fake_key(keycode, false)?;
+        if let Some(shift_keycode) = shift_keycode {
+            self.fake_key(shift_keycode, false)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
 impl SystemInputImpl for LinuxSystemInput {
     fn new() -> Result<Self, SystemError> {
-        #[cfg(feature = "x11-support")]
-        {
-            use x11::xlib;
-            use std::ffi::CString;
-            use std::ptr::null;
-            
-            unsafe {
-                let display = xlib::XOpenDisplay(null());
-                if display.is_null() {
-                    return Err(SystemError::ApiError("XOpenDisplay failed".to_string()));
-                }
-                
-                Ok(Self { display: display as *mut std::ffi::c_void })
+        use x11rb::connection::{Connection, RequestConnection};
+        use x11rb::protocol::xtest::{self, ConnectionExt};
+
+        let (conn, screen_num) = x11rb::connect(None)
+            .map_err(|e| SystemError::ApiError(format!("Failed to connect to X server: {}", e)))?;
+
+        if conn.extension_information(xtest::X11_EXTENSION_NAME).map_err(x11_error)?.is_none() {
+            return Err(SystemError::UnsupportedOperation("XTEST extension is not available".to_string()));
+        }
+        conn.xtest_get_version(2, 2)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let root = conn.setup().roots[screen_num].root;
+        let keymap = X11Keymap::load(&conn)?;
+
+        Ok(Self {
+            conn,
+            root,
+            keymap: parking_lot::Mutex::new(keymap),
+        })
+    }
+
+    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError> {
+        use x11rb::protocol::xproto::MOTION_NOTIFY_EVENT;
+
+        // detail 0: 絶対座標
+        self.fake_input(MOTION_NOTIFY_EVENT, 0, self.root, clamp_i16(x), clamp_i16(y))
+    }
+
+    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError> {
+        use x11rb::protocol::xproto::MOTION_NOTIFY_EVENT;
+
+        // detail 1: 相対座標
+        self.fake_input(MOTION_NOTIFY_EVENT, 1, x11rb::NONE, clamp_i16(dx), clamp_i16(dy))
+    }
+
+    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        self.fake_button(x11_button(button), true)
+    }
+
+    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        self.fake_button(x11_button(button), false)
+    }
+
+    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError> {
+        // X11ではホイールの1目盛りがボタン4（上）、5（下）、6（左）、7（右）の1回のクリック
+        let steps = [
+            (if delta_x > 0 { 7 } else { 6 }, delta_x.unsigned_abs()),
+            (if delta_y > 0 { 4 } else { 5 }, delta_y.unsigned_abs()),
+        ];
+
+        for (button, count) in steps {
+            for _ in 0..count {
+                self.fake_button(button, true)?;
+                self.fake_button(button, false)?;
             }
         }
-        
-        #[cfg(not(feature = "x11-support"))]
-        {
-            Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
+
+        Ok(())
+    }
+
+    fn key_down(&self, key: SystemKey) -> Result<(), SystemError> {
+        let keycode = self.keycode(key)?;
+        self.fake_key(keycode, true)
+    }
+
+    fn key_up(&self, key: SystemKey) -> Result<(), SystemError> {
+        let keycode = self.keycode(key)?;
+        self.fake_key(keycode, false)
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), SystemError> {
+        // 改行は"\r\n"でも1回だけ入力する
+        let text = text.replace("\r\n", "\n");
+
+        for c in text.chars() {
+            if let Some(keysym) = super::mapping::char_to_keysym(c) {
+                self.type_keysym(keysym)?;
+            }
         }
+
+        Ok(())
+    }
+
+    fn get_mouse_position(&self) -> Result<(i32, i32), SystemError> {
+        use x11rb::protocol::xproto::ConnectionExt;
+
+        let reply = self.conn.query_pointer(self.root)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        Ok((reply.root_x as i32, reply.root_y as i32))
+    }
+
+    fn get_screen_size(&self) -> Result<(u32, u32), SystemError> {
+        use x11rb::protocol::xproto::ConnectionExt;
+
+        // 接続後に解像度が変わることがあるため、接続時の情報ではなくルートウィンドウの現在のサイズを使う
+        let reply = self.conn.get_geometry(self.root)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        Ok((reply.width as u32, reply.height as u32))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+impl Drop for LinuxSystemInput {
+    fn drop(&mut self) {
+        use x11rb::connection::Connection;
+
+        // 一時的に割り当てたキーシムを戻す
+        let mut keymap = self.keymap.lock();
+        if keymap.spare_keysym.is_some() {
+            let _ = keymap.set_spare(&self.conn, 0);
+            let _ = self.conn.flush();
+        }
+    }
+}
+
+/// マウスボタンをX11のボタン番号に変換
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+fn x11_button(button: SystemMouseButton) -> u8 {
+    match button {
+        SystemMouseButton::Left => 1,
+        SystemMouseButton::Middle => 2,
+        SystemMouseButton::Right => 3,
+        SystemMouseButton::Back => 8,
+        SystemMouseButton::Forward => 9,
+    }
+}
+
+/// X11の座標の範囲に収める
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+fn clamp_i16(value: i32) -> i16 {
+    value.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+/// X11のエラーをシステムエラーに変換
+#[cfg(all(target_os = "linux", feature = "x11-support"))]
+fn x11_error<E: fmt::Display>(e: E) -> SystemError {
+    SystemError::ApiError(e.to_string())
+}
+
+// Linux実装（X11サポートなし）
+#[cfg(all(target_os = "linux", not(feature = "x11-support")))]
+struct LinuxSystemInput;
+
+#[cfg(all(target_os = "linux", not(feature = "x11-support")))]
+impl SystemInputImpl for LinuxSystemInput {
+    fn new() -> Result<Self, SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    // Linux向け実装... (省略)
-    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn mouse_move(&self, _x: i32, _y: i32) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn mouse_move_relative(&self, _dx: i32, _dy: i32) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn mouse_down(&self, _button: SystemMouseButton) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn mouse_up(&self, _button: SystemMouseButton) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn mouse_scroll(&self, _delta_x: i32, _delta_y: i32) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn key_down(&self, key: SystemKey) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn key_down(&self, _key: SystemKey) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn key_up(&self, key: SystemKey) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn key_up(&self, _key: SystemKey) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
-    fn input_text(&self, text: &str) -> Result<(), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+    fn input_text(&self, _text: &str) -> Result<(), SystemError> {
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
     fn get_mouse_position(&self) -> Result<(i32, i32), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
     
     fn get_screen_size(&self) -> Result<(u32, u32), SystemError> {
-        Err(SystemError::UnsupportedOperation("Not implemented".to_string()))
+        Err(SystemError::UnsupportedOperation("X11 support is not enabled".to_string()))
     }
 }
 
@@ -0,0 +1,445 @@
+//! uinputの入力バックエンド
+//!
+//! `/dev/uinput`に仮想キーボードと絶対座標のポインターを作成し、evdevのイベントを書き込みます。
+//! XTESTと違ってディスプレイサーバーに依存しないため、Waylandのコンポジターや
+//! コンソールのセッションでも使用できます。`/dev/uinput`への書き込み権限が必要です。
+//!
+//! デバイスの作成後はイベントを書き込むだけなので、`with_writers`にファイル以外の
+//! 書き込み先を渡すと、書き込まれたイベントをテストで確認できます。
+
+use super::mapping::{char_to_evdev, vk_to_evdev};
+use super::sink::InputSink;
+use super::system::{SystemError, SystemKey, SystemMouseButton};
+use parking_lot::Mutex;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// uinputのデバイスファイル
+pub const UINPUT_PATH: &str = "/dev/uinput";
+
+/// イベントの種類
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+
+/// イベントのコード
+const SYN_REPORT: u16 = 0x00;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const KEY_LEFTSHIFT: u16 = 42;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+
+/// 仮想デバイスのバス種別（`BUS_VIRTUAL`）
+const BUS_VIRTUAL: u16 = 0x06;
+
+/// ioctl番号（`linux/uinput.h`、x86やARMなどの汎用の形式）
+const UINPUT_IOCTL_BASE: u64 = b'U' as u64;
+const UI_DEV_CREATE: u64 = (UINPUT_IOCTL_BASE << 8) | 1;
+const UI_DEV_SETUP: u64 = uinput_iow(3, size_of::<libc::uinput_setup>());
+const UI_ABS_SETUP: u64 = uinput_iow(4, size_of::<libc::uinput_abs_setup>());
+const UI_SET_EVBIT: u64 = uinput_iow(100, size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u64 = uinput_iow(101, size_of::<libc::c_int>());
+const UI_SET_RELBIT: u64 = uinput_iow(102, size_of::<libc::c_int>());
+const UI_SET_ABSBIT: u64 = uinput_iow(103, size_of::<libc::c_int>());
+
+/// `input_event`の時刻の部分の大きさ（時刻はカーネルが書き込み時に設定するため0を送る）
+const EVENT_TIME_SIZE: usize = size_of::<libc::input_event>() - 8;
+
+/// `_IOW('U', nr, size)`
+const fn uinput_iow(nr: u64, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | (UINPUT_IOCTL_BASE << 8) | nr
+}
+
+/// uinputの入力バックエンド
+pub struct UinputInput {
+    /// 仮想キーボードへの書き込み先
+    keyboard: Mutex<Box<dyn Write + Send>>,
+    /// 仮想ポインターへの書き込み先
+    pointer: Mutex<Box<dyn Write + Send>>,
+    /// 絶対座標の範囲（画面サイズ）
+    screen_size: (u32, u32),
+    /// 最後に送ったポインターの位置
+    cursor: Mutex<(i32, i32)>,
+}
+
+impl UinputInput {
+    /// `/dev/uinput`に仮想デバイスを作成
+    ///
+    /// ポインターの絶対座標の範囲は`screen_size`になり、コンポジターが画面全体に対応付けます。
+    pub fn new(screen_size: (u32, u32)) -> Result<Self, SystemError> {
+        Self::open(UINPUT_PATH, screen_size)
+    }
+
+    /// 指定したuinputのデバイスファイルに仮想デバイスを作成
+    pub fn open(path: impl AsRef<Path>, screen_size: (u32, u32)) -> Result<Self, SystemError> {
+        let path = path.as_ref();
+        let screen_size = (screen_size.0.max(1), screen_size.1.max(1));
+
+        let keyboard = create_device(path, "remote-desktop-rs keyboard", |file| {
+            set_bit(file, UI_SET_EVBIT, EV_KEY)?;
+            for code in (0..=0xFF).filter_map(vk_to_evdev) {
+                set_bit(file, UI_SET_KEYBIT, code)?;
+            }
+            Ok(())
+        })?;
+
+        let pointer = create_device(path, "remote-desktop-rs pointer", |file| {
+            set_bit(file, UI_SET_EVBIT, EV_KEY)?;
+            for button in [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, BTN_SIDE, BTN_EXTRA] {
+                set_bit(file, UI_SET_KEYBIT, button)?;
+            }
+            set_bit(file, UI_SET_EVBIT, EV_REL)?;
+            set_bit(file, UI_SET_RELBIT, REL_WHEEL)?;
+            set_bit(file, UI_SET_RELBIT, REL_HWHEEL)?;
+            set_bit(file, UI_SET_EVBIT, EV_ABS)?;
+            for (axis, size) in [(ABS_X, screen_size.0), (ABS_Y, screen_size.1)] {
+                set_bit(file, UI_SET_ABSBIT, axis)?;
+                let setup = libc::uinput_abs_setup {
+                    code: axis,
+                    absinfo: libc::input_absinfo {
+                        value: 0,
+                        minimum: 0,
+                        maximum: size as i32 - 1,
+                        fuzz: 0,
+                        flat: 0,
+                        resolution: 0,
+                    },
+                };
+                ioctl_ptr(file, UI_ABS_SETUP, &setup)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(Self::with_writers(Box::new(keyboard), Box::new(pointer), screen_size))
+    }
+
+    /// 作成済みのデバイス（またはテスト用の書き込み先）を使用
+    pub fn with_writers(
+        keyboard: Box<dyn Write + Send>,
+        pointer: Box<dyn Write + Send>,
+        screen_size: (u32, u32),
+    ) -> Self {
+        let screen_size = (screen_size.0.max(1), screen_size.1.max(1));
+        Self {
+            keyboard: Mutex::new(keyboard),
+            pointer: Mutex::new(pointer),
+            screen_size,
+            cursor: Mutex::new(((screen_size.0 / 2) as i32, (screen_size.1 / 2) as i32)),
+        }
+    }
+
+    /// ポインターを絶対座標に移動（画面内に収める）
+    fn move_pointer(&self, x: i32, y: i32) -> Result<(), SystemError> {
+        let (width, height) = self.screen_size;
+        let x = x.clamp(0, width as i32 - 1);
+        let y = y.clamp(0, height as i32 - 1);
+
+        let mut cursor = self.cursor.lock();
+        write_events(&self.pointer, &[(EV_ABS, ABS_X, x), (EV_ABS, ABS_Y, y)])?;
+        *cursor = (x, y);
+
+        Ok(())
+    }
+
+    /// 仮想キーコードに対応するevdevのキーコードを取得
+    fn keycode(&self, key: SystemKey) -> Result<u16, SystemError> {
+        vk_to_evdev(key.0)
+            .ok_or_else(|| SystemError::UnsupportedOperation(format!("No evdev key for {}", key)))
+    }
+}
+
+impl InputSink for UinputInput {
+    fn name(&self) -> &str {
+        "uinput"
+    }
+
+    fn mouse_move(&self, x: i32, y: i32) -> Result<(), SystemError> {
+        self.move_pointer(x, y)
+    }
+
+    fn mouse_move_relative(&self, dx: i32, dy: i32) -> Result<(), SystemError> {
+        // ポインターは絶対座標のデバイスのため、最後に送った位置からの移動にする
+        let (x, y) = *self.cursor.lock();
+        self.move_pointer(x.saturating_add(dx), y.saturating_add(dy))
+    }
+
+    fn mouse_down(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        write_events(&self.pointer, &[(EV_KEY, evdev_button(button), 1)])
+    }
+
+    fn mouse_up(&self, button: SystemMouseButton) -> Result<(), SystemError> {
+        write_events(&self.pointer, &[(EV_KEY, evdev_button(button), 0)])
+    }
+
+    fn mouse_scroll(&self, delta_x: i32, delta_y: i32) -> Result<(), SystemError> {
+        // 正のREL_WHEELは上、正のREL_HWHEELは右
+        let mut events = Vec::new();
+        if delta_y != 0 {
+            events.push((EV_REL, REL_WHEEL, delta_y));
+        }
+        if delta_x != 0 {
+            events.push((EV_REL, REL_HWHEEL, delta_x));
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+        write_events(&self.pointer, &events)
+    }
+
+    fn key_down(&self, key: SystemKey) -> Result<(), SystemError> {
+        let code = self.keycode(key)?;
+        write_events(&self.keyboard, &[(EV_KEY, code, 1)])
+    }
+
+    fn key_up(&self, key: SystemKey) -> Result<(), SystemError> {
+        let code = self.keycode(key)?;
+        write_events(&self.keyboard, &[(EV_KEY, code, 0)])
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), SystemError> {
+        // 入力できない文字がある場合は1文字も入力しない
+        let keys = text.chars()
+            .map(|c| char_to_evdev(c)
+                .ok_or_else(|| SystemError::UnsupportedOperation(format!("No evdev key for {:?}", c))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (code, shift) in keys {
+            if shift {
+                write_events(&self.keyboard, &[(EV_KEY, KEY_LEFTSHIFT, 1)])?;
+            }
+            write_events(&self.keyboard, &[(EV_KEY, code, 1)])?;
+            write_events(&self.keyboard, &[(EV_KEY, code, 0)])?;
+            if shift {
+                write_events(&self.keyboard, &[(EV_KEY, KEY_LEFTSHIFT, 0)])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_mouse_position(&self) -> Result<(i32, i32), SystemError> {
+        // uinputからは実際の位置を取得できないため、最後に送った位置を返す
+        Ok(*self.cursor.lock())
+    }
+
+    fn get_screen_size(&self) -> Result<(u32, u32), SystemError> {
+        Ok(self.screen_size)
+    }
+}
+
+/// uinputのデバイスファイルを開き、`configure`で設定してからデバイスを作成
+fn create_device(
+    path: &Path,
+    name: &str,
+    configure: impl FnOnce(&File) -> io::Result<()>,
+) -> Result<File, SystemError> {
+    let file = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => {
+                SystemError::PermissionError(format!("{}に書き込めません: {}", path.display(), e))
+            },
+            _ => SystemError::ApiError(format!("{}を開けません: {}", path.display(), e)),
+        })?;
+
+    let mut setup = libc::uinput_setup {
+        id: libc::input_id {
+            bustype: BUS_VIRTUAL,
+            vendor: 0,
+            product: 0,
+            version: 1,
+        },
+        name: [0; libc::UINPUT_MAX_NAME_SIZE],
+        ff_effects_max: 0,
+    };
+    for (dst, src) in setup.name.iter_mut().zip(name.bytes().take(libc::UINPUT_MAX_NAME_SIZE - 1)) {
+        *dst = src as libc::c_char;
+    }
+
+    configure(&file)
+        .and_then(|_| ioctl_ptr(&file, UI_DEV_SETUP, &setup))
+        .and_then(|_| ioctl(&file, UI_DEV_CREATE, 0))
+        .map_err(|e| SystemError::ApiError(format!("uinputデバイスの作成に失敗しました: {}", e)))?;
+
+    Ok(file)
+}
+
+/// `UI_SET_*BIT`でイベントの種類やコードを有効化
+fn set_bit(file: &File, request: u64, bit: u16) -> io::Result<()> {
+    ioctl(file, request, bit as libc::c_ulong)
+}
+
+/// 整数の引数でioctlを呼び出す
+fn ioctl(file: &File, request: u64, arg: libc::c_ulong) -> io::Result<()> {
+    // SAFETY: `file`は開いているファイルで、引数はポインターではない
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 構造体へのポインターを引数にしてioctlを呼び出す
+fn ioctl_ptr<T>(file: &File, request: u64, arg: &T) -> io::Result<()> {
+    // SAFETY: `request`は`T`の大きさを含むioctl番号で、カーネルは`arg`を読むだけ
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *const T) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// イベントの後に`SYN_REPORT`を付けて1度に書き込む
+fn write_events(device: &Mutex<Box<dyn Write + Send>>, events: &[(u16, u16, i32)]) -> Result<(), SystemError> {
+    let mut buffer = Vec::with_capacity((events.len() + 1) * size_of::<libc::input_event>());
+    for &(type_, code, value) in events.iter().chain(&[(EV_SYN, SYN_REPORT, 0)]) {
+        buffer.extend_from_slice(&[0; EVENT_TIME_SIZE]);
+        buffer.extend_from_slice(&type_.to_ne_bytes());
+        buffer.extend_from_slice(&code.to_ne_bytes());
+        buffer.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    device.lock().write_all(&buffer)
+        .map_err(|e| SystemError::ApiError(format!("uinputへの書き込みに失敗しました: {}", e)))
+}
+
+/// マウスボタンをevdevのボタンに変換
+fn evdev_button(button: SystemMouseButton) -> u16 {
+    match button {
+        SystemMouseButton::Left => BTN_LEFT,
+        SystemMouseButton::Right => BTN_RIGHT,
+        SystemMouseButton::Middle => BTN_MIDDLE,
+        SystemMouseButton::Back => BTN_SIDE,
+        SystemMouseButton::Forward => BTN_EXTRA,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// 書き込まれたイベントを記録する書き込み先
+    #[derive(Clone, Default)]
+    struct EventRecorder(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for EventRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl EventRecorder {
+        /// 記録したイベントを取り出す（`SYN_REPORT`は`(0, 0, 0)`）
+        fn take(&self) -> Vec<(u16, u16, i32)> {
+            let bytes = std::mem::take(&mut *self.0.lock());
+            bytes.chunks_exact(size_of::<libc::input_event>())
+                .map(|event| {
+                    let event = &event[EVENT_TIME_SIZE..];
+                    (
+                        u16::from_ne_bytes([event[0], event[1]]),
+                        u16::from_ne_bytes([event[2], event[3]]),
+                        i32::from_ne_bytes([event[4], event[5], event[6], event[7]]),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    const SYN: (u16, u16, i32) = (EV_SYN, SYN_REPORT, 0);
+
+    fn recording_input() -> (UinputInput, EventRecorder, EventRecorder) {
+        let (keyboard, pointer) = (EventRecorder::default(), EventRecorder::default());
+        let input = UinputInput::with_writers(Box::new(keyboard.clone()), Box::new(pointer.clone()), (800, 600));
+        (input, keyboard, pointer)
+    }
+
+    #[test]
+    fn test_keys_and_text() {
+        let (input, keyboard, pointer) = recording_input();
+
+        input.key_down(SystemKey(0x11)).unwrap();
+        input.key_down(SystemKey(0x43)).unwrap();
+        input.key_up(SystemKey(0x43)).unwrap();
+        input.key_up(SystemKey(0x11)).unwrap();
+        assert_eq!(keyboard.take(), vec![
+            (EV_KEY, 29, 1), SYN, // KEY_LEFTCTRL
+            (EV_KEY, 46, 1), SYN, // KEY_C
+            (EV_KEY, 46, 0), SYN,
+            (EV_KEY, 29, 0), SYN,
+        ]);
+        assert!(input.key_down(SystemKey(0xFF)).is_err());
+
+        input.input_text("a!").unwrap();
+        assert_eq!(keyboard.take(), vec![
+            (EV_KEY, 30, 1), SYN,
+            (EV_KEY, 30, 0), SYN,
+            (EV_KEY, KEY_LEFTSHIFT, 1), SYN,
+            (EV_KEY, 2, 1), SYN,
+            (EV_KEY, 2, 0), SYN,
+            (EV_KEY, KEY_LEFTSHIFT, 0), SYN,
+        ]);
+
+        // 入力できない文字を含む場合は何も送らない
+        assert!(input.input_text("aあ").is_err());
+        assert!(keyboard.take().is_empty());
+        assert!(pointer.take().is_empty());
+    }
+
+    #[test]
+    fn test_pointer_events() {
+        let (input, keyboard, pointer) = recording_input();
+        assert_eq!(input.get_mouse_position().unwrap(), (400, 300));
+
+        input.mouse_move(10, 20).unwrap();
+        input.mouse_move_relative(-30, 700).unwrap();
+        assert_eq!(input.get_mouse_position().unwrap(), (0, 599));
+
+        input.mouse_down(SystemMouseButton::Back).unwrap();
+        input.mouse_up(SystemMouseButton::Back).unwrap();
+        input.mouse_scroll(-2, 3).unwrap();
+        input.mouse_scroll(0, 0).unwrap();
+
+        assert_eq!(pointer.take(), vec![
+            (EV_ABS, ABS_X, 10), (EV_ABS, ABS_Y, 20), SYN,
+            (EV_ABS, ABS_X, 0), (EV_ABS, ABS_Y, 599), SYN,
+            (EV_KEY, BTN_SIDE, 1), SYN,
+            (EV_KEY, BTN_SIDE, 0), SYN,
+            (EV_REL, REL_WHEEL, 3), (EV_REL, REL_HWHEEL, -2), SYN,
+        ]);
+        assert!(keyboard.take().is_empty());
+    }
+
+    #[test]
+    fn test_create_device() {
+        // /dev/uinputに書き込めない環境では省略
+        let input = match UinputInput::new((640, 480)) {
+            Ok(input) => input,
+            Err(SystemError::PermissionError(_)) => return,
+            Err(_) if !Path::new(UINPUT_PATH).exists() => return,
+            Err(e) => panic!("{}", e),
+        };
+
+        input.mouse_move(320, 240).unwrap();
+        input.key_down(SystemKey(0x10)).unwrap();
+        input.key_up(SystemKey(0x10)).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+//! アクセス制御
+//!
+//! 接続元IPアドレスの許可/拒否リストを評価します。
+//! すべてのリスナーが`ClientSession`を作成する前にこのフィルタを参照します。
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use log::warn;
+use thiserror::Error;
+
+/// アクセス制御エラー
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AccessControlError {
+    /// 解釈できないアドレスまたはCIDR表記
+    #[error("不正なIPアドレスまたはCIDR表記です: {0}")]
+    InvalidRule(String),
+}
+
+/// IPアドレスの範囲（単一アドレスまたはCIDR）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRule {
+    /// ネットワークアドレス
+    network: IpAddr,
+    /// プレフィックス長
+    prefix_len: u8,
+}
+
+impl IpRule {
+    /// アドレスがこの範囲に含まれるかどうか
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, normalize(addr)) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = prefix_mask_v4(self.prefix_len);
+                u32::from(network) & mask == u32::from(addr) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = prefix_mask_v6(self.prefix_len);
+                u128::from(network) & mask == u128::from(addr) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRule {
+    type Err = AccessControlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AccessControlError::InvalidRule(s.to_string());
+        let s = s.trim();
+
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr = IpAddr::from_str(addr).map_err(|_| invalid())?;
+                let prefix = prefix.parse::<u8>().map_err(|_| invalid())?;
+                (addr, prefix)
+            },
+            None => {
+                let addr = IpAddr::from_str(s).map_err(|_| invalid())?;
+                let prefix = if addr.is_ipv4() { 32 } else { 128 };
+                (addr, prefix)
+            },
+        };
+
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_prefix {
+            return Err(invalid());
+        }
+
+        // IPv4射影アドレスの範囲はIPv4の範囲として扱う
+        let (network, prefix_len) = match addr {
+            IpAddr::V6(v6) if prefix_len >= 96 => match v6.to_ipv4_mapped() {
+                Some(v4) => (IpAddr::V4(v4), prefix_len - 96),
+                None => (addr, prefix_len),
+            },
+            _ => (addr, prefix_len),
+        };
+
+        Ok(Self { network, prefix_len })
+    }
+}
+
+/// IPv4射影IPv6アドレスをIPv4アドレスに変換
+fn normalize(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        _ => addr,
+    }
+}
+
+/// IPv4のプレフィックスマスク
+fn prefix_mask_v4(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+/// IPv6のプレフィックスマスク
+fn prefix_mask_v6(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0)
+}
+
+/// 接続元IPアドレスのアクセス制御
+///
+/// 拒否リストは許可リストより優先されます。許可リストが空の場合は、
+/// 拒否リストに含まれないすべてのアドレスを許可します。
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    /// 許可する範囲
+    allowed: Vec<IpRule>,
+    /// 拒否する範囲
+    denied: Vec<IpRule>,
+    /// 拒否した接続数（クローン間で共有）
+    rejected: Arc<AtomicU64>,
+}
+
+impl AccessControl {
+    /// 許可/拒否リストからアクセス制御を作成
+    pub fn new(allowed_ips: &[String], denied_ips: &[String]) -> Result<Self, AccessControlError> {
+        let parse = |list: &[String]| -> Result<Vec<IpRule>, AccessControlError> {
+            list.iter()
+                .filter(|entry| !entry.trim().is_empty())
+                .map(|entry| entry.parse())
+                .collect()
+        };
+
+        Ok(Self {
+            allowed: parse(allowed_ips)?,
+            denied: parse(denied_ips)?,
+            rejected: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// アドレスからの接続を許可するかどうか
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        if self.denied.iter().any(|rule| rule.contains(addr)) {
+            return false;
+        }
+
+        self.allowed.is_empty() || self.allowed.iter().any(|rule| rule.contains(addr))
+    }
+
+    /// 接続元をチェックし、拒否した場合はログに記録して件数を数える
+    pub fn check(&self, peer: &SocketAddr) -> bool {
+        if self.is_allowed(peer.ip()) {
+            return true;
+        }
+
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        warn!("アクセス制御により接続を拒否しました: {}", peer);
+        false
+    }
+
+    /// これまでに拒否した接続数
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(allowed: &[&str], denied: &[&str]) -> AccessControl {
+        let to_vec = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        AccessControl::new(&to_vec(allowed), &to_vec(denied)).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_empty_lists_allow_all() {
+        let acl = build(&[], &[]);
+        assert!(acl.is_allowed(ip("203.0.113.5")));
+        assert!(acl.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_cidr_ranges() {
+        let acl = build(&["192.168.1.0/24", "10.0.0.1", "2001:db8::/32"], &[]);
+        assert!(acl.is_allowed(ip("192.168.1.200")));
+        assert!(!acl.is_allowed(ip("192.168.2.1")));
+        assert!(acl.is_allowed(ip("10.0.0.1")));
+        assert!(!acl.is_allowed(ip("10.0.0.2")));
+        assert!(acl.is_allowed(ip("2001:db8:1234::1")));
+        assert!(!acl.is_allowed(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn test_deny_wins() {
+        let acl = build(&["10.0.0.0/8"], &["10.1.0.0/16"]);
+        assert!(acl.is_allowed(ip("10.2.3.4")));
+        assert!(!acl.is_allowed(ip("10.1.3.4")));
+
+        let acl = build(&["::/0", "0.0.0.0/0"], &["fe80::/10"]);
+        assert!(!acl.is_allowed(ip("fe80::1")));
+        assert!(acl.is_allowed(ip("::1")));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses() {
+        let acl = build(&["127.0.0.1"], &[]);
+        assert!(acl.is_allowed(ip("::ffff:127.0.0.1")));
+
+        let acl = build(&[], &["::ffff:192.168.0.0/112"]);
+        assert!(!acl.is_allowed(ip("192.168.4.4")));
+    }
+
+    #[test]
+    fn test_rejections_are_counted() {
+        let acl = build(&[], &["198.51.100.0/24"]);
+        let shared = acl.clone();
+        assert!(!shared.check(&"198.51.100.7:4000".parse().unwrap()));
+        assert!(acl.check(&"192.0.2.1:4000".parse().unwrap()));
+        assert_eq!(acl.rejected_count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(AccessControl::new(&["10.0.0.0/33".to_string()], &[]).is_err());
+        assert!(AccessControl::new(&[], &["not-an-ip".to_string()]).is_err());
+        assert!(AccessControl::new(&["".to_string()], &[]).is_ok());
+    }
+}
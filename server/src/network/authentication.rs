@@ -2,24 +2,321 @@
 //!
 //! ユーザー認証を担当するモジュール
 
+use crate::ui::settings::UserCredential;
+use remote_desktop_rs_common::encryption::{
+    generate_auth_nonce, generate_salt, hmac_sha256, password_hash_params,
+    verify_challenge_response, PASSWORD_HASH_ITERATIONS,
+};
+use remote_desktop_rs_common::protocol::AuthMethod;
+use remote_desktop_rs_common::signing::{parse_public_key, verify_challenge_signature};
+use remote_desktop_rs_common::totp::{Totp, DEFAULT_SKEW};
+
+use base64::Engine;
+use log::{error, warn};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// 認証エラー
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// ユーザー名またはパスワードが不正
+    #[error("ユーザー名またはパスワードが正しくありません")]
+    InvalidCredentials,
+
+    /// ユーザー名が空
+    #[error("ユーザー名が指定されていません")]
+    EmptyUsername,
+
+    /// 応答に対応するチャレンジが発行されていない
+    #[error("認証チャレンジが発行されていません")]
+    NoPendingChallenge,
+
+    /// ワンタイムパスワードが不正、または使用済み
+    #[error("ワンタイムパスワードが正しくありません")]
+    InvalidOtp,
+
+    /// ワンタイムパスワードを要求していない
+    #[error("ワンタイムパスワードは要求されていません")]
+    NoPendingOtp,
+}
+
+/// 認証済みユーザー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    /// ユーザー名
+    pub username: String,
+    /// 権限レベル（0=ビューアー、1=制御者、2=管理者）
+    pub permission_level: u8,
+}
+
+/// 発行済みの認証チャレンジ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthChallenge {
+    /// 対象のユーザー名
+    pub username: String,
+    /// ランダムなノンス（Base64）
+    pub nonce: String,
+    /// パスワードハッシュのソルト（Base64、公開鍵認証では空）
+    pub salt: String,
+    /// PBKDF2の反復回数（公開鍵認証では0）
+    pub iterations: u32,
+    /// 公開鍵認証で使用する公開鍵（パスワード認証では`None`）
+    pub public_key: Option<String>,
+}
 
 /// 認証ハンドラ
 #[derive(Clone)]
 pub struct Authenticator {
-    // 内部状態
+    /// ユーザー名をキーとした認証情報
+    users: HashMap<String, UserCredential>,
+    /// 未登録ユーザー向けのダミーソルトを導出する秘密鍵
+    decoy_key: Vec<u8>,
+    /// ユーザーごとに最後に受け付けたTOTPの時間ステップ（再利用防止）
+    used_otp_steps: Arc<Mutex<HashMap<String, u64>>>,
 }
 
 impl Authenticator {
-    /// 新しい認証ハンドラを作成
-    pub fn new() -> Self {
-        Self {}
-    }
-    
-    /// ユーザー認証を行う
-    pub fn authenticate(&self, username: &str, password: &str) -> bool {
-        // 実際の認証ロジックを実装
-        // 簡易的な例として、usernameとpasswordが一致していれば認証成功
-        !username.is_empty() && username == password
+    /// 認証情報のリストから認証ハンドラを作成
+    pub fn new(users: Vec<UserCredential>) -> Self {
+        let users = users
+            .into_iter()
+            .map(|user| (user.username.clone(), user))
+            .collect();
+
+        Self {
+            users,
+            decoy_key: generate_salt(32),
+            used_otp_steps: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 認証チャレンジを発行
+    ///
+    /// 未登録のユーザーにもユーザー名から導出したダミーのソルトで応答し、
+    /// ユーザーの存在を推測できないようにします。公開鍵認証では鍵が登録済みかどうかに
+    /// 関わらずノンスのみを返します。
+    pub fn create_challenge(&self, username: &str, method: &AuthMethod) -> Result<AuthChallenge, AuthError> {
+        if username.is_empty() {
+            return Err(AuthError::EmptyUsername);
+        }
+
+        if let AuthMethod::PublicKey { public_key } = method {
+            return Ok(AuthChallenge {
+                username: username.to_string(),
+                nonce: generate_auth_nonce(),
+                salt: String::new(),
+                iterations: 0,
+                public_key: Some(public_key.clone()),
+            });
+        }
+
+        let (iterations, salt) = self.users
+            .get(username)
+            .and_then(|user| password_hash_params(&user.password_hash))
+            .unwrap_or_else(|| (PASSWORD_HASH_ITERATIONS, self.decoy_salt(username)));
+
+        Ok(AuthChallenge {
+            username: username.to_string(),
+            nonce: generate_auth_nonce(),
+            salt,
+            iterations,
+            public_key: None,
+        })
+    }
+
+    /// チャレンジへの応答を検証
+    ///
+    /// パスワード認証ではSCRAM形式の証明、公開鍵認証ではノンスへの署名を検証します。
+    pub fn verify_response(&self, challenge: &AuthChallenge, response: &str) -> Result<AuthenticatedUser, AuthError> {
+        let user = match self.users.get(&challenge.username) {
+            Some(user) => user,
+            None => return Err(AuthError::InvalidCredentials),
+        };
+
+        let verified = match &challenge.public_key {
+            Some(public_key) => Self::verify_key_response(user, public_key, challenge, response),
+            None => verify_challenge_response(&user.password_hash, &challenge.nonce, response),
+        };
+
+        if !verified {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        Ok(AuthenticatedUser {
+            username: user.username.clone(),
+            permission_level: user.permission_level,
+        })
+    }
+
+    /// 登録済みの公開鍵による署名を検証
+    fn verify_key_response(user: &UserCredential, public_key: &str, challenge: &AuthChallenge, signature: &str) -> bool {
+        let presented = match parse_public_key(public_key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+
+        let authorized = user.authorized_keys.iter().any(|entry| match parse_public_key(entry) {
+            Ok(key) => key == presented,
+            Err(e) => {
+                warn!("登録された公開鍵が不正です: {}: {}", user.username, e);
+                false
+            }
+        });
+
+        authorized && verify_challenge_signature(&presented, &challenge.username, &challenge.nonce, signature)
+    }
+
+    /// ユーザーがワンタイムパスワードを必要とするかどうか
+    pub fn requires_otp(&self, username: &str) -> bool {
+        self.users
+            .get(username)
+            .is_some_and(|user| user.totp_secret.is_some())
+    }
+
+    /// パスワード認証済みユーザーのワンタイムパスワードを検証
+    pub fn verify_otp(&self, user: &AuthenticatedUser, code: &str) -> Result<(), AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.verify_otp_at(user, code, now)
+    }
+
+    /// 指定したUNIX時刻でワンタイムパスワードを検証
+    ///
+    /// 一度受け付けたコードと、それ以前の時間ステップのコードは拒否します。
+    pub fn verify_otp_at(&self, user: &AuthenticatedUser, code: &str, unix_time: u64) -> Result<(), AuthError> {
+        let secret = match self.users.get(&user.username).and_then(|u| u.totp_secret.as_deref()) {
+            Some(secret) => secret,
+            None => return Err(AuthError::NoPendingOtp),
+        };
+
+        // 設定が壊れている場合は認証を通さない
+        let totp = Totp::from_base32(secret).map_err(|e| {
+            error!("TOTPシークレットが不正です: {}: {}", user.username, e);
+            AuthError::InvalidOtp
+        })?;
+
+        let step = totp.verify_at(code, unix_time, DEFAULT_SKEW).ok_or(AuthError::InvalidOtp)?;
+
+        let mut used = self.used_otp_steps.lock().unwrap();
+        if used.get(&user.username).is_some_and(|&last| step <= last) {
+            return Err(AuthError::InvalidOtp);
+        }
+        used.insert(user.username.clone(), step);
+
+        Ok(())
+    }
+
+    /// 未登録ユーザー向けのダミーソルトを導出
+    fn decoy_salt(&self, username: &str) -> String {
+        let digest = hmac_sha256(&self.decoy_key, username.as_bytes()).unwrap_or_default();
+        base64::engine::general_purpose::STANDARD.encode(&digest[..digest.len().min(16)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_desktop_rs_common::encryption::{compute_challenge_response, hash_password_with};
+    use remote_desktop_rs_common::signing::ClientKeypair;
+
+    /// RFC 6238のSHA1シークレット"12345678901234567890"のBase32表現
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn authenticator() -> Authenticator {
+        let password_hash = hash_password_with("secret", b"0123456789abcdef", 1000).unwrap();
+        Authenticator::new(vec![
+            UserCredential {
+                username: "admin".to_string(),
+                password_hash: password_hash.clone(),
+                permission_level: 2,
+                totp_secret: Some(RFC_SECRET.to_string()),
+                authorized_keys: Vec::new(),
+            },
+            UserCredential {
+                username: "viewer".to_string(),
+                password_hash,
+                permission_level: 0,
+                totp_secret: None,
+                authorized_keys: Vec::new(),
+            },
+        ])
+    }
+
+    fn login(auth: &Authenticator, username: &str, password: &str) -> Result<AuthenticatedUser, AuthError> {
+        let challenge = auth.create_challenge(username, &AuthMethod::Password)?;
+        let response = compute_challenge_response(password, &challenge.salt, challenge.iterations, &challenge.nonce)
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        auth.verify_response(&challenge, &response)
+    }
+
+    #[test]
+    fn test_password_then_otp() {
+        let auth = authenticator();
+
+        assert_eq!(login(&auth, "admin", "wrong"), Err(AuthError::InvalidCredentials));
+
+        let user = login(&auth, "admin", "secret").unwrap();
+        assert!(auth.requires_otp(&user.username));
+
+        // RFC 6238: T=59の6桁コードは287082
+        assert_eq!(auth.verify_otp_at(&user, "000000", 59), Err(AuthError::InvalidOtp));
+        assert_eq!(auth.verify_otp_at(&user, "287082", 59), Ok(()));
+    }
+
+    #[test]
+    fn test_otp_replay_is_rejected() {
+        let auth = authenticator();
+        let user = login(&auth, "admin", "secret").unwrap();
+
+        // T=1111111109のコード（081804）は次のステップでも許容範囲内
+        assert_eq!(auth.verify_otp_at(&user, "081804", 1111111109), Ok(()));
+        assert_eq!(auth.verify_otp_at(&user, "081804", 1111111111), Err(AuthError::InvalidOtp));
+        assert_eq!(auth.verify_otp_at(&user, "050471", 1111111111), Ok(()));
+    }
+
+    #[test]
+    fn test_users_without_secret_skip_otp() {
+        let auth = authenticator();
+        let user = login(&auth, "viewer", "secret").unwrap();
+
+        assert!(!auth.requires_otp(&user.username));
+        assert_eq!(auth.verify_otp_at(&user, "287082", 59), Err(AuthError::NoPendingOtp));
+    }
+
+    #[test]
+    fn test_public_key_login() {
+        let keypair = ClientKeypair::generate();
+        let auth = Authenticator::new(vec![UserCredential {
+            username: "robot".to_string(),
+            password_hash: String::new(),
+            permission_level: 1,
+            totp_secret: None,
+            authorized_keys: vec![format!("{} ci@example", keypair.public_key())],
+        }]);
+        let method = AuthMethod::PublicKey { public_key: keypair.public_key() };
+
+        let challenge = auth.create_challenge("robot", &method).unwrap();
+        assert_eq!(challenge.iterations, 0);
+        let signature = keypair.sign_challenge("robot", &challenge.nonce);
+        let user = auth.verify_response(&challenge, &signature).unwrap();
+        assert_eq!(user.permission_level, 1);
+
+        // 署名は別のノンスには使い回せない
+        let next = auth.create_challenge("robot", &method).unwrap();
+        assert_eq!(auth.verify_response(&next, &signature), Err(AuthError::InvalidCredentials));
+
+        // 未登録の鍵は正しく署名していても拒否する
+        let stranger = ClientKeypair::generate();
+        let method = AuthMethod::PublicKey { public_key: stranger.public_key() };
+        let challenge = auth.create_challenge("robot", &method).unwrap();
+        let signature = stranger.sign_challenge("robot", &challenge.nonce);
+        assert_eq!(auth.verify_response(&challenge, &signature), Err(AuthError::InvalidCredentials));
+
+        // パスワードハッシュが空のユーザーはパスワードでログインできない
+        assert_eq!(login(&auth, "robot", ""), Err(AuthError::InvalidCredentials));
     }
 }
@@ -0,0 +1,475 @@
+//! 帯域に応じた配信品質の調整
+//!
+//! 配信中のセッションごとに、フレームの送信バイト数、`Ping`から求めたRTT、
+//! `Command::StreamCredit`でフレームの受信が確認されるまでの時間を記録します。
+//! 受信確認の遅れが平常時より大きくなったら輻輳とみなし、画質・FPS・解像度の順に下げます。
+//! 遅れのない状態がしばらく続いたら、逆の順に設定の上限まで戻します。
+
+use remote_desktop_rs_common::protocol::StreamQuality;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// 調整を判断する間隔
+pub const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 平常時の受信確認の遅れに対して、輻輳とみなすまでの余裕
+pub const CONGESTION_MARGIN: Duration = Duration::from_millis(150);
+
+/// 品質を上げるまでに必要な、輻輳のない調整間隔の数
+pub const RECOVERY_PERIODS: u32 = 3;
+
+/// 品質を上げるときの画質の刻み
+const QUALITY_STEP: u8 = 5;
+
+/// 品質を上げるときのFPSの刻み
+const FPS_STEP: u8 = 5;
+
+/// 解像度の倍率の刻み
+const SCALE_STEP: f32 = 0.125;
+
+/// RTTの平滑化係数
+const RTT_SMOOTHING: f64 = 0.125;
+
+/// 動画の目標ビットレートの下限（bps）
+const MIN_VIDEO_BITRATE: u32 = 250_000;
+
+/// 調整する範囲
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityBounds {
+    /// 最低画質
+    pub min_quality: u8,
+    /// 最高画質
+    pub max_quality: u8,
+    /// 最小の解像度の倍率
+    pub min_scale: f32,
+    /// 最大の解像度の倍率
+    pub max_scale: f32,
+    /// 最低FPS
+    pub min_fps: u8,
+    /// 最高FPS
+    pub max_fps: u8,
+}
+
+impl Default for QualityBounds {
+    fn default() -> Self {
+        Self {
+            min_quality: 20,
+            max_quality: 90,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            min_fps: 5,
+            max_fps: 30,
+        }
+    }
+}
+
+impl QualityBounds {
+    /// 下限が上限を超えないように補正した範囲
+    ///
+    /// 設定ファイルの値は下限と上限が逆になっている場合があるため、上限を有効な範囲に収めてから
+    /// 下限を上限以下に丸めます。
+    pub fn normalized(self) -> Self {
+        let max_quality = self.max_quality.min(100);
+        let max_scale = self.max_scale.clamp(SCALE_STEP, 1.0);
+        let max_fps = self.max_fps.max(1);
+        Self {
+            min_quality: self.min_quality.min(max_quality),
+            max_quality,
+            min_scale: self.min_scale.max(SCALE_STEP).min(max_scale),
+            max_scale,
+            min_fps: self.min_fps.max(1).min(max_fps),
+            max_fps,
+        }
+    }
+}
+
+/// セッションごとの輻輳制御
+#[derive(Debug, Clone)]
+pub struct CongestionController {
+    /// 調整する範囲
+    bounds: QualityBounds,
+    /// クライアントが要求した画質（画質を戻す上限）
+    target_quality: u8,
+    /// クライアントが要求したFPS（FPSを戻す上限）
+    target_fps: u8,
+    /// 現在の画質
+    quality: u8,
+    /// 現在の解像度の倍率
+    scale: f32,
+    /// 現在のFPS
+    fps: u8,
+    /// 受信確認待ちのフレームの送信時刻（送信順）
+    in_flight: VecDeque<Instant>,
+    /// 平滑化したRTT
+    smoothed_rtt: Option<Duration>,
+    /// 受信確認までの時間の最小値（平常時の遅れ）
+    base_delay: Option<Duration>,
+    /// 現在の調整間隔での受信確認までの時間の合計
+    window_delay: Duration,
+    /// 現在の調整間隔での受信確認の数
+    window_acks: u32,
+    /// 現在の調整間隔での送信バイト数
+    window_bytes: u64,
+    /// 現在の調整間隔での送信フレーム数
+    window_frames: u32,
+    /// 現在の調整間隔の開始時刻
+    window_start: Instant,
+    /// 直前の調整間隔のビットレート（bps）
+    bitrate: u64,
+    /// 輻輳のない調整間隔が続いた数
+    clear_periods: u32,
+}
+
+impl CongestionController {
+    /// 範囲の上限から始める輻輳制御を作成
+    pub fn new(bounds: QualityBounds, now: Instant) -> Self {
+        let bounds = bounds.normalized();
+        Self {
+            bounds,
+            target_quality: bounds.max_quality,
+            target_fps: bounds.max_fps,
+            quality: bounds.max_quality,
+            scale: bounds.max_scale,
+            fps: bounds.max_fps,
+            in_flight: VecDeque::new(),
+            smoothed_rtt: None,
+            base_delay: None,
+            window_delay: Duration::ZERO,
+            window_acks: 0,
+            window_bytes: 0,
+            window_frames: 0,
+            window_start: now,
+            bitrate: 0,
+            clear_periods: 0,
+        }
+    }
+
+    /// 現在の画質
+    pub fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    /// 現在の解像度の倍率
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// 現在のFPS
+    pub fn fps(&self) -> u8 {
+        self.fps
+    }
+
+    /// クライアントが要求した画質を設定（範囲内に丸め、以降はこれを上限に調整する）
+    pub fn set_target_quality(&mut self, quality: u8) {
+        self.target_quality = quality.clamp(self.bounds.min_quality, self.bounds.max_quality);
+        self.quality = self.target_quality;
+    }
+
+    /// クライアントが要求したFPSを設定（範囲内に丸め、以降はこれを上限に調整する）
+    pub fn set_target_fps(&mut self, fps: u8) {
+        self.target_fps = fps.clamp(self.bounds.min_fps, self.bounds.max_fps);
+        self.fps = self.target_fps;
+    }
+
+    /// 配信の開始時に、前回の配信の計測結果を破棄
+    pub fn restart(&mut self, now: Instant) {
+        self.in_flight.clear();
+        self.window_delay = Duration::ZERO;
+        self.window_acks = 0;
+        self.window_bytes = 0;
+        self.window_frames = 0;
+        self.window_start = now;
+        self.clear_periods = 0;
+    }
+
+    /// フレームの送信を記録
+    pub fn on_frame_sent(&mut self, bytes: usize, now: Instant) {
+        self.in_flight.push_back(now);
+        self.window_bytes += bytes as u64;
+        self.window_frames += 1;
+    }
+
+    /// クライアントの受信確認（返されたクレジット）を記録
+    ///
+    /// 送信していないフレームの分（変更がなく送らなかった分など）は無視します。
+    pub fn on_frames_acked(&mut self, count: u32, now: Instant) {
+        for _ in 0..count {
+            let sent = match self.in_flight.pop_front() {
+                Some(sent) => sent,
+                None => break,
+            };
+            let delay = now.saturating_duration_since(sent);
+            self.base_delay = Some(self.base_delay.map_or(delay, |base| base.min(delay)));
+            self.window_delay += delay;
+            self.window_acks += 1;
+        }
+    }
+
+    /// `Ping`から求めたRTTを記録
+    pub fn on_rtt_sample(&mut self, rtt: Duration) {
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            Some(smoothed) => smoothed.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING),
+            None => rtt,
+        });
+    }
+
+    /// 調整間隔が過ぎていれば品質を調整し、変更した場合は`true`を返す
+    pub fn update(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < ADJUST_INTERVAL {
+            return false;
+        }
+
+        self.bitrate = (self.window_bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u64;
+        let congested = self.is_congested(now);
+        let idle = self.window_frames == 0 && self.window_acks == 0;
+
+        self.window_delay = Duration::ZERO;
+        self.window_acks = 0;
+        self.window_bytes = 0;
+        self.window_frames = 0;
+        self.window_start = now;
+
+        if congested {
+            self.clear_periods = 0;
+            return self.step_down();
+        }
+
+        // 何も送っていない間は帯域に余裕があるかどうか分からない
+        if idle {
+            return false;
+        }
+
+        self.clear_periods += 1;
+        if self.clear_periods < RECOVERY_PERIODS {
+            return false;
+        }
+        self.clear_periods = 0;
+        self.step_up()
+    }
+
+    /// 動画の目標ビットレート（bps）
+    ///
+    /// `max_bitrate`を現在の画質と解像度の倍率（面積）に合わせて下げます。
+    pub fn video_bitrate(&self, max_bitrate: u32) -> u32 {
+        let ratio = self.quality as f64 / 100.0 * (self.scale as f64).powi(2);
+        ((max_bitrate as f64 * ratio) as u32).clamp(MIN_VIDEO_BITRATE.min(max_bitrate), max_bitrate)
+    }
+
+    /// 現在の調整結果
+    pub fn decision(&self) -> StreamQuality {
+        StreamQuality {
+            quality: self.quality,
+            scale: self.scale,
+            fps: self.fps,
+            bitrate: self.bitrate,
+            rtt_ms: self.smoothed_rtt.map(|rtt| rtt.as_millis() as u64),
+        }
+    }
+
+    /// 受信確認が平常時より遅れているかどうか
+    fn is_congested(&self, now: Instant) -> bool {
+        let base = self.base_delay.unwrap_or(Duration::ZERO);
+        let limit = base.max(self.smoothed_rtt.unwrap_or(Duration::ZERO)) + CONGESTION_MARGIN;
+
+        // 受信確認が1つも返らないまま古いフレームが残っている場合も輻輳とみなす
+        if let Some(oldest) = self.in_flight.front() {
+            if now.saturating_duration_since(*oldest) > limit.max(ADJUST_INTERVAL) {
+                return true;
+            }
+        }
+
+        self.window_acks > 0 && self.window_delay / self.window_acks > limit
+    }
+
+    /// 画質・FPS・解像度の順に1段階下げる
+    fn step_down(&mut self) -> bool {
+        if self.quality > self.bounds.min_quality {
+            self.quality = (self.quality as u32 * 3 / 4).max(self.bounds.min_quality as u32) as u8;
+        } else if self.fps > self.bounds.min_fps {
+            self.fps = (self.fps as u32 * 3 / 4).max(self.bounds.min_fps as u32) as u8;
+        } else if self.scale > self.bounds.min_scale {
+            self.scale = (self.scale - SCALE_STEP).max(self.bounds.min_scale);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// 解像度・FPS・画質の順に1段階戻す
+    fn step_up(&mut self) -> bool {
+        if self.scale < self.bounds.max_scale {
+            self.scale = (self.scale + SCALE_STEP).min(self.bounds.max_scale);
+        } else if self.fps < self.target_fps {
+            self.fps = self.fps.saturating_add(FPS_STEP).min(self.target_fps);
+        } else if self.quality < self.target_quality {
+            self.quality = self.quality.saturating_add(QUALITY_STEP).min(self.target_quality);
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1つの調整間隔で、指定した遅れで受信確認されるフレームを送る
+    fn send_period(controller: &mut CongestionController, start: Instant, delay: Duration) -> Instant {
+        for i in 0..10 {
+            let sent = start + Duration::from_millis(i * 100);
+            controller.on_frame_sent(10_000, sent);
+            controller.on_frames_acked(1, sent + delay);
+        }
+        start + ADJUST_INTERVAL
+    }
+
+    #[test]
+    fn test_steps_down_quality_then_fps_then_scale() {
+        let bounds = QualityBounds {
+            min_quality: 40,
+            max_quality: 60,
+            min_scale: 0.75,
+            max_scale: 1.0,
+            min_fps: 10,
+            max_fps: 15,
+        };
+        let now = Instant::now();
+        let mut controller = CongestionController::new(bounds, now);
+
+        // 平常時の遅れを記録
+        let now = send_period(&mut controller, now, Duration::from_millis(20));
+        assert!(!controller.update(now));
+        assert_eq!(controller.decision().bitrate, 800_000);
+
+        let mut decisions = Vec::new();
+        let mut now = now;
+        for _ in 0..6 {
+            now = send_period(&mut controller, now, Duration::from_millis(500));
+            controller.update(now);
+            decisions.push((controller.quality(), controller.fps(), controller.scale()));
+        }
+
+        assert_eq!(decisions, vec![
+            (45, 15, 1.0),
+            (40, 15, 1.0),
+            (40, 11, 1.0),
+            (40, 10, 1.0),
+            (40, 10, 0.875),
+            (40, 10, 0.75),
+        ]);
+        assert!(!controller.step_down());
+    }
+
+    #[test]
+    fn test_video_bitrate_follows_congestion() {
+        let bounds = QualityBounds {
+            min_quality: 40,
+            max_quality: 80,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            min_fps: 15,
+            max_fps: 15,
+        };
+        let now = Instant::now();
+        let mut controller = CongestionController::new(bounds, now);
+        assert_eq!(controller.video_bitrate(5_000_000), 4_000_000);
+
+        let mut now = send_period(&mut controller, now, Duration::from_millis(20));
+        controller.update(now);
+        let mut bitrates = vec![controller.video_bitrate(5_000_000)];
+        for _ in 0..4 {
+            now = send_period(&mut controller, now, Duration::from_millis(500));
+            controller.update(now);
+            bitrates.push(controller.video_bitrate(5_000_000));
+        }
+
+        // 画質と解像度が下がるたびにビットレートも下がる
+        assert!(bitrates.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", bitrates);
+        assert_eq!(controller.video_bitrate(100_000), 100_000);
+    }
+
+    #[test]
+    fn test_recovers_up_to_client_target() {
+        let now = Instant::now();
+        let mut controller = CongestionController::new(QualityBounds::default(), now);
+        controller.set_target_quality(50);
+        controller.set_target_fps(10);
+        assert_eq!((controller.quality(), controller.fps()), (50, 10));
+
+        let now = send_period(&mut controller, now, Duration::from_millis(20));
+        controller.update(now);
+        let mut now = send_period(&mut controller, now, Duration::from_millis(600));
+        assert!(controller.update(now));
+        assert_eq!(controller.quality(), 37);
+
+        // 輻輳のない間隔が続くまでは戻さない
+        for _ in 1..RECOVERY_PERIODS {
+            now = send_period(&mut controller, now, Duration::from_millis(20));
+            assert!(!controller.update(now));
+        }
+        now = send_period(&mut controller, now, Duration::from_millis(20));
+        assert!(controller.update(now));
+        assert_eq!(controller.quality(), 42);
+
+        for _ in 0..(RECOVERY_PERIODS * 4) {
+            now = send_period(&mut controller, now, Duration::from_millis(20));
+            controller.update(now);
+        }
+        assert_eq!(controller.quality(), 50);
+        assert_eq!(controller.fps(), 10);
+    }
+
+    #[test]
+    fn test_unacknowledged_frames_count_as_congestion() {
+        let now = Instant::now();
+        let mut controller = CongestionController::new(QualityBounds::default(), now);
+        controller.on_frame_sent(50_000, now);
+
+        assert!(!controller.update(now + Duration::from_millis(500)));
+        assert!(controller.update(now + Duration::from_secs(2)));
+        assert!(controller.quality() < QualityBounds::default().max_quality);
+    }
+
+    #[test]
+    fn test_bounds_clamp_client_requests() {
+        let mut controller = CongestionController::new(QualityBounds::default(), Instant::now());
+        controller.set_target_quality(100);
+        controller.set_target_fps(1);
+        controller.on_rtt_sample(Duration::from_millis(80));
+
+        let decision = controller.decision();
+        assert_eq!(decision.quality, 90);
+        assert_eq!(decision.fps, 5);
+        assert_eq!(decision.rtt_ms, Some(80));
+    }
+
+    #[test]
+    fn test_inverted_bounds_are_normalized() {
+        let bounds = QualityBounds {
+            min_quality: 120,
+            max_quality: 80,
+            min_scale: 2.0,
+            max_scale: 0.5,
+            min_fps: 5,
+            max_fps: 3,
+        };
+        assert_eq!(bounds.normalized(), QualityBounds {
+            min_quality: 80,
+            max_quality: 80,
+            min_scale: 0.5,
+            max_scale: 0.5,
+            min_fps: 3,
+            max_fps: 3,
+        });
+
+        // 逆転した範囲でもクライアントの要求を丸められる
+        let mut controller = CongestionController::new(bounds, Instant::now());
+        controller.set_target_quality(10);
+        controller.set_target_fps(30);
+        assert_eq!(controller.quality(), 80);
+        assert_eq!(controller.fps(), 3);
+        assert_eq!(controller.scale(), 0.5);
+    }
+}
@@ -0,0 +1,344 @@
+//! ログイン試行の追跡
+//!
+//! 認証失敗を接続元IPアドレスとユーザー名ごとに記録し、
+//! 指数バックオフとアカウントロックアウトを適用します。
+//! すべてのトランスポートで同じインスタンスを共有します。
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// バックオフ中の再試行に返すエラーコード
+pub const ERROR_CODE_TOO_MANY_ATTEMPTS: i32 = 429;
+
+/// ロックアウト中に返すエラーコード
+pub const ERROR_CODE_LOCKED_OUT: i32 = 423;
+
+/// 記録を保持する最大件数（超えた場合は期限切れの記録を削除）
+const MAX_TRACKED_ENTRIES: usize = 4096;
+
+/// ロックアウトポリシー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// ロックアウトまでの連続失敗回数（0の場合はロックアウトしない）
+    pub max_failures: u32,
+    /// ロックアウト時間
+    pub lockout_duration: Duration,
+    /// 最初の失敗後の待機時間（失敗ごとに倍増）
+    pub base_delay: Duration,
+    /// 待機時間の上限
+    pub max_delay: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            lockout_duration: Duration::from_secs(300),
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// ログインが拒否された理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginBlocked {
+    /// 直前の失敗からの待機時間中
+    Backoff {
+        /// 再試行できるまでの時間
+        retry_after: Duration,
+    },
+    /// ロックアウト中
+    LockedOut {
+        /// ロックアウト解除までの時間
+        retry_after: Duration,
+    },
+}
+
+impl LoginBlocked {
+    /// クライアントに返すエラーコード
+    pub fn error_code(&self) -> i32 {
+        match self {
+            LoginBlocked::Backoff { .. } => ERROR_CODE_TOO_MANY_ATTEMPTS,
+            LoginBlocked::LockedOut { .. } => ERROR_CODE_LOCKED_OUT,
+        }
+    }
+
+    /// クライアントに返すメッセージ
+    pub fn message(&self) -> String {
+        match self {
+            LoginBlocked::Backoff { retry_after } => format!(
+                "ログイン試行が多すぎます。{}秒後に再試行してください",
+                retry_after.as_secs().max(1)
+            ),
+            LoginBlocked::LockedOut { retry_after } => format!(
+                "ログイン失敗が続いたためロックされています。{}秒後に再試行してください",
+                retry_after.as_secs().max(1)
+            ),
+        }
+    }
+
+    /// 再試行できるまでの時間
+    fn retry_after(&self) -> Duration {
+        match self {
+            LoginBlocked::Backoff { retry_after } | LoginBlocked::LockedOut { retry_after } => *retry_after,
+        }
+    }
+}
+
+/// 失敗の記録
+#[derive(Debug, Clone, Copy)]
+struct AttemptRecord {
+    /// 連続失敗回数
+    failures: u32,
+    /// 最後の失敗時刻
+    last_failure: Instant,
+    /// ロックアウト解除時刻
+    locked_until: Option<Instant>,
+}
+
+/// ログイン試行トラッカー
+#[derive(Debug)]
+pub struct LoginAttemptTracker {
+    /// ポリシー
+    policy: LockoutPolicy,
+    /// 接続元IPアドレスごとの記録
+    by_ip: Mutex<HashMap<IpAddr, AttemptRecord>>,
+    /// ユーザー名ごとの記録
+    by_user: Mutex<HashMap<String, AttemptRecord>>,
+}
+
+impl Default for LoginAttemptTracker {
+    fn default() -> Self {
+        Self::new(LockoutPolicy::default())
+    }
+}
+
+impl LoginAttemptTracker {
+    /// 新しいトラッカーを作成
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            by_ip: Mutex::new(HashMap::new()),
+            by_user: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// ポリシーを取得
+    pub fn policy(&self) -> LockoutPolicy {
+        self.policy
+    }
+
+    /// ログインを試行してよいかを確認
+    pub fn check(&self, ip: IpAddr, username: &str) -> Result<(), LoginBlocked> {
+        self.check_at(ip, username, Instant::now())
+    }
+
+    /// 認証失敗を記録
+    pub fn record_failure(&self, ip: IpAddr, username: &str) {
+        self.record_failure_at(ip, username, Instant::now());
+    }
+
+    /// 認証成功を記録（失敗回数をリセット）
+    pub fn record_success(&self, ip: IpAddr, username: &str) {
+        self.by_ip.lock().unwrap().remove(&ip);
+        self.by_user.lock().unwrap().remove(username);
+    }
+
+    fn check_at(&self, ip: IpAddr, username: &str, now: Instant) -> Result<(), LoginBlocked> {
+        let ip_block = self.by_ip.lock().unwrap().get(&ip).and_then(|record| self.blocked(record, now));
+        let user_block = self.by_user.lock().unwrap().get(username).and_then(|record| self.blocked(record, now));
+
+        // ロックアウトを優先し、同種の場合は長い方を返す
+        let block = match (ip_block, user_block) {
+            (Some(a), Some(b)) => Some(match (a, b) {
+                (LoginBlocked::LockedOut { .. }, LoginBlocked::Backoff { .. }) => a,
+                (LoginBlocked::Backoff { .. }, LoginBlocked::LockedOut { .. }) => b,
+                _ if a.retry_after() >= b.retry_after() => a,
+                _ => b,
+            }),
+            (a, b) => a.or(b),
+        };
+
+        match block {
+            Some(block) => Err(block),
+            None => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, username: &str, now: Instant) {
+        let mut by_ip = self.by_ip.lock().unwrap();
+        self.prune(&mut by_ip, now);
+        self.bump(by_ip.entry(ip).or_insert_with(|| Self::empty_record(now)), now);
+        drop(by_ip);
+
+        let mut by_user = self.by_user.lock().unwrap();
+        self.prune(&mut by_user, now);
+        self.bump(by_user.entry(username.to_string()).or_insert_with(|| Self::empty_record(now)), now);
+    }
+
+    fn empty_record(now: Instant) -> AttemptRecord {
+        AttemptRecord {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        }
+    }
+
+    /// 失敗回数を増やし、必要ならロックアウトする
+    fn bump(&self, record: &mut AttemptRecord, now: Instant) {
+        // ロックアウトが明けた、または長時間失敗がなければ数え直す
+        let lock_expired = record.locked_until.is_some_and(|until| now >= until);
+        if lock_expired || now.duration_since(record.last_failure) >= self.policy.lockout_duration {
+            record.failures = 0;
+            record.locked_until = None;
+        }
+
+        record.failures = record.failures.saturating_add(1);
+        record.last_failure = now;
+
+        if self.policy.max_failures > 0 && record.failures >= self.policy.max_failures {
+            record.locked_until = Some(now + self.policy.lockout_duration);
+        }
+    }
+
+    /// 記録からブロック状態を判定
+    fn blocked(&self, record: &AttemptRecord, now: Instant) -> Option<LoginBlocked> {
+        if let Some(until) = record.locked_until {
+            if now < until {
+                return Some(LoginBlocked::LockedOut { retry_after: until - now });
+            }
+            return None;
+        }
+
+        if record.failures == 0 {
+            return None;
+        }
+
+        let ready_at = record.last_failure + self.backoff_delay(record.failures);
+        if now < ready_at {
+            Some(LoginBlocked::Backoff { retry_after: ready_at - now })
+        } else {
+            None
+        }
+    }
+
+    /// 失敗回数に応じた待機時間（base_delay * 2^(failures-1)、上限あり）
+    fn backoff_delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31);
+        self.policy.base_delay
+            .checked_mul(1u32 << exponent)
+            .unwrap_or(self.policy.max_delay)
+            .min(self.policy.max_delay)
+    }
+
+    /// 期限切れの記録を削除
+    fn prune<K>(&self, records: &mut HashMap<K, AttemptRecord>, now: Instant) {
+        if records.len() < MAX_TRACKED_ENTRIES {
+            return;
+        }
+
+        let retention = self.policy.lockout_duration.max(self.policy.max_delay);
+        records.retain(|_, record| {
+            record.locked_until.is_some_and(|until| now < until)
+                || now.duration_since(record.last_failure) < retention
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 3,
+            lockout_duration: Duration::from_secs(60),
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let tracker = LoginAttemptTracker::new(policy());
+        let addr = ip("192.0.2.1");
+        let start = Instant::now();
+
+        assert!(tracker.check_at(addr, "alice", start).is_ok());
+
+        tracker.record_failure_at(addr, "alice", start);
+        assert_eq!(
+            tracker.check_at(addr, "alice", start),
+            Err(LoginBlocked::Backoff { retry_after: Duration::from_secs(1) })
+        );
+        assert!(tracker.check_at(addr, "alice", start + Duration::from_secs(1)).is_ok());
+
+        // 2回目の失敗後は待機時間が倍になる
+        let second = start + Duration::from_secs(1);
+        tracker.record_failure_at(addr, "alice", second);
+        assert!(tracker.check_at(addr, "alice", second + Duration::from_secs(1)).is_err());
+        assert!(tracker.check_at(addr, "alice", second + Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn test_backoff_applies_per_ip_and_per_username() {
+        let tracker = LoginAttemptTracker::new(policy());
+        let now = Instant::now();
+
+        tracker.record_failure_at(ip("192.0.2.1"), "alice", now);
+
+        // 同じIPから別ユーザー、別IPから同じユーザーも待機させる
+        assert!(tracker.check_at(ip("192.0.2.1"), "bob", now).is_err());
+        assert!(tracker.check_at(ip("192.0.2.2"), "alice", now).is_err());
+        assert!(tracker.check_at(ip("192.0.2.2"), "bob", now).is_ok());
+    }
+
+    #[test]
+    fn test_lockout_after_max_failures() {
+        let tracker = LoginAttemptTracker::new(policy());
+        let mut now = Instant::now();
+
+        for i in 0..3 {
+            // 別々のIPから試行してもユーザー単位でロックされる
+            tracker.record_failure_at(ip(&format!("198.51.100.{}", i)), "alice", now);
+            now += Duration::from_secs(20);
+        }
+
+        let last_failure = now - Duration::from_secs(20);
+        let blocked = tracker.check_at(ip("203.0.113.9"), "alice", last_failure + Duration::from_secs(30));
+        assert_eq!(blocked, Err(LoginBlocked::LockedOut { retry_after: Duration::from_secs(30) }));
+        assert_eq!(blocked.unwrap_err().error_code(), ERROR_CODE_LOCKED_OUT);
+
+        // ロックアウト時間が過ぎれば再試行できる
+        assert!(tracker.check_at(ip("203.0.113.9"), "alice", last_failure + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let tracker = LoginAttemptTracker::new(policy());
+        let now = Instant::now();
+        let addr = ip("2001:db8::1");
+
+        tracker.record_failure_at(addr, "alice", now);
+        tracker.record_failure_at(addr, "alice", now);
+        tracker.record_success(addr, "alice");
+
+        assert!(tracker.check_at(addr, "alice", now).is_ok());
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let tracker = LoginAttemptTracker::new(LockoutPolicy { max_failures: 0, ..policy() });
+        assert_eq!(tracker.backoff_delay(1), Duration::from_secs(1));
+        assert_eq!(tracker.backoff_delay(4), Duration::from_secs(8));
+        assert_eq!(tracker.backoff_delay(5), Duration::from_secs(10));
+        assert_eq!(tracker.backoff_delay(100), Duration::from_secs(10));
+    }
+}
@@ -7,13 +7,26 @@ pub mod tcp_server;
 pub mod websocket_server;
 pub mod webrtc_server;
 pub mod protocol;
+pub mod access_control;
 pub mod authentication;
+pub mod login_tracker;
+pub mod permission;
 pub mod session;
+pub mod stream;
+pub mod congestion;
 
 use remote_desktop_rs_common::protocol::{Command, Response, ClientInfo};
+use remote_desktop_rs_common::capabilities::{Capabilities, Feature};
+use remote_desktop_rs_common::compression::CompressionAlgorithm;
+use remote_desktop_rs_common::encryption::EncryptionAlgorithm;
+use remote_desktop_rs_common::protocol::ImageFormat;
+use remote_desktop_rs_common::wire::WireFormat;
 use crate::capture::{ScreenCapture, CapturedImage};
 use crate::input::InputHandler;
 use crate::error::ServerError;
+use crate::ui::settings::UserCredential;
+use login_tracker::LoginAttemptTracker;
+use congestion::QualityBounds;
 
 use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
@@ -77,6 +90,18 @@ pub struct ServerConfig {
     pub client_timeout: u64,
     /// キープアライブ間隔（秒）
     pub keep_alive_interval: u64,
+    /// 認証に使用するユーザー情報
+    pub users: Vec<UserCredential>,
+    /// 接続を許可するIPアドレス/CIDR（空の場合はすべて許可）
+    pub allowed_ips: Vec<String>,
+    /// 接続を拒否するIPアドレス/CIDR（許可リストより優先）
+    pub denied_ips: Vec<String>,
+    /// ログイン試行トラッカー（すべてのトランスポートで共有）
+    pub login_tracker: Arc<LoginAttemptTracker>,
+    /// セッションを切断するまでの不正なコマンドの回数
+    pub max_protocol_violations: u32,
+    /// 配信品質を帯域に合わせて調整する範囲
+    pub quality_bounds: QualityBounds,
 }
 
 impl Default for ServerConfig {
@@ -91,6 +116,12 @@ impl Default for ServerConfig {
             max_connections: 5,
             client_timeout: 60,
             keep_alive_interval: 30,
+            users: Vec::new(),
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
+            login_tracker: Arc::new(LoginAttemptTracker::default()),
+            max_protocol_violations: 3,
+            quality_bounds: QualityBounds::default(),
         }
     }
 }
@@ -111,6 +142,9 @@ pub trait NetworkServer {
     
     /// アドレスを取得
     fn get_address(&self) -> SocketAddr;
+    
+    /// アクセス制御により拒否した接続数を取得
+    fn rejected_connections(&self) -> u64;
 }
 
 /// サーバーファクトリー
@@ -179,6 +213,43 @@ impl ServerFactory {
     }
 }
 
+/// このサーバーが対応する機能
+pub fn server_capabilities() -> Capabilities {
+    // QOIは色数の少ない矩形にだけ使うため、既定の形式にはしない
+    let mut codecs = vec![ImageFormat::JPEG, ImageFormat::PNG, ImageFormat::QOI];
+    if cfg!(feature = "webp-support") {
+        codecs.push(ImageFormat::WebP);
+    }
+    // 動画は変更の多い間だけ使うため、優先順位は最後
+    if cfg!(feature = "video-support") {
+        codecs.push(ImageFormat::H264);
+    }
+    
+    let mut features = vec![Feature::RunApplication, Feature::Streaming, Feature::TileCache];
+    if cfg!(feature = "clipboard") {
+        features.push(Feature::Clipboard);
+    }
+    if cfg!(feature = "file-transfer") {
+        features.push(Feature::FileTransfer);
+    }
+    if cfg!(feature = "system-info") {
+        features.push(Feature::SystemInfo);
+    }
+    
+    Capabilities {
+        codecs,
+        compression: vec![
+            CompressionAlgorithm::LZ4,
+            CompressionAlgorithm::Zlib,
+            CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Deflate,
+            CompressionAlgorithm::None,
+        ],
+        encryption: vec![EncryptionAlgorithm::Aes256Gcm],
+        features,
+    }
+}
+
 /// セッション情報
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
@@ -190,6 +261,10 @@ pub struct SessionInfo {
     pub ip_address: String,
     /// 認証済みかどうか
     pub authenticated: bool,
+    /// 認証済みユーザー名
+    pub username: Option<String>,
+    /// 権限レベル（0=ビューアー、1=制御者、2=管理者）
+    pub permission_level: u8,
     /// 接続日時
     pub connection_time: std::time::SystemTime,
     /// 最後のアクティビティ
@@ -202,6 +277,12 @@ pub struct SessionInfo {
     pub quality: u8,
     /// 最後のレイテンシー（ms）
     pub last_latency: Option<u64>,
+    /// ネゴシエーション済みのワイヤーフォーマット
+    pub wire_format: WireFormat,
+    /// ネゴシエーション済みのプロトコルバージョン（`Hello`を送らない旧クライアントでは`None`）
+    pub protocol_version: Option<u32>,
+    /// ネゴシエーション済みの機能（`Hello`を送らない旧クライアントでは`None`）
+    pub capabilities: Option<Capabilities>,
 }
 
 impl SessionInfo {
@@ -213,12 +294,17 @@ impl SessionInfo {
             client_info: None,
             ip_address,
             authenticated: false,
+            username: None,
+            permission_level: 0,
             connection_time: now,
             last_activity: now,
             bytes_sent: 0,
             bytes_received: 0,
             quality: 70,
             last_latency: None,
+            wire_format: WireFormat::Json,
+            protocol_version: None,
+            capabilities: None,
         }
     }
     
@@ -0,0 +1,92 @@
+//! 権限ポリシー
+//!
+//! ユーザーの権限レベルと、各コマンドの実行に必要な権限レベルを定義します。
+
+use remote_desktop_rs_common::protocol::Command;
+
+/// 権限レベル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PermissionLevel {
+    /// ビューアー（画面の閲覧のみ）
+    Viewer = 0,
+    /// 制御者（マウス・キーボード・クリップボード操作）
+    Controller = 1,
+    /// 管理者（ファイル転送・アプリケーション実行）
+    Admin = 2,
+}
+
+impl PermissionLevel {
+    /// 数値から権限レベルを取得
+    ///
+    /// 未知の値は管理者ではなく最も弱いビューアーとして扱います。
+    pub fn from_level(level: u8) -> Self {
+        match level {
+            1 => PermissionLevel::Controller,
+            2 => PermissionLevel::Admin,
+            _ => PermissionLevel::Viewer,
+        }
+    }
+
+    /// 表示名を取得
+    pub fn name(&self) -> &'static str {
+        match self {
+            PermissionLevel::Viewer => "ビューアー",
+            PermissionLevel::Controller => "制御者",
+            PermissionLevel::Admin => "管理者",
+        }
+    }
+
+    /// コマンドの実行に必要な権限レベルを取得
+    ///
+    /// 認証前でも受け付けるコマンドは`None`を返します。
+    pub fn required_for(command: &Command) -> Option<Self> {
+        let level = match command {
+            Command::Authenticate { .. }
+            | Command::AuthChallengeResponse { .. }
+            | Command::SubmitOtp { .. }
+            | Command::NegotiateWireFormat { .. }
+            | Command::Hello { .. }
+            | Command::Disconnect => return None,
+
+            // 画面の閲覧と、そのセッション内の表示設定
+            Command::RequestScreenshot { .. }
+            | Command::SetQuality { .. }
+            | Command::SetImageFormat { .. }
+            | Command::SetFps { .. }
+            | Command::StartStream { .. }
+            | Command::StreamCredit { .. }
+            | Command::StopStream
+            | Command::RequestKeyframe
+            | Command::Ping { .. } => PermissionLevel::Viewer,
+
+            // 入力操作とクリップボード
+            Command::MouseMove { .. }
+            | Command::MouseClick { .. }
+            | Command::MouseDown { .. }
+            | Command::MouseUp { .. }
+            | Command::MouseScroll { .. }
+            | Command::KeyDown { .. }
+            | Command::KeyUp { .. }
+            | Command::TextInput { .. }
+            | Command::KeyCombo { .. }
+            | Command::RequestClipboardContent
+            | Command::SetClipboardContent { .. }
+            | Command::RequestSystemInfo => PermissionLevel::Controller,
+
+            // ホストに変更を加える操作
+            Command::RunApplication { .. }
+            | Command::StartFileTransfer { .. }
+            | Command::FileData { .. } => PermissionLevel::Admin,
+        };
+
+        Some(level)
+    }
+
+    /// このレベルでコマンドを実行できるかどうか
+    pub fn allows(&self, command: &Command) -> bool {
+        match Self::required_for(command) {
+            Some(required) => *self >= required,
+            None => true,
+        }
+    }
+}
@@ -3,27 +3,38 @@
 //! 個々のクライアント接続に対するセッションを管理します。
 //! 各セッションはクライアントからのコマンド受信、処理、レスポンス送信を担当します。
 
-use super::{NetworkError, ServerConfig, SessionInfo};
-use super::authentication::Authenticator;
-use remote_desktop_rs_common::protocol::{Command, Response, ClientInfo, ImageFormat};
-use crate::capture::{ScreenCapture, CapturedImage};
+use super::{server_capabilities, NetworkError, ServerConfig, SessionInfo};
+use super::authentication::{AuthChallenge, AuthError, AuthenticatedUser, Authenticator};
+use super::permission::PermissionLevel;
+use super::stream::FrameStream;
+use super::congestion::CongestionController;
+use super::login_tracker::LoginBlocked;
+use remote_desktop_rs_common::protocol::{AuthMethod, Command, Envelope, FrameRect, RectContent, Response, ClientInfo, ImageFormat};
+use remote_desktop_rs_common::tile_cache::TILE_CACHE_CAPACITY;
+use remote_desktop_rs_common::capabilities::{self, Capabilities, Feature, MIN_PROTOCOL_VERSION};
+use remote_desktop_rs_common::wire::{self, WireFormat};
+use crate::capture::{self, ScreenCapture, CapturedImage, RegionContent, UpdateEncoder};
+use crate::capture::encoder::DEFAULT_VIDEO_BITRATE;
 use crate::input::InputHandler;
 
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
-use serde_json;
 use log::{debug, info, warn, error};
 
 /// クライアント接続インターフェース
 pub trait ClientConnection {
-    /// レスポンスをクライアントに送信
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError>;
+    /// 封筒に入れたレスポンスをクライアントに送信
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError>;
     
     /// 生のデータをクライアントに送信
     fn send_raw(&mut self, data: &[u8]) -> Result<(), NetworkError>;
     
-    /// クライアントからコマンドを受信
-    fn receive(&mut self) -> Result<Command, NetworkError>;
+    /// クライアントからコマンドを受信（封筒のない旧形式は`Envelope::legacy`として返す）
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError>;
+    
+    /// 以降の送信に使用するワイヤーフォーマットを設定
+    fn set_wire_format(&mut self, format: WireFormat);
     
     /// タイムアウトを設定
     fn set_timeout(&mut self, duration: Duration) -> Result<(), NetworkError>;
@@ -32,18 +43,337 @@ pub trait ClientConnection {
     fn close(&mut self) -> Result<(), NetworkError>;
 }
 
+/// 要求と応答を相関IDで対応付けて送受信する接続
+///
+/// 処理中の要求のIDを覚えておき、その間に送信したレスポンスの`reply_to`に設定します。
+/// 封筒を使わない旧クライアントには封筒なしで送信します。
+struct CorrelatedConnection<C: ?Sized> {
+    /// 最後に送信したメッセージのID
+    last_id: u64,
+    /// 処理中の要求のID
+    reply_to: Option<u64>,
+    /// クライアントが封筒を使用しているかどうか
+    enveloped: bool,
+    /// 下位の接続
+    inner: Box<C>,
+}
+
+impl<C: ClientConnection + ?Sized> CorrelatedConnection<C> {
+    /// 接続をラップ
+    fn new(inner: Box<C>) -> Self {
+        Self {
+            last_id: 0,
+            reply_to: None,
+            enveloped: false,
+            inner,
+        }
+    }
+    
+    /// 受信した要求の処理を開始
+    fn begin_request(&mut self, envelope: Envelope<Command>) -> Command {
+        if envelope.is_legacy() {
+            self.reply_to = None;
+        } else {
+            self.enveloped = true;
+            self.reply_to = Some(envelope.id);
+        }
+        envelope.message
+    }
+    
+    /// 要求の処理を終了（以降の送信は応答として扱わない）
+    fn end_request(&mut self) {
+        self.reply_to = None;
+    }
+    
+    /// コマンドを受信し、その要求の処理を開始
+    fn receive(&mut self) -> Result<Command, NetworkError> {
+        let envelope = self.inner.receive()?;
+        Ok(self.begin_request(envelope))
+    }
+    
+    /// 処理中の要求への応答として送信
+    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
+        let reply_to = self.reply_to;
+        self.send_envelope(response, reply_to)
+    }
+    
+    /// 要求への応答ではないメッセージとして送信
+    fn notify(&mut self, response: &Response) -> Result<(), NetworkError> {
+        self.send_envelope(response, None)
+    }
+    
+    fn send_envelope(&mut self, response: &Response, reply_to: Option<u64>) -> Result<(), NetworkError> {
+        let envelope = if self.enveloped {
+            self.last_id += 1;
+            Envelope::reply(self.last_id, reply_to, response)
+        } else {
+            Envelope::legacy(response)
+        };
+        self.inner.send(&envelope)
+    }
+    
+    /// 以降の送信に使用するワイヤーフォーマットを設定
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.inner.set_wire_format(format);
+    }
+    
+    /// 接続を閉じる
+    fn close(&mut self) -> Result<(), NetworkError> {
+        self.inner.close()
+    }
+}
+
+/// コマンドを拒否した理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PermissionDenied {
+    /// 認証前に権限が必要なコマンドを受信した
+    Unauthenticated,
+    /// 権限レベルが足りない
+    Insufficient {
+        required: PermissionLevel,
+        granted: PermissionLevel,
+    },
+}
+
+impl From<PermissionDenied> for Response {
+    fn from(denied: PermissionDenied) -> Self {
+        match denied {
+            PermissionDenied::Unauthenticated => Response::Error {
+                code: 401,
+                message: "認証が必要です".to_string(),
+            },
+            PermissionDenied::Insufficient { required, granted } => Response::Error {
+                code: 403,
+                message: format!(
+                    "権限がありません: このコマンドには{}権限が必要です（現在: {}）",
+                    required.name(),
+                    granted.name()
+                ),
+            },
+        }
+    }
+}
+
+/// コマンドを実行してよいかを判定
+///
+/// 認証が無効な場合は従来どおりすべてのコマンドを許可します。
+fn authorize_command(session_info: &SessionInfo, config: &ServerConfig, command: &Command) -> Result<(), PermissionDenied> {
+    if !config.require_auth {
+        return Ok(());
+    }
+    
+    let required = match PermissionLevel::required_for(command) {
+        Some(required) => required,
+        None => return Ok(()),
+    };
+    
+    if !session_info.authenticated {
+        return Err(PermissionDenied::Unauthenticated);
+    }
+    
+    let granted = PermissionLevel::from_level(session_info.permission_level);
+    if !granted.allows(command) {
+        return Err(PermissionDenied::Insufficient { required, granted });
+    }
+    
+    Ok(())
+}
+
+/// ネゴシエーションされていない機能を使うコマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NotNegotiated {
+    /// 機能
+    Feature(Feature),
+    /// 画像形式
+    Codec(ImageFormat),
+}
+
+impl From<NotNegotiated> for Response {
+    fn from(missing: NotNegotiated) -> Self {
+        match missing {
+            NotNegotiated::Feature(feature) => Response::Error {
+                code: 501,
+                message: format!("機能 {:?} はネゴシエーションされていません", feature),
+            },
+            NotNegotiated::Codec(format) => Response::Error {
+                code: 415,
+                message: format!("画像形式 {:?} はネゴシエーションされていません", format),
+            },
+        }
+    }
+}
+
+/// ネゴシエーション済みの機能でコマンドを実行できるかを判定
+///
+/// `Hello`を送らない旧クライアントは従来どおりすべてのコマンドを許可します。
+fn check_capabilities(session_info: &SessionInfo, command: &Command) -> Result<(), NotNegotiated> {
+    let capabilities = match &session_info.capabilities {
+        Some(capabilities) => capabilities,
+        None => return Ok(()),
+    };
+    
+    if let Some(feature) = Feature::required_for(command) {
+        if !capabilities.supports(feature) {
+            return Err(NotNegotiated::Feature(feature));
+        }
+    }
+    
+    if let Command::SetImageFormat { format } = command {
+        if !capabilities.supports_codec(*format) {
+            return Err(NotNegotiated::Codec(*format));
+        }
+    }
+    
+    Ok(())
+}
+
+/// セッション状態
+///
+/// `Handshake → Authenticating → Authenticated → Closing`の順に遷移します。
+/// 認証が無効な場合は`Authenticated`から開始します。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// 接続直後（認証開始待ち）
+    Handshake,
+    /// 認証チャレンジまたはワンタイムパスワードへの応答待ち
+    Authenticating,
+    /// 認証済み
+    Authenticated,
+    /// 切断処理中（以降のコマンドはすべて拒否）
+    Closing,
+}
+
+impl SessionState {
+    /// この状態でコマンドを受け付けるかどうか
+    pub fn accepts(&self, command: &Command) -> bool {
+        match (self, command) {
+            (SessionState::Closing, _) => false,
+            // 切断・（再）認証・ネゴシエーションはいつでも受け付ける
+            (_, Command::Disconnect)
+            | (_, Command::Authenticate { .. })
+            | (_, Command::NegotiateWireFormat { .. })
+            | (_, Command::Hello { .. }) => true,
+            (SessionState::Authenticating, Command::AuthChallengeResponse { .. })
+            | (SessionState::Authenticating, Command::SubmitOtp { .. }) => true,
+            (SessionState::Authenticated, Command::AuthChallengeResponse { .. })
+            | (SessionState::Authenticated, Command::SubmitOtp { .. }) => false,
+            (SessionState::Authenticated, _) => true,
+            _ => false,
+        }
+    }
+}
+
+/// セッション状態と不正なコマンドの回数を管理
+#[derive(Debug)]
+struct SessionStateMachine {
+    /// 現在の状態
+    state: SessionState,
+    /// 認証を必要とするかどうか
+    require_auth: bool,
+    /// 不正なコマンドの回数
+    violations: u32,
+    /// 切断するまでの不正なコマンドの回数（0の場合は切断しない）
+    max_violations: u32,
+}
+
+impl SessionStateMachine {
+    /// サーバー設定から作成
+    fn new(config: &ServerConfig) -> Self {
+        let mut machine = Self {
+            state: SessionState::Handshake,
+            require_auth: config.require_auth,
+            violations: 0,
+            max_violations: config.max_protocol_violations,
+        };
+        machine.state = machine.unauthenticated_state();
+        machine
+    }
+    
+    /// 未認証時の状態（認証が無効な場合は認証済みとして扱う）
+    fn unauthenticated_state(&self) -> SessionState {
+        if self.require_auth {
+            SessionState::Handshake
+        } else {
+            SessionState::Authenticated
+        }
+    }
+    
+    /// 状態を遷移（`Closing`からは遷移しない）
+    fn transition(&mut self, next: SessionState) {
+        if self.state != SessionState::Closing && self.state != next {
+            debug!("セッション状態: {:?} -> {:?}", self.state, next);
+            self.state = next;
+        }
+    }
+    
+    /// コマンドを受け付けるかを判定
+    ///
+    /// 拒否した場合はクライアントにエラーを送信し、不正なコマンドの回数が
+    /// 上限に達した場合は接続を閉じて`Closing`に遷移します。
+    fn admit<C: ClientConnection + ?Sized>(
+        &mut self,
+        command: &Command,
+        connection: &mut CorrelatedConnection<C>,
+    ) -> Result<bool, NetworkError> {
+        if self.state.accepts(command) {
+            return Ok(true);
+        }
+        
+        if self.state == SessionState::Closing {
+            return Ok(false);
+        }
+        
+        self.violations += 1;
+        warn!("状態{:?}で不正なコマンド: {:?} ({}回目)", self.state, command, self.violations);
+        
+        let response = match self.state {
+            SessionState::Handshake | SessionState::Authenticating
+                if PermissionLevel::required_for(command).is_some() =>
+            {
+                Response::Error {
+                    code: 401,
+                    message: "認証が必要です".to_string(),
+                }
+            },
+            _ => Response::Error {
+                code: 409,
+                message: format!("現在の状態（{:?}）では実行できないコマンドです", self.state),
+            },
+        };
+        connection.send(&response)?;
+        
+        if self.max_violations > 0 && self.violations >= self.max_violations {
+            warn!("不正なコマンドが上限（{}回）に達したためセッションを閉じます", self.max_violations);
+            self.state = SessionState::Closing;
+            let _ = connection.notify(&Response::ConnectionStatus {
+                connected: false,
+                message: "不正なコマンドが多すぎるため切断します".to_string(),
+            });
+            connection.close()?;
+        }
+        
+        Ok(false)
+    }
+}
+
 /// クライアントセッション
 pub struct ClientSession {
     /// セッション情報
     session_info: SessionInfo,
     /// クライアント接続
-    connection: Box<dyn ClientConnection>,
+    connection: CorrelatedConnection<dyn ClientConnection>,
     /// スクリーンキャプチャー
     screen_capture: Arc<Mutex<ScreenCapture>>,
     /// 入力ハンドラー
     input_handler: Arc<Mutex<InputHandler>>,
     /// 認証ハンドラ
     authenticator: Arc<Authenticator>,
+    /// 応答待ちの認証チャレンジ
+    pending_challenge: Option<AuthChallenge>,
+    /// ワンタイムパスワード待ちのユーザー（パスワード認証済み）
+    pending_otp: Option<AuthenticatedUser>,
+    /// セッション状態
+    state: SessionStateMachine,
     /// サーバー設定
     config: ServerConfig,
     /// アクティブ状態
@@ -52,6 +382,14 @@ pub struct ClientSession {
     last_keep_alive: Instant,
     /// コマンドプロセッサ
     command_processor: CommandProcessor,
+    /// フレームのプッシュ配信
+    stream: FrameStream,
+    /// 配信する差分フレームのエンコーダ
+    updates: UpdateEncoder,
+    /// 帯域に応じた配信品質の調整
+    congestion: CongestionController,
+    /// 配信したフレームの座標から画面の座標への倍率（解像度を下げている間は1より大きい）
+    frame_to_screen: (f32, f32),
 }
 
 impl ClientSession {
@@ -64,16 +402,26 @@ impl ClientSession {
         authenticator: Arc<Authenticator>,
         config: ServerConfig,
     ) -> Self {
+        let mut congestion = CongestionController::new(config.quality_bounds, Instant::now());
+        congestion.set_target_quality(session_info.quality);
+        
         Self {
             session_info,
-            connection,
+            connection: CorrelatedConnection::new(connection),
             screen_capture,
             input_handler,
             authenticator,
+            pending_challenge: None,
+            pending_otp: None,
+            state: SessionStateMachine::new(&config),
             config,
             active: true,
             last_keep_alive: Instant::now(),
             command_processor: CommandProcessor::new(),
+            stream: FrameStream::new(),
+            updates: UpdateEncoder::default(),
+            congestion,
+            frame_to_screen: (1.0, 1.0),
         }
     }
     
@@ -87,11 +435,25 @@ impl ClientSession {
         self.active
     }
     
+    /// セッション状態を取得
+    pub fn state(&self) -> SessionState {
+        self.state.state
+    }
+    
     /// アイドル時間（秒）を取得
     pub fn idle_time(&self) -> u64 {
         self.session_info.idle_time()
     }
     
+    /// 接続元IPアドレスを取得
+    fn peer_ip(&self) -> IpAddr {
+        let address = &self.session_info.ip_address;
+        address.parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .or_else(|_| address.parse::<IpAddr>())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+    
     /// データを処理
     pub fn process_data(&mut self, data: &[u8]) -> Result<(), NetworkError> {
         // データが来た = アクティビティがあった
@@ -99,17 +461,20 @@ impl ClientSession {
         session_info.update_activity();
         self.session_info = session_info;
         
-        // JSONデータをパース
-        let command: Command = match serde_json::from_slice(data) {
-            Ok(cmd) => cmd,
+        // JSONまたはバイナリ形式のデータをパース
+        let envelope: Envelope<Command> = match wire::decode_envelope(data) {
+            Ok(envelope) => envelope,
             Err(e) => {
-                error!("JSONパースエラー: {}", e);
-                return Err(NetworkError::ProtocolError(format!("JSONパースエラー: {}", e)));
+                error!("コマンドのパースエラー: {}", e);
+                return Err(NetworkError::ProtocolError(e.to_string()));
             }
         };
         
         // コマンドを処理
-        self.handle_command(command)
+        let command = self.connection.begin_request(envelope);
+        let result = self.handle_command(command);
+        self.connection.end_request();
+        result
     }
     
     /// コマンドを受信して処理
@@ -123,26 +488,33 @@ impl ClientSession {
         self.session_info = session_info;
         
         // コマンドを処理
-        self.handle_command(command)
+        let result = self.handle_command(command);
+        self.connection.end_request();
+        result
     }
     
     /// コマンドを処理
     fn handle_command(&mut self, command: Command) -> Result<(), NetworkError> {
         debug!("コマンド受信: {:?}", command);
         
-        // 認証チェック（Authenticateコマンド以外は認証が必要）
-        match &command {
-            Command::Authenticate { .. } => {},
-            _ => {
-                if self.config.require_auth && !self.session_info.authenticated {
-                    warn!("認証されていないクライアントからのコマンド: {:?}", command);
-                    let response = Response::Error {
-                        code: 401,
-                        message: "認証が必要です".to_string(),
-                    };
-                    return self.connection.send(&response);
-                }
+        // セッション状態のチェック
+        if !self.state.admit(&command, &mut self.connection)? {
+            if self.state.state == SessionState::Closing {
+                self.active = false;
             }
+            return Ok(());
+        }
+        
+        // 認証・権限チェック
+        if let Err(denied) = authorize_command(&self.session_info, &self.config, &command) {
+            warn!("拒否されたコマンド: {:?} (ユーザー: {:?})", command, self.session_info.username);
+            return self.connection.send(&Response::from(denied));
+        }
+        
+        // ネゴシエーション済みの機能のチェック
+        if let Err(missing) = check_capabilities(&self.session_info, &command) {
+            warn!("未対応の機能のコマンド: {:?}", command);
+            return self.connection.send(&Response::from(missing));
         }
         
         // キープアライブ処理
@@ -152,8 +524,20 @@ impl ClientSession {
         
         // コマンドに応じて処理
         match command {
-            Command::Authenticate { username, password_hash, client_info } => {
-                self.handle_authenticate(username, password_hash, client_info)
+            Command::Authenticate { username, client_info, method } => {
+                self.handle_authenticate(username, client_info, method)
+            },
+            Command::AuthChallengeResponse { response } => {
+                self.handle_auth_challenge_response(response)
+            },
+            Command::SubmitOtp { code } => {
+                self.handle_submit_otp(code)
+            },
+            Command::NegotiateWireFormat { formats } => {
+                self.handle_negotiate_wire_format(formats)
+            },
+            Command::Hello { protocol_version, capabilities } => {
+                self.handle_hello(protocol_version, capabilities)
             },
             Command::RequestScreenshot { quality, width, height, monitor } => {
                 self.handle_screenshot_request(quality, width, height, monitor)
@@ -194,6 +578,18 @@ impl ClientSession {
             Command::SetFps { fps } => {
                 self.handle_set_fps(fps)
             },
+            Command::StartStream { fps, credits } => {
+                self.handle_start_stream(fps, credits)
+            },
+            Command::StreamCredit { credits } => {
+                self.handle_stream_credit(credits)
+            },
+            Command::StopStream => {
+                self.handle_stop_stream()
+            },
+            Command::RequestKeyframe => {
+                self.handle_request_keyframe()
+            },
             Command::RunApplication { command } => {
                 self.handle_run_application(command)
             },
@@ -222,29 +618,212 @@ impl ClientSession {
     }
     
     /// 認証処理
-    fn handle_authenticate(&mut self, username: String, password_hash: String, client_info: ClientInfo) -> Result<(), NetworkError> {
-        info!("認証要求: ユーザー名={}, クライアント={}/{}", username, client_info.app_name, client_info.version);
+    ///
+    /// パスワードは送信させず、ノンスとソルトを含むチャレンジを返します。
+    /// 公開鍵認証ではノンスのみを返し、クライアントの署名を待ちます。
+    fn handle_authenticate(&mut self, username: String, client_info: ClientInfo, method: AuthMethod) -> Result<(), NetworkError> {
+        info!(
+            "認証要求: ユーザー名={}, 方式={}, クライアント={}/{}",
+            username,
+            if matches!(method, AuthMethod::PublicKey { .. }) { "公開鍵" } else { "パスワード" },
+            client_info.app_name,
+            client_info.version
+        );
+        
+        // 再認証時は以前の認証状態を破棄
+        let mut session_info = self.session_info.clone();
+        session_info.authenticated = false;
+        session_info.username = None;
+        session_info.permission_level = 0;
+        session_info.client_info = Some(client_info);
+        self.session_info = session_info;
+        self.pending_otp = None;
+        let next = self.state.unauthenticated_state();
+        self.state.transition(next);
+        
+        // 連続失敗によるバックオフ・ロックアウト中は認証を受け付けない
+        if let Err(blocked) = self.config.login_tracker.check(self.peer_ip(), &username) {
+            return self.reject_blocked_login(&username, blocked);
+        }
         
-        // 認証を実行
-        let auth_result = self.authenticator.authenticate(&username, &password_hash);
+        match self.authenticator.create_challenge(&username, &method) {
+            Ok(challenge) => {
+                let response = Response::AuthChallenge {
+                    nonce: challenge.nonce.clone(),
+                    salt: challenge.salt.clone(),
+                    iterations: challenge.iterations,
+                };
+                self.pending_challenge = Some(challenge);
+                self.state.transition(SessionState::Authenticating);
+                self.connection.send(&response)
+            },
+            Err(e) => {
+                warn!("認証失敗: {}: {}", username, e);
+                self.pending_challenge = None;
+                self.connection.send(&Response::AuthResult {
+                    success: false,
+                    message: format!("認証に失敗しました: {}", e),
+                })
+            }
+        }
+    }
+    
+    /// バックオフ・ロックアウト中のログイン試行を拒否
+    fn reject_blocked_login(&mut self, username: &str, blocked: LoginBlocked) -> Result<(), NetworkError> {
+        warn!("ログイン試行を拒否: {} ({}): {}", username, self.session_info.ip_address, blocked.message());
+        self.pending_challenge = None;
+        let next = self.state.unauthenticated_state();
+        self.state.transition(next);
+        self.connection.send(&Response::Error {
+            code: blocked.error_code(),
+            message: blocked.message(),
+        })
+    }
+    
+    /// 認証チャレンジ応答の処理
+    fn handle_auth_challenge_response(&mut self, response: String) -> Result<(), NetworkError> {
+        // チャレンジは一度だけ使用できる
+        let auth_result = match self.pending_challenge.take() {
+            Some(challenge) => {
+                // チャレンジの取得後に他のセッションで失敗が記録されている場合があるため、
+                // 応答を検証する前にもう一度確認する
+                if let Err(blocked) = self.config.login_tracker.check(self.peer_ip(), &challenge.username) {
+                    return self.reject_blocked_login(&challenge.username, blocked);
+                }
+
+                let result = self.authenticator.verify_response(&challenge, &response);
+                
+                // 試行結果を記録（第二要素が必要な場合は成功をまだ記録しない）
+                let tracker = &self.config.login_tracker;
+                match &result {
+                    Ok(user) if self.authenticator.requires_otp(&user.username) => {},
+                    Ok(_) => tracker.record_success(self.peer_ip(), &challenge.username),
+                    Err(_) => tracker.record_failure(self.peer_ip(), &challenge.username),
+                }
+                
+                result
+            },
+            None => Err(AuthError::NoPendingChallenge),
+        };
+        
+        // TOTPが設定されたユーザーはワンタイムパスワードを要求
+        if let Ok(user) = &auth_result {
+            if self.authenticator.requires_otp(&user.username) {
+                info!("ワンタイムパスワードを要求: {}", user.username);
+                self.pending_otp = auth_result.ok();
+                return self.connection.send(&Response::OtpRequired {
+                    message: "ワンタイムパスワードを入力してください".to_string(),
+                });
+            }
+        }
+        
+        self.complete_authentication(auth_result)
+    }
+    
+    /// ワンタイムパスワードの処理
+    fn handle_submit_otp(&mut self, code: String) -> Result<(), NetworkError> {
+        // 要求は一度だけ使用できる
+        let auth_result = match self.pending_otp.take() {
+            Some(user) => {
+                let result = self.authenticator.verify_otp(&user, &code);
+                
+                let tracker = &self.config.login_tracker;
+                match &result {
+                    Ok(_) => tracker.record_success(self.peer_ip(), &user.username),
+                    Err(_) => tracker.record_failure(self.peer_ip(), &user.username),
+                }
+                
+                result.map(|_| user)
+            },
+            None => Err(AuthError::NoPendingOtp),
+        };
         
+        self.complete_authentication(auth_result)
+    }
+    
+    /// ワイヤーフォーマットのネゴシエーション
+    ///
+    /// 応答は切り替え前の形式で送信し、その後の送信から新しい形式を使用します。
+    fn handle_negotiate_wire_format(&mut self, formats: Vec<WireFormat>) -> Result<(), NetworkError> {
+        let format = wire::negotiate(&formats);
+        debug!("ワイヤーフォーマット: {} (提示: {:?})", format.name(), formats);
+        
+        self.connection.send(&Response::WireFormatSelected { format })?;
+        self.connection.set_wire_format(format);
+        
+        let mut session_info = self.session_info.clone();
+        session_info.wire_format = format;
+        self.session_info = session_info;
+        
+        Ok(())
+    }
+    
+    /// プロトコルバージョンと機能のネゴシエーション
+    fn handle_hello(&mut self, protocol_version: u32, offered: Capabilities) -> Result<(), NetworkError> {
+        let version = match capabilities::negotiate_version(protocol_version) {
+            Some(version) => version,
+            None => {
+                warn!("非対応のプロトコルバージョン: {}", protocol_version);
+                return self.connection.send(&Response::Error {
+                    code: 426,
+                    message: format!(
+                        "プロトコルバージョン {} には対応していません（{} 以上が必要です）",
+                        protocol_version, MIN_PROTOCOL_VERSION
+                    ),
+                });
+            }
+        };
+        
+        let agreed = offered.intersect(&server_capabilities());
+        debug!("ネゴシエーション結果: バージョン={}, 機能={:?}", version, agreed);
+        
+        let mut session_info = self.session_info.clone();
+        session_info.protocol_version = Some(version);
+        session_info.capabilities = Some(agreed.clone());
+        self.session_info = session_info;
+        
+        self.connection.send(&Response::Welcome {
+            protocol_version: version,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: agreed,
+        })
+    }
+    
+    /// 認証結果をセッションに反映し、クライアントに通知
+    fn complete_authentication(&mut self, auth_result: Result<AuthenticatedUser, AuthError>) -> Result<(), NetworkError> {
         // セッション情報を更新
         let mut session_info = self.session_info.clone();
         session_info.authenticated = auth_result.is_ok();
-        session_info.client_info = Some(client_info);
+        match &auth_result {
+            Ok(user) => {
+                session_info.username = Some(user.username.clone());
+                session_info.permission_level = user.permission_level;
+            },
+            Err(_) => {
+                session_info.username = None;
+                session_info.permission_level = 0;
+            }
+        }
         self.session_info = session_info;
         
+        let next = if auth_result.is_ok() {
+            SessionState::Authenticated
+        } else {
+            self.state.unauthenticated_state()
+        };
+        self.state.transition(next);
+        
         // 認証結果を返す
         let response = match auth_result {
-            Ok(_) => {
-                info!("認証成功: {}", username);
+            Ok(user) => {
+                info!("認証成功: {}", user.username);
                 Response::AuthResult {
                     success: true,
                     message: "認証に成功しました".to_string(),
                 }
             },
             Err(e) => {
-                warn!("認証失敗: {}: {}", username, e);
+                warn!("認証失敗: {}", e);
                 Response::AuthResult {
                     success: false,
                     message: format!("認証に失敗しました: {}", e),
@@ -295,6 +874,9 @@ impl ClientSession {
     
     /// マウス移動処理
     fn handle_mouse_move(&mut self, x: i32, y: i32) -> Result<(), NetworkError> {
+        // クライアントは受信したフレーム上の座標を送るため、縮小している場合は元の画面の座標に戻す
+        let (scale_x, scale_y) = self.frame_to_screen;
+        let (x, y) = ((x as f32 * scale_x).round() as i32, (y as f32 * scale_y).round() as i32);
         let result = self.input_handler.lock().unwrap().handle_command(&Command::MouseMove { x, y });
         
         match result {
@@ -491,10 +1073,13 @@ impl ClientSession {
         session_info.quality = quality;
         self.session_info = session_info;
         
+        // 配信中は要求された画質を上限として帯域に合わせて調整する
+        self.congestion.set_target_quality(quality);
+        
         let response = Response::CommandResult {
             success: true,
             message: format!("画質を設定しました: {}", quality),
-            data: None,
+            data: Some(self.congestion.decision().to_data()),
         };
         
         self.connection.send(&response)
@@ -502,11 +1087,21 @@ impl ClientSession {
     
     /// 画像フォーマット設定処理
     fn handle_set_image_format(&mut self, format: ImageFormat) -> Result<(), NetworkError> {
-        // フォーマットを設定（ここではセッション情報に保存しない、キャプチャ時のオプションとして使用）
-        let response = Response::CommandResult {
-            success: true,
-            message: format!("画像フォーマットを設定しました: {:?}", format),
-            data: None,
+        // H.264は前のフレームを参照するため、配信の動画モードでのみ使用する
+        let response = if matches!(format, ImageFormat::H264) {
+            Response::CommandResult {
+                success: false,
+                message: "H.264は画像フォーマットとして設定できません".to_string(),
+                data: None,
+            }
+        } else {
+            // 配信するフレームのエンコードに使用する（画質は帯域に合わせて調整する）
+            self.updates.encoder_mut().set_format(capture::ImageFormat::from(format));
+            Response::CommandResult {
+                success: true,
+                message: format!("画像フォーマットを設定しました: {:?}", format),
+                data: None,
+            }
         };
         
         self.connection.send(&response)
@@ -514,16 +1109,166 @@ impl ClientSession {
     
     /// FPS設定処理
     fn handle_set_fps(&mut self, fps: u8) -> Result<(), NetworkError> {
-        // FPSを設定（サーバー側では特に何もしない、クライアント制御用）
+        // 配信中のフレームレートにも反映（要求されたFPSを上限として帯域に合わせて調整する）
+        self.congestion.set_target_fps(fps);
+        self.apply_stream_fps();
+        
+        let response = Response::CommandResult {
+            success: true,
+            message: format!("FPSを設定しました: {}", self.stream.fps()),
+            data: Some(self.congestion.decision().to_data()),
+        };
+        
+        self.connection.send(&response)
+    }
+    
+    /// フレーム配信の開始処理
+    fn handle_start_stream(&mut self, fps: Option<u8>, credits: u32) -> Result<(), NetworkError> {
+        let credits = self.stream.start(fps, credits);
+        
+        // 前回の配信の受信確認待ちは破棄し、要求されたFPSから調整し直す
+        self.congestion.restart(Instant::now());
+        self.congestion.set_target_fps(self.stream.fps());
+        self.stream.set_fps(self.congestion.fps());
+        
+        // クライアントは合成元の画像を持っていないため、最初はキーフレームを送る
+        self.updates.request_keyframe();
+        
+        // タイルキャッシュはネゴシエーションで合意した場合のみ使用
+        let tile_cache = self.session_info.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(Feature::TileCache));
+        self.updates.set_tile_cache(if tile_cache { Some(TILE_CACHE_CAPACITY) } else { None });
+
+        // 動きの多い画面での動画への切り替えも、H.264で合意した場合のみ
+        let video = self.session_info.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports_codec(ImageFormat::H264));
+        self.updates.set_video(video);
+        
+        // 色数の少ない矩形の可逆圧縮も、QOIで合意した場合のみ
+        let qoi = self.session_info.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports_codec(ImageFormat::QOI));
+        self.updates.set_qoi(qoi);
+
+        // 配信間隔より短い間隔での再キャプチャは不要
+        self.screen_capture.lock().unwrap().set_min_interval(self.stream.interval());
+        
+        info!("フレーム配信開始: {}fps, クレジット={} ({})", self.stream.fps(), credits, self.session_info.ip_address);
+        
+        let response = Response::StreamStarted {
+            fps: self.stream.fps(),
+            credits,
+        };
+        
+        self.connection.send(&response)
+    }
+    
+    /// フレーム配信のクレジット追加処理
+    fn handle_stream_credit(&mut self, credits: u32) -> Result<(), NetworkError> {
+        // フレームごとに届くため、レスポンスは返さない（トラフィック削減のため）
+        self.stream.grant(credits);
+        
+        // クレジットの返却をフレームの受信確認として扱う
+        self.congestion.on_frames_acked(credits, Instant::now());
+        Ok(())
+    }
+    
+    /// キーフレーム要求の処理
+    fn handle_request_keyframe(&mut self) -> Result<(), NetworkError> {
+        self.updates.request_keyframe();
+        
         let response = Response::CommandResult {
             success: true,
-            message: format!("FPSを設定しました: {}", fps),
+            message: "次のフレームをキーフレームとして送信します".to_string(),
             data: None,
         };
         
         self.connection.send(&response)
     }
     
+    /// フレーム配信の停止処理
+    fn handle_stop_stream(&mut self) -> Result<(), NetworkError> {
+        self.stream.stop();
+        self.frame_to_screen = (1.0, 1.0);
+        
+        info!("フレーム配信停止: {}", self.session_info.ip_address);
+        
+        let response = Response::StreamStopped {
+            reason: "クライアントの要求により停止しました".to_string(),
+        };
+        
+        self.connection.send(&response)
+    }
+    
+    /// 配信中であれば、送信時刻になったフレームを送信
+    ///
+    /// 次に呼び出すまでの待ち時間を返します。停止中またはクレジットがなく、
+    /// クライアントからのコマンドを待つしかない場合は`None`を返します。
+    pub fn pump_stream(&mut self) -> Result<Option<Duration>, NetworkError> {
+        if !self.active {
+            return Ok(None);
+        }
+        
+        let now = Instant::now();
+        if !self.stream.take_frame(now) {
+            return Ok(self.stream.next_frame_in(now));
+        }
+        
+        match self.next_frame_update() {
+            Ok(Some(response)) => {
+                self.connection.notify(&response)?;
+                self.congestion.on_frame_sent(frame_size(&response), now);
+            },
+            Ok(None) => {
+                // 変更がなければ送信せず、消費したクレジットを戻す
+                self.stream.grant(1);
+            },
+            Err(e) => {
+                // キャプチャできない状態で送り続けても意味がないため配信を止める
+                error!("フレーム配信のキャプチャエラー: {}", e);
+                self.stream.stop();
+                self.frame_to_screen = (1.0, 1.0);
+                
+                let response = Response::StreamStopped {
+                    reason: format!("スクリーンショット取得に失敗しました: {}", e),
+                };
+                self.connection.notify(&response)?;
+            }
+        }
+        
+        // 帯域に合わせて品質を調整したらクライアントに通知
+        if self.stream.is_active() && self.congestion.update(Instant::now()) {
+            self.apply_stream_fps();
+            
+            let decision = self.congestion.decision();
+            debug!("配信品質を調整: {:?} ({})", decision, self.session_info.ip_address);
+            
+            let response = Response::CommandResult {
+                success: true,
+                message: format!(
+                    "配信品質を調整しました: 画質={}, 解像度={:.0}%, {}fps",
+                    decision.quality,
+                    decision.scale * 100.0,
+                    decision.fps
+                ),
+                data: Some(decision.to_data()),
+            };
+            self.connection.notify(&response)?;
+        }
+        
+        Ok(self.stream.next_frame_in(Instant::now()))
+    }
+    
+    /// 輻輳制御で決めたFPSを配信に反映
+    fn apply_stream_fps(&mut self) {
+        self.stream.set_fps(self.congestion.fps());
+        if self.stream.is_active() {
+            self.screen_capture.lock().unwrap().set_min_interval(self.stream.interval());
+        }
+    }
+    
     /// アプリケーション実行処理
     fn handle_run_application(&mut self, command: String) -> Result<(), NetworkError> {
         // セキュリティ上の理由から通常は無効
@@ -676,6 +1421,9 @@ impl ClientSession {
             let mut session_info = self.session_info.clone();
             session_info.last_latency = Some(latency);
             self.session_info = session_info;
+            
+            // 片道のレイテンシの往復分をRTTとみなす
+            self.congestion.on_rtt_sample(Duration::from_millis(latency.saturating_mul(2)));
             latency
         } else {
             0
@@ -705,6 +1453,7 @@ impl ClientSession {
         let _ = self.connection.send(&response);
         
         // セッションを終了
+        self.state.transition(SessionState::Closing);
         self.active = false;
         
         Ok(())
@@ -744,6 +1493,74 @@ impl ClientSession {
         Ok((encoded, format))
     }
     
+    /// 次の差分フレームをキャプチャしてエンコード
+    ///
+    /// 前回から変更がなければ`None`を返します。
+    fn next_frame_update(&mut self) -> Result<Option<Response>, NetworkError> {
+        let image = self.screen_capture.lock().unwrap().capture()
+            .map_err(|e| NetworkError::Other(format!("スクリーンキャプチャに失敗: {}", e)))?;
+        
+        // 帯域が足りない間は解像度を下げる（サイズが変わるためキーフレームになる）
+        let scale = self.congestion.scale();
+        let (screen_width, screen_height) = (image.image.width(), image.image.height());
+        let image = if scale < 1.0 {
+            let width = ((screen_width as f32 * scale) as u32).max(1);
+            let height = ((screen_height as f32 * scale) as u32).max(1);
+            image.resize(width, height)
+        } else {
+            image
+        };
+        self.frame_to_screen = (
+            screen_width as f32 / image.image.width() as f32,
+            screen_height as f32 / image.image.height() as f32,
+        );
+        
+        // フォーマットはセッションの設定のまま、画質だけを帯域に合わせる
+        self.updates.encoder_mut().set_quality(self.congestion.quality());
+        if let Some(video) = self.updates.video_mut() {
+            video.set_bitrate(self.congestion.video_bitrate(DEFAULT_VIDEO_BITRATE));
+        }
+        
+        let update = self.updates.next_frame(&image)
+            .map_err(|e| NetworkError::Other(format!("画像のエンコードに失敗: {}", e)))?;
+        
+        if update.is_empty() {
+            return Ok(None);
+        }
+        
+        let rects = update.regions
+            .into_iter()
+            .map(|region| FrameRect {
+                x: region.rect.x,
+                y: region.rect.y,
+                width: region.rect.width,
+                height: region.rect.height,
+                content: match region.content {
+                    RegionContent::Encoded { image, tiles } => RectContent::Encoded {
+                        format: image.format.into(),
+                        data: image.data,
+                        tiles,
+                    },
+                    RegionContent::TileRef(hash) => RectContent::TileRef { hash },
+                    RegionContent::CopyRect { src_x, src_y } => RectContent::CopyRect { src_x, src_y },
+                },
+            })
+            .collect();
+        
+        Ok(Some(Response::FrameUpdate {
+            sequence: update.sequence,
+            keyframe: update.keyframe,
+            width: update.width,
+            height: update.height,
+            tile_size: update.tile_size,
+            rects,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or(Duration::from_secs(0))
+                .as_millis() as u64,
+        }))
+    }
+    
     /// キープアライブを送信
     fn send_keep_alive(&mut self) -> Result<(), NetworkError> {
         let response = Response::Pong {
@@ -754,13 +1571,27 @@ impl ClientSession {
                 .as_millis() as u64,
         };
         
-        let result = self.connection.send(&response);
+        let result = self.connection.notify(&response);
         self.last_keep_alive = Instant::now();
         
         result
     }
 }
 
+/// 差分フレームの画素データのバイト数
+fn frame_size(response: &Response) -> usize {
+    match response {
+        Response::FrameUpdate { rects, .. } => rects
+            .iter()
+            .map(|rect| match &rect.content {
+                RectContent::Encoded { data, .. } => data.len(),
+                RectContent::TileRef { .. } | RectContent::CopyRect { .. } => 0,
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
 /// キャプチャオプション
 struct CaptureOptions {
     /// 品質（1-100）
@@ -1014,4 +1845,505 @@ struct FileTransfer {
     progress: u64,
     /// 開始時間
     start_time: Instant,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::congestion::QualityBounds;
+    use super::super::login_tracker::{LockoutPolicy, LoginAttemptTracker};
+    use crate::capture::TestPatternSource;
+    use crate::input::sink::{InputEvent, RecordingSink};
+    use crate::ui::settings::UserCredential;
+    use remote_desktop_rs_common::encryption::{compute_challenge_response, hash_password_with};
+    use remote_desktop_rs_common::protocol::MouseButton;
+    use std::collections::VecDeque;
+    
+    /// 送受信を記録するテスト用接続
+    #[derive(Default)]
+    struct MockConnection {
+        incoming: VecDeque<Envelope<Command>>,
+        sent: Vec<Envelope<Response>>,
+        closed: bool,
+    }
+    
+    impl ClientConnection for MockConnection {
+        fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+            if self.closed {
+                return Err(NetworkError::CommunicationError("接続は閉じられています".to_string()));
+            }
+            self.sent.push(Envelope::reply(envelope.id, envelope.reply_to, envelope.message.clone()));
+            Ok(())
+        }
+        
+        fn send_raw(&mut self, _data: &[u8]) -> Result<(), NetworkError> {
+            Ok(())
+        }
+        
+        fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
+            self.incoming.pop_front()
+                .ok_or_else(|| NetworkError::CommunicationError("受信データがありません".to_string()))
+        }
+        
+        fn set_wire_format(&mut self, _format: WireFormat) {}
+        
+        fn set_timeout(&mut self, _duration: Duration) -> Result<(), NetworkError> {
+            Ok(())
+        }
+        
+        fn close(&mut self) -> Result<(), NetworkError> {
+            self.closed = true;
+            Ok(())
+        }
+    }
+    
+    /// 送信したレスポンスを共有するテスト用接続（`ClientSession`に渡した後も確認できる）
+    #[derive(Clone, Default)]
+    struct SharedConnection {
+        sent: Arc<Mutex<Vec<Response>>>,
+    }
+    
+    impl SharedConnection {
+        /// 送信したレスポンスを取り出す
+        fn take(&self) -> Vec<Response> {
+            std::mem::take(&mut *self.sent.lock().unwrap())
+        }
+    }
+    
+    impl ClientConnection for SharedConnection {
+        fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+            self.sent.lock().unwrap().push(envelope.message.clone());
+            Ok(())
+        }
+        
+        fn send_raw(&mut self, _data: &[u8]) -> Result<(), NetworkError> {
+            Ok(())
+        }
+        
+        fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
+            Err(NetworkError::CommunicationError("受信データがありません".to_string()))
+        }
+        
+        fn set_wire_format(&mut self, _format: WireFormat) {}
+        
+        fn set_timeout(&mut self, _duration: Duration) -> Result<(), NetworkError> {
+            Ok(())
+        }
+        
+        fn close(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+    
+    /// テストパターンを配信し、入力を記録するセッションを作成
+    ///
+    /// ユーザー"user"（パスワード"secret"、制御者）で認証できます。
+    fn test_session(config: ServerConfig) -> (ClientSession, SharedConnection, RecordingSink) {
+        let mut screen_capture = ScreenCapture::with_source(Box::new(TestPatternSource::new(320, 240)));
+        screen_capture.set_min_interval(Duration::ZERO);
+        let sink = RecordingSink::new(320, 240);
+        let authenticator = Authenticator::new(vec![UserCredential {
+            username: "user".to_string(),
+            password_hash: hash_password_with("secret", b"0123456789abcdef", 1000).unwrap(),
+            permission_level: 1,
+            totp_secret: None,
+            authorized_keys: Vec::new(),
+        }]);
+        
+        let connection = SharedConnection::default();
+        let session = ClientSession::new(
+            SessionInfo::new("test".to_string(), "127.0.0.1:50000".to_string()),
+            Box::new(connection.clone()),
+            Arc::new(Mutex::new(screen_capture)),
+            Arc::new(Mutex::new(InputHandler::with_sink(Arc::new(sink.clone())))),
+            Arc::new(authenticator),
+            config,
+        );
+        (session, connection, sink)
+    }
+    
+    /// 認証チャレンジを要求し、`password`から計算した応答を返す
+    fn challenge_response(session: &mut ClientSession, connection: &SharedConnection, password: &str) -> String {
+        session.handle_command(authenticate_command()).unwrap();
+        match connection.take().pop() {
+            Some(Response::AuthChallenge { nonce, salt, iterations }) => {
+                compute_challenge_response(password, &salt, iterations, &nonce).unwrap()
+            },
+            other => panic!("認証チャレンジではありません: {:?}", other),
+        }
+    }
+    
+    /// 封筒なしのコマンドを受信するテスト用接続を作成
+    fn mock_connection(commands: Vec<Command>) -> CorrelatedConnection<MockConnection> {
+        let mut mock = MockConnection::default();
+        mock.incoming.extend(commands.into_iter().map(Envelope::legacy));
+        CorrelatedConnection::new(Box::new(mock))
+    }
+    
+    /// 受信キューのコマンドをすべて状態マシンに通し、受け付けたものを返す
+    fn drive(machine: &mut SessionStateMachine, connection: &mut CorrelatedConnection<MockConnection>) -> Vec<Command> {
+        let mut admitted = Vec::new();
+        while let Ok(command) = connection.receive() {
+            if machine.admit(&command, connection).unwrap() {
+                admitted.push(command);
+            }
+        }
+        admitted
+    }
+    
+    fn authenticate_command() -> Command {
+        Command::Authenticate {
+            username: "user".to_string(),
+            client_info: ClientInfo {
+                app_name: "test".to_string(),
+                version: "0.0.0".to_string(),
+                os_type: "test".to_string(),
+                os_version: String::new(),
+                device_name: String::new(),
+                screen_width: 0,
+                screen_height: 0,
+                capabilities: Vec::new(),
+            },
+            method: AuthMethod::Password,
+        }
+    }
+    
+    fn error_codes(connection: &CorrelatedConnection<MockConnection>) -> Vec<i32> {
+        connection.inner.sent.iter()
+            .filter_map(|envelope| match &envelope.message {
+                Response::Error { code, .. } => Some(*code),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    fn auth_config() -> ServerConfig {
+        ServerConfig {
+            require_auth: true,
+            ..Default::default()
+        }
+    }
+    
+    fn session_with_level(level: u8) -> SessionInfo {
+        let mut info = SessionInfo::new("test".to_string(), "127.0.0.1:50000".to_string());
+        info.authenticated = true;
+        info.username = Some("user".to_string());
+        info.permission_level = level;
+        info
+    }
+    
+    fn viewer_commands() -> Vec<Command> {
+        vec![
+            Command::RequestScreenshot { quality: None, width: None, height: None, monitor: None },
+            Command::Ping { timestamp: 0 },
+        ]
+    }
+    
+    fn controller_commands() -> Vec<Command> {
+        vec![
+            Command::MouseMove { x: 10, y: 10 },
+            Command::MouseClick { button: MouseButton::Left, double: false },
+            Command::KeyDown { key_code: 65, modifiers: vec![] },
+            Command::TextInput { text: "a".to_string() },
+            Command::RequestClipboardContent,
+            Command::SetClipboardContent { content: "a".to_string() },
+        ]
+    }
+    
+    fn admin_commands() -> Vec<Command> {
+        vec![
+            Command::RunApplication { command: "true".to_string() },
+            Command::StartFileTransfer { filename: "a.txt".to_string(), size: 1, checksum: String::new() },
+            Command::FileData { transfer_id: 1, data: vec![0], offset: 0 },
+        ]
+    }
+    
+    fn assert_denied<E: Into<Response>>(result: Result<(), E>, expected_code: i32) {
+        match result.map_err(Into::into) {
+            Err(Response::Error { code, .. }) => assert_eq!(code, expected_code),
+            other => panic!("拒否されるべきコマンドが許可されました: {:?}", other.is_ok()),
+        }
+    }
+    
+    #[test]
+    fn test_viewer_permissions() {
+        let config = auth_config();
+        let info = session_with_level(0);
+        
+        for command in viewer_commands() {
+            assert!(authorize_command(&info, &config, &command).is_ok(), "{:?}", command);
+        }
+        for command in controller_commands().into_iter().chain(admin_commands()) {
+            assert_denied(authorize_command(&info, &config, &command), 403);
+        }
+    }
+    
+    #[test]
+    fn test_controller_permissions() {
+        let config = auth_config();
+        let info = session_with_level(1);
+        
+        for command in viewer_commands().into_iter().chain(controller_commands()) {
+            assert!(authorize_command(&info, &config, &command).is_ok(), "{:?}", command);
+        }
+        for command in admin_commands() {
+            assert_denied(authorize_command(&info, &config, &command), 403);
+        }
+    }
+    
+    #[test]
+    fn test_admin_permissions() {
+        let config = auth_config();
+        let info = session_with_level(2);
+        
+        for command in viewer_commands().into_iter().chain(controller_commands()).chain(admin_commands()) {
+            assert!(authorize_command(&info, &config, &command).is_ok(), "{:?}", command);
+        }
+    }
+    
+    #[test]
+    fn test_unauthenticated_session() {
+        let config = auth_config();
+        let info = SessionInfo::new("test".to_string(), "127.0.0.1:50000".to_string());
+        
+        // 認証前でも認証開始と切断は受け付ける
+        assert!(authorize_command(&info, &config, &Command::Disconnect).is_ok());
+        assert!(authorize_command(&info, &config, &Command::AuthChallengeResponse { response: String::new() }).is_ok());
+        
+        assert_denied(authorize_command(&info, &config, &Command::Ping { timestamp: 0 }), 401);
+    }
+    
+    #[test]
+    fn test_unknown_level_is_viewer() {
+        let config = auth_config();
+        let info = session_with_level(200);
+        
+        assert_denied(authorize_command(&info, &config, &Command::MouseMove { x: 0, y: 0 }), 403);
+    }
+    
+    #[test]
+    fn test_negotiated_capabilities() {
+        // Helloを送らない旧クライアントは制限しない
+        let mut info = session_with_level(2);
+        assert!(check_capabilities(&info, &Command::RequestSystemInfo).is_ok());
+    
+        info.capabilities = Some(Capabilities {
+            codecs: vec![ImageFormat::PNG],
+            features: vec![Feature::Clipboard],
+            ..Default::default()
+        });
+    
+        assert!(check_capabilities(&info, &Command::RequestClipboardContent).is_ok());
+        assert!(check_capabilities(&info, &Command::SetImageFormat { format: ImageFormat::PNG }).is_ok());
+        assert!(check_capabilities(&info, &Command::MouseMove { x: 0, y: 0 }).is_ok());
+        assert_denied(check_capabilities(&info, &Command::RequestSystemInfo), 501);
+        assert_denied(check_capabilities(&info, &Command::SetImageFormat { format: ImageFormat::JPEG }), 415);
+    }
+    
+    #[test]
+    fn test_handshake_rejects_commands_before_authentication() {
+        let mut machine = SessionStateMachine::new(&auth_config());
+        let mut connection = mock_connection(vec![
+            Command::Ping { timestamp: 0 },
+            Command::AuthChallengeResponse { response: String::new() },
+            Command::NegotiateWireFormat { formats: vec![WireFormat::MessagePack] },
+            authenticate_command(),
+        ]);
+        
+        let admitted = drive(&mut machine, &mut connection);
+        
+        assert_eq!(admitted.len(), 2);
+        assert!(matches!(admitted[0], Command::NegotiateWireFormat { .. }));
+        assert!(matches!(admitted[1], Command::Authenticate { .. }));
+        assert_eq!(error_codes(&connection), vec![401, 409]);
+        assert_eq!(machine.state, SessionState::Handshake);
+        assert!(!connection.inner.closed);
+    }
+    
+    #[test]
+    fn test_state_transitions() {
+        let mut machine = SessionStateMachine::new(&auth_config());
+        let mut connection = mock_connection(Vec::new());
+        
+        machine.transition(SessionState::Authenticating);
+        assert!(machine.admit(&Command::AuthChallengeResponse { response: String::new() }, &mut connection).unwrap());
+        assert!(machine.admit(&Command::SubmitOtp { code: String::new() }, &mut connection).unwrap());
+        assert!(!machine.admit(&Command::MouseMove { x: 0, y: 0 }, &mut connection).unwrap());
+        
+        machine.transition(SessionState::Authenticated);
+        assert!(machine.admit(&Command::MouseMove { x: 0, y: 0 }, &mut connection).unwrap());
+        assert!(!machine.admit(&Command::AuthChallengeResponse { response: String::new() }, &mut connection).unwrap());
+        assert_eq!(error_codes(&connection), vec![401, 409]);
+        
+        // Closingからは遷移しない
+        machine.transition(SessionState::Closing);
+        machine.transition(SessionState::Authenticated);
+        assert_eq!(machine.state, SessionState::Closing);
+        assert!(!machine.admit(&Command::Disconnect, &mut connection).unwrap());
+    }
+    
+    #[test]
+    fn test_session_closes_after_max_violations() {
+        let config = ServerConfig {
+            max_protocol_violations: 2,
+            ..auth_config()
+        };
+        let mut machine = SessionStateMachine::new(&config);
+        let mut connection = mock_connection(vec![
+            Command::MouseMove { x: 0, y: 0 },
+            Command::RequestSystemInfo,
+            authenticate_command(),
+        ]);
+        
+        let admitted = drive(&mut machine, &mut connection);
+        
+        assert!(admitted.is_empty());
+        assert_eq!(machine.state, SessionState::Closing);
+        assert!(connection.inner.closed);
+        assert_eq!(error_codes(&connection), vec![401, 401]);
+        assert!(matches!(
+            connection.inner.sent.last().map(|envelope| &envelope.message),
+            Some(Response::ConnectionStatus { connected: false, .. })
+        ));
+    }
+    
+    #[test]
+    fn test_auth_disabled_starts_authenticated() {
+        let mut machine = SessionStateMachine::new(&ServerConfig::default());
+        let mut connection = mock_connection(Vec::new());
+        
+        assert_eq!(machine.state, SessionState::Authenticated);
+        assert!(machine.admit(&Command::RequestSystemInfo, &mut connection).unwrap());
+        assert!(connection.inner.sent.is_empty());
+    }
+    
+    #[test]
+    fn test_responses_are_correlated_with_requests() {
+        let mut mock = MockConnection::default();
+        mock.incoming.push_back(Envelope::new(41, Command::SetQuality { quality: 50 }));
+        mock.incoming.push_back(Envelope::new(42, Command::RequestClipboardContent));
+        let mut connection = CorrelatedConnection::new(Box::new(mock));
+        
+        for _ in 0..2 {
+            let command = connection.receive().unwrap();
+            connection.send(&Response::CommandResult {
+                success: true,
+                message: format!("{:?}", command),
+                data: None,
+            }).unwrap();
+            connection.end_request();
+        }
+        connection.notify(&Response::Pong { original_timestamp: 0, server_time: 0 }).unwrap();
+        
+        let sent: Vec<_> = connection.inner.sent.iter().map(|envelope| (envelope.id, envelope.reply_to)).collect();
+        assert_eq!(sent, vec![(1, Some(41)), (2, Some(42)), (3, None)]);
+        
+        // 封筒を使わない旧クライアントには封筒なしで応答する
+        let mut legacy = mock_connection(vec![Command::Ping { timestamp: 0 }]);
+        legacy.receive().unwrap();
+        legacy.send(&Response::Pong { original_timestamp: 0, server_time: 0 }).unwrap();
+        assert!(legacy.inner.sent[0].is_legacy());
+    }
+    
+    #[test]
+    fn test_backoff_applies_to_pending_challenges() {
+        let tracker = Arc::new(LoginAttemptTracker::new(LockoutPolicy {
+            base_delay: Duration::from_secs(60),
+            ..LockoutPolicy::default()
+        }));
+        let config = ServerConfig {
+            login_tracker: tracker,
+            ..auth_config()
+        };
+        
+        // 失敗が記録される前に2つのセッションでチャレンジを取得
+        let (mut first, first_connection, _) = test_session(config.clone());
+        let (mut second, second_connection, _) = test_session(config);
+        let wrong = challenge_response(&mut first, &first_connection, "wrong");
+        let response = challenge_response(&mut second, &second_connection, "secret");
+        
+        first.handle_command(Command::AuthChallengeResponse { response: wrong }).unwrap();
+        assert!(matches!(first_connection.take().as_slice(), [Response::AuthResult { success: false, .. }]));
+        
+        // 正しい応答でも待機時間中は検証せずに拒否する
+        second.handle_command(Command::AuthChallengeResponse { response }).unwrap();
+        assert!(matches!(second_connection.take().as_slice(), [Response::Error { .. }]));
+        assert!(!second.session_info().authenticated);
+        assert_eq!(second.state(), SessionState::Handshake);
+    }
+    
+    #[test]
+    fn test_pointer_is_scaled_to_downscaled_frames() {
+        // 帯域に関係なく解像度を半分に下げて配信する
+        let config = ServerConfig {
+            quality_bounds: QualityBounds { min_scale: 0.5, max_scale: 0.5, ..Default::default() },
+            ..Default::default()
+        };
+        let (mut session, connection, sink) = test_session(config);
+        
+        session.handle_command(Command::StartStream { fps: Some(30), credits: 1 }).unwrap();
+        session.pump_stream().unwrap();
+        let frame_size = connection.take().into_iter().find_map(|response| match response {
+            Response::FrameUpdate { width, height, .. } => Some((width, height)),
+            _ => None,
+        });
+        assert_eq!(frame_size, Some((160, 120)));
+        
+        // フレーム上の座標は元の画面の座標に戻して入力する
+        session.handle_command(Command::MouseMove { x: 80, y: 60 }).unwrap();
+        session.handle_command(Command::StopStream).unwrap();
+        session.handle_command(Command::MouseMove { x: 80, y: 60 }).unwrap();
+        assert_eq!(sink.take_events(), vec![
+            InputEvent::MouseMove { x: 160, y: 120 },
+            InputEvent::MouseMove { x: 80, y: 60 },
+        ]);
+    }
+    
+    #[test]
+    fn test_stream_keeps_session_image_format() {
+        let (mut session, connection, _sink) = test_session(ServerConfig::default());
+        session.handle_command(Command::SetImageFormat { format: ImageFormat::PNG }).unwrap();
+        session.handle_command(Command::SetQuality { quality: 50 }).unwrap();
+        session.handle_command(Command::StartStream { fps: Some(30), credits: 1 }).unwrap();
+        session.pump_stream().unwrap();
+        assert!(connection.take().iter().any(|response| matches!(response, Response::FrameUpdate { .. })));
+        
+        // 画質だけが反映され、フォーマットは変わらない
+        let config = session.updates.encoder_mut().config();
+        assert_eq!(config.format, capture::ImageFormat::PNG);
+        assert_eq!(config.quality, 50);
+        
+        // H.264は画像フォーマットとして設定できない
+        session.handle_command(Command::SetImageFormat { format: ImageFormat::H264 }).unwrap();
+        assert!(matches!(connection.take().as_slice(), [Response::CommandResult { success: false, .. }]));
+        assert_eq!(session.updates.encoder_mut().config().format, capture::ImageFormat::PNG);
+    }
+    
+    #[test]
+    fn test_stream_sends_frames_from_capture_source() {
+        let (mut session, connection, _sink) = test_session(ServerConfig::default());
+        session.handle_command(Command::StartStream { fps: Some(30), credits: 2 }).unwrap();
+        assert!(matches!(connection.take().as_slice(), [Response::StreamStarted { credits: 2, .. }]));
+        
+        // クレジットを使い切るまで配信する（テストパターンは毎フレーム動くため変更のないフレームはない）
+        for _ in 0..100 {
+            match session.pump_stream().unwrap() {
+                Some(wait) => std::thread::sleep(wait),
+                None => break,
+            }
+        }
+        let frames: Vec<_> = connection.take().into_iter()
+            .filter_map(|response| match response {
+                Response::FrameUpdate { keyframe, width, height, rects, .. } => Some((keyframe, width, height, rects.len())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].0, "最初のフレームはキーフレーム");
+        assert!(!frames[1].0, "2つ目のフレームは差分");
+        assert!(frames.iter().all(|&(_, width, height, rects)| (width, height) == (320, 240) && rects > 0));
+        
+        session.handle_command(Command::StopStream).unwrap();
+        assert!(matches!(connection.take().as_slice(), [Response::StreamStopped { .. }]));
+        assert_eq!(session.pump_stream().unwrap(), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! フレームのプッシュ配信
+//!
+//! `Command::StartStream`を受けたセッションは、クライアントの要求を待たずに
+//! 設定されたFPSでフレームを送信します。クライアントは受信したフレームの数だけ
+//! `Command::StreamCredit`でクレジットを返し、クレジットが尽きると送信を止めるため、
+//! 処理の遅いクライアントに未処理のフレームが溜まることはありません。
+
+use std::time::{Duration, Instant};
+
+/// 既定のフレームレート
+pub const DEFAULT_FPS: u8 = 30;
+
+/// 最大のフレームレート
+pub const MAX_FPS: u8 = 60;
+
+/// 保持できるクレジットの上限
+pub const MAX_CREDITS: u32 = 16;
+
+/// フレーム配信の状態
+#[derive(Debug, Clone)]
+pub struct FrameStream {
+    /// 配信中かどうか
+    active: bool,
+    /// フレームレート
+    fps: u8,
+    /// 残りのクレジット
+    credits: u32,
+    /// 最後にフレームを送信した時刻
+    last_frame: Option<Instant>,
+}
+
+impl FrameStream {
+    /// 停止状態の配信を作成
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            fps: DEFAULT_FPS,
+            credits: 0,
+            last_frame: None,
+        }
+    }
+
+    /// 配信を開始
+    ///
+    /// クレジットは上限で切り詰め、実際に受け付けた値を返します。
+    pub fn start(&mut self, fps: Option<u8>, credits: u32) -> u32 {
+        if let Some(fps) = fps {
+            self.set_fps(fps);
+        }
+        self.active = true;
+        self.credits = credits.min(MAX_CREDITS);
+        self.last_frame = None;
+        self.credits
+    }
+
+    /// 配信を停止
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.credits = 0;
+    }
+
+    /// 配信中かどうか
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// フレームレート
+    pub fn fps(&self) -> u8 {
+        self.fps
+    }
+
+    /// フレームレートを設定（1〜`MAX_FPS`に丸める）
+    pub fn set_fps(&mut self, fps: u8) {
+        self.fps = fps.clamp(1, MAX_FPS);
+    }
+
+    /// 残りのクレジット
+    pub fn credits(&self) -> u32 {
+        self.credits
+    }
+
+    /// クレジットを追加
+    pub fn grant(&mut self, credits: u32) {
+        self.credits = self.credits.saturating_add(credits).min(MAX_CREDITS);
+    }
+
+    /// フレームの間隔
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.fps as u32
+    }
+
+    /// 次のフレームを送信できるまでの時間
+    ///
+    /// 停止中またはクレジットがない場合は`None`を返します。
+    pub fn next_frame_in(&self, now: Instant) -> Option<Duration> {
+        if !self.active || self.credits == 0 {
+            return None;
+        }
+
+        match self.last_frame {
+            Some(last) => Some(self.interval().saturating_sub(now.saturating_duration_since(last))),
+            None => Some(Duration::ZERO),
+        }
+    }
+
+    /// フレームを送信してよければクレジットを1つ消費して`true`を返す
+    pub fn take_frame(&mut self, now: Instant) -> bool {
+        if self.next_frame_in(now) != Some(Duration::ZERO) {
+            return false;
+        }
+
+        self.credits -= 1;
+        self.last_frame = Some(now);
+        true
+    }
+}
+
+impl Default for FrameStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_are_paced_and_limited_by_credits() {
+        let mut stream = FrameStream::new();
+        let now = Instant::now();
+        assert!(!stream.take_frame(now));
+
+        assert_eq!(stream.start(Some(10), 2), 2);
+        assert_eq!(stream.interval(), Duration::from_millis(100));
+
+        // 最初のフレームはすぐに送り、次はFPSの間隔を空ける
+        assert!(stream.take_frame(now));
+        assert_eq!(stream.next_frame_in(now + Duration::from_millis(40)), Some(Duration::from_millis(60)));
+        assert!(!stream.take_frame(now + Duration::from_millis(40)));
+        assert!(stream.take_frame(now + Duration::from_millis(100)));
+
+        // クレジットが尽きたら、返されるまで送らない
+        assert_eq!(stream.next_frame_in(now + Duration::from_secs(1)), None);
+        stream.grant(1);
+        assert!(stream.take_frame(now + Duration::from_secs(1)));
+
+        stream.grant(u32::MAX);
+        assert_eq!(stream.credits(), MAX_CREDITS);
+
+        stream.stop();
+        assert_eq!(stream.next_frame_in(now + Duration::from_secs(2)), None);
+    }
+
+    #[test]
+    fn test_fps_is_clamped() {
+        let mut stream = FrameStream::new();
+        stream.set_fps(0);
+        assert_eq!(stream.fps(), 1);
+        stream.set_fps(200);
+        assert_eq!(stream.fps(), MAX_FPS);
+        assert_eq!(stream.start(None, 100), MAX_CREDITS);
+        assert_eq!(stream.fps(), MAX_FPS);
+    }
+}
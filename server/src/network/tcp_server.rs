@@ -5,8 +5,10 @@
 
 use super::{NetworkServer, NetworkError, ServerConfig, SessionInfo};
 use super::session::ClientSession;
+use super::access_control::AccessControl;
 use super::authentication::Authenticator;
-use remote_desktop_rs_common::protocol::{Command, Response};
+use remote_desktop_rs_common::protocol::{Command, Envelope, Response};
+use remote_desktop_rs_common::wire::{self, WireFormat};
 use crate::capture::ScreenCapture;
 use crate::input::InputHandler;
 
@@ -15,7 +17,6 @@ use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
-use serde_json;
 use log::{debug, info, warn, error};
 
 // TLS関連
@@ -33,6 +34,8 @@ pub struct TcpServer {
     input_handler: Arc<Mutex<InputHandler>>,
     /// 認証ハンドラ
     authenticator: Authenticator,
+    /// アクセス制御
+    access_control: AccessControl,
     /// リスナースレッド
     listener_thread: Option<thread::JoinHandle<()>>,
     /// スレッド管理用チャネル
@@ -52,11 +55,16 @@ impl TcpServer {
         screen_capture: Arc<Mutex<ScreenCapture>>,
         input_handler: Arc<Mutex<InputHandler>>,
     ) -> Result<Self, NetworkError> {
+        let authenticator = Authenticator::new(config.users.clone());
+        let access_control = AccessControl::new(&config.allowed_ips, &config.denied_ips)
+            .map_err(|e| NetworkError::Other(e.to_string()))?;
+        
         Ok(Self {
             config,
             screen_capture,
             input_handler,
-            authenticator: Authenticator::new(),
+            authenticator,
+            access_control,
             listener_thread: None,
             thread_control: None,
             client_sessions: Arc::new(Mutex::new(Vec::new())),
@@ -101,7 +109,6 @@ impl TcpServer {
         thread::spawn(move || {
             info!("クライアント接続: {}", client_addr);
             
-            let mut buffer = [0; 4096];
             let mut stream = stream;
             
             // 接続タイムアウトを設定
@@ -115,19 +122,39 @@ impl TcpServer {
             let _ = stream.set_recv_buffer_size(1024 * 1024 * 10); // 10MB
             let _ = stream.set_send_buffer_size(1024 * 1024);      // 1MB
             
+            let client_timeout = Duration::from_secs(config.client_timeout);
+            
             loop {
-                match stream.read(&mut buffer) {
-                    Ok(0) => {
-                        // 接続が閉じられた
-                        info!("クライアント切断: {}", client_addr);
+                // 配信中であればフレームを送信し、次のフレームまでの時間だけデータの到着を待つ
+                let wait = match session.lock().unwrap().pump_stream() {
+                    Ok(wait) => wait,
+                    Err(e) => {
+                        error!("フレーム配信エラー: {}", e);
                         break;
-                    },
-                    Ok(bytes_read) => {
-                        // 受信データをセッションに渡す
+                    }
+                };
+                
+                if let Some(wait) = wait {
+                    // フレームの途中で読み取りが中断されないよう、到着の確認には`peek`を使う
+                    let _ = stream.set_read_timeout(Some(wait.max(Duration::from_millis(1))));
+                    let arrived = !matches!(
+                        stream.peek(&mut [0u8; 1]),
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock ||
+                                  e.kind() == std::io::ErrorKind::TimedOut
+                    );
+                    let _ = stream.set_read_timeout(Some(client_timeout));
+                    
+                    if !arrived {
+                        continue;
+                    }
+                }
+                
+                match read_frame(&mut stream) {
+                    Ok(data) => {
+                        // 受信したメッセージをセッションに渡す
                         let mut session_lock = session.lock().unwrap();
-                        let data = &buffer[..bytes_read];
                         
-                        if let Err(e) = session_lock.process_data(data) {
+                        if let Err(e) = session_lock.process_data(&data) {
                             error!("データ処理エラー: {}", e);
                             break;
                         }
@@ -137,21 +164,24 @@ impl TcpServer {
                             break;
                         }
                     },
-                    Err(e) => {
-                        // エラー発生
-                        if e.kind() == std::io::ErrorKind::WouldBlock || 
-                           e.kind() == std::io::ErrorKind::TimedOut {
-                            // タイムアウト - セッションがまだアクティブかチェック
-                            let session_lock = session.lock().unwrap();
-                            if session_lock.idle_time() > config.client_timeout {
-                                warn!("クライアントタイムアウト: {}", client_addr);
-                                break;
-                            }
-                        } else {
-                            // その他のエラー
-                            error!("クライアント接続エラー: {}", e);
+                    Err(NetworkError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                        // 接続が閉じられた
+                        info!("クライアント切断: {}", client_addr);
+                        break;
+                    },
+                    Err(NetworkError::IoError(e)) if e.kind() == std::io::ErrorKind::WouldBlock ||
+                                                     e.kind() == std::io::ErrorKind::TimedOut => {
+                        // タイムアウト - セッションがまだアクティブかチェック
+                        let session_lock = session.lock().unwrap();
+                        if session_lock.idle_time() > config.client_timeout {
+                            warn!("クライアントタイムアウト: {}", client_addr);
                             break;
                         }
+                    },
+                    Err(e) => {
+                        // その他のエラー（上限を超えるフレームを含む）
+                        error!("クライアント接続エラー: {}", e);
+                        break;
                     }
                 }
                 
@@ -201,6 +231,7 @@ impl NetworkServer for TcpServer {
         let screen_capture = self.screen_capture.clone();
         let input_handler = self.input_handler.clone();
         let authenticator = Arc::new(self.authenticator.clone());
+        let access_control = self.access_control.clone();
         let config = self.config.clone();
         let client_sessions = self.client_sessions.clone();
         let running = self.running.clone();
@@ -224,6 +255,12 @@ impl NetworkServer for TcpServer {
                 // 接続を受け付け
                 match listener.accept() {
                     Ok((stream, addr)) => {
+                        // アクセス制御リストのチェック
+                        if !access_control.check(&addr) {
+                            drop(stream);
+                            continue;
+                        }
+                        
                         // 最大接続数のチェック
                         let session_count = client_sessions.lock().unwrap().len();
                         if session_count >= config.max_connections {
@@ -327,34 +364,48 @@ impl NetworkServer for TcpServer {
             )
         })
     }
+    
+    fn rejected_connections(&self) -> u64 {
+        self.access_control.rejected_count()
+    }
+}
+
+/// 4バイトの長さプレフィックス付きメッセージを1つ読み取る
+///
+/// 長さが`wire::MAX_FRAME_SIZE`を超える場合は確保せずにプロトコルエラーを返します。
+fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, NetworkError> {
+    let mut len_bytes = [0u8; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = wire::frame_len(len_bytes)
+        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    
+    let mut buffer = vec![0u8; len];
+    reader.read_exact(&mut buffer)?;
+    
+    Ok(buffer)
 }
 
 /// TCPクライアント接続
 pub struct TcpClientConnection {
     stream: TcpStream,
+    wire_format: WireFormat,
 }
 
 impl TcpClientConnection {
     /// 新しいTCPクライアント接続を作成
     pub fn new(stream: TcpStream) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            wire_format: WireFormat::Json,
+        }
     }
     
-    /// コマンドをJSON文字列として送信
-    fn send_json<T: serde::Serialize>(&mut self, data: &T) -> Result<(), NetworkError> {
-        // JSONにシリアライズ
-        let json_data = serde_json::to_string(data)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONシリアライズエラー: {}", e)))?;
-        
-        // データ長を4バイトのプレフィックスとして送信
-        let len = json_data.len() as u32;
-        let len_bytes = len.to_be_bytes();
-        
-        self.stream.write_all(&len_bytes)?;
-        self.stream.write_all(json_data.as_bytes())?;
-        self.stream.flush()?;
+    /// メッセージを現在のワイヤーフォーマットで送信
+    fn send_encoded<T: serde::Serialize>(&mut self, data: &T) -> Result<(), NetworkError> {
+        let encoded = self.wire_format.encode(data)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
         
-        Ok(())
+        self.send_data(&encoded)
     }
     
     /// データを送信
@@ -372,35 +423,30 @@ impl TcpClientConnection {
     
     /// メッセージの受信
     fn receive_message(&mut self) -> Result<Vec<u8>, NetworkError> {
-        // メッセージ長のプレフィックスを読み取る
-        let mut len_bytes = [0u8; 4];
-        self.stream.read_exact(&mut len_bytes)?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
-        
-        // メッセージ本体を読み取る
-        let mut buffer = vec![0u8; len];
-        self.stream.read_exact(&mut buffer)?;
-        
-        Ok(buffer)
+        read_frame(&mut self.stream)
     }
 }
 
 /// トレイト実装：クライアント接続
 impl super::session::ClientConnection for TcpClientConnection {
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
-        self.send_json(response)
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+        self.send_encoded(envelope)
     }
     
     fn send_raw(&mut self, data: &[u8]) -> Result<(), NetworkError> {
         self.send_data(data)
     }
     
-    fn receive(&mut self) -> Result<Command, NetworkError> {
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
         let data = self.receive_message()?;
         
-        // JSONからデシリアライズ
-        serde_json::from_slice(&data)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
+        // 形式は先頭バイトから判別する
+        wire::decode_envelope(&data)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))
+    }
+    
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
     }
     
     fn set_timeout(&mut self, duration: Duration) -> Result<(), NetworkError> {
@@ -491,34 +537,33 @@ impl NetworkServer for TlsTcpServer {
     fn get_address(&self) -> SocketAddr {
         self.inner.get_address()
     }
+    
+    fn rejected_connections(&self) -> u64 {
+        self.inner.rejected_connections()
+    }
 }
 
 /// TLS TCP クライアント接続
 pub struct TlsClientConnection {
     stream: TlsStream<TcpStream>,
+    wire_format: WireFormat,
 }
 
 impl TlsClientConnection {
     /// 新しいTLS TCP接続を作成
     pub fn new(stream: TlsStream<TcpStream>) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            wire_format: WireFormat::Json,
+        }
     }
     
-    /// コマンドをJSON文字列として送信
-    fn send_json<T: serde::Serialize>(&mut self, data: &T) -> Result<(), NetworkError> {
-        // JSONにシリアライズ
-        let json_data = serde_json::to_string(data)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONシリアライズエラー: {}", e)))?;
-        
-        // データ長を4バイトのプレフィックスとして送信
-        let len = json_data.len() as u32;
-        let len_bytes = len.to_be_bytes();
-        
-        self.stream.write_all(&len_bytes)?;
-        self.stream.write_all(json_data.as_bytes())?;
-        self.stream.flush()?;
+    /// メッセージを現在のワイヤーフォーマットで送信
+    fn send_encoded<T: serde::Serialize>(&mut self, data: &T) -> Result<(), NetworkError> {
+        let encoded = self.wire_format.encode(data)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
         
-        Ok(())
+        self.send_data(&encoded)
     }
     
     /// データを送信
@@ -536,34 +581,29 @@ impl TlsClientConnection {
     
     /// メッセージの受信
     fn receive_message(&mut self) -> Result<Vec<u8>, NetworkError> {
-        // メッセージ長のプレフィックスを読み取る
-        let mut len_bytes = [0u8; 4];
-        self.stream.read_exact(&mut len_bytes)?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
-        
-        // メッセージ本体を読み取る
-        let mut buffer = vec![0u8; len];
-        self.stream.read_exact(&mut buffer)?;
-        
-        Ok(buffer)
+        read_frame(&mut self.stream)
     }
 }
 
 impl super::session::ClientConnection for TlsClientConnection {
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
-        self.send_json(response)
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+        self.send_encoded(envelope)
     }
     
     fn send_raw(&mut self, data: &[u8]) -> Result<(), NetworkError> {
         self.send_data(data)
     }
     
-    fn receive(&mut self) -> Result<Command, NetworkError> {
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
         let data = self.receive_message()?;
         
-        // JSONからデシリアライズ
-        serde_json::from_slice(&data)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
+        // 形式は先頭バイトから判別する
+        wire::decode_envelope(&data)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))
+    }
+    
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
     }
     
     fn set_timeout(&mut self, duration: Duration) -> Result<(), NetworkError> {
@@ -580,4 +620,52 @@ impl super::session::ClientConnection for TlsClientConnection {
         let _ = self.stream.shutdown();
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::TestPatternSource;
+    use crate::input::sink::RecordingSink;
+    
+    #[test]
+    fn test_oversized_frame_is_rejected() {
+        // 本体を送らずに巨大な長さだけを送っても確保せずに拒否する
+        let header = u32::MAX.to_be_bytes();
+        match read_frame(&mut &header[..]) {
+            Err(NetworkError::ProtocolError(_)) => {},
+            other => panic!("プロトコルエラーになるべきです: {:?}", other),
+        }
+        
+        let mut frame = 3u32.to_be_bytes().to_vec();
+        frame.extend_from_slice(b"abc");
+        assert_eq!(read_frame(&mut &frame[..]).unwrap(), b"abc");
+    }
+    
+    #[test]
+    fn test_denied_ips_from_config_reject_connections() {
+        let config = ServerConfig {
+            bind_address: "127.0.0.1".to_string(),
+            port: 0,
+            denied_ips: vec!["127.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let screen_capture = ScreenCapture::with_source(Box::new(TestPatternSource::new(64, 64)));
+        let input_handler = InputHandler::with_sink(Arc::new(RecordingSink::new(64, 64)));
+        let mut server = TcpServer::new(
+            config,
+            Arc::new(Mutex::new(screen_capture)),
+            Arc::new(Mutex::new(input_handler)),
+        ).unwrap();
+        server.start().unwrap();
+        
+        // 拒否された接続はセッションを作らずに閉じられる
+        let mut stream = TcpStream::connect(server.get_address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        
+        assert_eq!(server.rejected_connections(), 1);
+        assert_eq!(server.connected_clients(), 0);
+        server.stop().unwrap();
+    }
+}
@@ -5,8 +5,10 @@
 
 use super::{NetworkServer, NetworkError, ServerConfig, SessionInfo};
 use super::session::{ClientSession, ClientConnection};
+use super::access_control::AccessControl;
 use super::authentication::Authenticator;
-use remote_desktop_rs_common::protocol::{Command, Response};
+use remote_desktop_rs_common::protocol::{Command, Envelope, Response};
+use remote_desktop_rs_common::wire::{self, WireFormat};
 use crate::capture::ScreenCapture;
 use crate::input::InputHandler;
 
@@ -15,7 +17,6 @@ use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
-use serde_json;
 use log::{debug, info, warn, error};
 
 // WebRTC関連ライブラリ
@@ -44,6 +45,8 @@ pub struct WebRtcServer {
     input_handler: Arc<Mutex<InputHandler>>,
     /// 認証ハンドラ
     authenticator: Authenticator,
+    /// アクセス制御
+    access_control: AccessControl,
     /// シグナリングサーバースレッド
     signaling_thread: Option<thread::JoinHandle<()>>,
     /// WebRTCワーカースレッド
@@ -75,11 +78,16 @@ impl WebRtcServer {
             .build()
             .map_err(|e| NetworkError::Other(format!("Tokioランタイムの作成に失敗: {}", e)))?;
 
+        let authenticator = Authenticator::new(config.users.clone());
+        let access_control = AccessControl::new(&config.allowed_ips, &config.denied_ips)
+            .map_err(|e| NetworkError::Other(e.to_string()))?;
+        
         Ok(Self {
             config,
             screen_capture,
             input_handler,
-            authenticator: Authenticator::new(),
+            authenticator,
+            access_control,
             signaling_thread: None,
             worker_thread: None,
             thread_control: None,
@@ -112,6 +120,7 @@ impl WebRtcServer {
         let screen_capture = self.screen_capture.clone();
         let input_handler = self.input_handler.clone();
         let authenticator = Arc::new(self.authenticator.clone());
+        let access_control = self.access_control.clone();
         let config = self.config.clone();
         let client_sessions = self.client_sessions.clone();
         let running = self.running.clone();
@@ -136,6 +145,12 @@ impl WebRtcServer {
                 // 接続を受け付け
                 match listener.accept() {
                     Ok((stream, addr)) => {
+                        // アクセス制御リストのチェック
+                        if !access_control.check(&addr) {
+                            drop(stream);
+                            continue;
+                        }
+                        
                         // 最大接続数のチェック
                         let session_count = client_sessions.lock().unwrap().len();
                         if session_count >= config.max_connections {
@@ -329,6 +344,7 @@ impl WebRtcServer {
             peer_connection: peer_connection.clone(),
             message_rx: Some(message_rx),
             message_buffer: Vec::new(),
+            wire_format: WireFormat::Json,
         };
         
         // セッション情報を作成
@@ -369,6 +385,12 @@ impl WebRtcServer {
                     break;
                 }
                 
+                // 配信中であればフレームを送信
+                if let Err(e) = session.pump_stream() {
+                    error!("フレーム配信エラー: {}", e);
+                    break;
+                }
+                
                 // コマンドの受信と処理
                 if let Err(e) = session.receive_and_process() {
                     if let NetworkError::IoError(ref io_err) = e {
@@ -458,6 +480,10 @@ impl NetworkServer for WebRtcServer {
     fn get_address(&self) -> SocketAddr {
         self.server_addr.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap())
     }
+    
+    fn rejected_connections(&self) -> u64 {
+        self.access_control.rejected_count()
+    }
 }
 
 #[cfg(not(feature = "webrtc-support"))]
@@ -493,6 +519,10 @@ impl NetworkServer for WebRtcServer {
     fn get_address(&self) -> SocketAddr {
         "0.0.0.0:0".parse().unwrap()
     }
+    
+    fn rejected_connections(&self) -> u64 {
+        0
+    }
 }
 
 /// WebRTCクライアント接続
@@ -506,24 +536,39 @@ struct WebRtcClientConnection {
     message_rx: Option<tokio::sync::mpsc::Receiver<Vec<u8>>>,
     /// メッセージバッファ
     message_buffer: Vec<u8>,
+    /// ワイヤーフォーマット
+    wire_format: WireFormat,
 }
 
 #[cfg(feature = "webrtc-support")]
 impl ClientConnection for WebRtcClientConnection {
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
-        // レスポンスをJSON形式にシリアライズ
-        let json_data = serde_json::to_string(response)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONシリアライズエラー: {}", e)))?;
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+        // レスポンスを現在のワイヤーフォーマットでシリアライズ
+        let data = self.wire_format.encode(envelope)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+        
+        if self.wire_format.is_binary() {
+            return self.send_raw(&data);
+        }
         
-        // データを送信
-        self.send_raw(json_data.as_bytes())
+        // JSONはテキストメッセージとして送信
+        self.runtime.block_on(async {
+            if let Some(dc) = self.peer_connection.data_channels().await.get(0) {
+                dc.send_text(String::from_utf8_lossy(&data).into_owned()).await
+                    .map(|_| ())
+                    .map_err(|e| NetworkError::CommunicationError(format!("データ送信エラー: {}", e)))
+            } else {
+                Err(NetworkError::ConnectionError("データチャネルがありません".to_string()))
+            }
+        })
     }
     
     fn send_raw(&mut self, data: &[u8]) -> Result<(), NetworkError> {
-        // 既存のデータチャネルを使用
+        // 既存のデータチャネルでバイナリメッセージとして送信
         self.runtime.block_on(async {
             if let Some(dc) = self.peer_connection.data_channels().await.get(0) {
-                dc.send_text(String::from_utf8_lossy(data).to_string()).await
+                dc.send(&bytes::Bytes::copy_from_slice(data)).await
+                    .map(|_| ())
                     .map_err(|e| NetworkError::CommunicationError(format!("データ送信エラー: {}", e)))
             } else {
                 Err(NetworkError::ConnectionError("データチャネルがありません".to_string()))
@@ -531,7 +576,7 @@ impl ClientConnection for WebRtcClientConnection {
         })
     }
     
-    fn receive(&mut self) -> Result<Command, NetworkError> {
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
         // メッセージキューからデータを受信
         if let Some(rx) = &mut self.message_rx {
             let data = self.runtime.block_on(async {
@@ -543,14 +588,18 @@ impl ClientConnection for WebRtcClientConnection {
                 }
             })?;
             
-            // JSONからデシリアライズ
-            serde_json::from_slice(&data)
-                .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
+            // 形式は先頭バイトから判別する
+            wire::decode_envelope(&data)
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))
         } else {
             Err(NetworkError::ConnectionError("受信チャネルがありません".to_string()))
         }
     }
     
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
+    }
+    
     fn set_timeout(&mut self, _duration: Duration) -> Result<(), NetworkError> {
         // WebRTC接続ではタイムアウトの設定は不要
         // (receive関数内で設定しているため)
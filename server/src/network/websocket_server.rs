@@ -5,8 +5,10 @@
 
 use super::{NetworkServer, NetworkError, ServerConfig, SessionInfo};
 use super::session::{ClientSession, ClientConnection};
+use super::access_control::AccessControl;
 use super::authentication::Authenticator;
-use remote_desktop_rs_common::protocol::{Command, Response};
+use remote_desktop_rs_common::protocol::{Command, Envelope, Response};
+use remote_desktop_rs_common::wire::{self, WireFormat};
 use crate::capture::ScreenCapture;
 use crate::input::InputHandler;
 
@@ -15,7 +17,6 @@ use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
-use serde_json;
 use log::{debug, info, warn, error};
 
 // WebSocket関連
@@ -37,6 +38,8 @@ pub struct WebSocketServer {
     input_handler: Arc<Mutex<InputHandler>>,
     /// 認証ハンドラ
     authenticator: Authenticator,
+    /// アクセス制御
+    access_control: AccessControl,
     /// リスナースレッド
     listener_thread: Option<thread::JoinHandle<()>>,
     /// スレッド管理用チャネル
@@ -89,11 +92,16 @@ impl WebSocketServer {
             None
         };
         
+        let authenticator = Authenticator::new(config.users.clone());
+        let access_control = AccessControl::new(&config.allowed_ips, &config.denied_ips)
+            .map_err(|e| NetworkError::Other(e.to_string()))?;
+        
         Ok(Self {
             config,
             screen_capture,
             input_handler,
-            authenticator: Authenticator::new(),
+            authenticator,
+            access_control,
             listener_thread: None,
             thread_control: None,
             client_sessions: Arc::new(Mutex::new(Vec::new())),
@@ -153,9 +161,9 @@ impl WebSocketServer {
             
             // 接続タイプに応じたクライアントセッションを作成
             let client_connection: Box<dyn ClientConnection> = if is_tls {
-                Box::new(WebSocketTlsClientConnection { websocket })
+                Box::new(WebSocketTlsClientConnection { websocket, wire_format: WireFormat::Json })
             } else {
-                Box::new(WebSocketClientConnection { websocket })
+                Box::new(WebSocketClientConnection { websocket, wire_format: WireFormat::Json })
             };
             
             // クライアントセッションを作成
@@ -186,6 +194,12 @@ impl WebSocketServer {
                     break;
                 }
                 
+                // 配信中であればフレームを送信
+                if let Err(e) = session_lock.pump_stream() {
+                    error!("フレーム配信エラー: {}", e);
+                    break;
+                }
+                
                 // コマンドの受信と処理
                 if let Err(e) = session_lock.receive_and_process() {
                     if let NetworkError::IoError(ref io_err) = e {
@@ -255,6 +269,7 @@ impl NetworkServer for WebSocketServer {
         let screen_capture = self.screen_capture.clone();
         let input_handler = self.input_handler.clone();
         let authenticator = Arc::new(self.authenticator.clone());
+        let access_control = self.access_control.clone();
         let config = self.config.clone();
         let client_sessions = self.client_sessions.clone();
         let running = self.running.clone();
@@ -279,6 +294,12 @@ impl NetworkServer for WebSocketServer {
                 // 接続を受け付け
                 match listener.accept() {
                     Ok((stream, addr)) => {
+                        // アクセス制御リストのチェック
+                        if !access_control.check(&addr) {
+                            drop(stream);
+                            continue;
+                        }
+                        
                         // 最大接続数のチェック
                         let session_count = client_sessions.lock().unwrap().len();
                         if session_count >= config.max_connections {
@@ -377,21 +398,32 @@ impl NetworkServer for WebSocketServer {
     fn get_address(&self) -> SocketAddr {
         self.server_addr.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap())
     }
+    
+    fn rejected_connections(&self) -> u64 {
+        self.access_control.rejected_count()
+    }
 }
 
 /// WebSocketクライアント接続
 struct WebSocketClientConnection {
     websocket: WebSocket<TcpStream>,
+    wire_format: WireFormat,
 }
 
 impl ClientConnection for WebSocketClientConnection {
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
-        // レスポンスをJSON形式にシリアライズ
-        let json_data = serde_json::to_string(response)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONシリアライズエラー: {}", e)))?;
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+        // レスポンスを現在のワイヤーフォーマットでシリアライズ
+        let data = self.wire_format.encode(envelope)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+        
+        // バイナリ形式はBinaryフレーム、JSONはTextフレームで送信
+        let message = if self.wire_format.is_binary() {
+            Message::Binary(data)
+        } else {
+            Message::Text(String::from_utf8_lossy(&data).into_owned())
+        };
         
-        // WebSocketで送信
-        self.websocket.write_message(Message::Text(json_data))
+        self.websocket.write_message(message)
             .map_err(|e| NetworkError::CommunicationError(format!("WebSocket送信エラー: {}", e)))?;
         
         Ok(())
@@ -405,7 +437,7 @@ impl ClientConnection for WebSocketClientConnection {
         Ok(())
     }
     
-    fn receive(&mut self) -> Result<Command, NetworkError> {
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
         // 非ブロッキングモードに設定
         self.websocket.get_mut().set_nonblocking(true)?;
         
@@ -422,13 +454,13 @@ impl ClientConnection for WebSocketClientConnection {
         match message {
             Message::Text(text) => {
                 // JSONからデシリアライズ
-                serde_json::from_str(&text)
+                wire::decode_envelope(text.as_bytes())
                     .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
             },
             Message::Binary(data) => {
-                // バイナリデータをJSONとしてデシリアライズ
-                serde_json::from_slice(&data)
-                    .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
+                // 形式は先頭バイトから判別する
+                wire::decode_envelope(&data)
+                    .map_err(|e| NetworkError::ProtocolError(e.to_string()))
             },
             Message::Close(_) => {
                 // クライアントが接続を閉じた
@@ -441,6 +473,10 @@ impl ClientConnection for WebSocketClientConnection {
         }
     }
     
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
+    }
+    
     fn set_timeout(&mut self, duration: Duration) -> Result<(), NetworkError> {
         // WebSocketの基盤となるTCPストリームにタイムアウトを設定
         self.websocket.get_mut().set_read_timeout(Some(duration))?;
@@ -465,16 +501,23 @@ impl ClientConnection for WebSocketClientConnection {
 /// WebSocket TLSクライアント接続
 struct WebSocketTlsClientConnection {
     websocket: WebSocket<TlsStream<TcpStream>>,
+    wire_format: WireFormat,
 }
 
 impl ClientConnection for WebSocketTlsClientConnection {
-    fn send(&mut self, response: &Response) -> Result<(), NetworkError> {
-        // レスポンスをJSON形式にシリアライズ
-        let json_data = serde_json::to_string(response)
-            .map_err(|e| NetworkError::ProtocolError(format!("JSONシリアライズエラー: {}", e)))?;
+    fn send(&mut self, envelope: &Envelope<&Response>) -> Result<(), NetworkError> {
+        // レスポンスを現在のワイヤーフォーマットでシリアライズ
+        let data = self.wire_format.encode(envelope)
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+        
+        // バイナリ形式はBinaryフレーム、JSONはTextフレームで送信
+        let message = if self.wire_format.is_binary() {
+            Message::Binary(data)
+        } else {
+            Message::Text(String::from_utf8_lossy(&data).into_owned())
+        };
         
-        // WebSocketで送信
-        self.websocket.write_message(Message::Text(json_data))
+        self.websocket.write_message(message)
             .map_err(|e| NetworkError::CommunicationError(format!("WebSocket送信エラー: {}", e)))?;
         
         Ok(())
@@ -488,7 +531,7 @@ impl ClientConnection for WebSocketTlsClientConnection {
         Ok(())
     }
     
-    fn receive(&mut self) -> Result<Command, NetworkError> {
+    fn receive(&mut self) -> Result<Envelope<Command>, NetworkError> {
         // 非ブロッキングモードに設定（TLSストリームでは直接設定できないため注意が必要）
         // 通常はTLS接続の基底となるTCPストリームにアクセスする必要がある
         
@@ -505,13 +548,13 @@ impl ClientConnection for WebSocketTlsClientConnection {
         match message {
             Message::Text(text) => {
                 // JSONからデシリアライズ
-                serde_json::from_str(&text)
+                wire::decode_envelope(text.as_bytes())
                     .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
             },
             Message::Binary(data) => {
-                // バイナリデータをJSONとしてデシリアライズ
-                serde_json::from_slice(&data)
-                    .map_err(|e| NetworkError::ProtocolError(format!("JSONデシリアライズエラー: {}", e)))
+                // 形式は先頭バイトから判別する
+                wire::decode_envelope(&data)
+                    .map_err(|e| NetworkError::ProtocolError(e.to_string()))
             },
             Message::Close(_) => {
                 // クライアントが接続を閉じた
@@ -524,6 +567,10 @@ impl ClientConnection for WebSocketTlsClientConnection {
         }
     }
     
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
+    }
+    
     fn set_timeout(&mut self, _duration: Duration) -> Result<(), NetworkError> {
         // TLS接続ではタイムアウトの設定は不要/直接設定できない
         // TLS接続のタイムアウト制御は基底のTCPソケットに依存する
@@ -68,6 +68,8 @@ pub struct StatusInfo {
     pub sent_bytes: u64,
     /// 受信データ量（バイト）
     pub received_bytes: u64,
+    /// アクセス制御により拒否した接続数
+    pub rejected_connections: u64,
 }
 
 impl Default for StatusInfo {
@@ -78,6 +80,7 @@ impl Default for StatusInfo {
             uptime: 0,
             sent_bytes: 0,
             received_bytes: 0,
+            rejected_connections: 0,
         }
     }
 }
\ No newline at end of file
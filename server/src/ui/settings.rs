@@ -16,6 +16,9 @@ pub struct ServerSettings {
     pub capture: CaptureSettings,
     /// ログ設定
     pub logging: LoggingSettings,
+    /// 入力設定
+    #[serde(default)]
+    pub input: InputSettings,
 }
 
 /// ネットワーク設定
@@ -48,8 +51,11 @@ pub struct NetworkSettings {
 pub struct SecuritySettings {
     /// 認証必須フラグ
     pub require_auth: bool,
-    /// 接続IPアドレス制限
+    /// 接続を許可するIPアドレス/CIDR（空の場合はすべて許可）
     pub allowed_ips: Vec<String>,
+    /// 接続を拒否するIPアドレス/CIDR（許可リストより優先）
+    #[serde(default)]
+    pub denied_ips: Vec<String>,
     /// クリップボード共有許可
     pub allow_clipboard: bool,
     /// ファイル転送許可
@@ -58,6 +64,27 @@ pub struct SecuritySettings {
     pub allow_application_launch: bool,
     /// ユーザー/パスワードのリスト
     pub users: Vec<UserCredential>,
+    /// ロックアウトまでの連続ログイン失敗回数（0でロックアウトしない）
+    #[serde(default = "default_max_login_failures")]
+    pub max_login_failures: u32,
+    /// ロックアウト時間（秒）
+    #[serde(default = "default_lockout_duration_secs")]
+    pub lockout_duration_secs: u64,
+    /// セッションを切断するまでの不正なコマンドの回数
+    #[serde(default = "default_max_protocol_violations")]
+    pub max_protocol_violations: u32,
+}
+
+fn default_max_login_failures() -> u32 {
+    5
+}
+
+fn default_lockout_duration_secs() -> u64 {
+    300
+}
+
+fn default_max_protocol_violations() -> u32 {
+    3
 }
 
 /// キャプチャ設定
@@ -69,10 +96,45 @@ pub struct CaptureSettings {
     pub default_format: String,
     /// キャプチャFPS制限
     pub max_fps: u8,
+    /// 帯域が足りない場合に下げる画質の下限
+    #[serde(default = "default_min_quality")]
+    pub min_quality: u8,
+    /// 帯域が足りない場合に下げる解像度の倍率の下限
+    #[serde(default = "default_min_scale")]
+    pub min_scale: f32,
+    /// 帯域が足りない場合に下げるFPSの下限
+    #[serde(default = "default_min_fps")]
+    pub min_fps: u8,
     /// モニター選択
     pub monitor_index: Option<usize>,
     /// キャプチャ領域
     pub capture_region: Option<CaptureRegion>,
+    /// キャプチャ元（"system"=OSの画面、"test-pattern"=合成のテストパターン、"replay:<ディレクトリ>"=PNGの連番）
+    #[serde(default = "default_capture_source")]
+    pub source: String,
+}
+
+fn default_min_quality() -> u8 {
+    20
+}
+
+fn default_min_scale() -> f32 {
+    0.5
+}
+
+fn default_min_fps() -> u8 {
+    5
+}
+
+fn default_capture_source() -> String {
+    "system".to_string()
+}
+
+/// 入力設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputSettings {
+    /// 入力の出力先（"system"=OSに送る、"uinput"=Linuxの/dev/uinputに送る、"recording"=直近のイベントを記録するのみ）
+    pub backend: String,
 }
 
 /// ログ設定
@@ -97,10 +159,16 @@ pub struct LoggingSettings {
 pub struct UserCredential {
     /// ユーザー名
     pub username: String,
-    /// パスワードハッシュ
+    /// パスワードハッシュ（`common::encryption::hash_password`形式）
     pub password_hash: String,
     /// 権限レベル（0=ビューアー、1=制御者、2=管理者）
     pub permission_level: u8,
+    /// TOTPシークレット（Base32、設定した場合はワンタイムパスワードを要求）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    /// 公開鍵認証を許可するEd25519公開鍵（`ed25519 <Base64> [コメント]`形式）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authorized_keys: Vec<String>,
 }
 
 /// キャプチャ領域
@@ -123,6 +191,7 @@ impl Default for ServerSettings {
             security: SecuritySettings::default(),
             capture: CaptureSettings::default(),
             logging: LoggingSettings::default(),
+            input: InputSettings::default(),
         }
     }
 }
@@ -149,16 +218,23 @@ impl Default for SecuritySettings {
         Self {
             require_auth: true,
             allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
             allow_clipboard: true,
             allow_file_transfer: false,
             allow_application_launch: false,
             users: vec![
                 UserCredential {
                     username: "admin".to_string(),
-                    password_hash: "5f4dcc3b5aa765d61d8327deb882cf99".to_string(), // "password"のMD5ハッシュ
+                    // "password"のPBKDF2-SHA256ハッシュ（初回起動後に変更すること）
+                    password_hash: "pbkdf2-sha256$100000$rzwHYUhcGIe3pn9xnAdeXQ==$QDSw9DCw1fn19iZtkeYdKoZUR1jVtiwXb1HyJfejcww=".to_string(),
                     permission_level: 2,
+                    totp_secret: None,
+                    authorized_keys: Vec::new(),
                 }
             ],
+            max_login_failures: default_max_login_failures(),
+            lockout_duration_secs: default_lockout_duration_secs(),
+            max_protocol_violations: default_max_protocol_violations(),
         }
     }
 }
@@ -169,8 +245,20 @@ impl Default for CaptureSettings {
             default_quality: 75,
             default_format: "jpeg".to_string(),
             max_fps: 30,
+            min_quality: default_min_quality(),
+            min_scale: default_min_scale(),
+            min_fps: default_min_fps(),
             monitor_index: None,
             capture_region: None,
+            source: default_capture_source(),
+        }
+    }
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            backend: "system".to_string(),
         }
     }
 }
@@ -247,4 +335,9 @@ impl ServerSettings {
     pub fn get_logging(&self) -> &LoggingSettings {
         &self.logging
     }
+    
+    /// 入力設定を取得
+    pub fn get_input(&self) -> &InputSettings {
+        &self.input
+    }
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+//! 統合テストの共通処理
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::rust_connection::RustConnection;
+
+/// テスト用のXvfb
+pub struct Xvfb {
+    /// 表示名（":99"など）
+    pub name: String,
+    /// テスト側の接続
+    pub conn: RustConnection,
+    /// ルートウィンドウ
+    pub root: u32,
+}
+
+/// Xvfbを起動して接続する（Xvfbがインストールされていない場合は`None`）
+///
+/// Xvfbは`-terminate`で起動するため、テストのプロセスが終了して
+/// 最後の接続が切れると自動的に終了します。背景は黒（`-br`）です。
+pub fn start_xvfb(width: u32, height: u32) -> Option<Xvfb> {
+    let number = (90..200).find(|n| !Path::new(&format!("/tmp/.X{}-lock", n)).exists())?;
+    let name = format!(":{}", number);
+    let screen = format!("{}x{}x24", width, height);
+
+    let spawned = Command::new("Xvfb")
+        .args([name.as_str(), "-screen", "0", screen.as_str(), "-nolisten", "tcp", "-br", "-terminate"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if spawned.is_err() {
+        eprintln!("Xvfbが見つからないため、X11のテストを省略します");
+        return None;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Ok((conn, screen_num)) = x11rb::connect(Some(&name)) {
+            let root = conn.setup().roots[screen_num].root;
+            return Some(Xvfb { name, conn, root });
+        }
+        if Instant::now() > deadline {
+            panic!("Xvfbに接続できません");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
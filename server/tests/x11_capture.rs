@@ -0,0 +1,120 @@
+//! X11キャプチャの統合テスト
+//!
+//! Xvfbのルートウィンドウに図形を描き、`X11Capture`と`ScreenCapture`で取得した画像を確認します。
+//! Xvfbがインストールされていない環境ではテストを省略します。
+
+#![cfg(all(target_os = "linux", feature = "x11-support"))]
+
+mod common;
+
+use image::Rgba;
+use remote_desktop_rs_server::capture::{ScreenCapture, X11Capture};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::{ConnectionExt as _, MonitorInfo};
+use x11rb::protocol::xproto::{ConnectionExt as _, CreateGCAux, Rectangle};
+
+/// 画面の幅
+const WIDTH: u32 = 640;
+/// 画面の高さ
+const HEIGHT: u32 = 480;
+/// 描画する矩形
+const RECT: (i16, i16, u16, u16) = (400, 100, 50, 40);
+/// 描画する色（0xRRGGBB）
+const COLOR: u32 = 0xFF8000;
+
+/// Xvfbを1度だけ起動し、矩形を描いてからテストを1つずつ実行するためのロックを返す
+fn display() -> Option<MutexGuard<'static, common::Xvfb>> {
+    static DISPLAY: OnceLock<Option<Mutex<common::Xvfb>>> = OnceLock::new();
+
+    let display = DISPLAY.get_or_init(|| {
+        let xvfb = common::start_xvfb(WIDTH, HEIGHT)?;
+
+        // `ScreenCapture`は環境変数DISPLAYのサーバーに接続する
+        std::env::set_var("DISPLAY", &xvfb.name);
+
+        let gc = xvfb.conn.generate_id().unwrap();
+        xvfb.conn.create_gc(gc, xvfb.root, &CreateGCAux::new().foreground(COLOR)).unwrap();
+        let (x, y, width, height) = RECT;
+        xvfb.conn.poly_fill_rectangle(xvfb.root, gc, &[Rectangle { x, y, width, height }]).unwrap();
+        xvfb.conn.get_input_focus().unwrap().reply().unwrap();
+
+        Some(Mutex::new(xvfb))
+    });
+
+    display.as_ref().map(|d| d.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// 描画した色
+fn color() -> Rgba<u8> {
+    Rgba([(COLOR >> 16) as u8, (COLOR >> 8) as u8, COLOR as u8, 255])
+}
+
+#[test]
+fn test_shm_and_get_image_capture_the_same_pixels() {
+    let Some(xvfb) = display() else { return };
+    let mut capture = X11Capture::connect(Some(&xvfb.name)).unwrap();
+    assert!(capture.is_shm_enabled());
+
+    let shm = capture.capture(0, 0, WIDTH, HEIGHT).unwrap();
+    assert!(capture.is_shm_enabled(), "MIT-SHMでのキャプチャに失敗しました");
+    assert_eq!(shm.dimensions(), (WIDTH, HEIGHT));
+    assert_eq!(*shm.get_pixel(410, 110), color());
+    assert_eq!(*shm.get_pixel(10, 10), Rgba([0, 0, 0, 255]));
+
+    capture.disable_shm();
+    let plain = capture.capture(0, 0, WIDTH, HEIGHT).unwrap();
+    assert_eq!(shm, plain);
+
+    // 画面からはみ出す領域は画面内に収める
+    let clipped = capture.capture(600, 400, 100, 100).unwrap();
+    assert_eq!(clipped.dimensions(), (40, 80));
+}
+
+#[test]
+fn test_randr_monitors() {
+    let Some(xvfb) = display() else { return };
+
+    // 既定では画面全体が1つのモニター
+    let monitors = ScreenCapture::new().get_monitors().unwrap();
+    assert_eq!(monitors.len(), 1);
+    assert_eq!(monitors[0].size, (WIDTH, HEIGHT));
+    assert!(monitors[0].is_primary);
+
+    // 画面を左右2つのモニターに分割（右をプライマリにする）
+    let mut names = Vec::new();
+    for (name, x, primary) in [("TEST-LEFT", 0, false), ("TEST-RIGHT", WIDTH as i16 / 2, true)] {
+        let atom = xvfb.conn.intern_atom(false, name.as_bytes()).unwrap().reply().unwrap().atom;
+        xvfb.conn.randr_set_monitor(xvfb.root, MonitorInfo {
+            name: atom,
+            primary,
+            automatic: false,
+            x,
+            y: 0,
+            width: WIDTH as u16 / 2,
+            height: HEIGHT as u16,
+            width_in_millimeters: 0,
+            height_in_millimeters: 0,
+            outputs: Vec::new(),
+        }).unwrap().check().unwrap();
+        names.push(atom);
+    }
+
+    let mut capture = ScreenCapture::new();
+    let monitors = capture.get_monitors().unwrap();
+    assert_eq!(monitors[0].name, "TEST-RIGHT");
+    assert!(monitors[0].is_primary);
+    assert_eq!(monitors[0].position, (WIDTH as i32 / 2, 0));
+    let left = monitors.iter().find(|m| m.name == "TEST-LEFT").unwrap();
+    assert_eq!(left.size, (WIDTH / 2, HEIGHT));
+
+    // モニターの左上が画像の原点になる
+    let image = capture.capture_monitor(0).unwrap();
+    assert_eq!(image.size(), (WIDTH / 2, HEIGHT));
+    let rgba = image.image.to_rgba8();
+    assert_eq!(*rgba.get_pixel(410 - WIDTH / 2, 110), color());
+
+    for atom in names {
+        xvfb.conn.randr_delete_monitor(xvfb.root, atom).unwrap().check().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+//! X11入力の統合テスト
+//!
+//! Xvfbを起動し、`SystemInput`がXTESTで送ったイベントを別の接続で確認します。
+//! Xvfbがインストールされていない環境ではテストを省略します。
+
+#![cfg(all(target_os = "linux", feature = "x11-support"))]
+
+mod common;
+
+use remote_desktop_rs_server::input::system::{SystemInput, SystemKey, SystemMouseButton};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ConnectionExt, CreateWindowAux, EventMask, InputFocus, KeyButMask, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+/// 画面の幅
+const WIDTH: u32 = 1024;
+/// 画面の高さ
+const HEIGHT: u32 = 768;
+
+/// テスト用のXサーバー
+struct TestDisplay {
+    /// イベントを確認するための接続
+    conn: RustConnection,
+    /// ルートウィンドウ
+    root: u32,
+}
+
+/// Xvfbを1度だけ起動し、テストを1つずつ実行するためのロックを返す
+fn display() -> Option<MutexGuard<'static, TestDisplay>> {
+    static DISPLAY: OnceLock<Option<Mutex<TestDisplay>>> = OnceLock::new();
+
+    let display = DISPLAY.get_or_init(|| {
+        let xvfb = common::start_xvfb(WIDTH, HEIGHT)?;
+
+        // `SystemInput`は環境変数DISPLAYのサーバーに接続する
+        std::env::set_var("DISPLAY", &xvfb.name);
+
+        Some(Mutex::new(TestDisplay { conn: xvfb.conn, root: xvfb.root }))
+    });
+
+    display.as_ref().map(|d| d.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+impl TestDisplay {
+    /// 画面全体を覆うウィンドウを作成して表示
+    fn create_window(&self, events: EventMask) -> u32 {
+        let window = self.conn.generate_id().unwrap();
+        self.conn.create_window(
+            0,
+            window,
+            self.root,
+            0,
+            0,
+            WIDTH as u16,
+            HEIGHT as u16,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new().event_mask(events | EventMask::STRUCTURE_NOTIFY),
+        ).unwrap();
+        self.conn.map_window(window).unwrap();
+        self.conn.flush().unwrap();
+
+        self.wait_for(1, |event| matches!(event, Event::MapNotify(e) if e.window == window).then_some(()));
+        window
+    }
+
+    /// 条件に合うイベントを指定した数だけ待つ
+    fn wait_for<T>(&self, count: usize, mut filter: impl FnMut(Event) -> Option<T>) -> Vec<T> {
+        let mut found = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while found.len() < count && Instant::now() < deadline {
+            match self.conn.poll_for_event().unwrap() {
+                Some(event) => found.extend(filter(event)),
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        found
+    }
+
+    /// 押されたキーのキーシムを取得（Shiftが押されていれば2番目のキーシム）
+    fn keysym(&self, keycode: u8, state: KeyButMask) -> u32 {
+        let mapping = self.conn.get_keyboard_mapping(keycode, 1).unwrap().reply().unwrap();
+        let level = if state.contains(KeyButMask::SHIFT) { 1 } else { 0 };
+        let keysym = mapping.keysyms[level.min(mapping.keysyms.len() - 1)];
+        if keysym == 0 { mapping.keysyms[0] } else { keysym }
+    }
+
+    /// ウィンドウを破棄
+    fn destroy_window(&self, window: u32) {
+        self.conn.destroy_window(window).unwrap();
+        self.conn.flush().unwrap();
+    }
+}
+
+#[test]
+fn test_pointer_position_and_screen_size() {
+    let Some(_display) = display() else { return };
+    let input = SystemInput::new().unwrap();
+
+    assert_eq!(input.get_screen_size().unwrap(), (WIDTH, HEIGHT));
+
+    input.mouse_move(100, 200).unwrap();
+    assert_eq!(input.get_mouse_position().unwrap(), (100, 200));
+
+    input.mouse_move_relative(15, -40).unwrap();
+    assert_eq!(input.get_mouse_position().unwrap(), (115, 160));
+
+    // 画面外の座標は画面の端に止まる
+    input.mouse_move(5000, -5000).unwrap();
+    assert_eq!(input.get_mouse_position().unwrap(), (WIDTH as i32 - 1, 0));
+}
+
+#[test]
+fn test_buttons_and_scroll() {
+    let Some(display) = display() else { return };
+    let input = SystemInput::new().unwrap();
+    let window = display.create_window(EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE);
+
+    input.mouse_move(300, 300).unwrap();
+    input.mouse_down(SystemMouseButton::Left).unwrap();
+    input.mouse_up(SystemMouseButton::Left).unwrap();
+    input.mouse_down(SystemMouseButton::Right).unwrap();
+    input.mouse_up(SystemMouseButton::Right).unwrap();
+    input.mouse_down(SystemMouseButton::Back).unwrap();
+    input.mouse_up(SystemMouseButton::Back).unwrap();
+    input.mouse_scroll(1, -2).unwrap();
+
+    let events = display.wait_for(12, |event| match event {
+        Event::ButtonPress(e) => Some((true, e.detail, e.root_x, e.root_y)),
+        Event::ButtonRelease(e) => Some((false, e.detail, e.root_x, e.root_y)),
+        _ => None,
+    });
+    let buttons: Vec<_> = events.iter().map(|&(press, button, _, _)| (press, button)).collect();
+    assert_eq!(buttons, vec![
+        (true, 1), (false, 1),
+        (true, 3), (false, 3),
+        (true, 8), (false, 8),
+        (true, 7), (false, 7), // 右へ1目盛り
+        (true, 5), (false, 5), // 下へ2目盛り
+        (true, 5), (false, 5),
+    ]);
+    assert!(events.iter().all(|&(_, _, x, y)| (x, y) == (300, 300)));
+
+    display.destroy_window(window);
+}
+
+#[test]
+fn test_keys_and_text() {
+    let Some(display) = display() else { return };
+    let input = SystemInput::new().unwrap();
+    let window = display.create_window(EventMask::KEY_PRESS);
+    display.conn.set_input_focus(InputFocus::POINTER_ROOT, window, x11rb::CURRENT_TIME).unwrap();
+    display.conn.flush().unwrap();
+
+    // 仮想キーコード（VK_A、VK_RETURN）
+    input.key_down(SystemKey(0x41)).unwrap();
+    input.key_up(SystemKey(0x41)).unwrap();
+    input.key_down(SystemKey(0x0D)).unwrap();
+    input.key_up(SystemKey(0x0D)).unwrap();
+
+    // Shiftが必要な文字と、キーマップにない文字を含むテキスト
+    input.input_text("Hi!あ").unwrap();
+
+    let presses = display.wait_for(8, |event| match event {
+        Event::KeyPress(e) => Some((e.detail, e.state)),
+        _ => None,
+    });
+    let keysyms: Vec<u32> = presses.iter()
+        .map(|&(keycode, state)| display.keysym(keycode, state))
+        .filter(|&keysym| keysym != 0xFFE1) // Shift_L
+        .collect();
+    assert_eq!(keysyms, vec![0x61, 0xFF0D, 0x48, 0x69, 0x21, 0x0100_3042]);
+
+    drop(input);
+    display.destroy_window(window);
+}
@@ -21,6 +21,49 @@
     debugMode: false
   };
   
+  // 配信中に先行して受け付けるフレーム数
+  const STREAM_CREDITS = 4;
+  
+  // 保持するタイル数（サーバーのTILE_CACHE_CAPACITYと一致させる）
+  const TILE_CACHE_CAPACITY = 4096;
+  
+  /**
+   * タイルのLRUキャッシュ
+   * 
+   * サーバーは送信したタイルを同じ容量・同じ順序で追跡しているため、
+   * 受信した順に取得・追加すれば内容は常にサーバー側と一致します。
+   */
+  class TileCache {
+    constructor(capacity) {
+      this.capacity = capacity;
+      this.tiles = new Map();
+    }
+    
+    // タイルを取得（使用済みとして末尾に移動）
+    get(hash) {
+      const tile = this.tiles.get(hash);
+      if (tile !== undefined) {
+        this.tiles.delete(hash);
+        this.tiles.set(hash, tile);
+      }
+      return tile;
+    }
+    
+    // タイルを追加（満杯なら最も長く使われていないタイルを破棄）
+    set(hash, tile) {
+      if (this.tiles.has(hash)) {
+        this.tiles.delete(hash);
+      } else if (this.tiles.size >= this.capacity) {
+        this.tiles.delete(this.tiles.keys().next().value);
+      }
+      this.tiles.set(hash, tile);
+    }
+    
+    clear() {
+      this.tiles.clear();
+    }
+  }
+  
   // グローバル変数
   let config = { ...DEFAULT_CONFIG };
   let isConnected = false;
@@ -36,6 +79,10 @@
   let webrtcPeerConnection = null;
   let keepAliveInterval = null;
   let updateInterval = null;
+  let streamingSupported = false;
+  let isStreaming = false;
+  let tileCache = new TileCache(TILE_CACHE_CAPACITY);
+  let frameQueue = Promise.resolve();
   let performanceStats = {
     latency: 0,
     avgLatency: 0,
@@ -226,6 +273,9 @@
         isConnected = true;
         isConnecting = false;
         
+        // 対応機能を通知（Welcomeで配信に対応していれば配信を開始）
+        sendHello();
+        
         // 認証情報があれば送信
         if (document.getElementById('username').value) {
           sendAuthInfo();
@@ -299,6 +349,9 @@
         isConnected = true;
         isConnecting = false;
         
+        // 対応機能を通知（Welcomeで配信に対応していれば配信を開始）
+        sendHello();
+        
         // 認証情報があれば送信
         if (document.getElementById('username').value) {
           sendAuthInfo();
@@ -460,11 +513,39 @@
           updateStatus('認証に成功しました');
           // 認証成功後にスクリーンショット要求
           requestScreenshot();
+          if (streamingSupported) {
+            startStream();
+          }
         } else {
           updateStatus('認証に失敗しました: ' + response.message);
         }
         break;
         
+      case 'Welcome':
+        streamingSupported = !!response.capabilities && response.capabilities.features.includes('Streaming');
+        // 認証が必要な場合は認証成功後に開始
+        if (streamingSupported && !document.getElementById('username').value) {
+          startStream();
+        }
+        break;
+        
+      case 'StreamStarted':
+        isStreaming = true;
+        break;
+        
+      case 'StreamStopped':
+        // 配信が止まったらスクリーンショットの定期要求に戻る
+        isStreaming = false;
+        tileCache.clear();
+        break;
+        
+      case 'FrameUpdate':
+        // タイルキャッシュは受信順に更新する必要があるため、フレームを順番に処理
+        frameQueue = frameQueue
+          .then(() => applyFrameUpdate(response))
+          .catch(error => console.error('フレーム処理エラー:', error));
+        break;
+        
       case 'ScreenshotData':
         if (response.data) {
           // データURLの場合
@@ -473,11 +554,7 @@
           }
           // JSON内のバイナリデータはBase64形式で来る場合の処理
           else if (typeof response.data === 'string') {
-            const binaryData = atob(response.data);
-            const byteArray = new Uint8Array(binaryData.length);
-            for (let i = 0; i < binaryData.length; i++) {
-              byteArray[i] = binaryData.charCodeAt(i);
-            }
+            const byteArray = base64ToBytes(response.data);
             processImageData(byteArray.buffer, response.format, response.width, response.height, response.timestamp);
           }
         }
@@ -588,6 +665,82 @@
     };
   }
   
+  /**
+   * 差分フレームをキャンバスに合成
+   * 
+   * 合成元の画像やタイルがない場合はキーフレームを要求します。
+   */
+  async function applyFrameUpdate(update) {
+    if (update.keyframe) {
+      tileCache.clear();
+      if (canvasElement.width !== update.width || canvasElement.height !== update.height) {
+        canvasElement.width = update.width;
+        canvasElement.height = update.height;
+      }
+    } else if (canvasElement.width !== update.width || canvasElement.height !== update.height) {
+      sendCommand({ type: 'RequestKeyframe' });
+      sendCommand({ type: 'StreamCredit', credits: 1 });
+      return;
+    }
+    
+    for (const rect of update.rects) {
+      const content = parseRectContent(rect.content);
+      
+      if (content.type === 'Encoded') {
+        const bytes = new Uint8Array(content.data);
+        const blob = new Blob([bytes], { type: `image/${content.format.toLowerCase()}` });
+        const bitmap = await createImageBitmap(blob);
+        canvasContext.drawImage(bitmap, rect.x, rect.y);
+        bitmap.close();
+        performanceStats.dataReceived += bytes.byteLength;
+        
+        // 矩形に含まれるタイルを左から順にキャッシュに保存
+        (content.tiles || []).forEach((hash, i) => {
+          const offset = i * update.tile_size;
+          const width = Math.min(update.tile_size, rect.width - offset);
+          tileCache.set(hash, canvasContext.getImageData(rect.x + offset, rect.y, width, rect.height));
+        });
+      } else if (content.type === 'TileRef') {
+        const tile = tileCache.get(content.hash);
+        if (!tile) {
+          // キャッシュがサーバーとずれているので、キーフレームで同期し直す
+          sendCommand({ type: 'RequestKeyframe' });
+          break;
+        }
+        canvasContext.putImageData(tile, rect.x, rect.y);
+      } else if (content.type === 'CopyRect') {
+        // スクロールやウィンドウの移動は、表示中の画像をずらして描画
+        canvasContext.drawImage(canvasElement, content.src_x, content.src_y, rect.width, rect.height, rect.x, rect.y, rect.width, rect.height);
+      }
+    }
+    
+    calculateFPS();
+    sendCommand({ type: 'StreamCredit', credits: 1 });
+  }
+  
+  /**
+   * 矩形の内容を種別付きのオブジェクトに変換
+   * 
+   * サーバーは`RectContent`を外部タグ形式（`{"Encoded": {...}}`、`{"TileRef": {...}}`、
+   * `{"CopyRect": {...}}`）で送り、画像データはバイト値の配列になります。
+   */
+  function parseRectContent(content) {
+    const type = Object.keys(content)[0];
+    return { type, ...content[type] };
+  }
+  
+  /**
+   * Base64文字列をバイト列に変換
+   */
+  function base64ToBytes(base64) {
+    const binaryData = atob(base64);
+    const byteArray = new Uint8Array(binaryData.length);
+    for (let i = 0; i < binaryData.length; i++) {
+      byteArray[i] = binaryData.charCodeAt(i);
+    }
+    return byteArray;
+  }
+  
   /**
    * 画像を表示
    */
@@ -657,6 +810,39 @@
     sendCommand(command);
   }
   
+  /**
+   * 対応するプロトコルバージョンと機能を通知
+   */
+  function sendHello() {
+    const command = {
+      type: 'Hello',
+      protocol_version: 1,
+      capabilities: {
+        codecs: ['JPEG', 'PNG'],
+        features: ['Streaming', 'TileCache']
+      }
+    };
+    
+    sendCommand(command);
+  }
+  
+  /**
+   * フレームのプッシュ配信を開始
+   */
+  function startStream() {
+    if (!isConnected || !connection) return;
+    
+    tileCache.clear();
+    
+    const command = {
+      type: 'StartStream',
+      fps: null,
+      credits: STREAM_CREDITS
+    };
+    
+    sendCommand(command);
+  }
+  
   /**
    * Pingを送信
    */
@@ -745,6 +931,9 @@
     // 状態をリセット
     isConnected = false;
     isConnecting = false;
+    streamingSupported = false;
+    isStreaming = false;
+    tileCache.clear();
     reconnectCount = 0;
     connection = null;
     
@@ -763,6 +952,9 @@
     clearIntervals();
     
     isConnected = false;
+    streamingSupported = false;
+    isStreaming = false;
+    tileCache.clear();
     
     // UI状態を更新
     updateStatus('切断されました');
@@ -799,7 +991,8 @@
     
     // スクリーンショット更新インターバル
     updateInterval = setInterval(() => {
-      if (isConnected) {
+      // 配信中はサーバーからフレームが届くので要求しない
+      if (isConnected && !isStreaming) {
         requestScreenshot();
       }
     }, config.updateInterval);
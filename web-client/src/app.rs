@@ -11,7 +11,7 @@ use crate::components::display::{MouseEventInfo, KeyEventInfo};
 use crate::components::status::{PerformanceInfo, SystemInfo, ConnectionStatus};
 use crate::components::settings::AppSettings;
 use crate::state::{AppState, ConnectionInfo};
-use crate::utils::{storage, logging, network, format};
+use crate::utils::{auth, storage, logging, network, format};
 use crate::utils::network::{get_data_channel_state, RTC_DATA_CHANNEL_OPEN};
 
 /// メインアプリケーション
@@ -68,6 +68,9 @@ pub fn app() -> Html {
     // エラーメッセージの状態管理
     let error_message = use_state(|| None::<String>);
     
+    // ワンタイムパスワード入力の要否
+    let otp_required = use_state(|| false);
+    
     // 初期化処理
     use_effect(move || {
         logging::log_info("Webクライアントが初期化されました");
@@ -87,6 +90,7 @@ pub fn app() -> Html {
         let websocket = websocket.clone();
         let webrtc = webrtc.clone();
         let error_message = error_message.clone();
+        let otp_required = otp_required.clone();
         let settings = settings.clone();
         
         Callback::from(move |info: ConnectionInfo| {
@@ -105,6 +109,7 @@ pub fn app() -> Html {
             
             // エラーメッセージをクリア
             error_message.set(None);
+            otp_required.set(false);
             
             // WebSocketまたはWebRTCで接続を試みる
             if info.prefer_webrtc && network::is_webrtc_supported() {
@@ -125,11 +130,98 @@ pub fn app() -> Html {
                 match web_sys::WebSocket::new(&ws_url) {
                     Ok(ws) => {
                         // イベントハンドラーを設定
-                        let onopen_callback = Closure::wrap(Box::new(move |_| {
-                            logging::log_info("WebSocket接続が確立されました");
-                            
-                            // WebSocket接続が確立された後の処理は別途実装が必要
-                        }) as Box<dyn FnMut(JsValue)>);
+                        let onopen_callback = {
+                            let ws = ws.clone();
+                            let info = info.clone();
+                            let connection_state = connection_state.clone();
+                            Closure::wrap(Box::new(move |_| {
+                                logging::log_info("WebSocket接続が確立されました");
+                                
+                                // 認証情報がある場合はチャレンジレスポンス認証を開始
+                                if let Some(username) = &info.username {
+                                    if let Err(e) = ws.send_with_str(&auth::authenticate_command(username)) {
+                                        logging::log_error(&format!("認証コマンド送信エラー: {:?}", e));
+                                    }
+                                    return;
+                                }
+                                
+                                let mut current = (*connection_state).clone();
+                                current.connected = true;
+                                current.connection_type = "WebSocket".to_string();
+                                current.status_message = "接続済み".to_string();
+                                connection_state.set(current);
+                            }) as Box<dyn FnMut(JsValue)>)
+                        };
+                        
+                        let onmessage_callback = {
+                            let ws = ws.clone();
+                            let info = info.clone();
+                            let connection_state = connection_state.clone();
+                            let error_message = error_message.clone();
+                            let otp_required = otp_required.clone();
+                            Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
+                                let text = match e.data().as_string() {
+                                    Some(text) => text,
+                                    None => return,
+                                };
+                                let message: serde_json::Value = match serde_json::from_str(&text) {
+                                    Ok(message) => message,
+                                    Err(_) => return,
+                                };
+                                
+                                if let Some(challenge) = message.get("AuthChallenge") {
+                                    // パスワードは送らず、ノンスから計算した証明のみを返す
+                                    let password = info.password.clone().unwrap_or_default();
+                                    let result = auth::compute_challenge_response(
+                                        &password,
+                                        challenge["salt"].as_str().unwrap_or_default(),
+                                        challenge["iterations"].as_u64().unwrap_or(0) as u32,
+                                        challenge["nonce"].as_str().unwrap_or_default(),
+                                    );
+                                    
+                                    match result {
+                                        Ok(response) => {
+                                            if let Err(e) = ws.send_with_str(&auth::challenge_response_command(&response)) {
+                                                logging::log_error(&format!("認証応答送信エラー: {:?}", e));
+                                            }
+                                        },
+                                        Err(e) => {
+                                            logging::log_error(&format!("認証応答の計算に失敗しました: {}", e));
+                                            error_message.set(Some("認証に失敗しました".to_string()));
+                                        }
+                                    }
+                                } else if let Some(request) = message.get("OtpRequired") {
+                                    // パスワード認証に成功し、第二要素を要求された
+                                    let text = request["message"].as_str().unwrap_or_default().to_string();
+                                    logging::log_info("ワンタイムパスワードを要求されました");
+                                    
+                                    let mut current = (*connection_state).clone();
+                                    current.status_message = text;
+                                    connection_state.set(current);
+                                    otp_required.set(true);
+                                } else if let Some(error) = message.get("Error") {
+                                    // ログイン試行の制限など、サーバーからのエラー
+                                    let text = error["message"].as_str().unwrap_or_default().to_string();
+                                    logging::log_warning(&format!("サーバーエラー({}): {}", error["code"], text));
+                                    error_message.set(Some(text));
+                                } else if let Some(result) = message.get("AuthResult") {
+                                    otp_required.set(false);
+                                    let success = result["success"].as_bool().unwrap_or(false);
+                                    let message = result["message"].as_str().unwrap_or_default().to_string();
+                                    
+                                    let mut current = (*connection_state).clone();
+                                    current.connected = success;
+                                    current.connection_type = "WebSocket".to_string();
+                                    current.status_message = message.clone();
+                                    connection_state.set(current);
+                                    
+                                    if !success {
+                                        logging::log_warning(&format!("認証に失敗しました: {}", message));
+                                        error_message.set(Some(message));
+                                    }
+                                }
+                            }) as Box<dyn FnMut(web_sys::MessageEvent)>)
+                        };
                         
                         let onerror_callback = {
                             let error_message = error_message.clone();
@@ -154,6 +246,7 @@ pub fn app() -> Html {
                         
                         // コールバックを設定
                         ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+                        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
                         ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
                         ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
                         
@@ -162,6 +255,7 @@ pub fn app() -> Html {
                         
                         // メモリリークを防ぐためにコールバックを忘れないようにする
                         onopen_callback.forget();
+                        onmessage_callback.forget();
                         onerror_callback.forget();
                         onclose_callback.forget();
                     },
@@ -201,6 +295,21 @@ pub fn app() -> Html {
         })
     };
     
+    // ワンタイムパスワード送信ハンドラー
+    let on_submit_otp = {
+        let websocket = websocket.clone();
+        
+        Callback::from(move |code: String| {
+            if let Some(ws) = &*websocket {
+                if ws.ready_state() == web_sys::WebSocket::OPEN {
+                    if let Err(e) = ws.send_with_str(&auth::submit_otp_command(&code)) {
+                        logging::log_error(&format!("ワンタイムパスワード送信エラー: {:?}", e));
+                    }
+                }
+            }
+        })
+    };
+    
     // 画質変更ハンドラー
     let on_quality_change = {
         let websocket = websocket.clone();
@@ -495,8 +604,10 @@ pub fn app() -> Html {
                     <ConnectionForm
                         connected={connection_state.connected}
                         connection_info={(*connection_info).clone()}
+                        otp_required={*otp_required}
                         on_connect={on_connect}
                         on_disconnect={on_disconnect}
+                        on_submit_otp={on_submit_otp}
                     />
                     
                     <ControlPanel
@@ -15,10 +15,16 @@ pub struct ConnectionFormProps {
     pub connected: bool,
     /// 接続情報
     pub connection_info: ConnectionInfo,
+    /// サーバーがワンタイムパスワードを要求しているかどうか
+    #[prop_or_default]
+    pub otp_required: bool,
     /// 接続ハンドラー
     pub on_connect: Callback<ConnectionInfo>,
     /// 切断ハンドラー
     pub on_disconnect: Callback<()>,
+    /// ワンタイムパスワード送信ハンドラー
+    #[prop_or_default]
+    pub on_submit_otp: Callback<String>,
 }
 
 /// 接続フォームの状態
@@ -38,6 +44,8 @@ struct ConnectionFormState {
     username: String,
     /// パスワード
     password: String,
+    /// ワンタイムパスワード
+    otp_code: String,
     /// 接続エラーメッセージ
     error_message: Option<String>,
 }
@@ -54,6 +62,7 @@ pub fn connection_form(props: &ConnectionFormProps) -> Html {
         prefer_webrtc: props.connection_info.prefer_webrtc,
         username: props.connection_info.username.clone().unwrap_or_default(),
         password: props.connection_info.password.clone().unwrap_or_default(),
+        otp_code: String::new(),
         error_message: None,
     });
 
@@ -150,6 +159,33 @@ pub fn connection_form(props: &ConnectionFormProps) -> Html {
         })
     };
 
+    // ワンタイムパスワードが変更された時のハンドラー
+    let on_otp_change = {
+        let state = state.clone();
+        Callback::from(move |e: Event| {
+            let target = e.target_dyn_into::<HtmlInputElement>();
+            if let Some(input) = target {
+                let mut new_state = (*state).clone();
+                new_state.otp_code = input.value();
+                state.set(new_state);
+            }
+        })
+    };
+
+    // ワンタイムパスワードの送信ボタンがクリックされた時のハンドラー
+    let on_otp_submit_click = {
+        let state = state.clone();
+        let on_submit_otp = props.on_submit_otp.clone();
+        Callback::from(move |_| {
+            on_submit_otp.emit(state.otp_code.clone());
+
+            // コードは使い捨てのため入力欄をクリア
+            let mut new_state = (*state).clone();
+            new_state.otp_code.clear();
+            state.set(new_state);
+        })
+    };
+
     // 接続ボタンがクリックされた時のハンドラー
     let on_connect_click = {
         let state = state.clone();
@@ -289,6 +325,30 @@ pub fn connection_form(props: &ConnectionFormProps) -> Html {
                 />
             </div>
             
+            {
+                if props.otp_required {
+                    html! {
+                        <div class="form-group otp-group">
+                            <label for="otp-code">{"ワンタイムパスワード:"}</label>
+                            <input 
+                                type="text" 
+                                id="otp-code" 
+                                inputmode="numeric" 
+                                autocomplete="one-time-code" 
+                                maxlength="8" 
+                                value={state.otp_code.clone()} 
+                                onchange={on_otp_change}
+                            />
+                            <button onclick={on_otp_submit_click} class="otp-submit-button">
+                                {"送信"}
+                            </button>
+                        </div>
+                    }
+                } else {
+                    html! {}
+                }
+            }
+            
             {
                 if let Some(error) = &state.error_message {
                     html! {
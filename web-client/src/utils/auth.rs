@@ -0,0 +1,96 @@
+//! 認証ユーティリティ
+//!
+//! サーバーのチャレンジレスポンス認証に応答するための関数を提供します。
+//! パスワードそのものはサーバーへ送信しません。
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2;
+use sha2::{Digest, Sha256};
+
+/// 導出キー長（バイト）
+const DERIVED_KEY_LEN: usize = 32;
+
+/// クライアントキーを導出するHMACのラベル
+const CLIENT_KEY_LABEL: &[u8] = b"Client Key";
+
+/// HMAC-SHA256を計算
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| format!("HMACの初期化に失敗: {}", e))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// 認証チャレンジへの応答を計算
+///
+/// PBKDF2-SHA256の結果から `ClientKey = HMAC(鍵, "Client Key")` を導出し、
+/// `ClientKey XOR HMAC(SHA256(ClientKey), ノンス)` をBase64で返します。
+pub fn compute_challenge_response(
+    password: &str,
+    salt: &str,
+    iterations: u32,
+    nonce: &str,
+) -> Result<String, String> {
+    if iterations == 0 {
+        return Err("反復回数が不正です".to_string());
+    }
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let salt = engine.decode(salt).map_err(|e| format!("ソルトのデコードに失敗: {}", e))?;
+    let nonce = engine.decode(nonce).map_err(|e| format!("ノンスのデコードに失敗: {}", e))?;
+
+    let mut key = [0u8; DERIVED_KEY_LEN];
+    pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, iterations, &mut key);
+
+    let client_key = hmac_sha256(&key, CLIENT_KEY_LABEL)?;
+    let signature = hmac_sha256(&Sha256::digest(&client_key), &nonce)?;
+    let proof: Vec<u8> = client_key.iter().zip(signature.iter()).map(|(a, b)| a ^ b).collect();
+
+    Ok(engine.encode(proof))
+}
+
+/// 認証開始コマンドを作成
+pub fn authenticate_command(username: &str) -> String {
+    let user_agent = web_sys::window()
+        .and_then(|window| window.navigator().user_agent().ok())
+        .unwrap_or_default();
+
+    serde_json::json!({
+        "Authenticate": {
+            "username": username,
+            "client_info": {
+                "app_name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "os_type": "web",
+                "os_version": user_agent,
+                "device_name": "",
+                "screen_width": 0,
+                "screen_height": 0,
+                "capabilities": [],
+            },
+        }
+    })
+    .to_string()
+}
+
+/// 認証チャレンジ応答コマンドを作成
+pub fn challenge_response_command(response: &str) -> String {
+    serde_json::json!({
+        "AuthChallengeResponse": {
+            "response": response,
+        }
+    })
+    .to_string()
+}
+
+/// ワンタイムパスワード送信コマンドを作成
+pub fn submit_otp_command(code: &str) -> String {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    serde_json::json!({
+        "SubmitOtp": {
+            "code": code,
+        }
+    })
+    .to_string()
+}
@@ -2,6 +2,7 @@
 //!
 //! このモジュールには、Webクライアントで使用される様々なユーティリティ関数や構造体が含まれています。
 
+pub mod auth;
 pub mod format;
 pub mod storage;
 pub mod network;